@@ -0,0 +1,286 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::collections::BTreeSet;
+use fxhash::FxHashMap;
+use anyhow::{Error, format_err};
+#[cfg(feature = "calibre-db")]
+use rusqlite::{Connection, OpenFlags};
+use crate::document::html::xml::XmlParser;
+use crate::metadata::Info;
+use crate::helpers::decode_entities;
+
+pub const OPF_FILENAME: &str = "metadata.opf";
+pub const DATABASE_FILENAME: &str = "metadata.db";
+
+// The metadata stored by Calibre for a book.
+#[derive(Debug, Clone, Default)]
+pub struct CalibreMetadata {
+    pub title: String,
+    pub authors: Vec<String>,
+    // The sort form of the authors, e.g. *Le Carré, John & Lynch, Scott*.
+    pub author_sort: String,
+    pub year: String,
+    pub language: String,
+    pub publisher: String,
+    pub series: String,
+    pub number: String,
+    pub identifier: String,
+    pub categories: BTreeSet<String>,
+}
+
+impl CalibreMetadata {
+    pub fn apply(&self, info: &mut Info) {
+        for (field, value) in [(&mut info.title, &self.title),
+                               (&mut info.year, &self.year),
+                               (&mut info.language, &self.language),
+                               (&mut info.publisher, &self.publisher),
+                               (&mut info.series, &self.series),
+                               (&mut info.number, &self.number),
+                               (&mut info.identifier, &self.identifier)] {
+            if !value.is_empty() {
+                *field = value.clone();
+            }
+        }
+
+        if !self.authors.is_empty() {
+            let mut authors = self.authors.clone();
+            if let Some(surname) = self.author_sort.split(" & ").next()
+                                       .and_then(|a| a.split(',').next())
+                                       .map(str::trim) {
+                authors[0] = sortable_author(&authors[0], surname);
+            }
+            info.author = authors.join(", ");
+        }
+
+        info.categories.extend(self.categories.iter().cloned());
+    }
+}
+
+pub fn metadata_from_opf<P: AsRef<Path>>(path: P) -> Result<CalibreMetadata, Error> {
+    let text = fs::read_to_string(path.as_ref())?;
+    let tree = XmlParser::new(&text).parse();
+    let md = tree.root().find("metadata")
+                 .ok_or_else(|| format_err!("the metadata element is missing"))?;
+    let mut metadata = CalibreMetadata::default();
+    let mut isbn = None;
+    let mut file_as = Vec::new();
+
+    for child in md.children() {
+        let text = || decode_entities(child.text().trim()).into_owned();
+        if child.tag_name() == Some("meta") {
+            let content = child.attribute("content").map(|s| decode_entities(s).into_owned());
+            match child.attribute("name") {
+                Some("calibre:series") => metadata.series = content.unwrap_or_default(),
+                Some("calibre:series_index") => metadata.number = content.as_deref().map(series_index).unwrap_or_default(),
+                Some("calibre:author_sort") => metadata.author_sort = content.unwrap_or_default(),
+                _ => (),
+            }
+            continue;
+        }
+        match child.tag_qualified_name() {
+            Some("dc:title") => metadata.title = text(),
+            Some("dc:creator") if child.attribute("opf:role").map_or(true, |role| role == "aut") => {
+                if let Some(sort) = child.attribute("opf:file-as") {
+                    file_as.push(decode_entities(sort).into_owned());
+                }
+                metadata.authors.push(text());
+            },
+            Some("dc:date") => metadata.year = year(&text()),
+            Some("dc:language") => metadata.language = text(),
+            Some("dc:publisher") => metadata.publisher = text(),
+            Some("dc:subject") => {
+                metadata.categories.insert(text());
+            },
+            Some("dc:identifier") => {
+                let value = text();
+                let scheme = child.attribute("opf:scheme").map(str::to_lowercase);
+                if scheme.as_deref() == Some("isbn") {
+                    isbn = Some(value);
+                } else if let Some(value) = value.strip_prefix("urn:isbn:").or_else(|| value.strip_prefix("isbn:")) {
+                    isbn = Some(value.to_string());
+                }
+            },
+            _ => (),
+        }
+    }
+
+    metadata.identifier = isbn.unwrap_or_default();
+
+    if metadata.author_sort.is_empty() {
+        metadata.author_sort = file_as.join(" & ");
+    }
+
+    Ok(metadata)
+}
+
+// The metadata of the books of a Calibre library, by book directory.
+pub struct CalibreDatabase {
+    books: FxHashMap<PathBuf, CalibreMetadata>,
+}
+
+impl CalibreDatabase {
+    #[cfg(feature = "calibre-db")]
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<CalibreDatabase, Error> {
+        let conn = Connection::open_with_flags(path.as_ref(), OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+        let mut by_id: FxHashMap<i64, (PathBuf, CalibreMetadata)> = FxHashMap::default();
+
+        let mut stmt = conn.prepare("SELECT id, path, title, author_sort, pubdate, series_index FROM books")?;
+        let rows = stmt.query_map([], |row| {
+            Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?, row.get::<_, String>(2)?,
+                row.get::<_, Option<String>>(3)?, row.get::<_, Option<String>>(4)?,
+                row.get::<_, Option<f64>>(5)?))
+        })?;
+        for row in rows {
+            let (id, path, title, author_sort, pubdate, index) = row?;
+            let metadata = CalibreMetadata {
+                title,
+                author_sort: author_sort.unwrap_or_default(),
+                year: pubdate.as_deref().map(year).unwrap_or_default(),
+                number: index.map(|index| series_index(&index.to_string())).unwrap_or_default(),
+                .. Default::default()
+            };
+            by_id.insert(id, (PathBuf::from(path), metadata));
+        }
+
+        let links = [("SELECT l.book, a.name FROM books_authors_link l JOIN authors a ON a.id = l.author ORDER BY l.id", 0),
+                     ("SELECT l.book, s.name FROM books_series_link l JOIN series s ON s.id = l.series", 1),
+                     ("SELECT l.book, t.name FROM books_tags_link l JOIN tags t ON t.id = l.tag", 2),
+                     ("SELECT l.book, g.lang_code FROM books_languages_link l JOIN languages g ON g.id = l.lang_code ORDER BY l.item_order", 3),
+                     ("SELECT l.book, p.name FROM books_publishers_link l JOIN publishers p ON p.id = l.publisher", 4),
+                     ("SELECT book, val FROM identifiers WHERE type = 'isbn'", 5)];
+
+        for (query, field) in links {
+            let mut stmt = conn.prepare(query)?;
+            let rows = stmt.query_map([], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?)))?;
+            for row in rows {
+                let (id, value) = row?;
+                if let Some((_, metadata)) = by_id.get_mut(&id) {
+                    match field {
+                        0 => metadata.authors.push(value),
+                        1 => metadata.series = value,
+                        2 => { metadata.categories.insert(value); },
+                        3 if metadata.language.is_empty() => metadata.language = value,
+                        4 => metadata.publisher = value,
+                        5 => metadata.identifier = value,
+                        _ => (),
+                    }
+                }
+            }
+        }
+
+        // The series index is meaningless for books that don't belong to a series.
+        let books = by_id.into_values().map(|(path, mut metadata)| {
+            if metadata.series.is_empty() {
+                metadata.number.clear();
+            }
+            (path, metadata)
+        }).collect();
+
+        Ok(CalibreDatabase { books })
+    }
+
+    #[cfg(not(feature = "calibre-db"))]
+    pub fn open<P: AsRef<Path>>(_path: P) -> Result<CalibreDatabase, Error> {
+        Err(format_err!("Calibre databases aren't supported by this build"))
+    }
+
+    // `dir` is the directory of the book, relative to the Calibre library.
+    pub fn get(&self, dir: &Path) -> Option<&CalibreMetadata> {
+        self.books.get(dir)
+    }
+}
+
+// Fills `info` with the metadata found in the `metadata.opf` file next to the book,
+// or else in the database. Returns false if no metadata was found.
+pub fn extract_metadata_from_calibre(prefix: &Path, db: Option<&CalibreDatabase>, info: &mut Info) -> bool {
+    let dir = info.file.path.parent().unwrap_or_else(|| Path::new(""));
+    let opf_path = prefix.join(dir).join(OPF_FILENAME);
+
+    if opf_path.exists() {
+        match metadata_from_opf(&opf_path) {
+            Ok(metadata) => {
+                metadata.apply(info);
+                return true;
+            },
+            Err(e) => eprintln!("Can't parse {}: {:#}.", opf_path.display(), e),
+        }
+    }
+
+    if let Some(metadata) = db.and_then(|db| db.get(dir)) {
+        metadata.apply(info);
+        return true;
+    }
+
+    false
+}
+
+// Calibre uses the year 101 for undefined dates.
+fn year(date: &str) -> String {
+    let year = date.chars().take(4).collect::<String>();
+    if year.len() == 4 && !year.starts_with('0') {
+        year
+    } else {
+        String::new()
+    }
+}
+
+// Joins the words of a multi-word surname with non-breaking spaces,
+// so that `Info::alphabetic_author` sorts by the whole surname.
+fn sortable_author(name: &str, surname: &str) -> String {
+    match name.strip_suffix(surname) {
+        Some(prefix) if surname.contains(' ') => format!("{}{}", prefix, surname.replace(' ', "\u{a0}")),
+        _ => name.to_string(),
+    }
+}
+
+fn series_index(index: &str) -> String {
+    match index.trim().parse::<f64>() {
+        Ok(value) if value.fract() == 0.0 => format!("{}", value as i64),
+        Ok(value) => format!("{}", value),
+        Err(_) => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_metadata_from_opf() {
+        let opf = r#"<?xml version='1.0' encoding='utf-8'?>
+<package xmlns="http://www.idpf.org/2007/opf" unique-identifier="uuid_id" version="2.0">
+    <metadata xmlns:dc="http://purl.org/dc/elements/1.1/" xmlns:opf="http://www.idpf.org/2007/opf">
+        <dc:identifier opf:scheme="calibre" id="calibre_id">42</dc:identifier>
+        <dc:identifier opf:scheme="ISBN">9780575077881</dc:identifier>
+        <dc:title>The Lies of Locke Lamora</dc:title>
+        <dc:creator opf:file-as="Lynch, Scott" opf:role="aut">Scott Lynch</dc:creator>
+        <dc:creator opf:role="ill">Someone Else</dc:creator>
+        <dc:date>2006-06-27T00:00:00+00:00</dc:date>
+        <dc:publisher>Gollancz</dc:publisher>
+        <dc:language>eng</dc:language>
+        <dc:subject>Fantasy</dc:subject>
+        <dc:subject>Heist</dc:subject>
+        <meta name="calibre:series" content="Gentleman Bastard"/>
+        <meta name="calibre:series_index" content="1.0"/>
+    </metadata>
+</package>"#;
+        let path = std::env::temp_dir().join(format!("plato-calibre-{}.opf", std::process::id()));
+        fs::write(&path, opf).unwrap();
+        let metadata = metadata_from_opf(&path).unwrap();
+        fs::remove_file(&path).ok();
+
+        let mut info = Info::default();
+        metadata.apply(&mut info);
+        assert_eq!(info.title, "The Lies of Locke Lamora");
+        assert_eq!(info.author, "Scott Lynch");
+        assert_eq!(info.year, "2006");
+        assert_eq!(info.series, "Gentleman Bastard");
+        assert_eq!(info.number, "1");
+        assert_eq!(info.identifier, "9780575077881");
+        assert_eq!(info.categories.len(), 2);
+        assert_eq!(series_index("2.5"), "2.5");
+        assert_eq!(sortable_author("John Le Carré", "Le Carré"), "John Le\u{a0}Carré");
+        assert_eq!(sortable_author("Scott Lynch", "Lynch"), "Scott Lynch");
+        assert_eq!(year("0101-01-01T00:00:00+00:00"), "");
+    }
+}
@@ -18,6 +18,7 @@ use crate::battery::Battery;
 use crate::geom::Rectangle;
 use crate::device::CURRENT_DEVICE;
 use crate::library::Library;
+use crate::kosync::ProgressSync;
 use crate::font::Fonts;
 use crate::rtc::Rtc;
 
@@ -50,12 +51,13 @@ pub struct Context {
 }
 
 impl Context {
-    pub fn new(fb: Box<dyn Framebuffer>, rtc: Option<Rtc>, library: Library,
+    pub fn new(fb: Box<dyn Framebuffer>, rtc: Option<Rtc>, mut library: Library,
                settings: Settings, fonts: Fonts, battery: Box<dyn Battery>,
                frontlight: Box<dyn Frontlight>, lightsensor: Box<dyn LightSensor>) -> Context {
         let dims = fb.dims();
         let rotation = CURRENT_DEVICE.transformed_rotation(fb.rotation());
         let rng = Xoroshiro128Plus::seed_from_u64(Local::now().timestamp_subsec_nanos() as u64);
+        library.progress_sync = ProgressSync::new(&settings.progress_sync);
         Context { fb, rtc, display: Display { dims, rotation },
                   library, settings, fonts, dictionaries: BTreeMap::new(),
                   keyboard_layouts: BTreeMap::new(), input_history: FxHashMap::default(),
@@ -0,0 +1,484 @@
+use std::io::{Read, Cursor};
+use std::fs::File;
+use std::path::Path;
+use std::cmp::Ordering;
+use std::collections::BTreeSet;
+use zip::ZipArchive;
+use anyhow::{Error, format_err};
+use image::{DynamicImage, ImageReader};
+use image::imageops::FilterType;
+use crate::framebuffer::Pixmap;
+use crate::helpers::decode_entities;
+use crate::document::{Document, Location, TocEntry, BoundedText, chapter, chapter_relative};
+use crate::metadata::TextAlign;
+use crate::geom::{Boundary, CycleDir};
+use super::html::engine::ResourceFetcher;
+use super::html::xml::XmlParser;
+use super::html::dom::XmlTree;
+
+const COMIC_INFO_FILENAME: &str = "ComicInfo.xml";
+const IMAGE_EXTENSIONS: [&str; 6] = ["jpg", "jpeg", "png", "gif", "webp", "bmp"];
+// The number of bytes read to find the dimensions of an image.
+const HEADER_SIZE: u64 = 1 << 16;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Part {
+    Whole,
+    Left,
+    Right,
+}
+
+#[derive(Debug, Clone)]
+struct Image {
+    name: String,
+    dims: (u32, u32),
+    // Double-page spreads are split in two pages.
+    spread: bool,
+    bookmark: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Page {
+    image: usize,
+    part: Part,
+}
+
+pub struct ComicDocument {
+    archive: Box<dyn ResourceFetcher>,
+    images: Vec<Image>,
+    pages: Vec<Page>,
+    info: Option<XmlTree>,
+    right_to_left: bool,
+}
+
+unsafe impl Send for ComicDocument {}
+unsafe impl Sync for ComicDocument {}
+
+impl ComicDocument {
+    pub fn new<P: AsRef<Path>>(path: P) -> Result<ComicDocument, Error> {
+        let file = File::open(path)?;
+        let mut archive = ZipArchive::new(file)?;
+        let mut names = Vec::new();
+        let mut info = None;
+
+        for index in 0..archive.len() {
+            let entry = archive.by_index(index)?;
+            if entry.is_dir() {
+                continue;
+            }
+            let name = entry.name().to_string();
+            if name.rsplit('/').next() == Some(COMIC_INFO_FILENAME) {
+                info = Some(name);
+            } else if is_image(&name) {
+                names.push(name);
+            }
+        }
+
+        let info = info.and_then(|name| archive.fetch(&name).ok())
+                       .map(|buf| XmlParser::new(&String::from_utf8_lossy(&buf)).parse());
+
+        let mut images = Vec::new();
+
+        for name in names {
+            let dims = {
+                let mut buf = Vec::new();
+                archive.by_name(&name)?.take(HEADER_SIZE).read_to_end(&mut buf)?;
+                image_dims(&buf).or_else(|_| archive.fetch(&name).and_then(|buf| image_dims(&buf)))
+            };
+            match dims {
+                Ok(dims) => images.push(Image { name, dims, spread: false, bookmark: None }),
+                Err(e) => eprintln!("Can't read {}: {:#}.", name, e),
+            }
+        }
+
+        ComicDocument::from_parts(Box::new(archive), images, info)
+    }
+
+    #[cfg(feature = "cbr")]
+    pub fn new_rar<P: AsRef<Path>>(path: P) -> Result<ComicDocument, Error> {
+        let mut archive = RarArchive { path: path.as_ref().to_path_buf() };
+        let mut images = Vec::new();
+        let mut info = None;
+
+        archive.for_each(|name, buf| {
+            if name.rsplit('/').next() == Some(COMIC_INFO_FILENAME) {
+                info = Some(XmlParser::new(&String::from_utf8_lossy(buf)).parse());
+            } else if is_image(name) {
+                match image_dims(buf) {
+                    Ok(dims) => images.push(Image { name: name.to_string(), dims, spread: false, bookmark: None }),
+                    Err(e) => eprintln!("Can't read {}: {:#}.", name, e),
+                }
+            }
+            false
+        })?;
+
+        ComicDocument::from_parts(Box::new(archive), images, info)
+    }
+
+    #[cfg(not(feature = "cbr"))]
+    pub fn new_rar<P: AsRef<Path>>(_path: P) -> Result<ComicDocument, Error> {
+        Err(format_err!("RAR archives aren't supported by this build"))
+    }
+
+    fn from_parts(archive: Box<dyn ResourceFetcher>, mut images: Vec<Image>, info: Option<XmlTree>) -> Result<ComicDocument, Error> {
+        if images.is_empty() {
+            return Err(format_err!("no images found"));
+        }
+
+        images.sort_by(|a, b| natural_cmp(&a.name, &b.name));
+
+        for image in &mut images {
+            image.spread = image.dims.0 > image.dims.1;
+        }
+
+        let mut right_to_left = false;
+
+        if let Some(ref info) = info {
+            right_to_left = comic_info(info, "Manga").as_deref() == Some("YesAndRightToLeft");
+            // The page attributes are indexed by image.
+            if let Some(pages) = info.root().find("Pages") {
+                for page in pages.children().filter(|child| child.tag_name() == Some("Page")) {
+                    let image = page.attribute("Image")
+                                    .and_then(|index| index.parse::<usize>().ok())
+                                    .and_then(|index| images.get_mut(index));
+                    if let Some(image) = image {
+                        if let Some(double_page) = page.attribute("DoublePage") {
+                            image.spread = double_page.eq_ignore_ascii_case("true");
+                        }
+                        image.bookmark = page.attribute("Bookmark")
+                                             .filter(|bookmark| !bookmark.is_empty())
+                                             .map(|bookmark| decode_entities(bookmark).into_owned());
+                    }
+                }
+            }
+        }
+
+        let mut doc = ComicDocument {
+            archive,
+            images,
+            pages: Vec::new(),
+            info,
+            right_to_left,
+        };
+
+        doc.paginate();
+
+        Ok(doc)
+    }
+
+    fn paginate(&mut self) {
+        let (first, second) = if self.right_to_left {
+            (Part::Right, Part::Left)
+        } else {
+            (Part::Left, Part::Right)
+        };
+
+        self.pages.clear();
+
+        for (image, entry) in self.images.iter().enumerate() {
+            if entry.spread {
+                self.pages.push(Page { image, part: first });
+                self.pages.push(Page { image, part: second });
+            } else {
+                self.pages.push(Page { image, part: Part::Whole });
+            }
+        }
+    }
+
+    // Returns the value of the given *ComicInfo* field.
+    pub fn comic_info(&self, key: &str) -> Option<String> {
+        self.info.as_ref().and_then(|info| comic_info(info, key))
+    }
+
+    pub fn series(&self) -> Option<(String, String)> {
+        self.comic_info("Series").map(|series| (series, self.comic_info("Number").unwrap_or_default()))
+    }
+
+    pub fn year(&self) -> Option<String> {
+        self.comic_info("Year")
+    }
+
+    pub fn publisher(&self) -> Option<String> {
+        self.comic_info("Publisher")
+    }
+
+    pub fn language(&self) -> Option<String> {
+        self.comic_info("LanguageISO")
+    }
+
+    pub fn categories(&self) -> BTreeSet<String> {
+        self.comic_info("Genre")
+            .map(|genres| genres.split(',')
+                                .map(|genre| genre.trim().to_string())
+                                .filter(|genre| !genre.is_empty())
+                                .collect())
+            .unwrap_or_default()
+    }
+
+    fn page_dims(&self, page: &Page) -> (u32, u32) {
+        let (width, height) = self.images[page.image].dims;
+        if page.part == Part::Whole {
+            (width, height)
+        } else {
+            (width / 2, height)
+        }
+    }
+}
+
+fn comic_info(info: &XmlTree, key: &str) -> Option<String> {
+    info.root().find(key)
+        .map(|node| decode_entities(node.text().trim()).into_owned())
+        .filter(|value| !value.is_empty())
+}
+
+fn is_image(name: &str) -> bool {
+    let file_name = name.rsplit('/').next().unwrap_or(name);
+    !file_name.starts_with('.') &&
+    file_name.rsplit_once('.').map_or(false, |(_, ext)| {
+        IMAGE_EXTENSIONS.contains(&ext.to_lowercase().as_str())
+    })
+}
+
+fn image_dims(buf: &[u8]) -> Result<(u32, u32), Error> {
+    ImageReader::new(Cursor::new(buf)).with_guessed_format()?
+                                      .into_dimensions()
+                                      .map_err(Into::into)
+}
+
+// Compares the names so that *page2* comes before *page10*.
+fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let mut a = a.chars().peekable();
+    let mut b = b.chars().peekable();
+
+    loop {
+        match (a.peek().copied(), b.peek().copied()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let mut m = String::new();
+                while let Some(c) = a.next_if(char::is_ascii_digit) {
+                    m.push(c);
+                }
+                let mut n = String::new();
+                while let Some(c) = b.next_if(char::is_ascii_digit) {
+                    n.push(c);
+                }
+                let m = m.trim_start_matches('0');
+                let n = n.trim_start_matches('0');
+                let ord = m.len().cmp(&n.len()).then_with(|| m.cmp(n));
+                if ord != Ordering::Equal {
+                    return ord;
+                }
+            },
+            (Some(x), Some(y)) => {
+                let ord = x.to_lowercase().cmp(y.to_lowercase());
+                if ord != Ordering::Equal {
+                    return ord;
+                }
+                a.next();
+                b.next();
+            },
+        }
+    }
+}
+
+impl Document for ComicDocument {
+    fn dims(&self, index: usize) -> Option<(f32, f32)> {
+        self.pages.get(index).map(|page| {
+            let (width, height) = self.page_dims(page);
+            (width as f32, height as f32)
+        })
+    }
+
+    fn pages_count(&self) -> usize {
+        self.pages.len()
+    }
+
+    fn pixmap(&mut self, loc: Location, scale: f32, samples: usize) -> Option<(Pixmap, usize)> {
+        let index = self.resolve_location(loc)?;
+        let page = self.pages[index];
+        let buf = self.archive.fetch(&self.images[page.image].name)
+                      .map_err(|e| eprintln!("Can't fetch {}: {:#}.", self.images[page.image].name, e))
+                      .ok()?;
+        let mut img = image::load_from_memory(&buf)
+                            .map_err(|e| eprintln!("Can't decode {}: {:#}.", self.images[page.image].name, e))
+                            .ok()?;
+
+        let half_width = img.width() / 2;
+        img = match page.part {
+            Part::Whole => img,
+            Part::Left => img.crop_imm(0, 0, half_width, img.height()),
+            Part::Right => img.crop_imm(half_width, 0, img.width() - half_width, img.height()),
+        };
+
+        let width = ((img.width() as f32 * scale).round() as u32).max(1);
+        let height = ((img.height() as f32 * scale).round() as u32).max(1);
+        let img = img.resize_exact(width, height, FilterType::Triangle);
+
+        let data = if samples == 1 {
+            DynamicImage::ImageLuma8(img.to_luma8()).into_bytes()
+        } else {
+            DynamicImage::ImageRgb8(img.to_rgb8()).into_bytes()
+        };
+
+        Some((Pixmap { width, height, samples, data }, index))
+    }
+
+    fn toc(&mut self) -> Option<Vec<TocEntry>> {
+        let mut entries = Vec::new();
+
+        for (location, page) in self.pages.iter().enumerate() {
+            if page.part == Part::Right && !self.right_to_left || page.part == Part::Left && self.right_to_left {
+                continue;
+            }
+            if let Some(ref title) = self.images[page.image].bookmark {
+                entries.push(TocEntry {
+                    title: title.clone(),
+                    location: Location::Exact(location),
+                    index: entries.len(),
+                    children: Vec::new(),
+                });
+            }
+        }
+
+        if entries.is_empty() {
+            None
+        } else {
+            Some(entries)
+        }
+    }
+
+    fn chapter<'a>(&mut self, offset: usize, toc: &'a [TocEntry]) -> Option<(&'a TocEntry, f32)> {
+        chapter(offset, self.pages_count(), toc)
+    }
+
+    fn chapter_relative<'a>(&mut self, offset: usize, dir: CycleDir, toc: &'a [TocEntry]) -> Option<&'a TocEntry> {
+        chapter_relative(offset, dir, toc)
+    }
+
+    fn words(&mut self, _loc: Location) -> Option<(Vec<BoundedText>, usize)> {
+        None
+    }
+
+    fn lines(&mut self, _loc: Location) -> Option<(Vec<BoundedText>, usize)> {
+        None
+    }
+
+    fn links(&mut self, _loc: Location) -> Option<(Vec<BoundedText>, usize)> {
+        None
+    }
+
+    fn images(&mut self, _loc: Location) -> Option<(Vec<Boundary>, usize)> {
+        None
+    }
+
+    fn metadata(&self, key: &str) -> Option<String> {
+        self.comic_info(key)
+    }
+
+    fn title(&self) -> Option<String> {
+        self.comic_info("Title")
+    }
+
+    fn author(&self) -> Option<String> {
+        self.comic_info("Writer")
+    }
+
+    fn is_reflowable(&self) -> bool {
+        false
+    }
+
+    fn is_right_to_left(&self) -> bool {
+        self.right_to_left
+    }
+
+    fn set_right_to_left(&mut self, right_to_left: bool) {
+        if self.right_to_left != right_to_left {
+            self.right_to_left = right_to_left;
+            self.paginate();
+        }
+    }
+
+    fn layout(&mut self, _width: u32, _height: u32, _font_size: f32, _dpi: u16) {
+    }
+
+    fn set_text_align(&mut self, _text_align: TextAlign) {
+    }
+
+    fn set_font_family(&mut self, _family_name: &str, _search_path: &str) {
+    }
+
+    fn set_margin_width(&mut self, _width: i32) {
+    }
+
+    fn set_line_height(&mut self, _line_height: f32) {
+    }
+
+    fn set_hyphen_penalty(&mut self, _hyphen_penalty: i32) {
+    }
+
+    fn set_stretch_tolerance(&mut self, _stretch_tolerance: f32) {
+    }
+
+    fn set_ignore_document_css(&mut self, _ignore: bool) {
+    }
+}
+
+// RAR archives can only be read sequentially.
+#[cfg(feature = "cbr")]
+struct RarArchive {
+    path: std::path::PathBuf,
+}
+
+#[cfg(feature = "cbr")]
+impl RarArchive {
+    // Calls `f` with the name and the content of each file, until it returns true.
+    fn for_each<F>(&mut self, mut f: F) -> Result<(), Error> where F: FnMut(&str, &[u8]) -> bool {
+        let mut archive = unrar::Archive::new(&self.path).open_for_processing()?;
+        while let Some(header) = archive.read_header()? {
+            archive = if header.entry().is_file() {
+                let name = header.entry().filename.to_string_lossy().replace('\\', "/");
+                let (buf, rest) = header.read()?;
+                if f(&name, &buf) {
+                    break;
+                }
+                rest
+            } else {
+                header.skip()?
+            };
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "cbr")]
+impl ResourceFetcher for RarArchive {
+    fn fetch(&mut self, name: &str) -> Result<Vec<u8>, Error> {
+        let mut result = None;
+        self.for_each(|entry_name, buf| {
+            if entry_name == name {
+                result = Some(buf.to_vec());
+                true
+            } else {
+                false
+            }
+        })?;
+        result.ok_or_else(|| format_err!("can't find {}", name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_natural_order() {
+        let mut names = vec!["p10.jpg", "p2.jpg", "P1.jpg", "p02b.jpg", "cover.jpg"];
+        names.sort_by(|a, b| natural_cmp(a, b));
+        assert_eq!(names, vec!["cover.jpg", "P1.jpg", "p2.jpg", "p02b.jpg", "p10.jpg"]);
+        assert!(is_image("Vol 1/001.JPG"));
+        assert!(!is_image("__MACOSX/._001.jpg"));
+        assert!(!is_image("ComicInfo.xml"));
+    }
+}
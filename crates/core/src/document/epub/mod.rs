@@ -1,3 +1,4 @@
+use std::iter;
 use std::io::Read;
 use std::fs::{self, File};
 use std::path::{Path, PathBuf};
@@ -9,21 +10,32 @@ use anyhow::{Error, format_err};
 use crate::framebuffer::Pixmap;
 use crate::helpers::{Normalize, decode_entities};
 use crate::document::{Document, Location, TextLocation, TocEntry, BoundedText, chapter_from_uri};
+use crate::document::TABLE_URI;
 use crate::unit::pt_to_px;
 use crate::geom::{Boundary, CycleDir};
 use super::pdf::PdfOpener;
 use super::html::dom::{XmlTree, NodeRef};
-use super::html::engine::{Page, Engine, ResourceFetcher};
+use super::html::engine::{Page, Engine, ResourceFetcher, load_image, writing_mode};
 use super::html::layout::{StyleData, LoopContext};
-use super::html::layout::{RootData, DrawState, DrawCommand, TextCommand, ImageCommand};
-use super::html::layout::TextAlign;
+use super::html::layout::{RootData, DrawState, DrawCommand, TextCommand, ImageCommand, TableCommand};
+use super::html::layout::{TextAlign, Direction};
+use super::html::bidi::{parse_direction, language_direction};
 use super::html::style::StyleSheet;
 use super::html::css::CssParser;
 use super::html::xml::XmlParser;
+use super::html::fragment_resources;
 
 const VIEWER_STYLESHEET: &str = "css/epub.css";
 const USER_STYLESHEET: &str = "css/epub-user.css";
 
+// Targets of longer links, or with longer texts, aren't guessed to be notes.
+const MAX_NOTEREF_LENGTH: usize = 8;
+const MAX_NOTE_LENGTH: usize = 2000;
+const NOTE_TYPES: [&str; 4] = ["footnote", "endnote", "rearnote", "note"];
+const NOTE_ROLES: [&str; 2] = ["doc-footnote", "doc-endnote"];
+// The number of parsed spine items kept: a link and its note can be in different items.
+const PARSED_ITEMS_COUNT: usize = 2;
+
 type UriCache = FxHashMap<String, usize>;
 
 impl ResourceFetcher for ZipArchive<File> {
@@ -42,7 +54,9 @@ pub struct EpubDocument {
     engine: Engine,
     spine: Vec<Chunk>,
     cache: FxHashMap<usize, Vec<Page>>,
+    trees: Vec<(String, XmlTree)>,
     ignore_document_css: bool,
+    right_to_left: bool,
 }
 
 #[derive(Debug)]
@@ -116,6 +130,17 @@ impl EpubDocument {
             return Err(format_err!("the spine is empty"));
         }
 
+        // Books written vertically are read from right to left.
+        let right_to_left = match info.root().find("spine")
+                                      .and_then(|spn| spn.attribute("page-progression-direction")) {
+            Some(direction) => direction == "rtl",
+            None => info.root().find("metadata").map_or(false, |md| {
+                md.children().any(|child| child.tag_name() == Some("meta") &&
+                                          child.attribute("name") == Some("primary-writing-mode") &&
+                                          child.attribute("content").map_or(false, |v| v.starts_with("vertical-rl")))
+            }),
+        };
+
         Ok(EpubDocument {
             archive,
             info,
@@ -123,7 +148,9 @@ impl EpubDocument {
             engine: Engine::new(),
             spine,
             cache: FxHashMap::default(),
+            trees: Vec::new(),
             ignore_document_css: false,
+            right_to_left,
         })
     }
 
@@ -311,6 +338,22 @@ impl EpubDocument {
         }
     }
 
+    // The last parsed items are kept for looking up the notes and the tables.
+    fn parse_item(&mut self, name: &str) -> Option<&XmlTree> {
+        if let Some(index) = self.trees.iter().position(|(n, _)| n == name) {
+            let item = self.trees.remove(index);
+            self.trees.push(item);
+        } else {
+            let text = self.archive.fetch(name).ok()
+                           .map(|bytes| String::from_utf8_lossy(&bytes).into_owned())?;
+            if self.trees.len() >= PARSED_ITEMS_COUNT {
+                self.trees.remove(0);
+            }
+            self.trees.push((name.to_string(), XmlParser::new(&text).parse()));
+        }
+        self.trees.last().map(|(_, tree)| tree)
+    }
+
     fn build_display_list(&mut self, index: usize, start_offset: usize) -> Vec<Page> {
         let mut text = String::new();
         let mut spine_dir = PathBuf::default();
@@ -352,20 +395,26 @@ impl EpubDocument {
                                 if let Ok(mut zf) = self.archive.by_name(name) {
                                     zf.read_to_string(&mut text).ok();
                                     let mut css = CssParser::new(&text).parse();
+                                    css.resolve_font_sources(Path::new(name).parent().unwrap_or_else(|| Path::new("")));
                                     inner_css.append(&mut css, false);
                                 }
                             }
                         }
                     } else if child.tag_name() == Some("style") && child.attribute("type") == Some("text/css") {
                         let mut css = CssParser::new(&child.text()).parse();
+                        css.resolve_font_sources(&spine_dir);
                         inner_css.append(&mut css, false);
                     }
                 }
             }
 
+            self.engine.load_font_faces(&inner_css, &mut self.archive);
             stylesheet.append(&mut inner_css, true);
         }
 
+        stylesheet.retain_media(&self.engine.media_device());
+        self.engine.set_writing_mode(writing_mode(root.root(), &stylesheet));
+
         let mut display_list = Vec::new();
 
         if let Some(body) = root.root().find("body") {
@@ -378,8 +427,15 @@ impl EpubDocument {
                     .map(String::from)
             });
 
+            let direction = root.root().find("html")
+                                .and_then(|html| html.attribute("dir"))
+                                .and_then(parse_direction)
+                                .or_else(|| language.as_deref().map(language_direction))
+                                .unwrap_or(Direction::Ltr);
+
             let style = StyleData {
                 language,
+                direction,
                 font_size: self.engine.font_size,
                 line_height: pt_to_px(self.engine.line_height * self.engine.font_size, self.engine.dpi).round() as i32,
                 text_align: self.engine.text_align,
@@ -405,6 +461,7 @@ impl EpubDocument {
 
             self.engine.build_display_list(body, &style, &loop_context, &stylesheet, &root_data, &mut self.archive, &mut draw_state, &mut display_list);
 
+            self.engine.rotate_display_list(&mut display_list);
             display_list.retain(|page| !page.is_empty());
 
             if display_list.is_empty() {
@@ -552,6 +609,14 @@ impl EpubDocument {
                     .and_then(|entry| entry.find_by_id(cover_id))
                     .and_then(|entry| entry.attribute("href"))
             })
+            .or_else(|| {
+                self.info.root().find("manifest")
+                    .and_then(|mf| mf.children().find(|child| {
+                        child.attribute("properties")
+                             .map_or(false, |props| props.split_whitespace().any(|p| p == "cover-image"))
+                    }))
+                    .and_then(|entry| entry.attribute("href"))
+            })
             .or_else(|| {
                 self.info.root().find("manifest")
                     .and_then(|mf| mf.children().find(|child| {
@@ -580,6 +645,16 @@ impl EpubDocument {
     }
 }
 
+fn has_epub_type(node: NodeRef, types: &[&str]) -> bool {
+    node.attribute("epub:type")
+        .map_or(false, |value| value.split_whitespace().any(|t| types.contains(&t)))
+}
+
+fn is_note(node: NodeRef) -> bool {
+    has_epub_type(node, &NOTE_TYPES) ||
+    node.attribute("role").map_or(false, |role| NOTE_ROLES.contains(&role))
+}
+
 impl Document for EpubDocument {
     fn preview_pixmap(&mut self, width: f32, height: f32, samples: usize) -> Option<Pixmap> {
         let opener = PdfOpener::new()?;
@@ -587,8 +662,8 @@ impl Document for EpubDocument {
             .map(|path| self.parent.join(path)
                             .to_string_lossy().into_owned())
             .and_then(|path| {
-                self.archive.fetch(&path).ok()
-                    .and_then(|buf| opener.open_memory(&path, &buf))
+                load_image(&path, &mut self.archive)
+                    .and_then(|(buf, magic)| opener.open_memory(&magic, &buf))
                     .and_then(|mut doc| {
                         doc.dims(0).and_then(|dims| {
                             let scale = (width / dims.0).min(height / dims.1);
@@ -829,6 +904,13 @@ impl Document for EpubDocument {
                             location: TextLocation::Dynamic(*offset),
                         })
                     },
+                    DrawCommand::Table(TableCommand { rect, offset }) => {
+                        Some(BoundedText {
+                            text: TABLE_URI.to_string(),
+                            rect: (*rect).into(),
+                            location: TextLocation::Dynamic(*offset),
+                        })
+                    },
                     _ => None,
                 }
             }).collect(), offset)
@@ -929,6 +1011,82 @@ impl Document for EpubDocument {
         true
     }
 
+    fn is_right_to_left(&self) -> bool {
+        self.right_to_left
+    }
+
+    fn set_right_to_left(&mut self, right_to_left: bool) {
+        self.right_to_left = right_to_left;
+    }
+
+    // A link targets a note when it's marked as a note reference, or when
+    // its target is marked as a note. Otherwise, short links pointing at short
+    // fragments of other spine items are assumed to be notes.
+    fn footnote(&mut self, offset: usize, uri: &str) -> Option<(String, Box<dyn ResourceFetcher>)> {
+        let (index, start_offset) = self.vertebra_coordinates(offset)?;
+        let path = self.spine[index].path.clone();
+        let frag_index = uri.find('#')?;
+        let name = if frag_index == 0 {
+            path.clone()
+        } else {
+            let parent = Path::new(&path).parent()
+                              .unwrap_or_else(|| Path::new(""));
+            parent.join(&uri[..frag_index]).normalize()
+                  .to_string_lossy().into_owned()
+        };
+        let id = &uri[frag_index+1..];
+
+        let (is_noteref, is_short) = {
+            let root = self.parse_item(&path)?;
+            let local_offset = offset - start_offset;
+            let link = root.root().descendants()
+                           .take_while(|n| n.offset() <= local_offset)
+                           .last()
+                           .and_then(|n| n.ancestors().find(|n| n.tag_name() == Some("a")))?;
+            (has_epub_type(link, &["noteref"]) || link.attribute("role") == Some("doc-noteref"),
+             link.text().trim().chars().count() <= MAX_NOTEREF_LENGTH)
+        };
+
+        self.parse_item(&name)?;
+        let (_, root) = self.trees.last()?;
+        let target = root.root().find_by_id(id)?;
+
+        let note = match iter::once(target).chain(target.ancestor_elements()).find(|n| is_note(*n)) {
+            Some(node) => node,
+            None => {
+                let node = if target.is_block() {
+                    target
+                } else {
+                    target.ancestor_elements().find(NodeRef::is_block).unwrap_or(target)
+                };
+                let is_heading = matches!(node.tag_name(), Some("h1" | "h2" | "h3" | "h4" | "h5" | "h6"));
+                let is_guessed = name != path && is_short && !is_heading &&
+                                 node.text().trim().chars().count() <= MAX_NOTE_LENGTH;
+                if !is_noteref && !is_guessed {
+                    return None;
+                }
+                node
+            },
+        };
+
+        let dir = Path::new(&name).parent().unwrap_or_else(|| Path::new(""));
+        let resources = fragment_resources(note, dir, &mut self.archive);
+        Some((format!("<html><body>{}</body></html>", note.to_html()), Box::new(resources)))
+    }
+
+    fn table(&mut self, offset: usize) -> Option<(String, Box<dyn ResourceFetcher>)> {
+        let (index, start_offset) = self.vertebra_coordinates(offset)?;
+        let path = self.spine[index].path.clone();
+        self.parse_item(&path)?;
+        let (_, root) = self.trees.last()?;
+        let local_offset = offset - start_offset;
+        let table = root.root().descendants()
+                        .find(|n| n.offset() == local_offset && n.tag_name() == Some("table"))?;
+        let dir = Path::new(&path).parent().unwrap_or_else(|| Path::new(""));
+        let resources = fragment_resources(table, dir, &mut self.archive);
+        Some((format!("<html><body>{}</body></html>", table.to_html()), Box::new(resources)))
+    }
+
     fn has_synthetic_page_numbers(&self) -> bool {
         true
     }
@@ -0,0 +1,406 @@
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::Path;
+use std::collections::BTreeSet;
+use fxhash::FxHashMap;
+use zip::ZipArchive;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use encoding_rs::Encoding;
+use anyhow::{Error, format_err};
+use crate::helpers::decode_entities;
+use super::html::dom::{XmlTree, NodeRef, NodeData, TextData};
+use super::html::engine::ResourceFetcher;
+use super::html::text::decode_text;
+
+const STYLESHEET: &str = "\
+.section-title, .notes-title { text-align: center; }
+.subtitle { text-align: center; font-weight: bold; }
+.epigraph { margin-left: 30%; font-style: italic; }
+.cite { margin: 1em 2em; }
+.text-author { text-align: right; font-style: normal; font-weight: bold; }
+.poem { margin: 1em 0 1em 2em; }
+.stanza { margin: 0.5em 0; }
+.verse { margin: 0; text-indent: 0; }
+.image, .cover { text-align: center; }
+.note-title { font-weight: bold; }
+s { text-decoration: line-through; }";
+
+// The embedded images, by name.
+#[derive(Default)]
+pub struct Binaries(FxHashMap<String, Vec<u8>>);
+
+impl ResourceFetcher for Binaries {
+    fn fetch(&mut self, name: &str) -> Result<Vec<u8>, Error> {
+        self.0.get(name).cloned()
+            .ok_or_else(|| format_err!("can't find binary {}", name))
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Fb2Metadata {
+    pub title: String,
+    pub authors: Vec<String>,
+    pub genres: BTreeSet<String>,
+    pub series: String,
+    pub number: String,
+    pub language: String,
+    pub year: String,
+    pub publisher: String,
+    pub identifier: String,
+    pub annotation: String,
+}
+
+// Reads an FB2 file, or the first FB2 file of a ZIP archive.
+pub fn read_fb2<P: AsRef<Path>>(path: P) -> Result<String, Error> {
+    let path = path.as_ref();
+    let is_zip = path.extension().map_or(false, |ext| ext.eq_ignore_ascii_case("zip"));
+
+    let bytes = if is_zip {
+        let file = File::open(path)?;
+        let mut archive = ZipArchive::new(file)?;
+        let name = archive.file_names()
+                          .find(|name| name.to_lowercase().ends_with(".fb2"))
+                          .map(String::from)
+                          .ok_or_else(|| format_err!("can't find an FB2 file in the archive"))?;
+        let mut entry = archive.by_name(&name)?;
+        let mut bytes = Vec::new();
+        entry.read_to_end(&mut bytes)?;
+        bytes
+    } else {
+        fs::read(path)?
+    };
+
+    Ok(decode_fb2(&bytes))
+}
+
+// FB2 files often declare a legacy encoding in their XML declaration.
+fn decode_fb2(bytes: &[u8]) -> String {
+    let head = String::from_utf8_lossy(&bytes[..bytes.len().min(256)]);
+    let encoding = head.find("encoding=").and_then(|index| {
+        let rest = &head[index+9..];
+        let quote = rest.chars().next()?;
+        let rest = &rest[quote.len_utf8()..];
+        rest.find(quote).map(|end| &rest[..end])
+    }).and_then(|label| Encoding::for_label(label.as_bytes()));
+
+    match encoding {
+        Some(encoding) if encoding != encoding_rs::UTF_8 => {
+            encoding.decode_without_bom_handling(bytes).0.into_owned()
+        },
+        _ => decode_text(bytes),
+    }
+}
+
+fn element_text(node: NodeRef) -> String {
+    decode_entities(node.text().trim()).into_owned()
+}
+
+fn child<'a>(node: NodeRef<'a>, name: &str) -> Option<NodeRef<'a>> {
+    node.children().find(|child| child.tag_name() == Some(name))
+}
+
+// Returns the value of the `href` attribute, whatever its namespace prefix.
+fn href<'a>(node: NodeRef<'a>) -> Option<&'a str> {
+    node.attributes().and_then(|attributes| {
+        attributes.iter()
+                  .find(|(key, _)| *key == "href" || key.ends_with(":href"))
+                  .map(|(_, value)| value.as_str())
+    })
+}
+
+fn author_name(node: NodeRef) -> String {
+    let name = ["first-name", "middle-name", "last-name"].iter()
+                   .filter_map(|name| child(node, name).map(element_text))
+                   .filter(|part| !part.is_empty())
+                   .collect::<Vec<String>>()
+                   .join(" ");
+    if name.is_empty() {
+        child(node, "nickname").map(element_text).unwrap_or_default()
+    } else {
+        name
+    }
+}
+
+pub fn metadata_from_fb2(tree: &XmlTree) -> Fb2Metadata {
+    let mut metadata = Fb2Metadata::default();
+
+    if let Some(title_info) = tree.root().find("title-info") {
+        for node in title_info.children() {
+            match node.tag_name() {
+                Some("book-title") => metadata.title = element_text(node),
+                Some("author") => {
+                    let name = author_name(node);
+                    if !name.is_empty() {
+                        metadata.authors.push(name);
+                    }
+                },
+                Some("genre") => {
+                    metadata.genres.insert(element_text(node));
+                },
+                Some("lang") => metadata.language = element_text(node),
+                Some("date") => {
+                    let date = node.attribute("value").map(String::from)
+                                   .unwrap_or_else(|| element_text(node));
+                    metadata.year = date.chars().take(4).collect();
+                },
+                Some("sequence") if metadata.series.is_empty() => {
+                    metadata.series = node.attribute("name").map(|s| decode_entities(s).into_owned())
+                                          .unwrap_or_default();
+                    metadata.number = node.attribute("number").map(String::from).unwrap_or_default();
+                },
+                Some("annotation") => metadata.annotation = element_text(node),
+                _ => (),
+            }
+        }
+    }
+
+    if let Some(publish_info) = tree.root().find("publish-info") {
+        for node in publish_info.children() {
+            match node.tag_name() {
+                Some("publisher") => metadata.publisher = element_text(node),
+                Some("year") if metadata.year.is_empty() => metadata.year = element_text(node),
+                Some("isbn") => metadata.identifier = element_text(node),
+                _ => (),
+            }
+        }
+    }
+
+    metadata
+}
+
+fn escape_attribute(value: &str) -> String {
+    value.replace('"', "&quot;")
+}
+
+struct Converter {
+    // Maps the binary identifiers to file names with an extension.
+    names: FxHashMap<String, String>,
+    in_notes: bool,
+    buf: String,
+}
+
+impl Converter {
+    fn open(&mut self, tag: &str, class: Option<&str>, node: NodeRef) {
+        self.buf.push('<');
+        self.buf.push_str(tag);
+        if let Some(class) = class {
+            self.buf.push_str(&format!(" class=\"{}\"", class));
+        }
+        if let Some(id) = node.attribute("id") {
+            self.buf.push_str(&format!(" id=\"{}\"", escape_attribute(id)));
+        }
+        self.buf.push('>');
+    }
+
+    fn close(&mut self, tag: &str) {
+        self.buf.push_str(&format!("</{}>", tag));
+    }
+
+    fn wrap(&mut self, tag: &str, class: Option<&str>, node: NodeRef, depth: usize) {
+        self.open(tag, class, node);
+        self.convert_children(node, depth);
+        self.close(tag);
+    }
+
+    fn image(&mut self, node: NodeRef) {
+        let name = href(node).map(|href| href.trim_start_matches('#'))
+                             .and_then(|id| self.names.get(id));
+        if let Some(name) = name {
+            let alt = node.attribute("alt").unwrap_or_default();
+            self.buf.push_str(&format!("<img src=\"{}\" alt=\"{}\"/>",
+                                       escape_attribute(name), escape_attribute(alt)));
+        }
+    }
+
+    fn convert_children(&mut self, node: NodeRef, depth: usize) {
+        for child in node.children() {
+            self.convert(child, depth);
+        }
+    }
+
+    fn convert(&mut self, node: NodeRef, depth: usize) {
+        let name = match node.data() {
+            NodeData::Text(TextData { text, .. }) |
+            NodeData::Whitespace(TextData { text, .. }) => {
+                self.buf.push_str(text);
+                return;
+            },
+            NodeData::Element(..) => node.tag_name().unwrap_or_default(),
+            _ => return,
+        };
+
+        match name {
+            "section" => self.wrap("div", Some("section"), node, depth + 1),
+            "title" => {
+                // The titles of the notes would clutter the table of contents.
+                let (tag, class) = if self.in_notes && depth > 0 {
+                    ("p".to_string(), "note-title")
+                } else if self.in_notes {
+                    ("h1".to_string(), "notes-title")
+                } else {
+                    (format!("h{}", depth.clamp(1, 6)), "section-title")
+                };
+                self.open(&tag, Some(class), node);
+                let mut first = true;
+                for child in node.children().filter(|child| child.tag_name() == Some("p")) {
+                    if !first {
+                        self.buf.push_str("<br/>");
+                    }
+                    self.convert_children(child, depth);
+                    first = false;
+                }
+                self.close(&tag);
+            },
+            "p" => self.wrap("p", None, node, depth),
+            "subtitle" => self.wrap("p", Some("subtitle"), node, depth),
+            "epigraph" => self.wrap("blockquote", Some("epigraph"), node, depth),
+            "cite" => self.wrap("blockquote", Some("cite"), node, depth),
+            "annotation" => self.wrap("div", Some("annotation"), node, depth),
+            "poem" => self.wrap("div", Some("poem"), node, depth),
+            "stanza" => self.wrap("div", Some("stanza"), node, depth),
+            "v" => self.wrap("p", Some("verse"), node, depth),
+            "text-author" => self.wrap("p", Some("text-author"), node, depth),
+            "empty-line" => self.buf.push_str("<p><br/></p>"),
+            "emphasis" => self.wrap("em", None, node, depth),
+            "strong" => self.wrap("strong", None, node, depth),
+            "strikethrough" => self.wrap("s", None, node, depth),
+            "sub" | "sup" | "code" => self.wrap(name, None, node, depth),
+            "a" => {
+                let uri = href(node).unwrap_or_default();
+                let class = if node.attribute("type") == Some("note") { " class=\"note\"" } else { "" };
+                self.buf.push_str(&format!("<a href=\"{}\"{}>", escape_attribute(uri), class));
+                self.convert_children(node, depth);
+                self.close("a");
+            },
+            "image" => {
+                let is_block = node.parent_element().and_then(|parent| parent.tag_name())
+                                   .map_or(false, |name| matches!(name, "section" | "body" | "coverpage"));
+                if is_block {
+                    self.buf.push_str("<div class=\"image\">");
+                    self.image(node);
+                    self.buf.push_str("</div>");
+                } else {
+                    self.image(node);
+                }
+            },
+            "table" | "tr" => self.wrap(name, None, node, depth),
+            "td" | "th" => {
+                self.buf.push('<');
+                self.buf.push_str(name);
+                for attr in ["colspan", "rowspan", "align"] {
+                    if let Some(value) = node.attribute(attr) {
+                        self.buf.push_str(&format!(" {}=\"{}\"", attr, escape_attribute(value)));
+                    }
+                }
+                self.buf.push('>');
+                self.convert_children(node, depth);
+                self.close(name);
+            },
+            _ => self.convert_children(node, depth),
+        }
+    }
+}
+
+// Converts an FB2 document to HTML, and extracts its embedded images.
+pub fn fb2_to_html(tree: &XmlTree) -> (String, Binaries) {
+    let root = tree.root().find("FictionBook").unwrap_or_else(|| tree.root());
+    let mut binaries = Binaries::default();
+    let mut names = FxHashMap::default();
+
+    for node in root.children().filter(|child| child.tag_name() == Some("binary")) {
+        let Some(id) = node.attribute("id") else {
+            continue;
+        };
+        let name = if id.contains('.') {
+            id.to_string()
+        } else {
+            let extension = match node.attribute("content-type") {
+                Some("image/png") => "png",
+                Some("image/gif") => "gif",
+                _ => "jpg",
+            };
+            format!("{}.{}", id, extension)
+        };
+        let data = node.text().chars().filter(|c| !c.is_whitespace()).collect::<String>();
+        match STANDARD.decode(data) {
+            Ok(bytes) => {
+                binaries.0.insert(name.clone(), bytes);
+                names.insert(id.to_string(), name);
+            },
+            Err(e) => eprintln!("Can't decode binary {}: {:#}.", id, e),
+        }
+    }
+
+    let metadata = metadata_from_fb2(tree);
+    let mut converter = Converter { names, in_notes: false, buf: String::new() };
+
+    converter.buf.push_str("<html>\n<head>\n");
+    converter.buf.push_str(&format!("<title>{}</title>\n",
+                                    metadata.title.replace('&', "&amp;").replace('<', "&lt;")));
+    if !metadata.authors.is_empty() {
+        converter.buf.push_str(&format!("<meta name=\"author\" content=\"{}\"/>\n",
+                                        escape_attribute(&metadata.authors.join(", "))));
+    }
+    converter.buf.push_str(&format!("<style type=\"text/css\">\n{}\n</style>\n</head>\n<body>\n", STYLESHEET));
+
+    if let Some(cover) = root.find("coverpage") {
+        if let Some(image) = cover.children().find(|child| child.tag_name() == Some("image")) {
+            converter.buf.push_str("<div class=\"cover\">");
+            converter.image(image);
+            converter.buf.push_str("</div>\n");
+        }
+    }
+
+    for body in root.children().filter(|child| child.tag_name() == Some("body")) {
+        converter.in_notes = matches!(body.attribute("name"), Some("notes") | Some("comments"));
+        let class = if converter.in_notes { "notes" } else { "body" };
+        converter.wrap("div", Some(class), body, 0);
+        converter.buf.push('\n');
+    }
+
+    converter.buf.push_str("</body>\n</html>\n");
+
+    (converter.buf, binaries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::document::html::xml::XmlParser;
+
+    #[test]
+    fn test_fb2() {
+        let text = r##"<?xml version="1.0" encoding="utf-8"?>
+<FictionBook xmlns="http://www.gribuser.ru/xml/fictionbook/2.0" xmlns:l="http://www.w3.org/1999/xlink">
+<description>
+<title-info>
+<genre>sf_space</genre>
+<author><first-name>Arkady</first-name><last-name>Strugatsky</last-name></author>
+<author><first-name>Boris</first-name><last-name>Strugatsky</last-name></author>
+<book-title>Roadside Picnic</book-title>
+<date value="1972-01-01">1972</date>
+<lang>ru</lang>
+<sequence name="Noon Universe" number="7"/>
+</title-info>
+</description>
+<body>
+<section><title><p>One</p></title><p>Text<a l:href="#n1" type="note">1</a>.</p></section>
+</body>
+<body name="notes">
+<section id="n1"><title><p>1</p></title><p>A note.</p></section>
+</body>
+</FictionBook>"##;
+        let tree = XmlParser::new(text).parse();
+        let metadata = metadata_from_fb2(&tree);
+        assert_eq!(metadata.title, "Roadside Picnic");
+        assert_eq!(metadata.authors, vec!["Arkady Strugatsky", "Boris Strugatsky"]);
+        assert_eq!(metadata.year, "1972");
+        assert_eq!(metadata.series, "Noon Universe");
+        assert_eq!(metadata.number, "7");
+        let (html, _) = fb2_to_html(&tree);
+        assert!(html.contains("<h1 class=\"section-title\">One</h1>"));
+        assert!(html.contains("<a href=\"#n1\" class=\"note\">1</a>"));
+        assert!(html.contains("<div class=\"section\" id=\"n1\"><p class=\"note-title\">1</p>"));
+    }
+}
@@ -0,0 +1,111 @@
+use unicode_bidi::{BidiInfo, ParagraphBidiInfo, BidiClass, Level, bidi_class, get_base_direction};
+use paragraph_breaker::Item as ParagraphItem;
+use crate::font::{Font, RenderPlan};
+use super::layout::{Direction, ParagraphElement};
+
+const RTL_LANGUAGES: [&str; 12] = ["ar", "arc", "dv", "fa", "he", "iw", "ps", "sd", "syr", "ug", "ur", "yi"];
+// Stands for the images within the text of a paragraph.
+const OBJECT_REPLACEMENT: &str = "\u{FFFC}";
+
+pub fn parse_direction(value: &str) -> Option<Direction> {
+    match value {
+        "ltr" => Some(Direction::Ltr),
+        "rtl" => Some(Direction::Rtl),
+        _ => None,
+    }
+}
+
+pub fn language_direction(language: &str) -> Direction {
+    let primary = language.split(['-', '_']).next().unwrap_or_default().to_lowercase();
+    if RTL_LANGUAGES.contains(&primary.as_str()) {
+        Direction::Rtl
+    } else {
+        Direction::Ltr
+    }
+}
+
+// The direction given by the first strong character.
+pub fn text_direction(text: &str) -> Option<Direction> {
+    match get_base_direction(text) {
+        unicode_bidi::Direction::Ltr => Some(Direction::Ltr),
+        unicode_bidi::Direction::Rtl => Some(Direction::Rtl),
+        unicode_bidi::Direction::Mixed => None,
+    }
+}
+
+// Shapes the given word in its own direction.
+pub fn plan_text(font: &mut Font, text: &str, features: Option<&[String]>) -> RenderPlan {
+    if text_direction(text) == Some(Direction::Rtl) {
+        font.plan_rtl(text, features)
+    } else {
+        font.plan(text, None, features)
+    }
+}
+
+// Resolves the embedding level of each item of a paragraph.
+// Returns `None` when the paragraph doesn't need to be reordered.
+pub fn embedding_levels(items: &[ParagraphItem<ParagraphElement>], direction: Direction) -> Option<Vec<u8>> {
+    let mut text = String::new();
+    let mut starts = Vec::with_capacity(items.len());
+
+    for itm in items {
+        let start = text.len();
+        match itm {
+            ParagraphItem::Box { data: ParagraphElement::Text(element), .. } => text.push_str(&element.text),
+            ParagraphItem::Box { data: ParagraphElement::Image(..) | ParagraphElement::Math(..), .. } => text.push_str(OBJECT_REPLACEMENT),
+            ParagraphItem::Glue { .. } => text.push(' '),
+            _ => (),
+        }
+        starts.push(if text.len() > start { Some(start) } else { None });
+    }
+
+    if direction == Direction::Ltr && !text.chars().any(|c| matches!(bidi_class(c), BidiClass::R | BidiClass::AL)) {
+        return None;
+    }
+
+    let base_level = base_level(direction);
+    let info = ParagraphBidiInfo::new(&text, Some(base_level));
+
+    Some(starts.into_iter()
+               .map(|start| start.map_or(base_level, |s| info.levels[s]).number())
+               .collect())
+}
+
+// Returns the visual order of the items of a line, given their embedding levels.
+pub fn visual_order(items: &[ParagraphItem<ParagraphElement>], levels: &[u8], direction: Direction) -> Vec<usize> {
+    let mut levels = levels.iter()
+                           .map(|&level| Level::new(level).unwrap_or_else(|_| Level::ltr()))
+                           .collect::<Vec<Level>>();
+
+    // The trailing spaces are reset to the paragraph level.
+    for (itm, level) in items.iter().zip(levels.iter_mut()).rev() {
+        if matches!(itm, ParagraphItem::Box { data: ParagraphElement::Text(..) | ParagraphElement::Image(..) | ParagraphElement::Math(..), .. }) {
+            break;
+        }
+        *level = base_level(direction);
+    }
+
+    BidiInfo::reorder_visual(&levels)
+}
+
+fn base_level(direction: Direction) -> Level {
+    if direction == Direction::Rtl {
+        Level::rtl()
+    } else {
+        Level::ltr()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_directions() {
+        assert_eq!(language_direction("he-IL"), Direction::Rtl);
+        assert_eq!(language_direction("en"), Direction::Ltr);
+        assert_eq!(text_direction("שלום"), Some(Direction::Rtl));
+        assert_eq!(text_direction("123 abc"), Some(Direction::Ltr));
+        assert_eq!(text_direction("123"), None);
+    }
+}
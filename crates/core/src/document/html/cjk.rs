@@ -0,0 +1,49 @@
+use xi_unicode::linebreak_property;
+
+// Line breaking classes, as numbered by ICU.
+const LB_CL: u8 = 8;
+const LB_ID: u8 = 14;
+const LB_NS: u8 = 18;
+const LB_OP: u8 = 20;
+const LB_H2: u8 = 31;
+const LB_JV: u8 = 35;
+const LB_CJ: u8 = 37;
+
+// The ideographs, the kana, the hangul syllables and the CJK punctuation.
+// The Latin brackets share some of these classes: they're excluded.
+pub fn is_cjk(c: char) -> bool {
+    c >= '\u{2E80}' && matches!(linebreak_property(c),
+                                LB_CL | LB_ID | LB_NS | LB_OP | LB_CJ | LB_H2..=LB_JV)
+}
+
+// Justified lines are stretched at the break opportunities that are
+// between two CJK characters.
+pub fn is_cjk_break(text: &str, index: usize) -> bool {
+    text[..index].chars().next_back().map_or(false, is_cjk) &&
+    text[index..].chars().next().map_or(false, is_cjk)
+}
+
+// A line break in the source between two CJK characters doesn't
+// stand for a space.
+pub fn is_removable_break(before: Option<char>, whitespace: &str, after: Option<char>) -> bool {
+    whitespace.contains('\n') &&
+    before.map_or(false, is_cjk) && after.map_or(false, is_cjk)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cjk_breaks() {
+        assert!(is_cjk('漢') && is_cjk('」') && is_cjk('ょ'));
+        assert!(!is_cjk('a') && !is_cjk(']'));
+        let text = "東京へ行った。";
+        assert!(is_cjk_break(text, "東".len()));
+        assert!(!is_cjk_break(text, 0));
+        assert!(!is_cjk_break(text, text.len()));
+        assert!(is_removable_break(Some('た'), "\n  ", Some('。')));
+        assert!(!is_removable_break(Some('た'), " ", Some('。')));
+        assert!(!is_removable_break(Some('a'), "\n", Some('。')));
+    }
+}
@@ -26,6 +26,12 @@ pub struct Attribute {
 pub enum PseudoClass {
     FirstChild,
     LastChild,
+    // `:nth-child(an+b)`
+    NthChild(i32, i32),
+    // `:nth-of-type(an+b)`
+    NthOfType(i32, i32),
+    // `:not(selectors)`
+    Not(Vec<Selector>),
 }
 
 #[derive(Debug, Clone)]
@@ -82,9 +88,20 @@ impl Selector {
         for sel in &self.simple_selectors {
             spec[0] = spec[0].saturating_add(sel.id.iter().count());
             spec[1] = spec[1].saturating_add(sel.classes.len());
-            spec[1] = spec[1].saturating_add(sel.pseudo_classes.len());
             spec[1] = spec[1].saturating_add(sel.attributes.len());
             spec[2] = spec[2].saturating_add(sel.tag_name.iter().count());
+            for pc in &sel.pseudo_classes {
+                // The specificity of `:not()` is the one of its most specific argument.
+                if let PseudoClass::Not(selectors) = pc {
+                    if let Some(arg_spec) = selectors.iter().map(Selector::specificity).max() {
+                        for i in 0..3 {
+                            spec[i] = spec[i].saturating_add(arg_spec[i]);
+                        }
+                    }
+                } else {
+                    spec[1] = spec[1].saturating_add(1);
+                }
+            }
         }
         spec
     }
@@ -111,6 +128,128 @@ impl Default for Declaration {
 pub struct Rule {
     pub selector: Selector,
     pub declarations: Vec<Declaration>,
+    // The media queries of the enclosing `@media` rule, if any.
+    pub media: Vec<MediaQuery>,
+}
+
+// A media query such as `not screen and (max-width: 600px)`.
+#[derive(Debug, Clone, Default)]
+pub struct MediaQuery {
+    pub negated: bool,
+    pub media_type: Option<String>,
+    pub features: Vec<MediaFeature>,
+}
+
+#[derive(Debug, Clone)]
+pub struct MediaFeature {
+    pub name: String,
+    pub value: Option<String>,
+}
+
+// The *or* keyword of the level 4 syntax isn't supported: all the features must match.
+fn parse_media_queries(text: &str) -> Vec<MediaQuery> {
+    text.split(',').filter_map(|part| {
+        let part = part.trim().to_ascii_lowercase();
+        if part.is_empty() {
+            return None;
+        }
+
+        let mut query = MediaQuery::default();
+        let mut rest = part.as_str();
+
+        if let Some(r) = rest.strip_prefix("only ") {
+            rest = r.trim_start();
+        } else if let Some(r) = rest.strip_prefix("not ") {
+            query.negated = true;
+            rest = r.trim_start();
+        }
+
+        if !rest.starts_with('(') {
+            let end = rest.find(|c: char| c.is_whitespace() || c == '(').unwrap_or(rest.len());
+            query.media_type = Some(rest[..end].to_string());
+            rest = &rest[end..];
+        }
+
+        for feature in rest.split('(').skip(1) {
+            let feature = feature.split(')').next().unwrap_or_default();
+            let mut parts = feature.splitn(2, ':');
+            let name = parts.next().unwrap_or_default().trim().to_string();
+            let value = parts.next().map(|v| v.trim().to_string());
+            query.features.push(MediaFeature { name, value });
+        }
+
+        Some(query)
+    }).collect()
+}
+
+// Parses the argument of `:nth-child()`: `odd`, `even`, `b`, `an`, `an+b`...
+fn parse_nth(text: &str) -> Option<(i32, i32)> {
+    let text = text.split(" of ").next()?
+                   .chars().filter(|c| !c.is_whitespace())
+                   .collect::<String>().to_ascii_lowercase();
+    match text.as_str() {
+        "odd" => return Some((2, 1)),
+        "even" => return Some((2, 0)),
+        _ => (),
+    }
+    if let Some(index) = text.find('n') {
+        let a = match &text[..index] {
+            "" | "+" => 1,
+            "-" => -1,
+            a => a.parse().ok()?,
+        };
+        let b = match &text[index+1..] {
+            "" => 0,
+            b => b.strip_prefix('+').unwrap_or(b).parse().ok()?,
+        };
+        Some((a, b))
+    } else {
+        text.parse().ok().map(|b| (0, b))
+    }
+}
+
+// An `@font-face` rule. The sources are the URLs of the `src` descriptor, in order.
+#[derive(Debug, Clone, Default)]
+pub struct FontFace {
+    pub family: String,
+    pub style: Option<String>,
+    pub weight: Option<String>,
+    pub sources: Vec<String>,
+}
+
+impl FontFace {
+    fn from_declarations(declarations: &[Declaration]) -> Option<FontFace> {
+        let mut face = FontFace::default();
+
+        for declaration in declarations {
+            let value = declaration.value.as_str();
+            match declaration.name.as_str() {
+                "font-family" => face.family = unquote(value).to_string(),
+                "font-style" => face.style = Some(value.to_string()),
+                "font-weight" => face.weight = Some(value.to_string()),
+                "src" => {
+                    face.sources = value.split("url(").skip(1)
+                                        .filter_map(|s| s.find(')').map(|i| unquote(&s[..i]).to_string()))
+                                        .filter(|s| !s.is_empty() && !s.starts_with("data:"))
+                                        .collect();
+                },
+                _ => (),
+            }
+        }
+
+        if face.family.is_empty() || face.sources.is_empty() {
+            None
+        } else {
+            Some(face)
+        }
+    }
+}
+
+fn unquote(value: &str) -> &str {
+    let value = value.trim();
+    value.strip_prefix('"').and_then(|v| v.strip_suffix('"'))
+         .or_else(|| value.strip_prefix('\'').and_then(|v| v.strip_suffix('\'')))
+         .unwrap_or(value)
 }
 
 #[derive(Debug)]
@@ -200,6 +339,15 @@ impl<'a> CssParser<'a> {
         }
     }
 
+    fn parse_font_face(&mut self, font_faces: &mut Vec<FontFace>) {
+        self.advance_while(|&c| c != '{');
+        self.advance(1);
+        let declarations = self.parse_declarations();
+        if let Some(face) = FontFace::from_declarations(&declarations) {
+            font_faces.push(face);
+        }
+    }
+
     fn skip_at_rule(&mut self) {
         self.advance_while(|&c| c != ';' && c != '{');
 
@@ -210,6 +358,30 @@ impl<'a> CssParser<'a> {
         }
     }
 
+    // Returns the text between the parentheses following a functional pseudo-class.
+    fn argument(&mut self) -> Option<&'a str> {
+        if self.next() != Some('(') {
+            return None;
+        }
+        self.advance(1);
+        let offset = self.offset;
+        let mut balance = 1u8;
+        while let Some(c) = self.next() {
+            match c {
+                '(' => balance = balance.saturating_add(1),
+                ')' => balance -= 1,
+                _ => (),
+            }
+            if balance == 0 {
+                break;
+            }
+            self.advance(1);
+        }
+        let argument = &self.input[offset..self.offset];
+        self.advance(1);
+        Some(argument)
+    }
+
     fn attribute_value(&mut self) -> String {
         match self.next() {
             Some(delim @ '"' | delim @ '\'') => {
@@ -321,21 +493,35 @@ impl<'a> CssParser<'a> {
                     }
                     let offset = self.offset;
                     self.skip_ident();
-                    match &self.input[offset..self.offset] {
-                        "first-child" => {
+                    let name = &self.input[offset..self.offset];
+                    let argument = self.argument();
+                    match (name, argument) {
+                        ("first-child", None) => {
                             selec.pseudo_classes.push(PseudoClass::FirstChild);
                         },
-                        "last-child" => {
+                        ("last-child", None) => {
                             selec.pseudo_classes.push(PseudoClass::LastChild);
                         },
+                        ("nth-child", Some(arg)) if parse_nth(arg).is_some() => {
+                            let (a, b) = parse_nth(arg).unwrap();
+                            selec.pseudo_classes.push(PseudoClass::NthChild(a, b));
+                        },
+                        ("nth-of-type", Some(arg)) if parse_nth(arg).is_some() => {
+                            let (a, b) = parse_nth(arg).unwrap();
+                            selec.pseudo_classes.push(PseudoClass::NthOfType(a, b));
+                        },
+                        ("not", Some(arg)) => {
+                            let selectors = CssParser::new(&format!("{}{{", arg)).parse_selectors();
+                            if selectors.is_empty() {
+                                supported = false;
+                            } else {
+                                selec.pseudo_classes.push(PseudoClass::Not(selectors));
+                            }
+                        },
                         _ => {
                             supported = false;
                         },
                     }
-                    if self.next() == Some('(') {
-                        self.advance_while(|&c| c != ')');
-                        self.advance(1);
-                    }
                 },
                 Some('*') => {
                     self.advance(1);
@@ -428,7 +614,8 @@ impl<'a> CssParser<'a> {
                                 self.advance(1);
                             },
                             Some('!') => {
-                                d.important = true;
+                                let rest = self.input[self.offset+1..].trim_start();
+                                d.important = rest.get(..9).map_or(false, |w| w.eq_ignore_ascii_case("important"));
                                 break;
                             },
                             _ => break,
@@ -436,7 +623,7 @@ impl<'a> CssParser<'a> {
                     }
 
                     d.value = self.input[offset..self.offset].trim().to_string();
-                    if d.important {
+                    if self.next() == Some('!') {
                         self.advance_while(|&c| c != ';' &&
                                                 c != '}');
                     }
@@ -476,24 +663,62 @@ impl<'a> CssParser<'a> {
             rules.push(Rule {
                 selector,
                 declarations: declarations.clone(),
+                media: Vec::new(),
             });
         }
     }
 
+    fn parse_media(&mut self, rules: &mut Vec<Rule>, font_faces: &mut Vec<FontFace>) {
+        self.advance("@media".len());
+        let offset = self.offset;
+        self.advance_while(|&c| c != ';' && c != '{');
+        let media = parse_media_queries(&self.input[offset..self.offset]);
+
+        if self.next() != Some('{') {
+            self.advance(1);
+            return;
+        }
+
+        self.advance(1);
+        let start = rules.len();
+
+        while !self.eof() {
+            self.skip_spaces_and_comments();
+
+            match self.next() {
+                None => break,
+                Some('}') => {
+                    self.advance(1);
+                    break;
+                },
+                Some('@') if self.starts_with("@font-face") => self.parse_font_face(font_faces),
+                Some('@') => self.skip_at_rule(),
+                _ => self.parse_rules(rules),
+            }
+        }
+
+        for rule in &mut rules[start..] {
+            rule.media = media.clone();
+        }
+    }
+
     pub fn parse(&mut self) -> StyleSheet {
         let mut rules = Vec::new();
+        let mut font_faces = Vec::new();
 
         while !self.eof() {
             self.skip_spaces_and_comments();
 
             match self.next() {
                 None => break,
+                Some('@') if self.starts_with("@font-face") => self.parse_font_face(&mut font_faces),
+                Some('@') if self.starts_with("@media") => self.parse_media(&mut rules, &mut font_faces),
                 Some('@') => self.skip_at_rule(),
                 _ => self.parse_rules(&mut rules),
             }
         }
 
-        StyleSheet { rules }
+        StyleSheet { rules, font_faces }
     }
 }
 
@@ -508,6 +733,53 @@ mod tests {
         println!("{:?}", css);
     }
 
+    #[test]
+    fn font_face_css() {
+        let text = "@font-face { font-family: 'Gentium'; font-style: italic;\
+                    src: local(Gentium), url(\"../fonts/GentiumItalic.woff\") format('woff'), url(g.ttf) }\
+                    p { font-family: Gentium, serif }";
+        let css = CssParser::new(text).parse();
+        assert_eq!(css.rules.len(), 1);
+        assert_eq!(css.font_faces.len(), 1);
+        let face = &css.font_faces[0];
+        assert_eq!(face.family, "Gentium");
+        assert_eq!(face.style.as_deref(), Some("italic"));
+        assert_eq!(face.sources, vec!["../fonts/GentiumItalic.woff".to_string(), "g.ttf".to_string()]);
+    }
+
+    #[test]
+    fn media_css() {
+        let text = "@media amzn-kf8 { p { a: b } } @media not print and (max-width: 600px), (orientation: portrait) { .c { d: e } }\
+                    @import url(x.css); h1 { f: g }";
+        let css = CssParser::new(text).parse();
+        assert_eq!(css.rules.len(), 3);
+        assert_eq!(css.rules[0].media[0].media_type.as_deref(), Some("amzn-kf8"));
+        let media = &css.rules[1].media;
+        assert_eq!(media.len(), 2);
+        assert!(media[0].negated);
+        assert_eq!(media[0].features[0].name, "max-width");
+        assert_eq!(media[0].features[0].value.as_deref(), Some("600px"));
+        assert_eq!(media[1].media_type, None);
+        assert!(css.rules[2].media.is_empty());
+    }
+
+    #[test]
+    fn pseudo_classes_css() {
+        assert_eq!(parse_nth("odd"), Some((2, 1)));
+        assert_eq!(parse_nth("-n + 3"), Some((-1, 3)));
+        assert_eq!(parse_nth("4"), Some((0, 4)));
+        assert_eq!(parse_nth("3n-2"), Some((3, -2)));
+        assert_eq!(parse_nth("x"), None);
+        let text = "p:not(.a, :nth-child(2)) { a: b !important; c: d ! IMPORTANT } li:nth-of-type(2n+1) { e: f !ie }";
+        let css = CssParser::new(text).parse();
+        assert_eq!(css.rules.len(), 2);
+        assert_eq!(css.rules[0].selector.specificity(), [0, 1, 1]);
+        assert!(css.rules[0].declarations.iter().all(|d| d.important));
+        assert_eq!(css.rules[0].declarations[1].value, "d");
+        assert!(!css.rules[1].declarations[0].important);
+        assert_eq!(css.rules[1].declarations[0].value, "f");
+    }
+
     #[test]
     fn combinators_css() {
         let text = "a#i.j.k > b { b: c } a + .l { u: v } a { x: y }";
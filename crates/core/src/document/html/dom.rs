@@ -384,6 +384,43 @@ impl<'a> NodeRef<'a> {
         self.descendants()
             .find(|n| n.id() == Some(id))
     }
+
+    // Serializes the subtree rooted at this node.
+    // The text and the attribute values are kept as they were parsed.
+    pub fn to_html(&self) -> String {
+        let mut html = String::new();
+        self.write_html(&mut html);
+        html
+    }
+
+    fn write_html(&self, html: &mut String) {
+        match self.node.data {
+            NodeData::Text(TextData { ref text, .. }) |
+            NodeData::Whitespace(TextData { ref text, .. }) => html.push_str(text),
+            NodeData::Element(ElementData { ref name, ref qualified_name, ref attributes, .. }) => {
+                let name = qualified_name.as_ref().unwrap_or(name);
+                html.push('<');
+                html.push_str(name);
+                for (key, value) in attributes {
+                    html.push_str(&format!(" {}=\"{}\"", key, value.replace('"', "&quot;")));
+                }
+                if self.has_children() {
+                    html.push('>');
+                    for child in self.children() {
+                        child.write_html(html);
+                    }
+                    html.push_str(&format!("</{}>", name));
+                } else {
+                    html.push_str("/>");
+                }
+            },
+            NodeData::Root | NodeData::Wrapper(..) => {
+                for child in self.children() {
+                    child.write_html(html);
+                }
+            },
+        }
+    }
 }
 
 impl<'a> NodeMut<'a> {
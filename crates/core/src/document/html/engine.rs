@@ -1,18 +1,23 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::convert::TryFrom;
+use std::mem;
 use anyhow::Error;
 use kl_hyphenate::{Standard, Hyphenator, Iter};
 use paragraph_breaker::{Item as ParagraphItem, Breakpoint, INFINITE_PENALTY};
 use paragraph_breaker::{total_fit, standard_fit};
 use xi_unicode::LineBreakIterator;
 use percent_encoding::percent_decode_str;
+use base64::Engine as _;
+use base64::engine::general_purpose::STANDARD;
 use septem::Roman;
 use crate::helpers::{Normalize, decode_entities};
 use crate::framebuffer::{Framebuffer, Pixmap};
-use crate::font::{FontOpener, FontFamily};
+use crate::color::{Color, GRAY08};
+use crate::font::{FontOpener, FontFamily, RenderPlan};
 use crate::document::{Document, Location};
 use crate::document::pdf::PdfOpener;
 use crate::unit::{mm_to_px, pt_to_px};
+use crate::device::CURRENT_DEVICE;
 use crate::geom::{Point, Vec2, Rectangle, Edge};
 use crate::settings::{HYPHEN_PENALTY, STRETCH_TOLERANCE};
 use crate::settings::{DEFAULT_FONT_SIZE, DEFAULT_MARGIN_WIDTH, DEFAULT_TEXT_ALIGN, DEFAULT_LINE_HEIGHT};
@@ -21,16 +26,24 @@ use super::parse::{parse_width, parse_height, parse_inline_material, parse_font_
 use super::parse::{parse_font_weight, parse_font_size, parse_font_features, parse_font_variant};
 use super::parse::{parse_letter_spacing, parse_word_spacing};
 use super::parse::{parse_line_height, parse_vertical_align, parse_color, parse_list_style_type};
-use super::dom::{NodeRef, NodeData, ElementData, TextData, WRAPPER_TAG_NAME};
-use super::layout::{StyleData, InlineMaterial, TextMaterial, ImageMaterial};
+use super::parse::{parse_border_side, parse_border_width, parse_text_decoration, parse_text_transform, parse_writing_mode};
+use super::dom::{NodeRef, NodeData, ElementData, TextData, Attributes, WRAPPER_TAG_NAME};
+use super::layout::{StyleData, InlineMaterial, TextMaterial, ImageMaterial, MathMaterial, MathElement};
 use super::layout::{GlueMaterial, PenaltyMaterial, ChildArtifact, SiblingStyle, LoopContext};
 use super::layout::{RootData, DrawState, DrawCommand, TextCommand, ImageCommand, FontKind, Fonts};
-use super::layout::{TextAlign, ParagraphElement, TextElement, ImageElement, Display, Float};
-use super::layout::{WordSpacing, ListStyleType, LineStats};
+use super::layout::SpanningCell;
+use super::layout::{TextAlign, ParagraphElement, TextElement, RubyElement, ImageElement, Display, Float};
+use super::layout::{WordSpacing, ListStyleType, LineStats, BoxCommand, RuleCommand, TableCommand};
+use super::layout::{Border, BorderSide, BorderStyle, TextDecoration, TextTransform};
+use super::layout::{FontStyle, FontWeight, EmbeddedFamily, EmbeddedFace, Direction, WritingMode};
 use super::layout::{hyph_lang, collapse_margins, DEFAULT_HYPH_LANG, HYPHENATION_PATTERNS};
 use super::layout::{EM_SPACE_RATIOS, WORD_SPACE_RATIOS, FONT_SPACES};
-use super::style::{StyleSheet, specified_values};
+use super::style::{StyleSheet, MediaDevice, specified_values};
+use super::bidi::{parse_direction, text_direction, plan_text, embedding_levels, visual_order};
+use super::cjk::{is_cjk_break, is_removable_break};
+use super::math::{MathFonts, MathItem, parse_math, layout_math};
 use super::xml::XmlExt;
+use super::svg::{SVG_MIME, parse_view_box, parse_svg_length, svg_document, data_uri, embed_images};
 
 const DEFAULT_DPI: u16 = 300;
 const DEFAULT_WIDTH: u32 = 1404;
@@ -42,6 +55,37 @@ pub trait ResourceFetcher {
     fn fetch(&mut self, name: &str) -> Result<Vec<u8>, Error>;
 }
 
+// Returns the bytes of an image, resource or `data:` URI, along with
+// the magic that lets MuPDF recognize their format.
+pub fn load_image(path: &str, resource_fetcher: &mut dyn ResourceFetcher) -> Option<(Vec<u8>, String)> {
+    if let Some(uri) = path.strip_prefix("data:") {
+        let (header, data) = uri.split_once(',')?;
+        let mime = header.split(';').next()
+                         .filter(|mime| !mime.is_empty())
+                         .unwrap_or("text/plain");
+        let buf = if header.ends_with(";base64") {
+            let data = data.chars().filter(|c| !c.is_whitespace()).collect::<String>();
+            STANDARD.decode(percent_decode_str(&data).decode_utf8_lossy().as_bytes()).ok()?
+        } else {
+            percent_decode_str(data).collect::<Vec<u8>>()
+        };
+        let buf = if mime == SVG_MIME {
+            embed_images(&buf, Path::new(""), resource_fetcher)
+        } else {
+            buf
+        };
+        Some((buf, mime.to_string()))
+    } else {
+        let buf = resource_fetcher.fetch(path).ok()?;
+        let buf = if path.ends_with(".svg") {
+            embed_images(&buf, Path::new(path).parent().unwrap_or_else(|| Path::new("")), resource_fetcher)
+        } else {
+            buf
+        };
+        Some((buf, path.to_string()))
+    }
+}
+
 // TODO: Add min_font_size.
 pub struct Engine {
     // The fonts used for each CSS font family.
@@ -62,6 +106,8 @@ pub struct Engine {
     pub dims: (u32, u32),
     // Device DPI.
     pub dpi: u16,
+    // Direction of the lines and of the blocks.
+    pub writing_mode: WritingMode,
 }
 
 impl Engine {
@@ -79,6 +125,7 @@ impl Engine {
             line_height,
             dims: (DEFAULT_WIDTH, DEFAULT_HEIGHT),
             dpi: DEFAULT_DPI,
+            writing_mode: WritingMode::HorizontalTb,
         }
     }
 
@@ -86,6 +133,14 @@ impl Engine {
     pub fn load_fonts(&mut self) {
         if self.fonts.is_none() {
             self.fonts = default_fonts().ok();
+            self.set_writing_mode(self.writing_mode);
+        }
+    }
+
+    pub fn set_writing_mode(&mut self, writing_mode: WritingMode) {
+        self.writing_mode = writing_mode;
+        if let Some(fonts) = self.fonts.as_mut() {
+            fonts.vertical = writing_mode == WritingMode::VerticalRl;
         }
     }
 
@@ -125,6 +180,76 @@ impl Engine {
         }
     }
 
+    // Loads the fonts declared by the `@font-face` rules of the given style sheet.
+    pub fn load_font_faces(&mut self, stylesheet: &StyleSheet, resource_fetcher: &mut dyn ResourceFetcher) {
+        if stylesheet.font_faces.is_empty() {
+            return;
+        }
+
+        self.load_fonts();
+
+        let opener = match FontOpener::new() {
+            Ok(opener) => opener,
+            Err(e) => {
+                eprintln!("Can't create font opener: {:#}.", e);
+                return;
+            },
+        };
+
+        if let Some(fonts) = self.fonts.as_mut() {
+            for face in &stylesheet.font_faces {
+                let style = face.style.as_deref().and_then(parse_font_style).unwrap_or(FontStyle::Normal);
+                let weight = face.weight.as_deref().and_then(parse_font_weight).unwrap_or(FontWeight::Normal);
+                let family_index = fonts.embedded.iter()
+                                        .position(|family| family.name.eq_ignore_ascii_case(&face.family));
+
+                if family_index.map_or(false, |index| fonts.embedded[index].faces.iter()
+                                                       .any(|f| f.style == style && f.weight == weight)) {
+                    continue;
+                }
+
+                let loaded = face.sources.iter().find_map(|path| {
+                    let data = resource_fetcher.fetch(path).ok()?;
+                    match opener.open_memory(&data) {
+                        Ok(font) => Some((font, data)),
+                        Err(e) => {
+                            eprintln!("Can't open font {}: {:#}.", path, e);
+                            None
+                        },
+                    }
+                });
+
+                if let Some((font, data)) = loaded {
+                    let family_index = family_index.unwrap_or_else(|| {
+                        fonts.embedded.push(EmbeddedFamily {
+                            name: face.family.clone(),
+                            faces: Vec::new(),
+                        });
+                        fonts.embedded.len() - 1
+                    });
+                    fonts.embedded[family_index].faces.push(EmbeddedFace {
+                        style,
+                        weight,
+                        font,
+                        data,
+                    });
+                }
+            }
+        }
+    }
+
+    // Embedded font families take precedence over the generic ones.
+    fn font_kind(&self, value: &str) -> Option<FontKind> {
+        self.fonts.as_ref().and_then(|fonts| {
+            value.split(',').find_map(|name| {
+                let name = name.trim().trim_matches(|c| c == '"' || c == '\'');
+                fonts.embedded.iter()
+                     .position(|family| family.name.eq_ignore_ascii_case(name))
+                     .map(FontKind::Embedded)
+            })
+        }).or_else(|| parse_font_kind(value))
+    }
+
     pub fn set_margin_width(&mut self, width: i32) {
         self.margin = Edge::uniform(mm_to_px(width as f32, self.dpi).round() as i32);
     }
@@ -133,14 +258,53 @@ impl Engine {
         self.line_height = line_height;
     }
 
+    // The vertical pages are laid out sideways, see `rotate_display_list`.
     #[inline]
     pub fn rect(&self) -> Rectangle {
         let (width, height) = self.dims;
-        rect![0, 0, width as i32, height as i32]
+        if self.writing_mode == WritingMode::VerticalRl {
+            rect![0, 0, height as i32, width as i32]
+        } else {
+            rect![0, 0, width as i32, height as i32]
+        }
+    }
+
+    // The dimensions of an inline SVG element, in pixels, derived from its
+    // *width*, *height* and *viewBox* attributes. Percentages are relative
+    // to the width of the container and to the height of the page.
+    fn svg_dims(&self, attributes: &Attributes, style: &StyleData, parent_style: &StyleData) -> Option<(i32, i32)> {
+        let page_height = self.rect().height() as i32 - self.margin.top - self.margin.bottom;
+        let view_box = attributes.get("viewBox").and_then(|value| parse_view_box(value));
+        let length = |name: &str, basis: i32| attributes.get(name).and_then(|value| {
+            parse_svg_length(value, style.font_size, self.font_size, basis, self.dpi)
+        });
+
+        match (length("width", parent_style.width), length("height", page_height), view_box) {
+            (Some(width), Some(height), _) => Some((width, height)),
+            (Some(width), None, Some((vw, vh))) => Some((width, (width as f32 * vh / vw).round() as i32)),
+            (None, Some(height), Some((vw, vh))) => Some(((height as f32 * vw / vh).round() as i32, height)),
+            (None, None, Some((vw, vh))) => {
+                let width = parse_svg_length(&vw.to_string(), style.font_size, self.font_size, 0, self.dpi)?;
+                let height = parse_svg_length(&vh.to_string(), style.font_size, self.font_size, 0, self.dpi)?;
+                Some((width, height))
+            },
+            _ => None,
+        }.filter(|&(width, height)| width > 0 && height > 0)
+    }
+
+    pub fn media_device(&self) -> MediaDevice {
+        let (width, height) = self.dims;
+        MediaDevice {
+            width,
+            height,
+            dpi: self.dpi,
+            font_size: self.font_size,
+            color: CURRENT_DEVICE.color_samples() > 1,
+        }
     }
 
     pub fn build_display_list(&mut self, node: NodeRef, parent_style: &StyleData, loop_context: &LoopContext, stylesheet: &StyleSheet, root_data: &RootData, resource_fetcher: &mut dyn ResourceFetcher, draw_state: &mut DrawState, display_list: &mut Vec<Page>) -> ChildArtifact {
-        // TODO: border, background, text-transform, tab-size, text-decoration.
+        // TODO: tab-size.
         let mut style = StyleData::default();
         let mut rects: Vec<Option<Rectangle>> = vec![None];
 
@@ -163,6 +327,8 @@ impl Engine {
         style.line_height = parent_style.line_height;
         style.retain_whitespace = parent_style.retain_whitespace;
 
+        let mut is_wide_table = false;
+
         match node.tag_name() {
             Some("pre") => style.retain_whitespace = true,
             Some("li") | Some(WRAPPER_TAG_NAME) => style.list_style_type = parent_style.list_style_type,
@@ -175,6 +341,10 @@ impl Engine {
                                           parent_style.text_align == TextAlign::Center;
                 self.compute_column_widths(node, parent_style, loop_context, stylesheet, root_data, resource_fetcher, draw_state);
                 draw_state.position = position;
+                draw_state.row_spans = vec![0; draw_state.min_column_widths.len()];
+                draw_state.spanning_cells.clear();
+                // The table can't fit, even when its columns are as narrow as possible.
+                is_wide_table = draw_state.min_column_widths.iter().sum::<i32>() > parent_style.end_x - parent_style.start_x;
             },
             _ => (),
         }
@@ -203,7 +373,7 @@ impl Engine {
                                     .unwrap_or(parent_style.vertical_align);
 
         style.font_kind = props.get("font-family")
-                               .and_then(|value| parse_font_kind(value))
+                               .and_then(|value| self.font_kind(value))
                                .unwrap_or(parent_style.font_kind);
 
         style.font_style = props.get("font-style")
@@ -218,15 +388,33 @@ impl Engine {
                            .and_then(|value| parse_color(value))
                            .unwrap_or(parent_style.color);
 
+        style.text_decoration = props.get("text-decoration-line")
+                                     .or_else(|| props.get("text-decoration"))
+                                     .and_then(|value| parse_text_decoration(value))
+                                     .unwrap_or(parent_style.text_decoration);
+
+        style.text_transform = props.get("text-transform")
+                                    .and_then(|value| parse_text_transform(value))
+                                    .unwrap_or(parent_style.text_transform);
+
+        style.background_color = props.get("background-color")
+                                      .and_then(|value| parse_color(value));
+
         style.text_indent = props.get("text-indent")
                                  .and_then(|value| parse_text_indent(value, style.font_size, self.font_size,
                                                                  parent_style.width, self.dpi))
                                  .unwrap_or(parent_style.text_indent);
 
+        style.direction = props.get("direction")
+                               .map(String::as_str)
+                               .or_else(|| node.attribute("dir"))
+                               .and_then(|value| if value == "auto" { text_direction(&node.text()) } else { parse_direction(value) })
+                               .unwrap_or(parent_style.direction);
+
         style.text_align = props.get("text-align")
                                 .map(String::as_str)
                                 .or_else(|| node.attribute("align"))
-                                .and_then(|value| parse_text_align(value))
+                                .and_then(|value| parse_text_align(value, style.direction))
                                 .unwrap_or(parent_style.text_align);
 
         style.font_features = props.get("font-feature-settings")
@@ -265,8 +453,30 @@ impl Engine {
                                        props.get("padding-bottom").map(String::as_str),
                                        props.get("padding-left").map(String::as_str),
                                        style.font_size, self.font_size, parent_style.width, self.dpi);
+
+            let border_side = |side: &str| {
+                let get = |kind: &str| props.get(&format!("border-{}-{}", side, kind)).map(String::as_str);
+                parse_border_side(get("width"), get("style"), get("color"), style.color,
+                                  style.font_size, self.font_size, self.dpi)
+            };
+
+            style.border = Border {
+                top: border_side("top"),
+                right: border_side("right"),
+                bottom: border_side("bottom"),
+                left: border_side("left"),
+            };
+
+            if !style.border.is_visible() {
+                if let Some(width) = border_attribute(node, style.font_size, self.font_size, self.dpi) {
+                    let side = BorderSide { width, style: BorderStyle::Solid, color: style.color };
+                    style.border = Border { top: side, right: side, bottom: side, left: side };
+                }
+            }
         }
 
+        let border_widths = style.border.widths();
+
         style.width = props.get("width")
                            .and_then(|value| parse_width(value, style.font_size, self.font_size,
                                                          parent_style.width, self.dpi))
@@ -277,28 +487,31 @@ impl Engine {
                                                            parent_style.width, self.dpi))
                             .unwrap_or(0);
 
-        style.start_x = parent_style.start_x + style.margin.left + style.padding.left;
-        style.end_x = parent_style.end_x - style.margin.right - style.padding.right;
+        style.start_x = parent_style.start_x + style.margin.left + border_widths.left + style.padding.left;
+        style.end_x = parent_style.end_x - style.margin.right - border_widths.right - style.padding.right;
 
         let mut width = style.end_x - style.start_x;
 
         if width < 0 {
             if style.width > 0 {
-                let total_space = style.margin.left + style.padding.left + style.margin.right + style.padding.right;
+                let total_space = style.margin.left + style.padding.left + style.margin.right + style.padding.right +
+                                  border_widths.left + border_widths.right;
                 let remaining_space = parent_style.width - style.width;
                 let ratio = remaining_space as f32 / total_space as f32;
                 style.margin.left = (style.margin.left as f32 * ratio).round() as i32;
                 style.padding.left = (style.padding.left as f32 * ratio).round() as i32;
                 style.margin.right = (style.margin.right as f32 * ratio).round() as i32;
                 style.padding.right = (style.padding.right as f32 * ratio).round() as i32;
-                style.start_x = parent_style.start_x + style.margin.left + style.padding.left;
-                style.end_x = parent_style.end_x - style.margin.right - style.padding.right;
+                style.start_x = parent_style.start_x + style.margin.left + border_widths.left + style.padding.left;
+                style.end_x = parent_style.end_x - style.margin.right - border_widths.right - style.padding.right;
                 width = style.width;
             } else {
                 style.margin.left = 0;
                 style.padding.left = 0;
                 style.margin.right = 0;
                 style.padding.right = 0;
+                style.border.left = Default::default();
+                style.border.right = Default::default();
                 style.start_x = parent_style.start_x;
                 style.end_x = parent_style.end_x;
                 width = parent_style.width;
@@ -312,7 +525,12 @@ impl Engine {
             draw_state.position.y = root_data.rect.min.y;
         }
 
-        draw_state.position.y += style.padding.top;
+        let start_page = display_list.len() - 1;
+        let start_y = draw_state.position.y;
+        // Where the box of the block goes, so that it's drawn before the boxes of its descendants.
+        let start_index = display_list[start_page].len();
+
+        draw_state.position.y += border_widths.top + style.padding.top;
 
         let has_blocks = node.children().any(|n| n.is_block());
 
@@ -364,33 +582,66 @@ impl Engine {
 
                     let start_x = style.start_x;
                     let end_x = style.end_x;
-                    let mut cur_x = start_x;
+                    let columns_count = draw_state.column_widths.len();
+                    let mut columns_x = vec![start_x; columns_count + 1];
+                    for (i, w) in draw_state.column_widths.iter().enumerate() {
+                        columns_x[i+1] = columns_x[i] + w;
+                    }
+                    draw_state.row_spans.resize(columns_count, 0);
+                    let mut row_spans = mem::take(&mut draw_state.row_spans);
+                    let mut spanning_cells = mem::take(&mut draw_state.spanning_cells);
+                    let mut cells = Vec::new();
                     let position = draw_state.position;
-                    let mut final_page = (0, position);
                     let page_index = display_list.len() - 1;
+                    let mut final_page = (page_index, position);
                     let mut index = 0;
 
-                    // TODO: rowspan, vertical-align
+                    // TODO: vertical-align
                     for child in node.children().filter(|child| child.is_element()) {
-                        if index >= draw_state.column_widths.len() {
+                        // Skip the columns covered by the cells of the previous rows.
+                        while index < columns_count && row_spans[index] > 0 {
+                            index += 1;
+                        }
+
+                        if index >= columns_count {
                             break;
                         }
 
                         let colspan = child.attribute("colspan")
                                            .and_then(|v| v.parse().ok())
                                            .unwrap_or(1)
-                                           .min(draw_state.column_widths.len()-index);
-                        let column_width = draw_state.column_widths[index..index+colspan]
-                                                     .iter().sum::<i32>();
+                                           .clamp(1, columns_count-index);
+                        let rowspan = child.attribute("rowspan")
+                                           .and_then(|v| v.parse::<usize>().ok())
+                                           .unwrap_or(1)
+                                           .max(1);
+                        let cur_x = columns_x[index];
+                        let next_x = columns_x[index+colspan];
                         let mut child_display_list = vec![Vec::new()];
                         style.start_x = cur_x;
-                        style.end_x = cur_x + column_width;
+                        style.end_x = next_x;
                         draw_state.position = position;
                         let artifact = self.build_display_list(child, &style, &inner_loop_context, stylesheet, root_data, resource_fetcher, draw_state, &mut child_display_list);
-                        let pages_count = child_display_list.len();
-                        if pages_count > final_page.0 ||
-                           (pages_count == final_page.0 && draw_state.position.y > final_page.1.y) {
-                            final_page = (pages_count, draw_state.position);
+                        let end_page = page_index + child_display_list.len() - 1;
+                        let end_y = draw_state.position.y;
+
+                        // The height of a cell spanning several rows is accounted for in its last row.
+                        if rowspan > 1 {
+                            for span in &mut row_spans[index..index+colspan] {
+                                *span = rowspan;
+                            }
+                            spanning_cells.push(SpanningCell {
+                                rows: rowspan,
+                                page: end_page,
+                                end_y,
+                                min_x: cur_x,
+                                max_x: next_x,
+                            });
+                        } else {
+                            cells.push((end_page, end_y, cur_x, next_x));
+                            if (end_page, end_y) > (final_page.0, final_page.1.y) {
+                                final_page = (end_page, draw_state.position);
+                            }
                         }
 
                         for (i, mut pg) in child_display_list.into_iter().enumerate() {
@@ -422,9 +673,38 @@ impl Engine {
                         }
 
                         index += colspan;
-                        cur_x += column_width;
                     }
 
+                    for cell in &spanning_cells {
+                        if (cell.rows == 1 || cell.page > final_page.0) &&
+                           (cell.page, cell.end_y) > (final_page.0, final_page.1.y) {
+                            final_page = (cell.page, pt!(final_page.1.x, cell.end_y));
+                        }
+                    }
+
+                    // Extend the boxes of the cells down to the bottom of the row.
+                    let bottom = final_page.1.y;
+                    for (page, end_y, min_x, max_x) in cells {
+                        if page == final_page.0 {
+                            stretch_cell_boxes(&mut display_list[page], min_x, max_x, end_y, bottom);
+                        }
+                    }
+
+                    for cell in &mut spanning_cells {
+                        if cell.rows == 1 && cell.page == final_page.0 {
+                            stretch_cell_boxes(&mut display_list[cell.page], cell.min_x, cell.max_x, cell.end_y, bottom);
+                        }
+                        cell.rows -= 1;
+                    }
+
+                    spanning_cells.retain(|cell| cell.rows > 0);
+
+                    for span in &mut row_spans {
+                        *span = span.saturating_sub(1);
+                    }
+
+                    draw_state.row_spans = row_spans;
+                    draw_state.spanning_cells = spanning_cells;
                     style.start_x = start_x;
                     style.end_x = end_x;
                     draw_state.position = final_page.1;
@@ -519,7 +799,34 @@ impl Engine {
             style.margin.top = 0;
         }
 
-        draw_state.position.y += style.padding.bottom;
+        if node.tag_name() == Some("table") {
+            // Cells spanning more rows than what remains.
+            let last_page = display_list.len() - 1;
+            for cell in draw_state.spanning_cells.drain(..) {
+                if cell.page == last_page {
+                    draw_state.position.y = draw_state.position.y.max(cell.end_y);
+                }
+            }
+
+            // Only the top right corner of the table opens the viewer: the taps on
+            // the rest of the table still turn the pages.
+            if is_wide_table {
+                let offset = root_data.start_offset + node.offset();
+                let side = pt_to_px(style.font_size, self.dpi).round() as i32;
+                for (i, rect) in rects.iter().enumerate() {
+                    if let Some(rect) = rect {
+                        let corner = rect![rect.max.x - side, rect.min.y, rect.max.x, rect.min.y + side];
+                        display_list[start_page + i].push(DrawCommand::Table(TableCommand { offset, rect: corner }));
+                    }
+                }
+            }
+        }
+
+        draw_state.position.y += style.padding.bottom + border_widths.bottom;
+
+        if style.background_color.is_some() || style.border.is_visible() {
+            self.insert_box_commands(node, &style, start_page, start_index, start_y, root_data, draw_state, &rects, display_list);
+        }
 
         if props.get("page-break-after").map(String::as_str) == Some("always") {
             display_list.push(Vec::new());
@@ -535,15 +842,85 @@ impl Engine {
         }
     }
 
+    // Adds the background and the borders of the given block to the pages it spans,
+    // in front of the commands of the block.
+    fn insert_box_commands(&self, node: NodeRef, style: &StyleData, start_page: usize, start_index: usize, start_y: i32, root_data: &RootData, draw_state: &DrawState, rects: &[Option<Rectangle>], display_list: &mut [Page]) {
+        let widths = style.border.widths();
+        let min_x = style.start_x - style.padding.left - widths.left;
+        let max_x = style.end_x + style.padding.right + widths.right;
+        let end_page = display_list.len() - 1;
+        let offset = root_data.start_offset + node.offset();
+        let mut first_page = start_page;
+
+        // The block was moved to the next page before drawing anything.
+        if end_page > start_page && rects.first().map_or(false, Option::is_none) {
+            first_page += 1;
+        }
+
+        for (index, page) in display_list.iter_mut().enumerate().skip(first_page) {
+            let min_y = if index == start_page { start_y + style.margin.top } else { root_data.rect.min.y };
+            let max_y = if index == end_page { draw_state.position.y } else { root_data.rect.max.y };
+
+            if max_y <= min_y || max_x <= min_x {
+                continue;
+            }
+
+            let mut border = style.border;
+            if index != first_page {
+                border.top = BorderSide::default();
+            }
+            if index != end_page {
+                border.bottom = BorderSide::default();
+            }
+
+            let offset = if index == first_page { offset } else { page.first().map_or(offset, DrawCommand::offset) };
+            let position = if index == start_page { start_index.min(page.len()) } else { 0 };
+            page.insert(position, DrawCommand::Box(BoxCommand {
+                offset,
+                rect: rect![min_x, min_y, max_x, max_y],
+                background: style.background_color,
+                border,
+            }));
+        }
+    }
+
     fn compute_column_widths(&mut self, node: NodeRef, parent_style: &StyleData, loop_context: &LoopContext, stylesheet: &StyleSheet, root_data: &RootData, resource_fetcher: &mut dyn ResourceFetcher, draw_state: &mut DrawState) {
+        let mut spanning_widths = Vec::new();
+        draw_state.row_spans.clear();
+        self.measure_cells(node, parent_style, loop_context, stylesheet, root_data, resource_fetcher, draw_state, &mut spanning_widths);
+
+        // The cells spanning several columns are handled once the other cells are known.
+        for (index, colspan, min_width, max_width) in spanning_widths {
+            let len = draw_state.min_column_widths.len();
+            let columns = index.min(len)..(index + colspan).min(len);
+            distribute_width(&mut draw_state.min_column_widths[columns.clone()], min_width);
+            distribute_width(&mut draw_state.max_column_widths[columns], max_width);
+        }
+
+        draw_state.row_spans.clear();
+    }
+
+    fn measure_cells(&mut self, node: NodeRef, parent_style: &StyleData, loop_context: &LoopContext, stylesheet: &StyleSheet, root_data: &RootData, resource_fetcher: &mut dyn ResourceFetcher, draw_state: &mut DrawState, spanning_widths: &mut Vec<(usize, usize, i32, i32)>) {
         if node.tag_name() == Some("tr") {
             let mut index = 0;
             for child in node.children().filter(|c| c.is_element()) {
+                // Skip the columns covered by the cells of the previous rows.
+                while draw_state.row_spans.get(index).map_or(false, |&n| n > 0) {
+                    index += 1;
+                }
+
                 let colspan = child.attribute("colspan")
                                    .and_then(|v| v.parse().ok())
-                                   .unwrap_or(1);
+                                   .unwrap_or(1)
+                                   .max(1);
+                let rowspan = child.attribute("rowspan")
+                                   .and_then(|v| v.parse::<usize>().ok())
+                                   .unwrap_or(1)
+                                   .max(1);
                 let mut display_list = vec![Vec::new()];
+                let row_spans = mem::take(&mut draw_state.row_spans);
                 let artifact = self.build_display_list(child, parent_style, loop_context, stylesheet, root_data, resource_fetcher, draw_state, &mut display_list);
+                draw_state.row_spans = row_spans;
                 let horiz_padding = artifact.sibling_style.padding.left +
                                     artifact.sibling_style.padding.right;
                 let min_width = display_list.into_iter()
@@ -559,24 +936,40 @@ impl Engine {
                 let max_width = artifact.rects.into_iter()
                                         .filter_map(|v| v.map(|r| r.width() as i32 + horiz_padding))
                                         .max().unwrap_or(0);
-                if colspan == 1 {
-                    if let Some(cw) = draw_state.min_column_widths.get_mut(index) {
-                        *cw = (*cw).max(min_width);
-                    } else {
-                        draw_state.min_column_widths.push(min_width);
-                    }
-                    if let Some(cw) = draw_state.max_column_widths.get_mut(index) {
-                        *cw = (*cw).max(max_width);
-                    } else {
-                        draw_state.max_column_widths.push(max_width);
+
+                if draw_state.min_column_widths.len() < index + colspan {
+                    draw_state.min_column_widths.resize(index + colspan, 0);
+                    draw_state.max_column_widths.resize(index + colspan, 0);
+                }
+
+                if draw_state.row_spans.len() < index + colspan {
+                    draw_state.row_spans.resize(index + colspan, 0);
+                }
+
+                if rowspan > 1 {
+                    for span in &mut draw_state.row_spans[index..index+colspan] {
+                        *span = rowspan;
                     }
                 }
 
+                if colspan == 1 {
+                    let cw = &mut draw_state.min_column_widths[index];
+                    *cw = (*cw).max(min_width);
+                    let cw = &mut draw_state.max_column_widths[index];
+                    *cw = (*cw).max(max_width);
+                } else {
+                    spanning_widths.push((index, colspan, min_width, max_width));
+                }
+
                 index += colspan;
             }
+
+            for span in &mut draw_state.row_spans {
+                *span = span.saturating_sub(1);
+            }
         } else {
-            for child in node.children().filter(|c| c.is_element()) {
-                self.compute_column_widths(child, parent_style, loop_context, stylesheet, root_data, resource_fetcher, draw_state);
+            for child in node.children().filter(|c| c.is_element() && c.tag_name() != Some("caption")) {
+                self.measure_cells(child, parent_style, loop_context, stylesheet, root_data, resource_fetcher, draw_state, spanning_widths);
             }
         }
     }
@@ -614,13 +1007,22 @@ impl Engine {
                                     .unwrap_or(0);
 
                 style.font_kind = props.get("font-family")
-                                       .and_then(|value| parse_font_kind(value))
+                                       .and_then(|value| self.font_kind(value))
                                        .unwrap_or(parent_style.font_kind);
 
                 style.color = props.get("color")
                                    .and_then(|value| parse_color(value))
                                    .unwrap_or(parent_style.color);
 
+                style.text_decoration = props.get("text-decoration-line")
+                                             .or_else(|| props.get("text-decoration"))
+                                             .and_then(|value| parse_text_decoration(value))
+                                             .unwrap_or(parent_style.text_decoration);
+
+                style.text_transform = props.get("text-transform")
+                                            .and_then(|value| parse_text_transform(value))
+                                            .unwrap_or(parent_style.text_transform);
+
                 style.letter_spacing = props.get("letter-spacing")
                                             .and_then(|value| parse_letter_spacing(value, style.font_size, self.font_size, self.dpi))
                                             .unwrap_or(parent_style.letter_spacing);
@@ -657,15 +1059,27 @@ impl Engine {
                 }
 
                 match name.as_ref() {
-                    "img" | "image" => {
-                        let attr = if name == "img" { "src" } else { "xlink:href" };
-
-                        let path = attributes.get(attr).and_then(|src| {
-                            spine_dir.join(src).normalize().to_str()
-                                     .map(|uri| percent_decode_str(&decode_entities(uri))
-                                                                  .decode_utf8_lossy()
-                                                                  .into_owned())
-                        }).unwrap_or_default();
+                    "img" | "image" | "svg" => {
+                        let path = if name == "svg" {
+                            let dims = self.svg_dims(attributes, &style, parent_style);
+                            if style.width == 0 && style.height == 0 {
+                                if let Some((width, _)) = dims {
+                                    style.width = width;
+                                }
+                            }
+                            data_uri(SVG_MIME, svg_document(node, spine_dir, dims).as_bytes())
+                        } else {
+                            let attr = if name == "img" { "src" } else { "xlink:href" };
+                            attributes.get(attr).or_else(|| attributes.get("href")).and_then(|src| {
+                                if src.starts_with("data:") {
+                                    return Some(decode_entities(src).into_owned());
+                                }
+                                spine_dir.join(src).normalize().to_str()
+                                         .map(|uri| percent_decode_str(&decode_entities(uri))
+                                                                      .decode_utf8_lossy()
+                                                                      .into_owned())
+                            }).unwrap_or_default()
+                        };
 
                         style.float = props.get("float").and_then(|value| parse_float(value));
 
@@ -699,6 +1113,53 @@ impl Engine {
                         inlines.push(InlineMaterial::LineBreak);
                         return;
                     },
+                    "ruby" => {
+                        let mut base = Vec::new();
+                        for child in node.children() {
+                            if matches!(child.tag_name(), Some("rt") | Some("rtc")) {
+                                let mut annotation = Vec::new();
+                                self.gather_inline_material(child, stylesheet, &style, spine_dir, markers, &mut annotation);
+                                let bases = text_runs(&base);
+                                let annotations = text_runs(&annotation);
+                                if !bases.is_empty() && bases.len() == annotations.len() {
+                                    inlines.extend(bases.into_iter().zip(annotations)
+                                                        .map(|(base, annotation)| InlineMaterial::Ruby(base, Box::new(annotation))));
+                                } else {
+                                    // The annotation can't be split between the runs of the base: it follows it.
+                                    inlines.append(&mut base);
+                                    if !bases.is_empty() && !annotations.is_empty() {
+                                        inlines.append(&mut annotation);
+                                    }
+                                }
+                                base.clear();
+                            } else if child.tag_name() != Some("rp") {
+                                self.gather_inline_material(child, stylesheet, &style, spine_dir, markers, &mut base);
+                            }
+                        }
+                        inlines.append(&mut base);
+                        return;
+                    },
+                    "math" => {
+                        if let Some(math) = parse_math(node) {
+                            let display = style.display == Display::Block ||
+                                          attributes.get("display").map(String::as_str) == Some("block");
+                            inlines.push(InlineMaterial::Math(MathMaterial {
+                                offset: *offset,
+                                node: math,
+                                display,
+                                style,
+                            }));
+                            return;
+                        }
+                        if let Some(alttext) = attributes.get("alttext") {
+                            inlines.push(InlineMaterial::Text(TextMaterial {
+                                offset: *offset,
+                                text: decode_entities(alttext).into_owned(),
+                                style,
+                            }));
+                            return;
+                        }
+                    },
                     _ => {},
                 }
 
@@ -717,9 +1178,13 @@ impl Engine {
                 }
             },
             NodeData::Text(TextData { offset, text }) => {
+                let mut text = decode_entities(text).into_owned();
+                if parent_style.text_transform != TextTransform::None {
+                    text = transform_text(&text, parent_style.text_transform);
+                }
                 inlines.push(InlineMaterial::Text(TextMaterial {
                     offset: *offset,
-                    text: decode_entities(text).into_owned(),
+                    text,
                     style: parent_style.clone(),
                 }));
             },
@@ -759,8 +1224,8 @@ impl Engine {
                     let mut scale = 1.0;
                     let dpi = self.dpi;
 
-                    if let Ok(buf) = resource_fetcher.fetch(path) {
-                        if let Some(doc) = PdfOpener::new().and_then(|opener| opener.open_memory(path, &buf)) {
+                    if let Some((buf, magic)) = load_image(path, resource_fetcher) {
+                        if let Some(doc) = PdfOpener::new().and_then(|opener| opener.open_memory(&magic, &buf)) {
                             if let Some((w, h)) = doc.dims(0) {
                                 if width == 0 && height == 0 {
                                     width = pt_to_px(w, dpi).round() as i32;
@@ -774,6 +1239,11 @@ impl Engine {
                             }
                         }
 
+                        // The vertical pages are laid out sideways.
+                        if self.writing_mode == WritingMode::VerticalRl {
+                            mem::swap(&mut width, &mut height);
+                        }
+
                         if width * height > 0 {
                             let element = ImageElement {
                                     offset: *offset,
@@ -822,7 +1292,7 @@ impl Engine {
                                                                 style.font_style,
                                                                 style.font_weight);
                                         font.set_size(font_size, self.dpi);
-                                        font.plan(buf, None, style.font_features.as_deref())
+                                        plan_text(font, buf, style.font_features.as_deref())
                                     };
                                     plan.space_out(style.letter_spacing);
 
@@ -841,6 +1311,7 @@ impl Engine {
                                             letter_spacing: style.letter_spacing,
                                             font_size,
                                             color: style.color,
+                                            text_decoration: style.text_decoration,
                                             uri: style.uri.clone(),
                                         }),
                                     });
@@ -875,8 +1346,17 @@ impl Engine {
                                                    inlines[index+1..].iter().any(|m| m.text().map_or(false,
                                                                                       |text| text.chars().any(|c| !c.is_xml_whitespace())));
 
+                                    let rest = &text[start_index+i..];
+                                    let next_text = rest.trim_start_matches(|c: char| c.is_xml_whitespace());
+                                    let next_c = next_text.chars().next().or_else(|| {
+                                        inlines[index+1..].iter().filter_map(InlineMaterial::text)
+                                                          .flat_map(str::chars)
+                                                          .find(|c| !c.is_xml_whitespace())
+                                    });
+
                                     if !parent_style.retain_whitespace && c.is_xml_whitespace() &&
-                                        (last_c.map(|c| c.is_xml_whitespace()) != Some(false) || !has_more) {
+                                        (last_c.map(|c| c.is_xml_whitespace()) != Some(false) || !has_more ||
+                                         is_removable_break(last_c, &rest[..rest.len()-next_text.len()], next_c)) {
                                             start_index += chunk.len();
                                             continue;
                                     }
@@ -941,7 +1421,11 @@ impl Engine {
                                 } else if end_index < text.len() {
                                     let penalty = if c == '-' { self.hyphen_penalty } else { 0 };
                                     let flagged = penalty > 0;
-                                    if matches!(parent_style.text_align, TextAlign::Justify | TextAlign::Center) {
+                                    if parent_style.text_align == TextAlign::Justify && is_cjk_break(text, end_index) {
+                                        // There are no spaces between the CJK characters.
+                                        let stretch = space_plan.glyph_advance(0) / 2;
+                                        items.push(ParagraphItem::Glue { width: 0, stretch, shrink: 0 });
+                                    } else if matches!(parent_style.text_align, TextAlign::Justify | TextAlign::Center) {
                                         items.push(ParagraphItem::Penalty { width: 0, penalty, flagged });
                                     } else {
                                         let stretch = 3 * space_plan.glyph_advance(0);
@@ -956,6 +1440,47 @@ impl Engine {
                         }
                     }
                 },
+                InlineMaterial::Ruby(base, annotation) => {
+                    let element = self.ruby_element(base, annotation);
+                    items.push(ParagraphItem::Box {
+                        width: element.base.plan.width.max(element.annotation.plan.width),
+                        data: ParagraphElement::Ruby(element),
+                    });
+                },
+                InlineMaterial::Math(MathMaterial { offset, node, display, style }) => {
+                    let font_size = (style.font_size * 64.0) as u32;
+                    let math_box = {
+                        let mut fonts = EngineMathFonts {
+                            fonts: self.fonts.as_mut().unwrap(),
+                            font_kind: style.font_kind,
+                            dpi: self.dpi,
+                        };
+                        layout_math(node, font_size, *display, &mut fonts)
+                    };
+                    // Display formulas are set on their own lines.
+                    if *display && !items.is_empty() {
+                        items.push(ParagraphItem::Penalty { penalty: INFINITE_PENALTY, width: 0, flagged: false });
+                        items.push(ParagraphItem::Glue { width: 0, stretch: line_width, shrink: 0 });
+                        items.push(ParagraphItem::Penalty { width: 0, penalty: -INFINITE_PENALTY, flagged: false });
+                    }
+                    items.push(ParagraphItem::Box {
+                        width: math_box.width,
+                        data: ParagraphElement::Math(MathElement {
+                            offset: *offset,
+                            math_box,
+                            display: *display,
+                            font_kind: style.font_kind,
+                            vertical_align: style.vertical_align,
+                            color: style.color,
+                            uri: style.uri.clone(),
+                        }),
+                    });
+                    if *display {
+                        items.push(ParagraphItem::Penalty { penalty: INFINITE_PENALTY, width: 0, flagged: false });
+                        items.push(ParagraphItem::Glue { width: 0, stretch: line_width, shrink: 0 });
+                        items.push(ParagraphItem::Penalty { width: 0, penalty: -INFINITE_PENALTY, flagged: false });
+                    }
+                },
                 InlineMaterial::LineBreak => {
                     let stretch = if parent_style.text_align == TextAlign::Center { big_stretch } else { line_width };
 
@@ -1171,6 +1696,8 @@ impl Engine {
             items = self.cleanup_paragraph(items, &hyph_indices, &mut glue_drifts, &mut bps);
         }
 
+        let levels = embedding_levels(&items, style.direction);
+
         let mut last_index = 0;
         let mut markers_index = 0;
         let mut last_x_position = 0;
@@ -1178,21 +1705,28 @@ impl Engine {
 
         if let Some(prefix) = draw_state.prefix.as_ref() {
             let font_size = (style.font_size * 64.0) as u32;
+            // The marker is on the right side of right-to-left paragraphs.
+            let prefix = if style.direction == Direction::Rtl {
+                format!(" {}", prefix.trim_end())
+            } else {
+                prefix.to_string()
+            };
             let prefix_plan = {
                 let font = self.fonts.as_mut().unwrap()
                                .get_mut(style.font_kind, style.font_style, style.font_weight);
                 font.set_size(font_size, self.dpi);
-                font.plan(prefix, None, style.font_features.as_deref())
+                font.plan(&prefix, None, style.font_features.as_deref())
             };
-            let (start_x, _) = para_shape[0];
-            let pt = pt!(start_x - prefix_plan.width, position.y);
+            let (start_x, end_x) = para_shape[0];
+            let x = if style.direction == Direction::Rtl { end_x } else { start_x - prefix_plan.width };
+            let pt = pt!(x, position.y);
             let rect = rect![pt + pt!(0, -ascender), pt + pt!(prefix_plan.width, -descender)];
             if let Some(first_offset) = inlines.iter().filter_map(|elt| elt.offset()).next() {
                 page.push(DrawCommand::ExtraText(TextCommand {
                     offset: root_data.start_offset + first_offset,
                     position: pt,
                     rect,
-                    text: prefix.to_string(),
+                    text: prefix,
                     plan: prefix_plan,
                     uri: None,
                     font_kind: style.font_kind,
@@ -1217,8 +1751,10 @@ impl Engine {
             let mut epsilon: f32 = 0.0;
             let current_text_indent = if is_first_line { text_indent } else { 0 };
 
+            // The indentation of right-to-left lines is taken into account by the line lengths.
             match style.text_align {
                 TextAlign::Right => position.x = end_x - width - current_text_indent,
+                _ if style.direction == Direction::Rtl => position.x = start_x,
                 _ => position.x = start_x + current_text_indent,
             }
 
@@ -1231,8 +1767,17 @@ impl Engine {
             }
 
             let start_command_index = page.len();
+            let mut decoration_span: Option<DecorationSpan> = None;
+            let mut line_ascent = ascender;
+            let mut line_descent = -descender;
+
+            let order = match levels.as_ref() {
+                Some(levels) => visual_order(&items[last_index..index], &levels[last_index..index], style.direction)
+                                    .into_iter().map(|k| last_index + k).collect(),
+                None => (last_index..index).collect::<Vec<usize>>(),
+            };
 
-            for i in last_index..index {
+            for i in order {
                 match items[i] {
                     ParagraphItem::Box { ref data, width } => {
                         match data {
@@ -1265,8 +1810,148 @@ impl Engine {
                                     font_size: element.font_size,
                                     color: element.color,
                                 }));
+                                if element.text_decoration.is_none() {
+                                    push_decoration_rules(decoration_span.take(), &mut page);
+                                } else {
+                                    match decoration_span.as_mut() {
+                                        Some(span) if span.decoration == element.text_decoration &&
+                                                      span.color == element.color && span.baseline == pt.y => {
+                                            span.end_x = pt.x + element.plan.width;
+                                        },
+                                        _ => {
+                                            push_decoration_rules(decoration_span.take(), &mut page);
+                                            decoration_span = Some(DecorationSpan {
+                                                offset: element.offset + root_data.start_offset,
+                                                decoration: element.text_decoration,
+                                                color: element.color,
+                                                baseline: pt.y,
+                                                size: pt_to_px(element.font_size as f32 / 64.0, self.dpi).round() as i32,
+                                                start_x: pt.x,
+                                                end_x: pt.x + element.plan.width,
+                                            });
+                                        },
+                                    }
+                                }
+                            },
+                            ParagraphElement::Ruby(RubyElement { base, annotation, shift }) => {
+                                push_decoration_rules(decoration_span.take(), &mut page);
+                                while let Some(offset) = markers.get(markers_index) {
+                                    if *offset < base.offset {
+                                        page.push(DrawCommand::Marker(root_data.start_offset + *offset));
+                                        markers_index += 1;
+                                    } else {
+                                        break;
+                                    }
+                                }
+                                let pt = pt!(position.x + (width - base.plan.width) / 2, position.y - base.vertical_align);
+                                let rect = rect![pt + pt!(0, -ascender), pt + pt!(base.plan.width, -descender)];
+                                let annotation_pt = pt!(position.x + (width - annotation.plan.width) / 2, pt.y - shift);
+                                let annotation_height = pt_to_px(annotation.font_size as f32 / 64.0, self.dpi).round() as i32;
+                                let annotation_rect = rect![annotation_pt.x, rect.min.y - annotation_height,
+                                                            annotation_pt.x + annotation.plan.width, rect.min.y];
+                                for r in [&rect, &annotation_rect] {
+                                    if let Some(pr) = page_rect.as_mut() {
+                                        pr.absorb(r);
+                                    } else {
+                                        page_rect = Some(*r);
+                                    }
+                                }
+                                page.push(DrawCommand::Text(TextCommand {
+                                    offset: base.offset + root_data.start_offset,
+                                    position: pt,
+                                    rect,
+                                    text: base.text.clone(),
+                                    plan: base.plan.clone(),
+                                    uri: base.uri.clone(),
+                                    font_kind: base.font_kind,
+                                    font_style: base.font_style,
+                                    font_weight: base.font_weight,
+                                    font_size: base.font_size,
+                                    color: base.color,
+                                }));
+                                page.push(DrawCommand::ExtraText(TextCommand {
+                                    offset: annotation.offset + root_data.start_offset,
+                                    position: annotation_pt,
+                                    rect: annotation_rect,
+                                    text: annotation.text.clone(),
+                                    plan: annotation.plan.clone(),
+                                    uri: annotation.uri.clone(),
+                                    font_kind: annotation.font_kind,
+                                    font_style: annotation.font_style,
+                                    font_weight: annotation.font_weight,
+                                    font_size: annotation.font_size,
+                                    color: annotation.color,
+                                }));
+                            },
+                            ParagraphElement::Math(element) => {
+                                push_decoration_rules(decoration_span.take(), &mut page);
+                                while let Some(offset) = markers.get(markers_index) {
+                                    if *offset < element.offset {
+                                        page.push(DrawCommand::Marker(root_data.start_offset + *offset));
+                                        markers_index += 1;
+                                    } else {
+                                        break;
+                                    }
+                                }
+                                let math_box = &element.math_box;
+                                // Tall formulas push the line down.
+                                let top = math_box.ascent + element.vertical_align;
+                                if top > line_ascent {
+                                    let delta = top - line_ascent;
+                                    for dc in &mut page[start_command_index..] {
+                                        if let Some(pt) = dc.position_mut() {
+                                            pt.y += delta;
+                                        }
+                                        if let Some(rect) = dc.rect_mut() {
+                                            *rect += pt!(0, delta);
+                                        }
+                                    }
+                                    position.y += delta;
+                                    line_ascent = top;
+                                }
+                                line_descent = line_descent.max(math_box.descent - element.vertical_align);
+                                if element.display {
+                                    position.x = (start_x + end_x - width) / 2;
+                                }
+                                let origin = pt!(position.x, position.y - element.vertical_align);
+                                let rect = rect![origin.x, origin.y - math_box.ascent,
+                                                 origin.x + width, origin.y + math_box.descent];
+                                if let Some(pr) = page_rect.as_mut() {
+                                    pr.absorb(&rect);
+                                } else {
+                                    page_rect = Some(rect);
+                                }
+                                for item in &math_box.items {
+                                    match item {
+                                        MathItem::Glyphs { position: pt, rect, text, plan, style: font_style, weight, size } => {
+                                            page.push(DrawCommand::ExtraText(TextCommand {
+                                                offset: element.offset + root_data.start_offset,
+                                                position: origin + *pt,
+                                                rect: *rect + origin,
+                                                text: text.clone(),
+                                                plan: plan.clone(),
+                                                uri: element.uri.clone(),
+                                                font_kind: element.font_kind,
+                                                font_style: *font_style,
+                                                font_weight: *weight,
+                                                font_size: *size,
+                                                color: element.color,
+                                            }));
+                                        },
+                                        MathItem::Rule { position: pt, width, thickness } => {
+                                            page.push(DrawCommand::Rule(RuleCommand {
+                                                offset: element.offset + root_data.start_offset,
+                                                position: origin + *pt,
+                                                width: *width,
+                                                thickness: *thickness,
+                                                color: element.color,
+                                            }));
+                                        },
+                                    }
+                                }
                             },
                             ParagraphElement::Image(element) => {
+                                push_decoration_rules(decoration_span.take(), &mut page);
                                 while let Some(offset) = markers.get(markers_index) {
                                     if *offset < element.offset {
                                         page.push(DrawCommand::Marker(root_data.start_offset + *offset));
@@ -1416,14 +2101,27 @@ impl Engine {
                         font.set_size(tc.font_size, self.dpi);
                         let mut hyphen_plan = font.plan("-", None, None);
                         tc.rect.max.x += hyphen_plan.width;
+                        if let Some(span) = decoration_span.as_mut() {
+                            span.end_x += hyphen_plan.width;
+                        }
                         tc.plan.append(&mut hyphen_plan);
                         tc.text.push('\u{00AD}');
                     }
                 }
             }
 
+            push_decoration_rules(decoration_span.take(), &mut page);
+
+            // Restore the logical order of the commands of reordered lines.
+            if levels.is_some() {
+                if let Some(commands) = page.get_mut(start_command_index..) {
+                    commands.sort_by_key(DrawCommand::offset);
+                }
+            }
+
             last_index = index;
             is_first_line = false;
+            position.y += line_descent + descender;
 
             if index < items.len() - 1 {
                 position.y += style.line_height;
@@ -1457,6 +2155,52 @@ impl Engine {
         display_list.push(page);
     }
 
+    fn text_element(&mut self, material: &TextMaterial) -> TextElement {
+        let TextMaterial { offset, text, style } = material;
+        let font_size = (style.font_size * 64.0) as u32;
+        let mut plan = {
+            let font = self.fonts.as_mut().unwrap()
+                           .get_mut(style.font_kind,
+                                    style.font_style,
+                                    style.font_weight);
+            font.set_size(font_size, self.dpi);
+            plan_text(font, text, style.font_features.as_deref())
+        };
+        plan.space_out(style.letter_spacing);
+        TextElement {
+            offset: *offset,
+            language: style.language.clone(),
+            text: text.clone(),
+            plan,
+            font_features: style.font_features.clone(),
+            font_kind: style.font_kind,
+            font_style: style.font_style,
+            font_weight: style.font_weight,
+            vertical_align: style.vertical_align,
+            letter_spacing: style.letter_spacing,
+            font_size,
+            color: style.color,
+            text_decoration: style.text_decoration,
+            uri: style.uri.clone(),
+        }
+    }
+
+    // The annotation sits on top of the base's ascender.
+    fn ruby_element(&mut self, base: &TextMaterial, annotation: &TextMaterial) -> RubyElement {
+        let base = self.text_element(base);
+        let annotation = Box::new(self.text_element(annotation));
+        let shift = {
+            let fonts = self.fonts.as_mut().unwrap();
+            let font = fonts.get_mut(base.font_kind, base.font_style, base.font_weight);
+            font.set_size(base.font_size, self.dpi);
+            let ascender = font.ascender();
+            let font = fonts.get_mut(annotation.font_kind, annotation.font_style, annotation.font_weight);
+            font.set_size(annotation.font_size, self.dpi);
+            ascender - font.descender()
+        };
+        RubyElement { base, annotation, shift }
+    }
+
     #[inline]
     fn box_from_chunk(&mut self, chunk: &str, index: usize, element: &TextElement) -> ParagraphItem<ParagraphElement> {
         let offset = element.offset + index;
@@ -1466,7 +2210,7 @@ impl Engine {
                                     element.font_style,
                                     element.font_weight);
             font.set_size(element.font_size, self.dpi);
-            font.plan(chunk, None, element.font_features.as_deref())
+            plan_text(font, chunk, element.font_features.as_deref())
         };
         plan.space_out(element.letter_spacing);
         ParagraphItem::Box {
@@ -1484,6 +2228,7 @@ impl Engine {
                 vertical_align: element.vertical_align,
                 letter_spacing: element.letter_spacing,
                 color: element.color,
+                text_decoration: element.text_decoration,
                 uri: element.uri.clone(),
             }),
         }
@@ -1584,7 +2329,7 @@ impl Engine {
                         let font = self.fonts.as_mut().unwrap()
                                        .get_mut(font_kind, font_style, font_weight);
                         font.set_size(font_size, self.dpi);
-                        font.plan(text, None, font_features.as_ref().map(Vec::as_slice))
+                        plan_text(font, text, font_features.as_ref().map(Vec::as_slice))
                     };
                     plan.space_out(letter_spacing);
                     merged_width = plan.width;
@@ -1647,7 +2392,7 @@ impl Engine {
                             let font = self.fonts.as_mut().unwrap()
                                            .get_mut(font_kind, font_style, font_weight);
                             font.set_size(font_size, self.dpi);
-                            font.plan(text, None, font_features.as_ref().map(Vec::as_slice))
+                            plan_text(font, text, font_features.as_ref().map(Vec::as_slice))
                         };
                         plan.space_out(letter_spacing);
                         merged_width = plan.width;
@@ -1669,11 +2414,67 @@ impl Engine {
         merged_items
     }
 
+    // The vertical pages are laid out as horizontal pages that are then
+    // rotated clockwise: the lines become columns, ordered from right to left.
+    // The glyphs aren't rotated, and the text is drawn downward from the
+    // middle of the top of each column.
+    pub fn rotate_display_list(&self, display_list: &mut [Page]) {
+        if self.writing_mode != WritingMode::VerticalRl {
+            return;
+        }
+
+        let width = self.dims.0 as i32;
+        let rotate = |rect: &Rectangle| rect![width - rect.max.y, rect.min.x,
+                                              width - rect.min.y, rect.max.x];
+
+        for dc in display_list.iter_mut().flatten() {
+            match dc {
+                DrawCommand::Text(tc) | DrawCommand::ExtraText(tc) => {
+                    tc.position = pt!(width - (tc.rect.min.y + tc.rect.max.y) / 2, tc.rect.min.x);
+                    tc.rect = rotate(&tc.rect);
+                },
+                DrawCommand::Image(ic) => {
+                    ic.rect = rotate(&ic.rect);
+                    ic.position = ic.rect.min;
+                },
+                DrawCommand::Box(bc) => {
+                    bc.rect = rotate(&bc.rect);
+                    let Border { top, right, bottom, left } = bc.border;
+                    bc.border = Border { top: left, right: top, bottom: right, left: bottom };
+                },
+                DrawCommand::Rule(RuleCommand { offset, position, width: length, thickness, color }) => {
+                    let rect = rotate(&rect![*position, *position + pt!(*length, *thickness)]);
+                    *dc = DrawCommand::Box(BoxCommand {
+                        offset: *offset,
+                        rect,
+                        background: Some(*color),
+                        border: Border::default(),
+                    });
+                },
+                DrawCommand::Table(tc) => {
+                    tc.rect = rotate(&tc.rect);
+                },
+                DrawCommand::Marker(..) => (),
+            }
+        }
+    }
+
     pub fn render_page(&mut self, page: &[DrawCommand], scale_factor: f32, samples: usize, resource_fetcher: &mut dyn ResourceFetcher) -> Option<Pixmap> {
         let width = (self.dims.0 as f32 * scale_factor) as u32;
         let height = (self.dims.1 as f32 * scale_factor) as u32;
         let mut fb = Pixmap::try_new(width, height, samples)?;
 
+        // Backgrounds and borders are drawn first, underneath the content.
+        for dc in page {
+            if let DrawCommand::Box(BoxCommand { rect, background, border, .. }) = dc {
+                let rect = scale_rect(rect, scale_factor);
+                if let Some(color) = background {
+                    fb.draw_rectangle(&rect, *color);
+                }
+                draw_border(&mut fb, &rect, border, scale_factor);
+            }
+        }
+
         for dc in page {
             match dc {
                 DrawCommand::Text(TextCommand { position, plan, font_kind,
@@ -1689,9 +2490,9 @@ impl Engine {
                     font.render(&mut fb, *color, &plan, position);
                 },
                 DrawCommand::Image(ImageCommand { position, path, scale, .. }) => {
-                    if let Ok(buf) = resource_fetcher.fetch(path) {
+                    if let Some((buf, magic)) = load_image(path, resource_fetcher) {
                         if let Some((pixmap, _)) = PdfOpener::new().and_then(|opener| {
-                            opener.open_memory(path, &buf)
+                            opener.open_memory(&magic, &buf)
                         }).and_then(|mut doc| {
                             doc.pixmap(Location::Exact(0), scale_factor * *scale, samples)
                         }) {
@@ -1700,6 +2501,14 @@ impl Engine {
                         }
                     }
                 },
+                DrawCommand::Rule(RuleCommand { position, width, thickness, color, .. }) => {
+                    let rect = scale_rect(&rect![*position, *position + pt!(*width, *thickness)], scale_factor);
+                    fb.draw_rectangle(&rect, *color);
+                },
+                DrawCommand::Table(TableCommand { rect, .. }) => {
+                    let rect = scale_rect(rect, scale_factor);
+                    fb.draw_triangle(&[rect.min, pt!(rect.max.x, rect.min.y), rect.max], GRAY08);
+                },
                 _ => (),
             }
         }
@@ -1708,6 +2517,225 @@ impl Engine {
     }
 }
 
+fn scale_rect(rect: &Rectangle, scale_factor: f32) -> Rectangle {
+    let min = Point::from(scale_factor * Vec2::from(rect.min));
+    let max = Point::from(scale_factor * Vec2::from(rect.max));
+    // Keep thin lines visible at small scales.
+    rect![min, pt!(max.x.max(min.x + 1), max.y.max(min.y + 1))]
+}
+
+fn draw_border(fb: &mut dyn Framebuffer, rect: &Rectangle, border: &Border, scale_factor: f32) {
+    let width = |side: &BorderSide| (side.width as f32 * scale_factor).round().max(1.0) as i32;
+    let sides = [(&border.top, true), (&border.bottom, true), (&border.left, false), (&border.right, false)];
+
+    for (index, (side, horizontal)) in sides.iter().enumerate() {
+        if !side.is_visible() {
+            continue;
+        }
+        let w = width(side);
+        let strip = match index {
+            0 => rect![rect.min.x, rect.min.y, rect.max.x, rect.min.y + w],
+            1 => rect![rect.min.x, rect.max.y - w, rect.max.x, rect.max.y],
+            2 => rect![rect.min.x, rect.min.y, rect.min.x + w, rect.max.y],
+            _ => rect![rect.max.x - w, rect.min.y, rect.max.x, rect.max.y],
+        };
+        draw_border_strip(fb, &strip, side.style, side.color, *horizontal, w);
+    }
+}
+
+fn draw_border_strip(fb: &mut dyn Framebuffer, strip: &Rectangle, style: BorderStyle, color: Color, horizontal: bool, w: i32) {
+    match style {
+        BorderStyle::Double if w >= 3 => {
+            let t = (w + 1) / 3;
+            if horizontal {
+                fb.draw_rectangle(&rect![strip.min.x, strip.min.y, strip.max.x, strip.min.y + t], color);
+                fb.draw_rectangle(&rect![strip.min.x, strip.max.y - t, strip.max.x, strip.max.y], color);
+            } else {
+                fb.draw_rectangle(&rect![strip.min.x, strip.min.y, strip.min.x + t, strip.max.y], color);
+                fb.draw_rectangle(&rect![strip.max.x - t, strip.min.y, strip.max.x, strip.max.y], color);
+            }
+        },
+        BorderStyle::Dashed | BorderStyle::Dotted => {
+            let (dash, gap) = if style == BorderStyle::Dashed { (3 * w, 2 * w) } else { (w, w) };
+            let (start, end) = if horizontal { (strip.min.x, strip.max.x) } else { (strip.min.y, strip.max.y) };
+            let mut a = start;
+            while a < end {
+                let b = (a + dash).min(end);
+                let segment = if horizontal {
+                    rect![a, strip.min.y, b, strip.max.y]
+                } else {
+                    rect![strip.min.x, a, strip.max.x, b]
+                };
+                fb.draw_rectangle(&segment, color);
+                a = b + gap;
+            }
+        },
+        BorderStyle::None => (),
+        _ => fb.draw_rectangle(strip, color),
+    }
+}
+
+// A run of decorated text on a line.
+struct DecorationSpan {
+    offset: usize,
+    decoration: TextDecoration,
+    color: Color,
+    baseline: i32,
+    size: i32,
+    start_x: i32,
+    end_x: i32,
+}
+
+// Extends the boxes of a cell, that end where the cell ends, down to the given bottom.
+fn stretch_cell_boxes(page: &mut Page, min_x: i32, max_x: i32, end_y: i32, bottom: i32) {
+    for dc in page.iter_mut() {
+        if let DrawCommand::Box(BoxCommand { rect, .. }) = dc {
+            if rect.max.y == end_y && rect.min.x >= min_x && rect.max.x <= max_x {
+                rect.max.y = bottom;
+            }
+        }
+    }
+}
+
+// Spreads the missing width of a cell spanning several columns evenly among them.
+fn distribute_width(widths: &mut [i32], width: i32) {
+    let missing = width - widths.iter().sum::<i32>();
+    if missing <= 0 || widths.is_empty() {
+        return;
+    }
+    let count = widths.len() as i32;
+    for (i, w) in widths.iter_mut().enumerate() {
+        *w += missing / count + if (i as i32) < missing % count { 1 } else { 0 };
+    }
+}
+
+// The borders implied by the border attribute of a table, for the table and its cells.
+fn border_attribute(node: NodeRef, em: f32, rem: f32, dpi: u16) -> Option<i32> {
+    let table = match node.tag_name() {
+        Some("table") => node,
+        Some("td" | "th") => node.ancestor_elements().find(|n| n.tag_name() == Some("table"))?,
+        _ => return None,
+    };
+    let value = table.attribute("border")?;
+    let width = if value.is_empty() { 1 } else { value.trim().parse::<i32>().ok()? };
+    if width <= 0 {
+        return None;
+    }
+    if node.tag_name() == Some("table") {
+        parse_border_width(&format!("{}px", width), em, rem, dpi)
+    } else {
+        parse_border_width("thin", em, rem, dpi)
+    }
+}
+
+fn push_decoration_rules(span: Option<DecorationSpan>, page: &mut Page) {
+    let span = match span {
+        Some(span) => span,
+        None => return,
+    };
+
+    let thickness = (span.size / 16).max(1);
+    let lines = [(span.decoration.underline, span.size / 8),
+                 (span.decoration.line_through, -3 * span.size / 10),
+                 (span.decoration.overline, -4 * span.size / 5)];
+
+    for (_, shift) in lines.iter().filter(|(enabled, _)| *enabled) {
+        page.push(DrawCommand::Rule(RuleCommand {
+            offset: span.offset,
+            position: pt!(span.start_x, span.baseline + shift - thickness / 2),
+            width: span.end_x - span.start_x,
+            thickness,
+            color: span.color,
+        }));
+    }
+}
+
+// Measures the tokens of the formulas with the fonts of the engine.
+struct EngineMathFonts<'a> {
+    fonts: &'a mut Fonts,
+    font_kind: FontKind,
+    dpi: u16,
+}
+
+impl<'a> MathFonts for EngineMathFonts<'a> {
+    fn plan(&mut self, text: &str, style: FontStyle, weight: FontWeight, size: u32) -> RenderPlan {
+        let font = self.fonts.get_mut(self.font_kind, style, weight);
+        font.set_size(size, self.dpi);
+        font.plan(text, None, None)
+    }
+
+    fn em(&mut self, size: u32) -> i32 {
+        let font = self.fonts.get_mut(self.font_kind, FontStyle::Normal, FontWeight::Normal);
+        font.set_size(size, self.dpi);
+        font.em() as i32
+    }
+}
+
+// The writing mode of the body, or else of the root element.
+pub fn writing_mode(root: NodeRef, stylesheet: &StyleSheet) -> WritingMode {
+    ["body", "html"].iter()
+                    .filter_map(|name| root.find(name))
+                    .filter_map(|node| {
+                        let props = specified_values(node, stylesheet);
+                        ["writing-mode", "-epub-writing-mode", "-webkit-writing-mode"].iter()
+                            .filter_map(|name| props.get(*name))
+                            .find_map(|value| parse_writing_mode(value))
+                    })
+                    .next()
+                    .unwrap_or(WritingMode::HorizontalTb)
+}
+
+// Splits the text of a ruby base or annotation into runs of contiguous text,
+// so that each run keeps the offset of its first character.
+fn text_runs(inlines: &[InlineMaterial]) -> Vec<TextMaterial> {
+    let mut runs: Vec<TextMaterial> = Vec::new();
+    for material in inlines.iter().filter_map(|m| match m {
+        InlineMaterial::Text(material) => Some(material),
+        _ => None,
+    }) {
+        match runs.last_mut() {
+            Some(run) if run.offset + run.text.len() == material.offset => run.text.push_str(&material.text),
+            _ => runs.push(material.clone()),
+        }
+    }
+    runs.retain(|run| !run.text.trim().is_empty());
+    for run in &mut runs {
+        run.offset += run.text.len() - run.text.trim_start().len();
+        run.text = run.text.trim().to_string();
+    }
+    runs
+}
+
+// Characters whose case mapping has a different length are left untouched:
+// the offsets of the text must remain valid.
+fn transform_text(text: &str, transform: TextTransform) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut last_c: Option<char> = None;
+
+    for c in text.chars() {
+        let upper = match transform {
+            TextTransform::Uppercase => true,
+            TextTransform::Capitalize => last_c.map_or(true, |c| c.is_whitespace() || c == '-'),
+            _ => false,
+        };
+        let mapped = if upper {
+            c.to_uppercase().collect::<String>()
+        } else if transform == TextTransform::Lowercase {
+            c.to_lowercase().collect::<String>()
+        } else {
+            c.to_string()
+        };
+        if mapped.len() == c.len_utf8() {
+            result.push_str(&mapped);
+        } else {
+            result.push(c);
+        }
+        last_c = Some(c);
+    }
+
+    result
+}
+
 fn format_list_prefix(kind: ListStyleType, index: usize) -> Option<String> {
     match kind {
         ListStyleType::None => None,
@@ -1757,8 +2785,81 @@ fn default_fonts() -> Result<Fonts, Error> {
         },
         cursive: opener.open("fonts/Parisienne-Regular.ttf")?,
         fantasy: opener.open("fonts/Delius-Regular.ttf")?,
+        embedded: Vec::new(),
+        vertical: false,
     };
     fonts.monospace.bold.set_variations(&["wght=600"]);
     fonts.monospace.bold_italic.set_variations(&["wght=600"]);
     Ok(fonts)
 }
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use super::{Engine, distribute_width, border_attribute};
+    use super::super::xml::XmlParser;
+    use super::super::parse::parse_color;
+    use super::super::style::StyleSheet;
+    use super::super::layout::{StyleData, LoopContext, RootData, DrawState, DrawCommand, BoxCommand};
+
+    #[test]
+    fn test_distribute_width() {
+        let mut widths = [10, 20, 30];
+        distribute_width(&mut widths, 65);
+        assert_eq!(widths, [12, 22, 31]);
+        distribute_width(&mut widths, 40);
+        assert_eq!(widths, [12, 22, 31]);
+        let mut widths = [0; 0];
+        distribute_width(&mut widths, 10);
+        assert!(widths.is_empty());
+    }
+
+    #[test]
+    fn test_border_attribute() {
+        let xml = XmlParser::new("<div><table border='2'><tr><td>a</td></tr></table>\
+                                  <table border=''><tr><th>b</th></tr></table>\
+                                  <table border='0'><tr><td>c</td></tr></table>\
+                                  <table><tr><td>d</td></tr></table><p>e</p></div>").parse();
+        let tables = xml.root().descendants()
+                        .filter(|n| n.tag_name() == Some("table"))
+                        .collect::<Vec<_>>();
+        let cell = |index: usize| tables[index].descendants()
+                                               .find(|n| matches!(n.tag_name(), Some("td" | "th")))
+                                               .unwrap();
+        assert_eq!(border_attribute(tables[0], 12.0, 12.0, 96), Some(2));
+        assert_eq!(border_attribute(cell(0), 12.0, 12.0, 96), Some(1));
+        assert_eq!(border_attribute(tables[1], 12.0, 12.0, 96), Some(1));
+        assert_eq!(border_attribute(cell(1), 12.0, 12.0, 96), Some(1));
+        assert_eq!(border_attribute(tables[2], 12.0, 12.0, 96), None);
+        assert_eq!(border_attribute(cell(3), 12.0, 12.0, 96), None);
+        assert_eq!(border_attribute(xml.root().find("p").unwrap(), 12.0, 12.0, 96), None);
+    }
+
+    #[test]
+    fn test_nested_boxes() {
+        let xml = XmlParser::new("<div style='background-color: #eee; height: 10px'></div>\
+                                  <div style='background-color: #000'>\
+                                  <div style='background-color: #888; height: 100px'></div></div>").parse();
+        let mut engine = Engine::new();
+        let rect = rect![0, 0, 600, 800];
+        let style = StyleData {
+            start_x: rect.min.x,
+            end_x: rect.max.x,
+            width: rect.width() as i32,
+            .. Default::default()
+        };
+        let root_data = RootData { start_offset: 0, spine_dir: PathBuf::default(), rect };
+        let mut draw_state = DrawState { position: rect.min, .. Default::default() };
+        let mut display_list = vec![Vec::new()];
+        engine.build_display_list(xml.root(), &style, &LoopContext::default(), &StyleSheet::new(), &root_data,
+                                  &mut PathBuf::default(), &mut draw_state, &mut display_list);
+        let boxes = display_list[0].iter().filter_map(|dc| match dc {
+            DrawCommand::Box(BoxCommand { rect, background, .. }) => Some((*rect, *background)),
+            _ => None,
+        }).collect::<Vec<_>>();
+        // The box of a block is drawn before the boxes of its descendants.
+        assert_eq!(boxes.iter().map(|b| b.1).collect::<Vec<_>>(),
+                   vec![parse_color("#eee"), parse_color("#000"), parse_color("#888")]);
+        assert!(boxes[1].0.contains(&boxes[2].0));
+    }
+}
@@ -9,6 +9,7 @@ use crate::geom::{Point, Rectangle, Edge};
 use crate::font::{FontFamily, Font, RenderPlan};
 pub use crate::metadata::TextAlign;
 use crate::color::BLACK;
+use super::math::{MathNode, MathBox};
 
 pub const DEFAULT_HYPH_LANG: &str = "en";
 
@@ -28,6 +29,19 @@ pub struct DrawState {
     pub max_column_widths: Vec<i32>,
     pub column_widths: Vec<i32>,
     pub center_table: bool,
+    // The number of rows still covered by a cell of a previous row, for each column.
+    pub row_spans: Vec<usize>,
+    pub spanning_cells: Vec<SpanningCell>,
+}
+
+// A cell spanning several rows, waiting for its last row to be placed.
+#[derive(Debug, Clone)]
+pub struct SpanningCell {
+    pub rows: usize,
+    pub page: usize,
+    pub end_y: i32,
+    pub min_x: i32,
+    pub max_x: i32,
 }
 
 impl Default for DrawState {
@@ -40,6 +54,8 @@ impl Default for DrawState {
             max_column_widths: Vec::new(),
             column_widths: Vec::new(),
             center_table: false,
+            row_spans: Vec::new(),
+            spanning_cells: Vec::new(),
         }
     }
 }
@@ -70,6 +86,103 @@ pub struct StyleData {
     pub vertical_align: i32,
     pub list_style_type: Option<ListStyleType>,
     pub uri: Option<String>,
+    pub border: Border,
+    pub background_color: Option<Color>,
+    pub text_decoration: TextDecoration,
+    pub text_transform: TextTransform,
+    pub direction: Direction,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Direction {
+    Ltr,
+    Rtl,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum WritingMode {
+    HorizontalTb,
+    VerticalRl,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum BorderStyle {
+    None,
+    Solid,
+    Dashed,
+    Dotted,
+    Double,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct BorderSide {
+    pub width: i32,
+    pub style: BorderStyle,
+    pub color: Color,
+}
+
+impl Default for BorderSide {
+    fn default() -> Self {
+        BorderSide {
+            width: 0,
+            style: BorderStyle::None,
+            color: BLACK,
+        }
+    }
+}
+
+impl BorderSide {
+    #[inline]
+    pub fn is_visible(&self) -> bool {
+        self.width > 0 && self.style != BorderStyle::None
+    }
+}
+
+#[derive(Debug, Copy, Clone, Default)]
+pub struct Border {
+    pub top: BorderSide,
+    pub right: BorderSide,
+    pub bottom: BorderSide,
+    pub left: BorderSide,
+}
+
+impl Border {
+    pub fn is_visible(&self) -> bool {
+        self.top.is_visible() || self.right.is_visible() ||
+        self.bottom.is_visible() || self.left.is_visible()
+    }
+
+    // The space taken by the visible sides.
+    pub fn widths(&self) -> Edge {
+        let width = |side: &BorderSide| if side.is_visible() { side.width } else { 0 };
+        Edge {
+            top: width(&self.top),
+            right: width(&self.right),
+            bottom: width(&self.bottom),
+            left: width(&self.left),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct TextDecoration {
+    pub underline: bool,
+    pub overline: bool,
+    pub line_through: bool,
+}
+
+impl TextDecoration {
+    pub fn is_none(&self) -> bool {
+        !(self.underline || self.overline || self.line_through)
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum TextTransform {
+    None,
+    Uppercase,
+    Lowercase,
+    Capitalize,
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -194,6 +307,11 @@ impl Default for StyleData {
             vertical_align: 0,
             list_style_type: None,
             uri: None,
+            border: Border::default(),
+            background_color: None,
+            text_decoration: TextDecoration::default(),
+            text_transform: TextTransform::None,
+            direction: Direction::Ltr,
         }
     }
 }
@@ -204,6 +322,9 @@ pub enum InlineMaterial {
     Image(ImageMaterial),
     Glue(GlueMaterial),
     Penalty(PenaltyMaterial),
+    // A ruby base and its annotation.
+    Ruby(TextMaterial, Box<TextMaterial>),
+    Math(MathMaterial),
     Box(i32),
     LineBreak,
 }
@@ -212,6 +333,8 @@ impl InlineMaterial {
     pub fn offset(&self) -> Option<usize> {
         match self {
             InlineMaterial::Text(TextMaterial { offset, .. }) |
+            InlineMaterial::Ruby(TextMaterial { offset, .. }, _) |
+            InlineMaterial::Math(MathMaterial { offset, .. }) |
             InlineMaterial::Image(ImageMaterial { offset, .. }) => Some(*offset),
             _ => None,
         }
@@ -219,7 +342,8 @@ impl InlineMaterial {
 
     pub fn text(&self) -> Option<&str> {
         match self {
-            InlineMaterial::Text(TextMaterial { ref text, .. }) => Some(text),
+            InlineMaterial::Text(TextMaterial { ref text, .. }) |
+            InlineMaterial::Ruby(TextMaterial { ref text, .. }, _) => Some(text),
             _ => None,
         }
     }
@@ -239,6 +363,15 @@ pub struct ImageMaterial {
     pub style: StyleData,
 }
 
+#[derive(Debug, Clone)]
+pub struct MathMaterial {
+    pub offset: usize,
+    pub node: MathNode,
+    // Whether the formula is set on its own line.
+    pub display: bool,
+    pub style: StyleData,
+}
+
 #[derive(Debug, Clone)]
 pub struct GlueMaterial {
     pub width: i32,
@@ -260,15 +393,17 @@ pub enum FontKind {
     Monospace,
     Cursive,
     Fantasy,
+    // An index in the embedded font families.
+    Embedded(usize),
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub enum FontStyle {
     Normal,
     Italic,
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub enum FontWeight {
     Normal,
     Bold,
@@ -280,10 +415,34 @@ pub struct Fonts {
     pub monospace: FontFamily,
     pub cursive: Font,
     pub fantasy: Font,
+    pub embedded: Vec<EmbeddedFamily>,
+    // Whether the text is shaped for a vertical writing mode.
+    pub vertical: bool,
+}
+
+// A font family declared by the document through `@font-face` rules.
+pub struct EmbeddedFamily {
+    pub name: String,
+    pub faces: Vec<EmbeddedFace>,
+}
+
+pub struct EmbeddedFace {
+    pub style: FontStyle,
+    pub weight: FontWeight,
+    // The font references the data: it must be dropped first.
+    pub font: Font,
+    pub data: Vec<u8>,
 }
 
 impl Fonts {
     pub fn get_mut(&mut self, font_kind: FontKind, font_style: FontStyle, font_weight: FontWeight) -> &mut Font {
+        let vertical = self.vertical;
+        let font = self.font_mut(font_kind, font_style, font_weight);
+        font.set_vertical(vertical);
+        font
+    }
+
+    fn font_mut(&mut self, font_kind: FontKind, font_style: FontStyle, font_weight: FontWeight) -> &mut Font {
         match font_kind {
             FontKind::Serif => {
                 match (font_style, font_weight) {
@@ -311,6 +470,16 @@ impl Fonts {
             },
             FontKind::Cursive => &mut self.cursive,
             FontKind::Fantasy => &mut self.fantasy,
+            FontKind::Embedded(index) => {
+                let faces = self.embedded.get(index).map(|family| &family.faces[..]).unwrap_or_default();
+                // Prefer the requested style over the requested weight.
+                match faces.iter().enumerate()
+                           .max_by_key(|(_, face)| 2 * (face.style == font_style) as u8 + (face.weight == font_weight) as u8)
+                           .map(|(i, _)| i) {
+                    Some(i) => &mut self.embedded[index].faces[i].font,
+                    None => self.font_mut(FontKind::Serif, font_style, font_weight),
+                }
+            },
         }
     }
 }
@@ -318,6 +487,8 @@ impl Fonts {
 #[derive(Debug, Clone)]
 pub enum ParagraphElement {
     Text(TextElement),
+    Ruby(RubyElement),
+    Math(MathElement),
     Image(ImageElement),
     Nothing,
 }
@@ -336,6 +507,27 @@ pub struct TextElement {
     pub letter_spacing: i32,
     pub vertical_align: i32,
     pub color: Color,
+    pub text_decoration: TextDecoration,
+    pub uri: Option<String>,
+}
+
+// The base and the annotation are centered within the box.
+#[derive(Debug, Clone)]
+pub struct RubyElement {
+    pub base: TextElement,
+    pub annotation: Box<TextElement>,
+    // The distance between the baselines of the base and of the annotation.
+    pub shift: i32,
+}
+
+#[derive(Debug, Clone)]
+pub struct MathElement {
+    pub offset: usize,
+    pub math_box: MathBox,
+    pub display: bool,
+    pub font_kind: FontKind,
+    pub vertical_align: i32,
+    pub color: Color,
     pub uri: Option<String>,
 }
 
@@ -358,6 +550,9 @@ pub enum DrawCommand {
     Text(TextCommand),
     ExtraText(TextCommand),
     Image(ImageCommand),
+    Box(BoxCommand),
+    Rule(RuleCommand),
+    Table(TableCommand),
     Marker(usize),
 }
 
@@ -386,12 +581,41 @@ pub struct ImageCommand {
     pub rect: Rectangle,
 }
 
+// The background and the borders of a block, on a given page.
+#[derive(Debug, Clone)]
+pub struct BoxCommand {
+    pub offset: usize,
+    pub rect: Rectangle,
+    pub background: Option<Color>,
+    pub border: Border,
+}
+
+// A horizontal line: underlines, overlines and strikethroughs.
+#[derive(Debug, Clone)]
+pub struct RuleCommand {
+    pub offset: usize,
+    pub position: Point,
+    pub width: i32,
+    pub thickness: i32,
+    pub color: Color,
+}
+
+// The corner of a table too wide for the page, that opens it in a viewer.
+#[derive(Debug, Clone)]
+pub struct TableCommand {
+    pub offset: usize,
+    pub rect: Rectangle,
+}
+
 impl DrawCommand {
     pub fn offset(&self) -> usize {
         match *self {
             DrawCommand::Text(TextCommand { offset, .. }) => offset,
             DrawCommand::ExtraText(TextCommand { offset, .. }) => offset,
             DrawCommand::Image(ImageCommand { offset, .. }) => offset,
+            DrawCommand::Box(BoxCommand { offset, .. }) => offset,
+            DrawCommand::Rule(RuleCommand { offset, .. }) => offset,
+            DrawCommand::Table(TableCommand { offset, .. }) => offset,
             DrawCommand::Marker(offset) => offset,
         }
     }
@@ -410,6 +634,16 @@ impl DrawCommand {
             DrawCommand::Text(TextCommand { ref mut position, .. }) => Some(position),
             DrawCommand::ExtraText(TextCommand { ref mut position, .. }) => Some(position),
             DrawCommand::Image(ImageCommand { ref mut position, .. }) => Some(position),
+            DrawCommand::Rule(RuleCommand { ref mut position, .. }) => Some(position),
+            _ => None,
+        }
+    }
+
+    pub fn rect_mut(&mut self) -> Option<&mut Rectangle> {
+        match *self {
+            DrawCommand::Text(TextCommand { ref mut rect, .. }) => Some(rect),
+            DrawCommand::ExtraText(TextCommand { ref mut rect, .. }) => Some(rect),
+            DrawCommand::Image(ImageCommand { ref mut rect, .. }) => Some(rect),
             _ => None,
         }
     }
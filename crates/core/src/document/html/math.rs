@@ -0,0 +1,589 @@
+use crate::font::RenderPlan;
+use crate::geom::{Point, Rectangle};
+use crate::helpers::decode_entities;
+use super::dom::NodeRef;
+use super::layout::{FontStyle, FontWeight};
+
+// The size of the scripts relative to their base.
+const SCRIPT_RATIO: f32 = 0.71;
+// The scripts of scripts of scripts aren't smaller.
+const MAX_SCRIPT_LEVEL: u8 = 2;
+// The extents of the tokens and the height of the math axis, in ems.
+const ASCENT: f32 = 0.75;
+const DESCENT: f32 = 0.25;
+const AXIS: f32 = 0.25;
+const FENCES: &str = "()[]{}|‖⌈⌉⌊⌋⟨⟩〈〉";
+const PUNCTUATION: &str = ",;";
+// Operators whose limits are set below and above in display style.
+const LIMITS_OPERATORS: &str = "∑∏∐⋃⋂⨀⨁⨂";
+const LARGE_OPERATORS: &str = "∑∏∐⋃⋂⨀⨁⨂∫∬∭∮";
+const LIMITS_WORDS: [&str; 6] = ["lim", "max", "min", "sup", "inf", "det"];
+const INVISIBLE_OPERATORS: &str = "\u{200B}\u{2061}\u{2062}\u{2063}\u{2064}";
+
+#[derive(Debug, Clone)]
+pub enum MathNode {
+    Token(Token),
+    Row(Vec<MathNode>),
+    // The numerator, the denominator and whether the bar is drawn.
+    Fraction(Box<MathNode>, Box<MathNode>, bool),
+    // The base, the subscript and the superscript.
+    Scripts(Box<MathNode>, Option<Box<MathNode>>, Option<Box<MathNode>>),
+    // The base, the underscript and the overscript.
+    UnderOver(Box<MathNode>, Option<Box<MathNode>>, Option<Box<MathNode>>),
+    // The radicand and the index.
+    Root(Box<MathNode>, Option<Box<MathNode>>),
+    Table(Vec<Vec<MathNode>>),
+    // A width in ems.
+    Space(f32),
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum TokenKind {
+    Identifier,
+    Number,
+    Operator,
+    Text,
+}
+
+#[derive(Debug, Clone)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub text: String,
+    pub style: FontStyle,
+    pub weight: FontWeight,
+}
+
+// The positions are relative to the origin of the enclosing box,
+// which is on its baseline.
+#[derive(Debug, Clone)]
+pub enum MathItem {
+    Glyphs {
+        position: Point,
+        rect: Rectangle,
+        text: String,
+        plan: RenderPlan,
+        style: FontStyle,
+        weight: FontWeight,
+        size: u32,
+    },
+    Rule {
+        position: Point,
+        width: i32,
+        thickness: i32,
+    },
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct MathBox {
+    pub width: i32,
+    pub ascent: i32,
+    pub descent: i32,
+    pub items: Vec<MathItem>,
+}
+
+// Measures the tokens. The sizes are in 64ths of a point.
+pub trait MathFonts {
+    fn plan(&mut self, text: &str, style: FontStyle, weight: FontWeight, size: u32) -> RenderPlan;
+    // The number of pixels per em.
+    fn em(&mut self, size: u32) -> i32;
+}
+
+impl MathItem {
+    fn translate(self, delta: Point) -> MathItem {
+        match self {
+            MathItem::Glyphs { position, rect, text, plan, style, weight, size } => {
+                MathItem::Glyphs { position: position + delta, rect: rect + delta, text, plan, style, weight, size }
+            },
+            MathItem::Rule { position, width, thickness } => {
+                MathItem::Rule { position: position + delta, width, thickness }
+            },
+        }
+    }
+}
+
+impl MathBox {
+    fn place(&mut self, other: MathBox, origin: Point) {
+        self.items.extend(other.items.into_iter().map(|item| item.translate(origin)));
+    }
+}
+
+// Returns `None` when the expression contains unsupported elements.
+pub fn parse_math(node: NodeRef) -> Option<MathNode> {
+    let name = node.tag_name()?;
+    // The annotations are alternative representations.
+    if name == "semantics" {
+        return node.children().find(|child| child.is_element()).and_then(parse_math);
+    }
+    let mut children = node.children().filter(|child| child.is_element())
+                           .map(parse_math).collect::<Option<Vec<MathNode>>>()?;
+
+    match name {
+        "math" | "mrow" | "mstyle" | "mpadded" | "merror" | "menclose" | "mtd" => {
+            Some(row(children))
+        },
+        "mphantom" => Some(MathNode::Row(Vec::new())),
+        "mi" | "mn" | "mo" | "mtext" | "ms" => {
+            let text = decode_entities(&node.text()).split_whitespace().collect::<Vec<&str>>().join(" ");
+            if text.chars().all(|c| INVISIBLE_OPERATORS.contains(c)) {
+                return Some(MathNode::Space(0.0));
+            }
+            let kind = match name {
+                "mi" => TokenKind::Identifier,
+                "mn" => TokenKind::Number,
+                "mo" => TokenKind::Operator,
+                _ => TokenKind::Text,
+            };
+            let italic = kind == TokenKind::Identifier && text.chars().count() == 1;
+            let (style, weight) = match node.attribute("mathvariant") {
+                Some("bold") => (FontStyle::Normal, FontWeight::Bold),
+                Some("italic") => (FontStyle::Italic, FontWeight::Normal),
+                Some("bold-italic") => (FontStyle::Italic, FontWeight::Bold),
+                Some(_) => (FontStyle::Normal, FontWeight::Normal),
+                None if italic => (FontStyle::Italic, FontWeight::Normal),
+                None => (FontStyle::Normal, FontWeight::Normal),
+            };
+            Some(MathNode::Token(Token { kind, text, style, weight }))
+        },
+        "mspace" => {
+            let width = node.attribute("width")
+                            .and_then(|value| value.trim().trim_end_matches("em").parse::<f32>().ok())
+                            .unwrap_or(0.0);
+            Some(MathNode::Space(width))
+        },
+        "mfrac" if children.len() == 2 => {
+            let bar = node.attribute("linethickness")
+                          .map_or(true, |value| value.trim_start_matches(['0', '.'])
+                                                     .starts_with(|c: char| c.is_ascii_digit() || c.is_alphabetic()));
+            let denominator = children.pop()?;
+            let numerator = children.pop()?;
+            Some(MathNode::Fraction(Box::new(numerator), Box::new(denominator), bar))
+        },
+        "msub" | "msup" | "munder" | "mover" if children.len() == 2 => {
+            let script = children.pop().map(Box::new);
+            let base = Box::new(children.pop()?);
+            Some(match name {
+                "msub" => MathNode::Scripts(base, script, None),
+                "msup" => MathNode::Scripts(base, None, script),
+                "munder" => MathNode::UnderOver(base, script, None),
+                _ => MathNode::UnderOver(base, None, script),
+            })
+        },
+        "msubsup" | "munderover" if children.len() == 3 => {
+            let second = children.pop().map(Box::new);
+            let first = children.pop().map(Box::new);
+            let base = Box::new(children.pop()?);
+            if name == "msubsup" {
+                Some(MathNode::Scripts(base, first, second))
+            } else {
+                Some(MathNode::UnderOver(base, first, second))
+            }
+        },
+        "msqrt" => Some(MathNode::Root(Box::new(row(children)), None)),
+        "mroot" if children.len() == 2 => {
+            let index = children.pop().map(Box::new);
+            Some(MathNode::Root(Box::new(children.pop()?), index))
+        },
+        "mtable" => {
+            let rows = children.into_iter().map(|child| match child {
+                MathNode::Row(cells) => cells,
+                cell => vec![cell],
+            }).collect();
+            Some(MathNode::Table(rows))
+        },
+        // The label of a labeled row is dropped.
+        "mtr" => Some(MathNode::Row(children)),
+        "mlabeledtr" => Some(MathNode::Row(children.into_iter().skip(1).collect())),
+        "mfenced" => {
+            let open = node.attribute("open").unwrap_or("(");
+            let close = node.attribute("close").unwrap_or(")");
+            let separators = node.attribute("separators").unwrap_or(",")
+                                 .chars().filter(|c| !c.is_whitespace()).collect::<Vec<char>>();
+            let fence = |text: &str| MathNode::Token(Token {
+                kind: TokenKind::Operator,
+                text: text.to_string(),
+                style: FontStyle::Normal,
+                weight: FontWeight::Normal,
+            });
+            let mut nodes = vec![fence(open)];
+            let count = children.len();
+            for (index, child) in children.into_iter().enumerate() {
+                nodes.push(child);
+                if index + 1 < count && !separators.is_empty() {
+                    let separator = separators[index.min(separators.len() - 1)];
+                    nodes.push(fence(&separator.to_string()));
+                }
+            }
+            nodes.push(fence(close));
+            Some(MathNode::Row(nodes))
+        },
+        _ => None,
+    }
+}
+
+fn row(mut children: Vec<MathNode>) -> MathNode {
+    if children.len() == 1 {
+        children.pop().unwrap()
+    } else {
+        MathNode::Row(children)
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Context {
+    size: u32,
+    display: bool,
+    level: u8,
+}
+
+impl Context {
+    fn script(self) -> Context {
+        let size = if self.level < MAX_SCRIPT_LEVEL {
+            (self.size as f32 * SCRIPT_RATIO) as u32
+        } else {
+            self.size
+        };
+        Context { size, display: false, level: self.level + 1 }
+    }
+
+    fn text(self) -> Context {
+        Context { display: false, .. self }
+    }
+}
+
+pub fn layout_math(node: &MathNode, size: u32, display: bool, fonts: &mut dyn MathFonts) -> MathBox {
+    layout(node, Context { size, display, level: 0 }, fonts)
+}
+
+fn layout(node: &MathNode, ctx: Context, fonts: &mut dyn MathFonts) -> MathBox {
+    let em = fonts.em(ctx.size);
+    let axis = (AXIS * em as f32).round() as i32;
+    let thickness = (em / 18).max(1);
+    let gap = (em / 8).max(thickness);
+
+    match node {
+        MathNode::Token(token) => layout_token(token, ctx, fonts),
+        MathNode::Space(width) => MathBox { width: (width * em as f32).round() as i32, .. Default::default() },
+        MathNode::Row(children) => layout_row(children, ctx, fonts),
+        MathNode::Fraction(numerator, denominator, bar) => {
+            let inner = if ctx.display { ctx.text() } else { ctx.script() };
+            let numerator = layout(numerator, inner, fonts);
+            let denominator = layout(denominator, inner, fonts);
+            let padding = em / 10;
+            let width = numerator.width.max(denominator.width) + 2 * padding;
+            let numerator_y = -(axis + thickness / 2 + gap + numerator.descent);
+            let denominator_y = -axis + thickness / 2 + gap + denominator.ascent;
+            let mut math_box = MathBox {
+                width,
+                ascent: -numerator_y + numerator.ascent,
+                descent: denominator_y + denominator.descent,
+                items: Vec::new(),
+            };
+            if *bar {
+                math_box.items.push(MathItem::Rule {
+                    position: pt!(padding / 2, -axis - thickness / 2),
+                    width: width - padding,
+                    thickness,
+                });
+            }
+            let (nw, dw) = (numerator.width, denominator.width);
+            math_box.place(numerator, pt!((width - nw) / 2, numerator_y));
+            math_box.place(denominator, pt!((width - dw) / 2, denominator_y));
+            math_box
+        },
+        MathNode::Scripts(base, sub, sup) if ctx.display && has_limits(base) => {
+            layout_under_over(base, sub.as_deref(), sup.as_deref(), ctx, fonts)
+        },
+        MathNode::Scripts(base, sub, sup) => {
+            let base = layout(base, ctx, fonts);
+            let sub = sub.as_ref().map(|node| layout(node, ctx.script(), fonts));
+            let sup = sup.as_ref().map(|node| layout(node, ctx.script(), fonts));
+            let mut sup_shift = (base.ascent - em / 3).max(2 * em / 5);
+            let mut sub_shift = (base.descent - em / 20).max(em / 5);
+            if let (Some(sub), Some(sup)) = (sub.as_ref(), sup.as_ref()) {
+                let space = (sup_shift - sup.descent) - (sub.ascent - sub_shift);
+                if space < 2 * gap {
+                    sub_shift += (2 * gap - space + 1) / 2;
+                    sup_shift += (2 * gap - space) / 2;
+                }
+            }
+            let x = base.width + em / 20;
+            let scripts_width = sub.as_ref().map_or(0, |b| b.width).max(sup.as_ref().map_or(0, |b| b.width));
+            let mut math_box = MathBox {
+                width: x + scripts_width,
+                ascent: base.ascent,
+                descent: base.descent,
+                items: Vec::new(),
+            };
+            math_box.place(base, Point::default());
+            if let Some(sub) = sub {
+                math_box.descent = math_box.descent.max(sub_shift + sub.descent);
+                math_box.place(sub, pt!(x, sub_shift));
+            }
+            if let Some(sup) = sup {
+                math_box.ascent = math_box.ascent.max(sup_shift + sup.ascent);
+                math_box.place(sup, pt!(x, -sup_shift));
+            }
+            math_box
+        },
+        MathNode::UnderOver(base, under, over) => {
+            layout_under_over(base, under.as_deref(), over.as_deref(), ctx, fonts)
+        },
+        MathNode::Root(radicand, index) => {
+            let radicand = layout(radicand, ctx.text(), fonts);
+            let height = radicand.ascent + radicand.descent + gap + thickness;
+            let ratio = (height as f32 / em as f32).max(1.0);
+            let sign_ctx = Context { size: (ctx.size as f32 * ratio) as u32, .. ctx };
+            let sign = layout_token(&Token {
+                kind: TokenKind::Operator,
+                text: "√".to_string(),
+                style: FontStyle::Normal,
+                weight: FontWeight::Normal,
+            }, sign_ctx, fonts);
+            let top = -(radicand.ascent + gap + thickness);
+            let index = index.as_ref().map(|node| layout(node, ctx.script().script(), fonts));
+            let sign_x = index.as_ref().map_or(0, |b| (b.width - sign.width / 2).max(0));
+            let radicand_x = sign_x + sign.width + em / 20;
+            let mut math_box = MathBox {
+                width: radicand_x + radicand.width + em / 10,
+                ascent: -top,
+                descent: radicand.descent,
+                items: Vec::new(),
+            };
+            math_box.items.push(MathItem::Rule {
+                position: pt!(sign_x + sign.width, top),
+                width: math_box.width - sign_x - sign.width,
+                thickness,
+            });
+            let sign_y = top + sign.ascent;
+            math_box.place(sign, pt!(sign_x, sign_y));
+            if let Some(index) = index {
+                let index_y = top + height / 2 - index.descent;
+                math_box.ascent = math_box.ascent.max(index.ascent - index_y);
+                math_box.place(index, pt!(0, index_y));
+            }
+            math_box.place(radicand, pt!(radicand_x, 0));
+            math_box
+        },
+        MathNode::Table(rows) => {
+            let cells = rows.iter().map(|cells| {
+                cells.iter().map(|cell| layout(cell, ctx.text(), fonts)).collect::<Vec<MathBox>>()
+            }).collect::<Vec<Vec<MathBox>>>();
+            let columns_count = cells.iter().map(Vec::len).max().unwrap_or(0);
+            let mut widths = vec![0; columns_count];
+            for row in &cells {
+                for (j, cell) in row.iter().enumerate() {
+                    widths[j] = widths[j].max(cell.width);
+                }
+            }
+            let column_gap = 4 * em / 5;
+            let row_gap = em / 4;
+            let extents = cells.iter().map(|row| {
+                (row.iter().map(|cell| cell.ascent).max().unwrap_or(0),
+                 row.iter().map(|cell| cell.descent).max().unwrap_or(0))
+            }).collect::<Vec<(i32, i32)>>();
+            let height = extents.iter().map(|(a, d)| a + d).sum::<i32>() +
+                         row_gap * (extents.len() as i32 - 1).max(0);
+            let width = widths.iter().sum::<i32>() + column_gap * (columns_count as i32 - 1).max(0);
+            let mut math_box = MathBox {
+                width,
+                ascent: axis + height / 2,
+                descent: height - height / 2 - axis,
+                items: Vec::new(),
+            };
+            let mut y = -math_box.ascent;
+            for (row, (ascent, descent)) in cells.into_iter().zip(extents) {
+                let mut x = 0;
+                for (cell, column_width) in row.into_iter().zip(widths.iter()) {
+                    let cell_x = x + (column_width - cell.width) / 2;
+                    math_box.place(cell, pt!(cell_x, y + ascent));
+                    x += column_width + column_gap;
+                }
+                y += ascent + descent + row_gap;
+            }
+            math_box
+        },
+    }
+}
+
+fn layout_token(token: &Token, ctx: Context, fonts: &mut dyn MathFonts) -> MathBox {
+    let large = ctx.display && token.kind == TokenKind::Operator &&
+                token.text.chars().all(|c| LARGE_OPERATORS.contains(c));
+    let size = if large { (ctx.size as f32 * 1.4) as u32 } else { ctx.size };
+    let em = fonts.em(size);
+    let plan = fonts.plan(&token.text, token.style, token.weight, size);
+    let ascent = (ASCENT * em as f32).round() as i32;
+    let descent = (DESCENT * em as f32).round() as i32;
+    // Large operators are centered on the axis of the normal text.
+    let shift = if large {
+        (AXIS * (em - fonts.em(ctx.size)) as f32).round() as i32
+    } else {
+        0
+    };
+    let rect = rect![0, shift - ascent, plan.width, shift + descent];
+    MathBox {
+        width: plan.width,
+        ascent: ascent - shift,
+        descent: descent + shift,
+        items: vec![MathItem::Glyphs {
+            position: pt!(0, shift),
+            rect,
+            text: token.text.clone(),
+            plan,
+            style: token.style,
+            weight: token.weight,
+            size,
+        }],
+    }
+}
+
+fn is_fence(node: &MathNode) -> bool {
+    matches!(node, MathNode::Token(Token { kind: TokenKind::Operator, text, .. })
+                   if text.chars().count() == 1 && text.chars().all(|c| FENCES.contains(c)))
+}
+
+fn has_limits(node: &MathNode) -> bool {
+    matches!(node, MathNode::Token(Token { kind: TokenKind::Operator | TokenKind::Identifier, text, .. })
+                   if text.chars().all(|c| LIMITS_OPERATORS.contains(c)) || LIMITS_WORDS.contains(&text.as_str()))
+}
+
+fn layout_row(children: &[MathNode], ctx: Context, fonts: &mut dyn MathFonts) -> MathBox {
+    let em = fonts.em(ctx.size);
+    let axis = (AXIS * em as f32).round() as i32;
+    let space = if ctx.level == 0 { 2 * em / 9 } else { 0 };
+
+    let mut boxes = children.iter().map(|child| {
+        if is_fence(child) {
+            None
+        } else {
+            Some(layout(child, ctx, fonts))
+        }
+    }).collect::<Vec<Option<MathBox>>>();
+
+    // The fences stretch to the height of the content.
+    let ascent = boxes.iter().flatten().map(|b| b.ascent).max().unwrap_or(0);
+    let descent = boxes.iter().flatten().map(|b| b.descent).max().unwrap_or(0);
+    let half_height = (ascent - axis).max(descent + axis);
+    let ratio = (2.0 * half_height as f32 / em as f32).clamp(1.0, 4.0);
+
+    for (child, math_box) in children.iter().zip(boxes.iter_mut()) {
+        if math_box.is_none() {
+            let size = (ctx.size as f32 * ratio) as u32;
+            let mut fence = layout(child, Context { size, .. ctx }, fonts);
+            let shift = (AXIS * (fonts.em(size) - em) as f32).round() as i32;
+            fence.items = fence.items.into_iter().map(|item| item.translate(pt!(0, shift))).collect();
+            fence.ascent -= shift;
+            fence.descent += shift;
+            *math_box = Some(fence);
+        }
+    }
+
+    let mut math_box = MathBox::default();
+    let mut previous: Option<&MathNode> = None;
+
+    for (child, child_box) in children.iter().zip(boxes.into_iter().flatten()) {
+        let (before, after) = match child {
+            MathNode::Token(Token { kind: TokenKind::Operator, text, .. }) if !is_fence(child) => {
+                if PUNCTUATION.contains(text.as_str()) {
+                    (0, space)
+                } else if previous.map_or(true, |node| matches!(node, MathNode::Token(Token { kind: TokenKind::Operator, .. }))) {
+                    // Unary operators.
+                    (0, 0)
+                } else {
+                    (space, space)
+                }
+            },
+            _ => (0, 0),
+        };
+        math_box.width += before;
+        math_box.ascent = math_box.ascent.max(child_box.ascent);
+        math_box.descent = math_box.descent.max(child_box.descent);
+        let width = child_box.width;
+        math_box.place(child_box, pt!(math_box.width, 0));
+        math_box.width += width + after;
+        previous = Some(child);
+    }
+
+    math_box
+}
+
+fn layout_under_over(base: &MathNode, under: Option<&MathNode>, over: Option<&MathNode>, ctx: Context, fonts: &mut dyn MathFonts) -> MathBox {
+    let em = fonts.em(ctx.size);
+    let gap = em / 10;
+    let base = layout(base, ctx, fonts);
+    let under = under.map(|node| layout(node, ctx.script(), fonts));
+    let over = over.map(|node| layout(node, ctx.script(), fonts));
+    let width = base.width.max(under.as_ref().map_or(0, |b| b.width))
+                          .max(over.as_ref().map_or(0, |b| b.width));
+    let mut math_box = MathBox {
+        width,
+        ascent: base.ascent,
+        descent: base.descent,
+        items: Vec::new(),
+    };
+    if let Some(under) = under {
+        let y = base.descent + gap + under.ascent;
+        math_box.descent = y + under.descent;
+        let w = under.width;
+        math_box.place(under, pt!((width - w) / 2, y));
+    }
+    if let Some(over) = over {
+        let y = -(base.ascent + gap + over.descent);
+        math_box.ascent = over.ascent - y;
+        let w = over.width;
+        math_box.place(over, pt!((width - w) / 2, y));
+    }
+    let w = base.width;
+    math_box.place(base, pt!((width - w) / 2, 0));
+    math_box
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::xml::XmlParser;
+
+    // Every character is half an em wide.
+    struct Monospace;
+
+    impl MathFonts for Monospace {
+        fn plan(&mut self, text: &str, _style: FontStyle, _weight: FontWeight, size: u32) -> RenderPlan {
+            let mut plan = RenderPlan::default();
+            plan.width = text.chars().count() as i32 * self.em(size) / 2;
+            plan
+        }
+
+        fn em(&mut self, size: u32) -> i32 {
+            (size / 64) as i32
+        }
+    }
+
+    fn parse(text: &str) -> Option<MathNode> {
+        let xml = XmlParser::new(text).parse();
+        let node = xml.root().find("math").unwrap();
+        parse_math(node)
+    }
+
+    #[test]
+    fn test_parse_math() {
+        let math = parse("<math><mfrac><mi>x</mi><mn>2</mn></mfrac><mo>+</mo><msqrt><mi>y</mi></msqrt></math>");
+        assert!(matches!(math, Some(MathNode::Row(ref children)) if children.len() == 3));
+        assert!(parse("<math><mfrac><mi>x</mi></mfrac></math>").is_none());
+        assert!(parse("<math><mglyph/></math>").is_none());
+        let math = parse("<math><semantics><mi>x</mi><annotation>x</annotation></semantics></math>");
+        assert!(matches!(math, Some(MathNode::Token(Token { style: FontStyle::Italic, .. }))));
+    }
+
+    #[test]
+    fn test_layout_math() {
+        let math = parse("<math><mi>a</mi><mo>=</mo><mfrac><mn>1</mn><mn>2</mn></mfrac></math>").unwrap();
+        let math_box = layout_math(&math, 20 * 64, true, &mut Monospace);
+        // a, =, the fraction and the spaces around the equal sign.
+        assert_eq!(math_box.width, 10 + 4 + 10 + 4 + 14);
+        assert!(math_box.ascent > 15 && math_box.descent > 5);
+        assert_eq!(math_box.items.iter().filter(|item| matches!(item, MathItem::Rule { .. })).count(), 1);
+        let math = parse("<math><msup><mi>x</mi><mn>2</mn></msup></math>").unwrap();
+        let math_box = layout_math(&math, 20 * 64, false, &mut Monospace);
+        assert_eq!(math_box.width, 10 + 1 + 7);
+        assert!(math_box.ascent > 15);
+    }
+}
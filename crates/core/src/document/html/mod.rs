@@ -5,24 +5,35 @@ pub mod parse;
 pub mod style;
 pub mod layout;
 pub mod engine;
+pub mod text;
+pub mod bidi;
+pub mod cjk;
+pub mod math;
+pub mod svg;
 
+use std::iter;
 use std::io::{Read, Write};
 use std::fs::{self, File};
 use std::path::{Path, PathBuf};
 use fxhash::FxHashMap;
-use anyhow::Error;
+use percent_encoding::percent_decode_str;
+use anyhow::{Error, format_err};
 use crate::framebuffer::Pixmap;
 use crate::helpers::{Normalize, decode_entities};
-use crate::document::{Document, Location, TextLocation, TocEntry, BoundedText};
+use crate::document::{Document, Location, TextLocation, TocEntry, BoundedText, TABLE_URI};
+use crate::document::{chapter, chapter_relative};
 use crate::unit::pt_to_px;
 use crate::geom::{Boundary, Edge, CycleDir};
 use self::dom::{XmlTree, NodeRef};
 use self::layout::{RootData, StyleData, DrawState, LoopContext};
-use self::layout::{DrawCommand, TextCommand, ImageCommand, TextAlign};
-use self::engine::{Page, Engine, ResourceFetcher};
+use self::layout::{DrawCommand, TextCommand, ImageCommand, TableCommand, TextAlign, Direction};
+use self::engine::{Page, Engine, ResourceFetcher, writing_mode};
 use self::style::StyleSheet;
 use self::css::CssParser;
 use self::xml::XmlParser;
+use self::text::{decode_text, text_to_html, markdown_to_html};
+use self::bidi::language_direction;
+use super::fb2::{read_fb2, fb2_to_html};
 
 const VIEWER_STYLESHEET: &str = "css/html.css";
 const USER_STYLESHEET: &str = "css/html-user.css";
@@ -32,9 +43,10 @@ type UriCache = FxHashMap<String, usize>;
 pub struct HtmlDocument {
     text: String,
     content: XmlTree,
+    toc: Vec<TocEntry>,
     engine: Engine,
     pages: Vec<Page>,
-    parent: PathBuf,
+    fetcher: Box<dyn ResourceFetcher>,
     size: usize,
     viewer_stylesheet: PathBuf,
     user_stylesheet: PathBuf,
@@ -50,6 +62,41 @@ impl ResourceFetcher for PathBuf {
     }
 }
 
+// The images of a fragment of a document, fetched beforehand under the names the engine asks for.
+pub struct FragmentResources(FxHashMap<String, Vec<u8>>);
+
+impl ResourceFetcher for FragmentResources {
+    fn fetch(&mut self, name: &str) -> Result<Vec<u8>, Error> {
+        self.0.get(name).cloned()
+            .ok_or_else(|| format_err!("can't find resource {}", name))
+    }
+}
+
+// `dir` is the directory of the file containing the fragment, relative to the root of the fetcher.
+pub fn fragment_resources(fragment: NodeRef, dir: &Path, fetcher: &mut dyn ResourceFetcher) -> FragmentResources {
+    let mut resources = FxHashMap::default();
+
+    for node in iter::once(fragment).chain(fragment.descendants()) {
+        let src = match node.tag_name() {
+            Some("img") => node.attribute("src"),
+            Some("image") => node.attribute("xlink:href").or_else(|| node.attribute("href")),
+            _ => None,
+        };
+        if let Some(src) = src.filter(|src| !src.starts_with("data:")) {
+            let src = percent_decode_str(&decode_entities(src)).decode_utf8_lossy().into_owned();
+            let key = Path::new(&src).normalize();
+            let path = dir.join(&src).normalize();
+            if let (Some(key), Some(path)) = (key.to_str(), path.to_str()) {
+                if let Ok(buf) = fetcher.fetch(path) {
+                    resources.insert(key.to_string(), buf);
+                }
+            }
+        }
+    }
+
+    FragmentResources(resources)
+}
+
 unsafe impl Send for HtmlDocument {}
 unsafe impl Sync for HtmlDocument {}
 
@@ -61,14 +108,16 @@ impl HtmlDocument {
         file.read_to_string(&mut text)?;
         let mut content = XmlParser::new(&text).parse();
         content.wrap_lost_inlines();
+        let toc = toc_from_headings(content.root());
         let parent = path.as_ref().parent().unwrap_or_else(|| Path::new(""));
 
         Ok(HtmlDocument {
             text,
             content,
+            toc,
             engine: Engine::new(),
             pages: Vec::new(),
-            parent: parent.to_path_buf(),
+            fetcher: Box::new(parent.to_path_buf()),
             size,
             viewer_stylesheet: PathBuf::from(VIEWER_STYLESHEET),
             user_stylesheet: PathBuf::from(USER_STYLESHEET),
@@ -80,13 +129,15 @@ impl HtmlDocument {
         let size = text.len();
         let mut content = XmlParser::new(text).parse();
         content.wrap_lost_inlines();
+        let toc = toc_from_headings(content.root());
 
         HtmlDocument {
             text: text.to_string(),
             content,
+            toc,
             engine: Engine::new(),
             pages: Vec::new(),
-            parent: PathBuf::default(),
+            fetcher: Box::new(PathBuf::default()),
             size,
             viewer_stylesheet: PathBuf::from(VIEWER_STYLESHEET),
             user_stylesheet: PathBuf::from(USER_STYLESHEET),
@@ -94,10 +145,60 @@ impl HtmlDocument {
         }
     }
 
+    // Plain text files are converted to HTML: the paragraphs are guessed
+    // and short uppercase lines become headings.
+    pub fn new_from_text<P: AsRef<Path>>(path: P) -> Result<HtmlDocument, Error> {
+        let bytes = fs::read(path.as_ref())?;
+        let text = decode_text(&bytes);
+        let html = text_to_html(&text, &file_stem(path.as_ref()));
+        Ok(HtmlDocument::new_from_conversion(text, &html, Box::new(parent_dir(path.as_ref()))))
+    }
+
+    pub fn new_from_markdown<P: AsRef<Path>>(path: P) -> Result<HtmlDocument, Error> {
+        let bytes = fs::read(path.as_ref())?;
+        let text = decode_text(&bytes);
+        let html = markdown_to_html(&text, &file_stem(path.as_ref()));
+        Ok(HtmlDocument::new_from_conversion(text, &html, Box::new(parent_dir(path.as_ref()))))
+    }
+
+    // The images embedded in FictionBook files are fetched from memory.
+    pub fn new_from_fb2<P: AsRef<Path>>(path: P) -> Result<HtmlDocument, Error> {
+        let text = read_fb2(path.as_ref())?;
+        let tree = XmlParser::new(&text).parse();
+        let (html, binaries) = fb2_to_html(&tree);
+        Ok(HtmlDocument::new_from_conversion(text, &html, Box::new(binaries)))
+    }
+
+    // The resources the content refers to are fetched with the given fetcher.
+    pub fn new_with_fetcher(text: &str, fetcher: Box<dyn ResourceFetcher>) -> HtmlDocument {
+        HtmlDocument::new_from_conversion(text.to_string(), text, fetcher)
+    }
+
+    // The original text is kept so that saving the document doesn't save the conversion.
+    fn new_from_conversion(text: String, html: &str, fetcher: Box<dyn ResourceFetcher>) -> HtmlDocument {
+        let mut content = XmlParser::new(html).parse();
+        content.wrap_lost_inlines();
+        let toc = toc_from_headings(content.root());
+
+        HtmlDocument {
+            size: html.len(),
+            text,
+            content,
+            toc,
+            engine: Engine::new(),
+            pages: Vec::new(),
+            fetcher,
+            viewer_stylesheet: PathBuf::from(VIEWER_STYLESHEET),
+            user_stylesheet: PathBuf::from(USER_STYLESHEET),
+            ignore_document_css: false,
+        }
+    }
+
     pub fn update(&mut self, text: &str) {
         self.size = text.len();
         self.content = XmlParser::new(text).parse();
         self.content.wrap_lost_inlines();
+        self.toc = toc_from_headings(self.content.root());
         self.text = text.to_string();
         self.pages.clear();
     }
@@ -181,9 +282,10 @@ impl HtmlDocument {
                     if child.tag_name() == Some("link") && child.attribute("rel") == Some("stylesheet") {
                         if let Some(href) = child.attribute("href") {
                             if let Some(name) = spine_dir.join(href).normalize().to_str() {
-                                if let Ok(buf) = self.parent.fetch(name) {
+                                if let Ok(buf) = self.fetcher.fetch(name) {
                                     if let Ok(text) = String::from_utf8(buf) {
                                         let mut css = CssParser::new(&text).parse();
+                                        css.resolve_font_sources(Path::new(name).parent().unwrap_or_else(|| Path::new("")));
                                         inner_css.append(&mut css, false);
                                     }
                                 }
@@ -196,9 +298,13 @@ impl HtmlDocument {
                 }
             }
 
+            self.engine.load_font_faces(&inner_css, self.fetcher.as_mut());
             stylesheet.append(&mut inner_css, true);
         }
 
+        stylesheet.retain_media(&self.engine.media_device());
+        self.engine.set_writing_mode(writing_mode(self.content.root(), &stylesheet));
+
         let mut pages = Vec::new();
 
         let mut rect = self.engine.rect();
@@ -209,8 +315,11 @@ impl HtmlDocument {
                            .and_then(|html| html.attribute("xml:lang"))
                            .map(String::from);
 
+        let direction = language.as_deref().map_or(Direction::Ltr, language_direction);
+
         let style = StyleData {
             language,
+            direction,
             font_size: self.engine.font_size,
             line_height: pt_to_px(self.engine.line_height * self.engine.font_size, self.engine.dpi).round() as i32,
             text_align: self.engine.text_align,
@@ -234,8 +343,9 @@ impl HtmlDocument {
 
         pages.push(Vec::new());
 
-        self.engine.build_display_list(self.content.root(), &style, &loop_context, &stylesheet, &root_data, &mut self.parent, &mut draw_state, &mut pages);
+        self.engine.build_display_list(self.content.root(), &style, &loop_context, &stylesheet, &root_data, self.fetcher.as_mut(), &mut draw_state, &mut pages);
 
+        self.engine.rotate_display_list(&mut pages);
         pages.retain(|page| !page.is_empty());
 
         if pages.is_empty() {
@@ -245,6 +355,12 @@ impl HtmlDocument {
         pages
     }
 
+    fn last_offset(&mut self, offset: usize) -> usize {
+        self.resolve_location(Location::Next(offset))
+            .map_or(self.size, |next_offset| next_offset.saturating_sub(1))
+            .max(offset)
+    }
+
     pub fn categories(&self) -> Option<String> {
         None
     }
@@ -276,15 +392,23 @@ impl Document for HtmlDocument {
     }
 
     fn toc(&mut self) -> Option<Vec<TocEntry>> {
-        None
+        if self.toc.is_empty() {
+            None
+        } else {
+            Some(self.toc.clone())
+        }
     }
 
-    fn chapter<'a>(&mut self, _offset: usize, _toc: &'a [TocEntry]) -> Option<(&'a TocEntry, f32)> {
-        None
+    // The headings are usually not at the start of a page: the offset of
+    // the last character of the page is used instead.
+    fn chapter<'a>(&mut self, offset: usize, toc: &'a [TocEntry]) -> Option<(&'a TocEntry, f32)> {
+        let last_offset = self.last_offset(offset);
+        chapter(last_offset, self.size, toc)
     }
 
-    fn chapter_relative<'a>(&mut self, _offset: usize, _dir: CycleDir, _toc: &'a [TocEntry]) -> Option<&'a TocEntry> {
-        None
+    fn chapter_relative<'a>(&mut self, offset: usize, dir: CycleDir, toc: &'a [TocEntry]) -> Option<&'a TocEntry> {
+        let last_offset = self.last_offset(offset);
+        chapter_relative(last_offset, dir, toc)
     }
 
     fn resolve_location(&mut self, loc: Location) -> Option<usize> {
@@ -367,6 +491,13 @@ impl Document for HtmlDocument {
                         location: TextLocation::Dynamic(*offset),
                     })
                 },
+                DrawCommand::Table(TableCommand { rect, offset }) => {
+                    Some(BoundedText {
+                        text: TABLE_URI.to_string(),
+                        rect: (*rect).into(),
+                        location: TextLocation::Dynamic(*offset),
+                    })
+                },
                 _ => None,
             }
         }).collect(), offset))
@@ -376,7 +507,7 @@ impl Document for HtmlDocument {
         let offset = self.resolve_location(loc)?;
         let page_index = self.page_index(offset)?;
         let page = self.pages[page_index].clone();
-        let pixmap = self.engine.render_page(&page, scale, samples, &mut self.parent)?;
+        let pixmap = self.engine.render_page(&page, scale, samples, self.fetcher.as_mut())?;
 
         Some((pixmap, offset))
     }
@@ -445,6 +576,13 @@ impl Document for HtmlDocument {
             .map_err(Into::into)
     }
 
+    fn table(&mut self, offset: usize) -> Option<(String, Box<dyn ResourceFetcher>)> {
+        let table = self.content.root().descendants()
+                        .find(|n| n.offset() == offset && n.tag_name() == Some("table"))?;
+        let resources = fragment_resources(table, Path::new(""), self.fetcher.as_mut());
+        Some((format!("<html><body>{}</body></html>", table.to_html()), Box::new(resources)))
+    }
+
     fn is_reflowable(&self) -> bool {
         true
     }
@@ -453,3 +591,51 @@ impl Document for HtmlDocument {
         true
     }
 }
+
+fn parent_dir(path: &Path) -> PathBuf {
+    path.parent().unwrap_or_else(|| Path::new("")).to_path_buf()
+}
+
+fn file_stem(path: &Path) -> String {
+    path.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default()
+}
+
+// Builds a table of contents from the h1-h6 elements.
+fn toc_from_headings(root: NodeRef) -> Vec<TocEntry> {
+    let headings = root.descendants().filter_map(|node| {
+        let level = match node.tag_name()? {
+            "h1" => 1, "h2" => 2, "h3" => 3,
+            "h4" => 4, "h5" => 5, "h6" => 6,
+            _ => return None,
+        };
+        let title = decode_entities(node.text().trim()).into_owned();
+        if title.is_empty() {
+            None
+        } else {
+            Some((level, title, node.offset()))
+        }
+    }).collect::<Vec<(u8, String, usize)>>();
+    let mut position = 0;
+    let mut index = 0;
+    nest_headings(&headings, 1, &mut position, &mut index)
+}
+
+fn nest_headings(headings: &[(u8, String, usize)], min_level: u8, position: &mut usize, index: &mut usize) -> Vec<TocEntry> {
+    let mut entries = Vec::new();
+    while let Some((level, title, offset)) = headings.get(*position) {
+        if *level < min_level {
+            break;
+        }
+        *position += 1;
+        let entry_index = *index;
+        *index += 1;
+        let children = nest_headings(headings, level + 1, position, index);
+        entries.push(TocEntry {
+            title: title.clone(),
+            location: Location::Exact(*offset),
+            index: entry_index,
+            children,
+        });
+    }
+    entries
+}
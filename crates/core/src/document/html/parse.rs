@@ -2,6 +2,7 @@ use fxhash::FxHashSet;
 use regex::Regex;
 use super::layout::{FontKind, FontStyle, FontWeight, WordSpacing};
 use super::layout::{TextAlign, Display, Float, ListStyleType};
+use super::layout::{BorderSide, BorderStyle, TextDecoration, TextTransform, Direction, WritingMode};
 use super::layout::{InlineMaterial, GlueMaterial, PenaltyMaterial};
 use crate::geom::Edge;
 use crate::color::{Color, BLACK, WHITE};
@@ -153,6 +154,8 @@ pub fn parse_font_weight(value: &str) -> Option<FontWeight> {
         Some(FontWeight::Normal)
     } else if value == "bold" {
         Some(FontWeight::Bold)
+    } else if let Ok(weight) = value.parse::<u16>() {
+        Some(if weight >= 600 { FontWeight::Bold } else { FontWeight::Normal })
     } else {
         None
     }
@@ -161,7 +164,7 @@ pub fn parse_font_weight(value: &str) -> Option<FontWeight> {
 pub fn parse_font_style(value: &str) -> Option<FontStyle> {
     if value == "normal" {
         Some(FontStyle::Normal)
-    } else if value == "italic" {
+    } else if value == "italic" || value == "oblique" {
         Some(FontStyle::Italic)
     } else {
         None
@@ -178,6 +181,15 @@ pub fn parse_display(value: &str) -> Option<Display> {
     }
 }
 
+// The vertical-lr mode isn't supported.
+pub fn parse_writing_mode(value: &str) -> Option<WritingMode> {
+    match value {
+        "horizontal-tb" | "lr-tb" => Some(WritingMode::HorizontalTb),
+        "vertical-rl" | "tb-rl" => Some(WritingMode::VerticalRl),
+        _ => None,
+    }
+}
+
 pub fn parse_float(value: &str) -> Option<Float> {
     match value {
         "left" => Some(Float::Left),
@@ -263,12 +275,13 @@ pub fn parse_edge(top_edge: Option<&str>, right_edge: Option<&str>, bottom_edge:
     e
 }
 
-pub fn parse_text_align(value: &str) -> Option<TextAlign> {
-    match value {
-        "justify" => Some(TextAlign::Justify),
-        "left" => Some(TextAlign::Left),
-        "right" => Some(TextAlign::Right),
-        "center" => Some(TextAlign::Center),
+// The meaning of `start` and `end` depends on the direction of the text.
+pub fn parse_text_align(value: &str, direction: Direction) -> Option<TextAlign> {
+    match (value, direction) {
+        ("justify", _) => Some(TextAlign::Justify),
+        ("left", _) | ("start", Direction::Ltr) | ("end", Direction::Rtl) => Some(TextAlign::Left),
+        ("right", _) | ("start", Direction::Rtl) | ("end", Direction::Ltr) => Some(TextAlign::Right),
+        ("center", _) => Some(TextAlign::Center),
         _ => None,
     }
 }
@@ -338,8 +351,95 @@ pub fn parse_font_variant(value: &str) -> Vec<String> {
     features.into_iter().map(String::from).collect()
 }
 
+pub fn parse_text_decoration(value: &str) -> Option<TextDecoration> {
+    let mut decoration = TextDecoration::default();
+
+    for token in value.split_whitespace() {
+        match token {
+            "none" => return Some(TextDecoration::default()),
+            "underline" => decoration.underline = true,
+            "overline" => decoration.overline = true,
+            "line-through" => decoration.line_through = true,
+            _ => (),
+        }
+    }
+
+    if decoration.is_none() {
+        None
+    } else {
+        Some(decoration)
+    }
+}
+
+pub fn parse_text_transform(value: &str) -> Option<TextTransform> {
+    match value {
+        "none" => Some(TextTransform::None),
+        "uppercase" => Some(TextTransform::Uppercase),
+        "lowercase" => Some(TextTransform::Lowercase),
+        "capitalize" => Some(TextTransform::Capitalize),
+        _ => None,
+    }
+}
+
+pub fn parse_border_style(value: &str) -> Option<BorderStyle> {
+    match value {
+        "none" | "hidden" => Some(BorderStyle::None),
+        "solid" | "groove" | "ridge" | "inset" | "outset" => Some(BorderStyle::Solid),
+        "dashed" => Some(BorderStyle::Dashed),
+        "dotted" => Some(BorderStyle::Dotted),
+        "double" => Some(BorderStyle::Double),
+        _ => None,
+    }
+}
+
+pub fn parse_border_width(value: &str, em: f32, rem: f32, dpi: u16) -> Option<i32> {
+    let px = match value {
+        "thin" => 1.0,
+        "medium" => 3.0,
+        "thick" => 5.0,
+        _ => return parse_length(value, em, rem, dpi).map(|w| w.max(0)),
+    };
+    Some(pt_to_px(px * 0.75, dpi).round().max(1.0) as i32)
+}
+
+// The color of a border defaults to the color of the text.
+pub fn parse_border_side(width: Option<&str>, style: Option<&str>, color: Option<&str>, current_color: Color, em: f32, rem: f32, dpi: u16) -> BorderSide {
+    let style = style.and_then(parse_border_style).unwrap_or(BorderStyle::None);
+    if style == BorderStyle::None {
+        return BorderSide::default();
+    }
+    BorderSide {
+        width: width.and_then(|value| parse_border_width(value, em, rem, dpi))
+                    .or_else(|| parse_border_width("medium", em, rem, dpi))
+                    .unwrap_or(0),
+        style,
+        color: color.and_then(parse_color).unwrap_or(current_color),
+    }
+}
+
+// Parses the arguments of rgb() and rgba(), the alpha channel is ignored.
+fn parse_rgb(args: &str) -> Option<Color> {
+    let mut rgb = [0u8; 3];
+    let mut components = args.split(|c: char| c == ',' || c == '/' || c.is_whitespace())
+                             .filter(|s| !s.is_empty());
+
+    for channel in &mut rgb {
+        let component = components.next()?;
+        *channel = if let Some(percent) = component.strip_suffix('%') {
+            (percent.parse::<f32>().ok()? * 2.55).round().clamp(0.0, 255.0) as u8
+        } else {
+            component.parse::<f32>().ok()?.round().clamp(0.0, 255.0) as u8
+        };
+    }
+
+    Some(Color::from_rgb(&rgb))
+}
+
 pub fn parse_color(value: &str) -> Option<Color> {
-    if value.starts_with('#') {
+    if let Some(args) = value.strip_prefix("rgb(").or_else(|| value.strip_prefix("rgba("))
+                             .and_then(|v| v.strip_suffix(')')) {
+        parse_rgb(args)
+    } else if value.starts_with('#') {
         if value.len() < 4 {
             return None;
         }
@@ -389,5 +489,16 @@ mod tests {
         assert_eq!(c, Some(Color::Rgb(0, 255, 0)));
         assert_eq!(d, Some(Color::Rgb(0, 0, 255)));
         assert_eq!(e, Some(Color::Rgb(255, 255, 255)));
+        assert_eq!(parse_color("rgb(255, 0, 0)"), b);
+        assert_eq!(parse_color("rgba(0 0 100% / 0.5)"), d);
+    }
+
+    #[test]
+    fn test_parse_text_decoration() {
+        assert_eq!(parse_text_decoration("underline"), Some(TextDecoration { underline: true, .. Default::default() }));
+        assert_eq!(parse_text_decoration("line-through overline"),
+                   Some(TextDecoration { overline: true, line_through: true, .. Default::default() }));
+        assert_eq!(parse_text_decoration("none"), Some(TextDecoration::default()));
+        assert_eq!(parse_text_decoration("inherit"), None);
     }
 }
@@ -1,19 +1,28 @@
+use std::path::Path;
+use std::cmp::Ordering;
 use fxhash::FxHashMap;
+use crate::helpers::Normalize;
 use super::dom::NodeRef;
-use super::css::{CssParser, Rule, Selector, SimpleSelector};
-use super::css::{Combinator, AttributeOperator, PseudoClass};
+use super::parse::{parse_color, parse_length};
+use super::css::{CssParser, Rule, Selector, SimpleSelector, FontFace};
+use super::css::{Combinator, AttributeOperator, PseudoClass, MediaQuery, MediaFeature};
+
+// The number of bits per pixel of grayscale displays.
+const MONOCHROME_BITS: i32 = 4;
 
 pub type PropertyMap = FxHashMap<String, String>;
 
 #[derive(Debug, Clone)]
 pub struct StyleSheet {
-    pub rules: Vec<Rule>
+    pub rules: Vec<Rule>,
+    pub font_faces: Vec<FontFace>,
 }
 
 impl StyleSheet {
     pub fn new() -> Self {
         StyleSheet {
             rules: Vec::new(),
+            font_faces: Vec::new(),
         }
     }
 
@@ -22,11 +31,88 @@ impl StyleSheet {
             other.sort();
         }
         self.rules.append(&mut other.rules);
+        self.font_faces.append(&mut other.font_faces);
+    }
+
+    // Makes the font sources relative to the root of the document.
+    pub fn resolve_font_sources(&mut self, dir: &Path) {
+        for face in &mut self.font_faces {
+            for source in &mut face.sources {
+                if let Some(path) = dir.join(source.as_str()).normalize().to_str() {
+                    *source = path.to_string();
+                }
+            }
+        }
     }
 
     pub fn sort(&mut self) {
         self.rules.sort_by_cached_key(|rule| rule.selector.specificity());
     }
+
+    // Removes the rules whose media queries don't match the given device.
+    pub fn retain_media(&mut self, device: &MediaDevice) {
+        self.rules.retain(|rule| rule.media.is_empty() ||
+                                 rule.media.iter().any(|query| query.matches(device)));
+    }
+}
+
+// The characteristics of the output device that media queries are evaluated against.
+#[derive(Debug, Copy, Clone)]
+pub struct MediaDevice {
+    // Dimensions in pixels.
+    pub width: u32,
+    pub height: u32,
+    pub dpi: u16,
+    // Font size in points.
+    pub font_size: f32,
+    pub color: bool,
+}
+
+impl MediaQuery {
+    fn matches(&self, device: &MediaDevice) -> bool {
+        // Kindle publishers target KF8 readers with `amzn-kf8` and older ones with `amzn-mobi`.
+        let type_matches = self.media_type.as_deref()
+                               .map_or(true, |t| matches!(t, "all" | "screen" | "amzn-kf8"));
+        (type_matches && self.features.iter().all(|f| f.matches(device))) != self.negated
+    }
+}
+
+impl MediaFeature {
+    fn matches(&self, device: &MediaDevice) -> bool {
+        let (name, ordering) = if let Some(name) = self.name.strip_prefix("min-") {
+            (name, Some(Ordering::Greater))
+        } else if let Some(name) = self.name.strip_prefix("max-") {
+            (name, Some(Ordering::Less))
+        } else {
+            (self.name.as_str(), None)
+        };
+
+        let (actual, expected) = match name {
+            "width" | "device-width" | "height" | "device-height" => {
+                let actual = if name.ends_with("width") { device.width } else { device.height } as i32;
+                let expected = self.value.as_deref().and_then(|v| {
+                    parse_length(v, device.font_size, device.font_size, device.dpi)
+                });
+                (actual, expected)
+            },
+            "monochrome" | "color" => {
+                let actual = if device.color == (name == "color") { MONOCHROME_BITS } else { 0 };
+                (actual, self.value.as_deref().and_then(|v| v.parse().ok()))
+            },
+            "orientation" => {
+                let orientation = if device.height >= device.width { "portrait" } else { "landscape" };
+                return ordering.is_none() && self.value.as_deref() == Some(orientation);
+            },
+            _ => return false,
+        };
+
+        match (expected, ordering) {
+            (None, None) => actual > 0,
+            (Some(expected), None) => actual == expected,
+            (Some(expected), Some(ordering)) => actual == expected || actual.cmp(&expected) == ordering,
+            (None, Some(..)) => false,
+        }
+    }
 }
 
 pub fn specified_values(node: NodeRef, stylesheet: &StyleSheet) -> PropertyMap {
@@ -49,7 +135,11 @@ pub fn specified_values(node: NodeRef, stylesheet: &StyleSheet) -> PropertyMap {
     }).unwrap_or_default();
 
     for declaration in &local_declarations {
-        expand_and_insert(&declaration.name, &declaration.value, &mut props);
+        if declaration.important {
+            important.push([&declaration.name, &declaration.value]);
+        } else {
+            expand_and_insert(&declaration.name, &declaration.value, &mut props);
+        }
     }
 
     for [name, value] in important {
@@ -157,54 +247,138 @@ impl PseudoClass {
         match self {
             PseudoClass::FirstChild => node.previous_sibling_element().is_none(),
             PseudoClass::LastChild => node.next_sibling_element().is_none(),
+            PseudoClass::NthChild(a, b) => {
+                let index = node.previous_sibling_elements().count() as i32 + 1;
+                is_nth(index, *a, *b)
+            },
+            PseudoClass::NthOfType(a, b) => {
+                let index = node.previous_sibling_elements()
+                                .filter(|n| n.tag_name() == node.tag_name())
+                                .count() as i32 + 1;
+                is_nth(index, *a, *b)
+            },
+            PseudoClass::Not(selectors) => selectors.iter().all(|s| !s.matches(node)),
+        }
+    }
+}
+
+// Whether *index* is of the form *an+b* for some non-negative integer *n*.
+fn is_nth(index: i32, a: i32, b: i32) -> bool {
+    if a == 0 {
+        index == b
+    } else {
+        (index - b) % a == 0 && (index - b) / a >= 0
+    }
+}
+
+const EDGE_SIDES: [&str; 4] = ["top", "right", "bottom", "left"];
+const BORDER_STYLES: [&str; 10] = ["none", "hidden", "solid", "dashed", "dotted",
+                                   "double", "groove", "ridge", "inset", "outset"];
+
+// Splits on whitespaces that aren't inside parentheses.
+fn split_values(value: &str) -> Vec<&str> {
+    let mut values = Vec::new();
+    let mut depth = 0;
+    let mut start = None;
+
+    for (i, c) in value.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            _ if c.is_whitespace() && depth <= 0 => {
+                if let Some(s) = start.take() {
+                    values.push(&value[s..i]);
+                }
+                continue;
+            },
+            _ => (),
+        }
+        if start.is_none() {
+            start = Some(i);
+        }
+    }
+
+    if let Some(s) = start {
+        values.push(&value[s..]);
+    }
+
+    values
+}
+
+// Maps one to four values to the top, right, bottom and left sides.
+fn edge_values(value: &str) -> Option<[&str; 4]> {
+    let values = split_values(value);
+    match values.len() {
+        1 => Some([values[0]; 4]),
+        2 => Some([values[0], values[1], values[0], values[1]]),
+        3 => Some([values[0], values[1], values[2], values[1]]),
+        4 => Some([values[0], values[1], values[2], values[3]]),
+        _ => None,
+    }
+}
+
+fn is_border_width(value: &str) -> bool {
+    matches!(value, "thin" | "medium" | "thick") ||
+    value.starts_with(|c: char| c.is_ascii_digit() || c == '.')
+}
+
+// Returns the width, style and color of a border shorthand.
+// The missing components are reset to their initial values.
+fn border_values(value: &str) -> [&str; 3] {
+    let mut values = ["medium", "none", "currentcolor"];
+
+    for token in split_values(value) {
+        if BORDER_STYLES.contains(&token) {
+            values[1] = token;
+        } else if is_border_width(token) {
+            values[0] = token;
+        } else {
+            values[2] = token;
         }
     }
+
+    values
 }
 
 fn expand_and_insert(name: &str, value: &str, props: &mut PropertyMap) {
     match name {
         "margin" | "padding" => {
-            let values = value.split_whitespace().collect::<Vec<&str>>();
-            match values.len() {
-                1 => {
-                    props.insert(format!("{}-top", name), value.to_string());
-                    props.insert(format!("{}-right", name), value.to_string());
-                    props.insert(format!("{}-bottom", name), value.to_string());
-                    props.insert(format!("{}-left", name), value.to_string());
-                },
-                2 => {
-                    let vertical = values[0];
-                    let horizontal = values[1];
-                    props.insert(format!("{}-top", name), vertical.to_string());
-                    props.insert(format!("{}-right", name), horizontal.to_string());
-                    props.insert(format!("{}-bottom", name), vertical.to_string());
-                    props.insert(format!("{}-left", name), horizontal.to_string());
-                },
-                3 => {
-                    let top = values[0];
-                    let horizontal = values[1];
-                    let bottom = values[2];
-                    props.insert(format!("{}-top", name), top.to_string());
-                    props.insert(format!("{}-right", name), horizontal.to_string());
-                    props.insert(format!("{}-bottom", name), bottom.to_string());
-                    props.insert(format!("{}-left", name), horizontal.to_string());
-                },
-                4 => {
-                    let top = values[0];
-                    let right = values[1];
-                    let bottom = values[2];
-                    let left = values[3];
-                    props.insert(format!("{}-top", name), top.to_string());
-                    props.insert(format!("{}-right", name), right.to_string());
-                    props.insert(format!("{}-bottom", name), bottom.to_string());
-                    props.insert(format!("{}-left", name), left.to_string());
-                },
-                _ => (),
+            if let Some(values) = edge_values(value) {
+                for (side, value) in EDGE_SIDES.iter().zip(values.iter()) {
+                    props.insert(format!("{}-{}", name, side), value.to_string());
+                }
+            }
+        },
+        "border-width" | "border-style" | "border-color" => {
+            let kind = &name["border-".len()..];
+            if let Some(values) = edge_values(value) {
+                for (side, value) in EDGE_SIDES.iter().zip(values.iter()) {
+                    props.insert(format!("border-{}-{}", side, kind), value.to_string());
+                }
+            }
+        },
+        "border" => {
+            let values = border_values(value);
+            for side in &EDGE_SIDES {
+                for (kind, value) in ["width", "style", "color"].iter().zip(values.iter()) {
+                    props.insert(format!("border-{}-{}", side, kind), value.to_string());
+                }
+            }
+        },
+        "border-top" | "border-right" | "border-bottom" | "border-left" => {
+            let values = border_values(value);
+            for (kind, value) in ["width", "style", "color"].iter().zip(values.iter()) {
+                props.insert(format!("{}-{}", name, kind), value.to_string());
+            }
+        },
+        "background" => {
+            let color = split_values(value).into_iter()
+                                           .find(|token| parse_color(token).is_some() ||
+                                                         *token == "transparent");
+            if let Some(color) = color {
+                props.insert("background-color".to_string(), color.to_string());
             }
         },
-        // TODO: border -> border-{top,right,bottom,left}-{width,style,color}
-        // border-left -> border-left-{width,style,color}
-        // border-style -> border-{top,right,bottom,left}-style
         _ => {
             props.insert(name.to_string(), value.to_string());
         }
@@ -213,7 +387,7 @@ fn expand_and_insert(name: &str, value: &str, props: &mut PropertyMap) {
 
 #[cfg(test)]
 mod tests {
-    use super::specified_values;
+    use super::{specified_values, MediaDevice};
     use super::super::css::CssParser;
     use super::super::xml::XmlParser;
 
@@ -232,4 +406,48 @@ mod tests {
                                                 ("c".to_string(), "7".to_string())].iter().cloned().collect());
         assert_eq!(specified_values(n2, &css), [("b".to_string(), "5".to_string())].iter().cloned().collect());
     }
+
+    #[test]
+    fn border_shorthands() {
+        let xml = XmlParser::new("<a style='border: 2px solid rgb(0, 0, 0); border-left-style: dashed; border-width: 1px 3px'/>").parse();
+        let css = CssParser::new("").parse();
+        let props = specified_values(xml.root().first_child().unwrap(), &css);
+        assert_eq!(props.get("border-top-width").map(String::as_str), Some("1px"));
+        assert_eq!(props.get("border-right-width").map(String::as_str), Some("3px"));
+        assert_eq!(props.get("border-bottom-color").map(String::as_str), Some("rgb(0, 0, 0)"));
+        assert_eq!(props.get("border-left-style").map(String::as_str), Some("dashed"));
+        assert_eq!(props.get("border-top-style").map(String::as_str), Some("solid"));
+    }
+
+    #[test]
+    fn selectors_and_cascade() {
+        let xml = XmlParser::new("<ul><li/><li class='a' style='c: 4 !important'/><li style='c: 5'/><p/><li/></ul>").parse();
+        let mut css = CssParser::new("li:nth-child(2n+1) { b: 1 }\
+                                      li:nth-of-type(4) { b: 2 }\
+                                      li:not(.a):not(:last-child) { c: 3 !important }").parse();
+        css.sort();
+        let items = xml.root().first_child().unwrap().children().collect::<Vec<_>>();
+        let props = items.iter().map(|&n| specified_values(n, &css)).collect::<Vec<_>>();
+        assert_eq!(props[0].get("b").map(String::as_str), Some("1"));
+        assert_eq!(props[0].get("c").map(String::as_str), Some("3"));
+        assert_eq!(props[1].get("b"), None);
+        assert_eq!(props[1].get("c").map(String::as_str), Some("4"));
+        assert_eq!(props[2].get("c").map(String::as_str), Some("3"));
+        assert_eq!(props[4].get("b").map(String::as_str), Some("2"));
+        assert_eq!(props[4].get("c"), None);
+    }
+
+    #[test]
+    fn media_queries() {
+        let device = MediaDevice { width: 1072, height: 1448, dpi: 300, font_size: 11.0, color: false };
+        let mut css = CssParser::new("@media amzn-mobi { a { b: 1 } }\
+                                      @media not amzn-mobi { a { b: 2 } }\
+                                      @media screen and (min-width: 300px) and (orientation: portrait) { a { b: 3 } }\
+                                      @media (max-width: 300px), print { a { b: 4 } }\
+                                      @media (monochrome) and (max-color: 0) { a { b: 5 } }\
+                                      @media (min-height: 5in) { a { b: 6 } }").parse();
+        css.retain_media(&device);
+        let values = css.rules.iter().map(|r| r.declarations[0].value.as_str()).collect::<Vec<_>>();
+        assert_eq!(values, vec!["2", "3", "5"]);
+    }
 }
@@ -0,0 +1,150 @@
+use std::path::{Path, PathBuf};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use percent_encoding::percent_decode_str;
+use crate::helpers::{Normalize, decode_entities};
+use super::dom::NodeRef;
+use super::engine::ResourceFetcher;
+use super::parse::{parse_width, parse_length};
+use super::xml::XmlParser;
+
+pub const SVG_MIME: &str = "image/svg+xml";
+const SVG_NAMESPACE: &str = "http://www.w3.org/2000/svg";
+const XLINK_NAMESPACE: &str = "http://www.w3.org/1999/xlink";
+
+// Returns the width and height of a *viewBox* attribute.
+pub fn parse_view_box(value: &str) -> Option<(f32, f32)> {
+    let values = value.split(|c: char| c.is_whitespace() || c == ',')
+                      .filter(|v| !v.is_empty())
+                      .map(|v| v.parse::<f32>().ok())
+                      .collect::<Option<Vec<f32>>>()?;
+    if values.len() == 4 && values[2] > 0.0 && values[3] > 0.0 {
+        Some((values[2], values[3]))
+    } else {
+        None
+    }
+}
+
+// Unitless lengths are in pixels. Percentages are relative to *basis*.
+pub fn parse_svg_length(value: &str, em: f32, rem: f32, basis: i32, dpi: u16) -> Option<i32> {
+    if value.parse::<f32>().is_ok() {
+        parse_length(&format!("{}px", value), em, rem, dpi)
+    } else {
+        parse_width(value, em, rem, basis, dpi)
+    }
+}
+
+// Serializes an inline SVG element as a standalone document. The base
+// directory of the relative references is recorded in the *xml:base* attribute.
+pub fn svg_document(node: NodeRef, base: &Path, dims: Option<(i32, i32)>) -> String {
+    let mut attributes = node.attributes().cloned().unwrap_or_default();
+    attributes.entry("xmlns".to_string())
+              .or_insert_with(|| SVG_NAMESPACE.to_string());
+    attributes.entry("xmlns:xlink".to_string())
+              .or_insert_with(|| XLINK_NAMESPACE.to_string());
+    attributes.insert("xml:base".to_string(), base.to_string_lossy().into_owned());
+
+    if let Some((width, height)) = dims {
+        attributes.insert("width".to_string(), width.to_string());
+        attributes.insert("height".to_string(), height.to_string());
+    } else {
+        attributes.retain(|name, value| !((name == "width" || name == "height") && value.ends_with('%')));
+    }
+
+    let mut svg = String::from("<svg");
+    for (name, value) in &attributes {
+        svg.push_str(&format!(" {}=\"{}\"", name, value.replace('"', "&quot;")));
+    }
+    svg.push('>');
+    for child in node.children() {
+        svg.push_str(&child.to_html());
+    }
+    svg.push_str("</svg>");
+    svg
+}
+
+pub fn data_uri(mime: &str, data: &[u8]) -> String {
+    format!("data:{};base64,{}", mime, STANDARD.encode(data))
+}
+
+fn image_mime(path: &Path) -> &'static str {
+    match path.extension().and_then(|e| e.to_str()).map(|e| e.to_ascii_lowercase()).as_deref() {
+        Some("png") => "image/png",
+        Some("gif") => "image/gif",
+        Some("svg") => SVG_MIME,
+        _ => "image/jpeg",
+    }
+}
+
+// MuPDF can't resolve the references to external images of an SVG
+// document opened from memory: they're replaced by data URIs.
+pub fn embed_images(svg: &[u8], dir: &Path, resource_fetcher: &mut dyn ResourceFetcher) -> Vec<u8> {
+    let mut text = String::from_utf8_lossy(svg).into_owned();
+
+    let references = {
+        let root = XmlParser::new(&text).parse();
+        let dir = root.root().find("svg")
+                      .and_then(|node| node.attribute("xml:base"))
+                      .map(PathBuf::from)
+                      .unwrap_or_else(|| dir.to_path_buf());
+        root.root().descendants()
+            .filter(|node| node.tag_name() == Some("image"))
+            .filter_map(|node| node.attribute("xlink:href").or_else(|| node.attribute("href")))
+            .filter(|href| !href.starts_with("data:"))
+            .map(|href| {
+                let path = percent_decode_str(&decode_entities(href)).decode_utf8_lossy().into_owned();
+                (href.to_string(), dir.join(path).normalize())
+            })
+            .collect::<Vec<(String, PathBuf)>>()
+    };
+
+    for (href, path) in references {
+        if let Some(buf) = path.to_str().and_then(|name| resource_fetcher.fetch(name).ok()) {
+            let uri = data_uri(image_mime(&path), &buf);
+            for quote in ['"', '\''] {
+                text = text.replace(&format!("href={0}{1}{0}", quote, href),
+                                    &format!("href={0}{1}{0}", quote, uri));
+            }
+        }
+    }
+
+    text.into_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::{Error, format_err};
+
+    struct Cover;
+
+    impl ResourceFetcher for Cover {
+        fn fetch(&mut self, name: &str) -> Result<Vec<u8>, Error> {
+            if name == "OEBPS/images/cover.jpg" {
+                Ok(vec![1, 2, 3])
+            } else {
+                Err(format_err!("can't find {}", name))
+            }
+        }
+    }
+
+    #[test]
+    fn test_svg_document() {
+        let text = r#"<div><svg width="100%" viewBox="0 0 600 800"><image xlink:href="../images/cover.jpg"/></svg></div>"#;
+        let xml = XmlParser::new(text).parse();
+        let node = xml.root().find("svg").unwrap();
+        let svg = svg_document(node, Path::new("OEBPS/text"), None);
+        assert!(!svg.contains("width="));
+        assert!(svg.contains(r#"xml:base="OEBPS/text""#));
+        let svg = String::from_utf8(embed_images(svg.as_bytes(), Path::new(""), &mut Cover)).unwrap();
+        assert!(svg.contains(r#"<image xlink:href="data:image/jpeg;base64,AQID"/>"#));
+    }
+
+    #[test]
+    fn test_view_box() {
+        assert_eq!(parse_view_box("0 0 600 800"), Some((600.0, 800.0)));
+        assert_eq!(parse_view_box("0,0, 12.5,4"), Some((12.5, 4.0)));
+        assert_eq!(parse_view_box("0 0 600"), None);
+        assert_eq!(parse_view_box("0 0 0 10"), None);
+    }
+}
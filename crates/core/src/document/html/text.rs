@@ -0,0 +1,145 @@
+use std::borrow::Cow;
+use chardetng::EncodingDetector;
+use pulldown_cmark::{Parser, Options, Event, Tag, TagEnd, HeadingLevel, html};
+
+// Lines longer than this aren't considered as headings.
+const MAX_HEADING_LENGTH: usize = 60;
+const HEADING_WORDS: [&str; 6] = ["chapter", "part", "book", "prologue", "epilogue", "appendix"];
+
+// Decodes the given bytes as UTF-8, or else guesses their encoding.
+pub fn decode_text(bytes: &[u8]) -> String {
+    let bytes = bytes.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(bytes);
+    match std::str::from_utf8(bytes) {
+        Ok(text) => text.to_string(),
+        Err(_) => {
+            let mut detector = EncodingDetector::new();
+            detector.feed(bytes, true);
+            let encoding = detector.guess(None, true);
+            encoding.decode_without_bom_handling(bytes).0.into_owned()
+        },
+    }
+}
+
+fn escape(text: &str) -> Cow<'_, str> {
+    if text.contains(['&', '<', '>', '"']) {
+        Cow::Owned(text.replace('&', "&amp;").replace('<', "&lt;")
+                       .replace('>', "&gt;").replace('"', "&quot;"))
+    } else {
+        Cow::Borrowed(text)
+    }
+}
+
+fn wrap_body(title: &str, body: &str) -> String {
+    format!("<html>\n<head>\n<title>{}</title>\n</head>\n<body>\n{}</body>\n</html>\n",
+            escape(title), body)
+}
+
+fn is_heading(line: &str) -> bool {
+    let line = line.trim();
+    if line.is_empty() || line.chars().count() > MAX_HEADING_LENGTH ||
+       line.ends_with(['.', ',', ';', ':', '!', '?', '"']) {
+        return false;
+    }
+    let first_word = line.split_whitespace().next().unwrap_or_default().to_lowercase();
+    HEADING_WORDS.contains(&first_word.as_str()) ||
+        (line.chars().any(char::is_alphabetic) &&
+         line.chars().filter(|c| c.is_alphabetic()).all(char::is_uppercase))
+}
+
+// Paragraphs are separated by blank lines. When there are none, an
+// indented line starts a new paragraph, or else each line is a paragraph.
+fn paragraphs(text: &str) -> Vec<String> {
+    let lines = text.lines().map(|line| line.trim_end()).collect::<Vec<&str>>();
+    let has_blank_lines = lines.windows(2).any(|w| !w[0].is_empty() && w[1].is_empty());
+    let has_indents = lines.iter().filter(|line| line.starts_with([' ', '\t'])).count() > lines.len() / 10;
+    let mut paragraphs = Vec::new();
+    let mut current = String::new();
+
+    for line in lines {
+        let starts_paragraph = if has_blank_lines {
+            line.is_empty()
+        } else if has_indents {
+            line.starts_with([' ', '\t']) || line.is_empty()
+        } else {
+            true
+        };
+
+        if starts_paragraph && !current.is_empty() {
+            paragraphs.push(current.clone());
+            current.clear();
+        }
+
+        let line = line.trim();
+        if !line.is_empty() {
+            if !current.is_empty() {
+                current.push(' ');
+            }
+            current.push_str(line);
+        }
+    }
+
+    if !current.is_empty() {
+        paragraphs.push(current);
+    }
+
+    paragraphs
+}
+
+pub fn text_to_html(text: &str, title: &str) -> String {
+    let mut body = String::new();
+
+    for paragraph in paragraphs(text) {
+        let tag = if is_heading(&paragraph) { "h2" } else { "p" };
+        body.push_str(&format!("<{0}>{1}</{0}>\n", tag, escape(&paragraph)));
+    }
+
+    wrap_body(title, &body)
+}
+
+// The first level one heading, if any, is used as title.
+pub fn markdown_to_html(text: &str, title: &str) -> String {
+    let options = Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH |
+                  Options::ENABLE_FOOTNOTES | Options::ENABLE_TASKLISTS;
+    let events = Parser::new_ext(text, options).collect::<Vec<Event>>();
+    let mut heading = None;
+    let mut in_heading = false;
+
+    for event in &events {
+        match event {
+            Event::Start(Tag::Heading { level: HeadingLevel::H1, .. }) if heading.is_none() => {
+                in_heading = true;
+                heading = Some(String::new());
+            },
+            Event::End(TagEnd::Heading(..)) => in_heading = false,
+            Event::Text(text) | Event::Code(text) if in_heading => {
+                if let Some(heading) = heading.as_mut() {
+                    heading.push_str(text);
+                }
+            },
+            _ => (),
+        }
+    }
+
+    let mut body = String::new();
+    html::push_html(&mut body, events.into_iter());
+    let heading = heading.filter(|h| !h.trim().is_empty());
+
+    wrap_body(heading.as_deref().unwrap_or(title), &body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_paragraphs() {
+        let text = "CHAPTER ONE\n\nIt was a bright cold day\nin April.\n\n\nThe clocks were striking thirteen.\n";
+        assert_eq!(paragraphs(text), vec!["CHAPTER ONE", "It was a bright cold day in April.",
+                                          "The clocks were striking thirteen."]);
+        assert!(is_heading("CHAPTER ONE"));
+        assert!(!is_heading("It was a bright cold day in April."));
+        let text = "  First paragraph,\nwrapped.\n  Second one.\n";
+        assert_eq!(paragraphs(text), vec!["First paragraph, wrapped.", "Second one."]);
+        assert_eq!(decode_text(b"caf\xE9"), "café");
+    }
+}
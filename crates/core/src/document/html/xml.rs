@@ -209,4 +209,14 @@ mod tests {
         let xml = XmlParser::new(text).parse();
         assert_eq!(xml.root().text(), " ");
     }
+
+    #[test]
+    fn test_to_html() {
+        let text = r#"<p id="n1">A <em>note</em>.<br/></p>"#;
+        let xml = XmlParser::new(text).parse();
+        assert_eq!(xml.root().to_html(), text);
+        let text = r#"<aside epub:type="footnote"><p>&amp; 'a'</p></aside>"#;
+        let xml = XmlParser::new(text).parse();
+        assert_eq!(xml.root().to_html(), text);
+    }
 }
@@ -2,6 +2,9 @@ pub mod djvu;
 pub mod pdf;
 pub mod epub;
 pub mod html;
+pub mod comic;
+pub mod fb2;
+pub mod reflow;
 
 mod djvulibre_sys;
 mod mupdf_sys;
@@ -11,7 +14,7 @@ use std::process::Command;
 use std::path::Path;
 use std::fs::{self, File};
 use std::ffi::OsStr;
-use std::collections::BTreeSet;
+use std::collections::{BTreeMap, BTreeSet};
 use std::os::unix::fs::FileExt;
 use anyhow::{Error, format_err};
 use regex::Regex;
@@ -26,14 +29,45 @@ use self::djvu::DjvuOpener;
 use self::pdf::PdfOpener;
 use self::epub::EpubDocument;
 use self::html::HtmlDocument;
+use self::comic::ComicDocument;
+use self::html::engine::ResourceFetcher;
 use crate::geom::{Boundary, CycleDir};
-use crate::metadata::{TextAlign, Annotation};
+use crate::metadata::{TextAlign, Annotation, ReaderInfo, Stroke};
 use crate::framebuffer::Pixmap;
 use crate::settings::INTERNAL_CARD_ROOT;
 use crate::device::CURRENT_DEVICE;
 
 pub const BYTES_PER_PAGE: f64 = 2048.0;
 
+// Whether the documents of the given kind, read with the given reading state,
+// are addressed by byte offsets instead of page indices.
+pub fn has_synthetic_page_numbers(kind: &str, reader: Option<&ReaderInfo>) -> bool {
+    matches!(kind, "epub" | "html" | "htm" | "txt" | "md" | "markdown" | "fb2" | "fb2.zip") &&
+    !is_paginated_text(kind, reader)
+}
+
+// The plain text and FictionBook files read before they were laid out by the HTML engine
+// are still opened with MuPDF, since their reading states use page indices.
+fn is_paginated_text(kind: &str, reader: Option<&ReaderInfo>) -> bool {
+    matches!(kind, "txt" | "fb2" | "fb2.zip") && reader.map_or(false, |r| r.version == 0)
+}
+
+// Opens the document the way it was opened when its reading state was saved.
+pub fn open_book<P: AsRef<Path>>(path: P, reader: Option<&ReaderInfo>) -> Option<Box<dyn Document>> {
+    let kind = file_kind(path.as_ref())?;
+    if is_paginated_text(&kind, reader) {
+        return PdfOpener::new().and_then(|mut o| {
+            o.load_user_stylesheet();
+            o.open(path)
+             .map(|d| Box::new(d) as Box<dyn Document>)
+        });
+    }
+    open(path)
+}
+
+// The URI of the links covering the tables that are too wide for their pages.
+pub const TABLE_URI: &str = "table:";
+
 #[derive(Debug, Clone)]
 pub enum Location {
     Exact(usize),
@@ -121,6 +155,26 @@ pub trait Document: Send+Sync {
         false
     }
 
+    // Whether the pages are meant to be turned from right to left.
+    fn is_right_to_left(&self) -> bool {
+        false
+    }
+
+    fn set_right_to_left(&mut self, _right_to_left: bool) {
+    }
+
+    // Returns the HTML content of the note targeted by the link at the given offset,
+    // and a fetcher for the images it refers to.
+    fn footnote(&mut self, _offset: usize, _uri: &str) -> Option<(String, Box<dyn ResourceFetcher>)> {
+        None
+    }
+
+    // Returns the HTML content of the table starting at the given offset,
+    // and a fetcher for the images it refers to.
+    fn table(&mut self, _offset: usize) -> Option<(String, Box<dyn ResourceFetcher>)> {
+        None
+    }
+
     fn save(&self, _path: &str) -> Result<(), Error> {
         Err(format_err!("this document can't be saved"))
     }
@@ -165,6 +219,13 @@ pub trait Document: Send+Sync {
 }
 
 pub fn file_kind<P: AsRef<Path>>(path: P) -> Option<String> {
+    let is_fb2_zip = path.as_ref().file_name()
+                         .and_then(OsStr::to_str)
+                         .map_or(false, |name| name.to_lowercase().ends_with(".fb2.zip"));
+    if is_fb2_zip {
+        return Some("fb2.zip".to_string());
+    }
+
     path.as_ref().extension()
         .and_then(OsStr::to_str)
         .map(str::to_lowercase)
@@ -233,6 +294,31 @@ pub fn open<P: AsRef<Path>>(path: P) -> Option<Box<dyn Document>> {
                              .map_err(|e| eprintln!("{}: {:#}.", path.as_ref().display(), e))
                              .map(|d| Box::new(d) as Box<dyn Document>).ok()
             },
+            "txt" => {
+                HtmlDocument::new_from_text(&path)
+                             .map_err(|e| eprintln!("{}: {:#}.", path.as_ref().display(), e))
+                             .map(|d| Box::new(d) as Box<dyn Document>).ok()
+            },
+            "fb2" | "fb2.zip" => {
+                HtmlDocument::new_from_fb2(&path)
+                             .map_err(|e| eprintln!("{}: {:#}.", path.as_ref().display(), e))
+                             .map(|d| Box::new(d) as Box<dyn Document>).ok()
+            },
+            "md" | "markdown" => {
+                HtmlDocument::new_from_markdown(&path)
+                             .map_err(|e| eprintln!("{}: {:#}.", path.as_ref().display(), e))
+                             .map(|d| Box::new(d) as Box<dyn Document>).ok()
+            },
+            "cbz" => {
+                ComicDocument::new(&path)
+                              .map_err(|e| eprintln!("{}: {:#}.", path.as_ref().display(), e))
+                              .map(|d| Box::new(d) as Box<dyn Document>).ok()
+            },
+            "cbr" => {
+                ComicDocument::new_rar(&path)
+                              .map_err(|e| eprintln!("{}: {:#}.", path.as_ref().display(), e))
+                              .map(|d| Box::new(d) as Box<dyn Document>).ok()
+            },
             "djvu" | "djv" => {
                 DjvuOpener::new().and_then(|o| {
                     o.open(path)
@@ -241,7 +327,7 @@ pub fn open<P: AsRef<Path>>(path: P) -> Option<Box<dyn Document>> {
             },
             _ => {
                 PdfOpener::new().and_then(|mut o| {
-                    if matches!(k.as_ref(), "mobi" | "fb2" | "xps" | "txt") {
+                    if matches!(k.as_ref(), "mobi" | "xps") {
                         o.load_user_stylesheet();
                     }
                     o.open(path)
@@ -309,12 +395,17 @@ pub fn toc_as_html_aux(toc: &[TocEntry], chap_index: usize, depth: usize, buf: &
     buf.push_str("</ul>\n");
 }
 
-pub fn annotations_as_html(annotations: &[Annotation], active_range: Option<(TextLocation, TextLocation)>) -> String {
+pub fn annotations_as_html(annotations: &[Annotation], ink: &BTreeMap<usize, Vec<Stroke>>, active_range: Option<(TextLocation, TextLocation)>) -> String {
     let mut buf = "<html>\n\t<head>\n\t\t<title>Annotations</title>\n\t\t\
                    <link rel=\"stylesheet\" type=\"text/css\" href=\"css/annotations.css\"/>\n\t\
                    </head>\n\t<body>\n".to_string();
     buf.push_str("\t\t<ul>\n");
+    let mut pages = ink.keys().peekable();
     for annot in annotations {
+        // The inked pages are listed before the first annotation that follows them.
+        while let Some(page) = pages.next_if(|page| **page < annot.selection[0].location()) {
+            buf.push_str(&format!("\t\t<li><a href=\"@{}\"><i>Ink</i> — page {}</a></li>\n", page, page + 1));
+        }
         let mut note = annot.note.replace('<', "&lt;").replace('>', "&gt;");
         let mut text = annot.text.replace('<', "&lt;").replace('>', "&gt;");
         let start = annot.selection[0];
@@ -330,6 +421,9 @@ pub fn annotations_as_html(annotations: &[Annotation], active_range: Option<(Tex
             buf.push_str(&format!("\t\t<li><a href=\"@{}\"><i>{}</i> — {}</a></li>\n", start.location(), note, text));
         }
     }
+    for page in pages {
+        buf.push_str(&format!("\t\t<li><a href=\"@{}\"><i>Ink</i> — page {}</a></li>\n", page, page + 1));
+    }
     buf.push_str("\t\t</ul>\n");
     buf.push_str("\t</body>\n</html>");
     buf
@@ -585,3 +679,39 @@ pub fn sys_info_as_html() -> String {
     buf.push_str("\t\t</table>\n\t</body>\n</html>");
     buf
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_annotations_as_html_ink() {
+        let annotations = vec![Annotation {
+            text: "<word>".to_string(),
+            selection: [TextLocation::Dynamic(5), TextLocation::Dynamic(9)],
+            .. Default::default()
+        }];
+        let mut ink = BTreeMap::new();
+        ink.insert(2, vec![Stroke::default()]);
+        ink.insert(7, vec![Stroke::default()]);
+        let html = annotations_as_html(&annotations, &ink, None);
+        let first = html.find("<a href=\"@2\"><i>Ink</i> — page 3</a>").unwrap();
+        let word = html.find("<a href=\"@5\">&lt;word&gt;</a>").unwrap();
+        let last = html.find("<a href=\"@7\"><i>Ink</i> — page 8</a>").unwrap();
+        assert!(first < word && word < last);
+        let html = annotations_as_html(&[], &ink, None);
+        assert_eq!(html.matches("<i>Ink</i>").count(), 2);
+    }
+
+    #[test]
+    fn test_paginated_text() {
+        let legacy: ReaderInfo = serde_json::from_str(r#"{"opened":"2020-01-01 00:00:00","currentPage":12,"pagesCount":80}"#).unwrap();
+        let current = ReaderInfo::default();
+        for kind in ["txt", "fb2", "fb2.zip"] {
+            assert!(!has_synthetic_page_numbers(kind, Some(&legacy)));
+            assert!(has_synthetic_page_numbers(kind, Some(&current)));
+            assert!(has_synthetic_page_numbers(kind, None));
+        }
+        assert!(has_synthetic_page_numbers("epub", Some(&legacy)));
+    }
+}
@@ -19,6 +19,10 @@ pub const FZ_TEXT_MEDIABOX_CLIP: libc::c_int = 64;
 pub const FZ_PAGE_BLOCK_TEXT: libc::c_int = 0;
 pub const FZ_PAGE_BLOCK_IMAGE: libc::c_int = 1;
 
+pub const PDF_ANNOT_TEXT: libc::c_int = 0;
+pub const PDF_ANNOT_HIGHLIGHT: libc::c_int = 8;
+pub const PDF_ANNOT_INK: libc::c_int = 15;
+
 pub const CACHE_SIZE: libc::size_t = 32 * 1024 * 1024;
 
 pub enum FzContext {}
@@ -38,6 +42,8 @@ pub enum FzLinkSetUriFn {}
 pub enum FzLinkDropLinkFn {}
 pub enum FzSeparations {}
 pub enum FzImage {}
+pub enum PdfDoc {}
+pub enum PdfAnnot {}
 
 #[link(name="mupdf")]
 #[link(name="mupdf_wrapper", kind="static")]
@@ -82,6 +88,17 @@ extern "C" {
     pub fn fz_union_rect(a: FzRect, b: FzRect) -> FzRect;
     pub fn fz_rect_from_quad(q: FzQuad) -> FzRect;
     pub fn fz_runetochar(buf: *mut u8, rune: libc::c_int) -> libc::c_int;
+    pub fn pdf_specifics(ctx: *mut FzContext, doc: *mut FzDocument) -> *mut PdfDoc;
+    pub fn mp_save_document(ctx: *mut FzContext, doc: *mut PdfDoc, path: *const libc::c_char) -> libc::c_int;
+    pub fn mp_create_annot(ctx: *mut FzContext, page: *mut FzPage, kind: libc::c_int) -> *mut PdfAnnot;
+    pub fn mp_update_annot(ctx: *mut FzContext, annot: *mut PdfAnnot) -> libc::c_int;
+    pub fn pdf_drop_annot(ctx: *mut FzContext, annot: *mut PdfAnnot);
+    pub fn mp_set_annot_rect(ctx: *mut FzContext, annot: *mut PdfAnnot, rect: FzRect) -> libc::c_int;
+    pub fn mp_set_annot_contents(ctx: *mut FzContext, annot: *mut PdfAnnot, text: *const libc::c_char) -> libc::c_int;
+    pub fn mp_set_annot_color(ctx: *mut FzContext, annot: *mut PdfAnnot, n: libc::c_int, color: *const libc::c_float) -> libc::c_int;
+    pub fn mp_set_annot_border_width(ctx: *mut FzContext, annot: *mut PdfAnnot, width: libc::c_float) -> libc::c_int;
+    pub fn mp_set_annot_quad_points(ctx: *mut FzContext, annot: *mut PdfAnnot, n: libc::c_int, quads: *const FzQuad) -> libc::c_int;
+    pub fn mp_set_annot_ink_list(ctx: *mut FzContext, annot: *mut PdfAnnot, n: libc::c_int, count: *const libc::c_int, points: *const FzPoint) -> libc::c_int;
     pub static fz_identity: FzMatrix;
 }
 
@@ -109,17 +126,17 @@ impl Default for FzRect {
 #[repr(C)]
 #[derive(Debug, Copy, Clone)]
 pub struct FzPoint {
-    x: libc::c_float,
-    y: libc::c_float,
+    pub x: libc::c_float,
+    pub y: libc::c_float,
 }
 
 #[repr(C)]
 #[derive(Debug, Copy, Clone)]
 pub struct FzQuad {
-    ul: FzPoint,
-    ur: FzPoint,
-    ll: FzPoint,
-    lr: FzPoint,
+    pub ul: FzPoint,
+    pub ur: FzPoint,
+    pub ll: FzPoint,
+    pub lr: FzPoint,
 }
 
 #[derive(Copy, Clone)]
@@ -9,14 +9,18 @@ use std::path::Path;
 use std::io::ErrorKind;
 use std::ffi::{CString, CStr};
 use std::os::unix::ffi::OsStrExt;
+use std::collections::{BTreeMap, BTreeSet};
+use anyhow::{Error, format_err};
 use super::{Document, Location, TextLocation, BoundedText, TocEntry};
 use super::{chapter, chapter_relative};
-use crate::metadata::TextAlign;
+use crate::metadata::{TextAlign, Annotation, Stroke};
 use crate::geom::{Boundary, CycleDir};
 use crate::unit::pt_to_px;
 use crate::framebuffer::Pixmap;
 
 const USER_STYLESHEET: &str = "css/html-user.css";
+const HIGHLIGHT_COLOR: [f32; 3] = [1.0, 0.9, 0.0];
+const NOTE_ICON_SIZE: f32 = 20.0;
 
 impl Into<Boundary> for FzRect {
     fn into(self) -> Boundary {
@@ -43,6 +47,11 @@ pub struct PdfPage<'a> {
     _doc: &'a PdfDocument,
 }
 
+struct PdfAnnotation {
+    ctx: Rc<PdfContext>,
+    annot: *mut PdfAnnot,
+}
+
 impl PdfOpener {
     pub fn new() -> Option<PdfOpener> {
         unsafe {
@@ -158,6 +167,55 @@ impl PdfDocument {
     pub fn is_protected(&self) -> bool {
         unsafe { fz_needs_password(self.ctx.0, self.doc) == 1 }
     }
+
+    // Writes a copy of the document with the given highlights, notes and handwritten strokes.
+    pub fn save_annotated<P: AsRef<Path>>(&self, path: P, annotations: &[Annotation], ink: &BTreeMap<usize, Vec<Stroke>>) -> Result<(), Error> {
+        let pdf = unsafe { pdf_specifics(self.ctx.0, self.doc) };
+        if pdf.is_null() {
+            return Err(format_err!("not a PDF document"));
+        }
+
+        let mut locations: BTreeSet<usize> = ink.keys().cloned().collect();
+        for annot in annotations {
+            locations.extend(annot.selection[0].location()..=annot.selection[1].location());
+        }
+
+        for index in locations {
+            let page = self.page(index).ok_or_else(|| format_err!("can't load page {}", index + 1))?;
+            let words = page.words().unwrap_or_default();
+
+            for annot in annotations {
+                let [start, end] = annot.selection;
+                if index < start.location() || index > end.location() {
+                    continue;
+                }
+                let rects = words.iter()
+                                 .filter(|w| w.location >= start && w.location <= end)
+                                 .map(|w| w.rect)
+                                 .collect::<Vec<Boundary>>();
+                if rects.is_empty() {
+                    continue;
+                }
+                page.add_highlight(&rects)?;
+                if !annot.note.is_empty() && index == start.location() {
+                    page.add_note(rects[0].min.y, &annot.note)?;
+                }
+            }
+
+            if let Some(strokes) = ink.get(&index) {
+                for stroke in strokes.iter().filter(|s| !s.points.is_empty()) {
+                    page.add_ink(stroke)?;
+                }
+            }
+        }
+
+        let c_path = CString::new(path.as_ref().as_os_str().as_bytes())?;
+        if unsafe { mp_save_document(self.ctx.0, pdf, c_path.as_ptr()) } == -1 {
+            Err(format_err!("can't save {}", path.as_ref().display()))
+        } else {
+            Ok(())
+        }
+    }
 }
 
 impl Document for PdfDocument {
@@ -317,6 +375,54 @@ impl Document for PdfDocument {
 }
 
 impl<'a> PdfPage<'a> {
+    fn create_annotation(&self, kind: libc::c_int) -> Result<PdfAnnotation, Error> {
+        let annot = unsafe { mp_create_annot(self.ctx.0, self.page, kind) };
+        if annot.is_null() {
+            Err(format_err!("can't create an annotation on page {}", self.index + 1))
+        } else {
+            Ok(PdfAnnotation { ctx: self.ctx.clone(), annot })
+        }
+    }
+
+    pub fn add_highlight(&self, rects: &[Boundary]) -> Result<(), Error> {
+        let quads = rects.iter().map(|r| FzQuad {
+            ul: FzPoint { x: r.min.x, y: r.min.y },
+            ur: FzPoint { x: r.max.x, y: r.min.y },
+            ll: FzPoint { x: r.min.x, y: r.max.y },
+            lr: FzPoint { x: r.max.x, y: r.max.y },
+        }).collect::<Vec<FzQuad>>();
+        let annot = self.create_annotation(PDF_ANNOT_HIGHLIGHT)?;
+        annot.set_color(&HIGHLIGHT_COLOR)?;
+        annot.set_quad_points(&quads)?;
+        annot.update()
+    }
+
+    // Adds a sticky note in the right margin, at the given height.
+    pub fn add_note(&self, y: f32, text: &str) -> Result<(), Error> {
+        let bounds = unsafe { fz_bound_page(self.ctx.0, self.page) };
+        let annot = self.create_annotation(PDF_ANNOT_TEXT)?;
+        annot.set_rect(FzRect {
+            x0: bounds.x1 - NOTE_ICON_SIZE,
+            y0: y,
+            x1: bounds.x1,
+            y1: y + NOTE_ICON_SIZE,
+        })?;
+        annot.set_contents(text)?;
+        annot.update()
+    }
+
+    pub fn add_ink(&self, stroke: &Stroke) -> Result<(), Error> {
+        let points = stroke.points.iter()
+                           .map(|p| FzPoint { x: p[0], y: p[1] })
+                           .collect::<Vec<FzPoint>>();
+        let color = stroke.color.rgb().map(|c| c as f32 / 255.0);
+        let annot = self.create_annotation(PDF_ANNOT_INK)?;
+        annot.set_color(&color)?;
+        annot.set_border_width(stroke.width)?;
+        annot.set_ink_list(&[points.len() as libc::c_int], &points)?;
+        annot.update()
+    }
+
     pub fn images(&self) -> Option<Vec<Boundary>> {
         unsafe {
             let mut images: Vec<Boundary> = Vec::new();
@@ -534,6 +640,46 @@ impl<'a> PdfPage<'a> {
     }
 }
 
+impl PdfAnnotation {
+    fn check(&self, ret: libc::c_int, property: &str) -> Result<(), Error> {
+        if ret == -1 {
+            Err(format_err!("can't set the {} of an annotation", property))
+        } else {
+            Ok(())
+        }
+    }
+
+    fn set_rect(&self, rect: FzRect) -> Result<(), Error> {
+        self.check(unsafe { mp_set_annot_rect(self.ctx.0, self.annot, rect) }, "rectangle")
+    }
+
+    fn set_contents(&self, text: &str) -> Result<(), Error> {
+        let c_text = CString::new(text)?;
+        self.check(unsafe { mp_set_annot_contents(self.ctx.0, self.annot, c_text.as_ptr()) }, "contents")
+    }
+
+    fn set_color(&self, color: &[f32]) -> Result<(), Error> {
+        self.check(unsafe { mp_set_annot_color(self.ctx.0, self.annot, color.len() as libc::c_int, color.as_ptr()) }, "color")
+    }
+
+    fn set_border_width(&self, width: f32) -> Result<(), Error> {
+        self.check(unsafe { mp_set_annot_border_width(self.ctx.0, self.annot, width) }, "border width")
+    }
+
+    fn set_quad_points(&self, quads: &[FzQuad]) -> Result<(), Error> {
+        self.check(unsafe { mp_set_annot_quad_points(self.ctx.0, self.annot, quads.len() as libc::c_int, quads.as_ptr()) }, "quad points")
+    }
+
+    fn set_ink_list(&self, count: &[libc::c_int], points: &[FzPoint]) -> Result<(), Error> {
+        self.check(unsafe { mp_set_annot_ink_list(self.ctx.0, self.annot, count.len() as libc::c_int, count.as_ptr(), points.as_ptr()) }, "ink list")
+    }
+
+    // Generates the appearance stream.
+    fn update(&self) -> Result<(), Error> {
+        self.check(unsafe { mp_update_annot(self.ctx.0, self.annot) }, "appearance")
+    }
+}
+
 impl Drop for PdfContext {
     fn drop(&mut self) {
         unsafe { fz_drop_context(self.0); }
@@ -551,3 +697,9 @@ impl<'a> Drop for PdfPage<'a> {
         unsafe { fz_drop_page(self.ctx.0, self.page); }
     }
 }
+
+impl Drop for PdfAnnotation {
+    fn drop(&mut self) {
+        unsafe { pdf_drop_annot(self.ctx.0, self.annot); }
+    }
+}
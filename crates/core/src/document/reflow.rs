@@ -0,0 +1,984 @@
+use fxhash::FxHashMap;
+use super::{Document, Location, TextLocation, BoundedText, TocEntry, BYTES_PER_PAGE};
+use super::{chapter, chapter_relative};
+use crate::metadata::TextAlign;
+use crate::geom::{Boundary, CycleDir, Point, Rectangle, Vec2};
+use crate::framebuffer::Pixmap;
+use crate::unit::{mm_to_px, pt_to_px};
+use crate::settings::{DEFAULT_FONT_SIZE, DEFAULT_MARGIN_WIDTH, DEFAULT_LINE_HEIGHT, DEFAULT_TEXT_ALIGN};
+
+// Each page of the original document spans this many locations: the location
+// of a reflowed page is made of the index of its original page and of the
+// ordinal of its first word.
+pub const REFLOW_PAGE_SPAN: usize = BYTES_PER_PAGE as usize;
+// The width of the renderings used to segment the pages without a text layer.
+const ANALYSIS_WIDTH: f32 = 1200.0;
+// The gray level under which a pixel is considered as ink.
+const INK_THRESHOLD: u8 = 160;
+const DEFAULT_DPI: u16 = 300;
+
+#[derive(Debug, Clone)]
+struct Word {
+    rect: Boundary,
+    // The text and the index of the word in the text layer.
+    text: Option<(String, usize)>,
+}
+
+#[derive(Debug, Clone)]
+struct Line {
+    rect: Boundary,
+    words: Vec<Word>,
+    // Whether the line starts a paragraph.
+    paragraph: bool,
+    figure: bool,
+}
+
+#[derive(Debug, Clone)]
+struct Placement {
+    ordinal: usize,
+    // The rectangle on the original page.
+    rect: Boundary,
+    position: Point,
+    // The additional scaling of the oversized words and figures.
+    ratio: f32,
+    text: Option<(String, usize)>,
+    figure: bool,
+}
+
+#[derive(Debug, Clone)]
+struct ReflowPage {
+    offset: usize,
+    placements: Vec<Placement>,
+}
+
+#[derive(Debug, Clone)]
+struct Reflow {
+    // The scaling factor from the original page to the screen.
+    scale: f32,
+    pages: Vec<ReflowPage>,
+}
+
+#[derive(Debug, Clone)]
+struct FlowSettings {
+    width: i32,
+    height: i32,
+    margin: i32,
+    // The target line height of the text, in pixels.
+    em: i32,
+    line_height: f32,
+    text_align: TextAlign,
+}
+
+// Reflows the words of a fixed-layout document onto screen-sized pages.
+pub struct ReflowDocument {
+    doc: Box<dyn Document>,
+    dims: (u32, u32),
+    font_size: f32,
+    dpi: u16,
+    margin_width: i32,
+    line_height: f32,
+    text_align: TextAlign,
+    lines: FxHashMap<usize, Vec<Line>>,
+    reflows: FxHashMap<usize, Reflow>,
+    // The last rendered original page, with its scale and samples.
+    rendering: Option<(usize, f32, usize, Pixmap)>,
+}
+
+impl ReflowDocument {
+    pub fn new(doc: Box<dyn Document>) -> ReflowDocument {
+        ReflowDocument {
+            doc,
+            dims: (600, 800),
+            font_size: DEFAULT_FONT_SIZE,
+            dpi: DEFAULT_DPI,
+            margin_width: DEFAULT_MARGIN_WIDTH,
+            line_height: DEFAULT_LINE_HEIGHT,
+            text_align: DEFAULT_TEXT_ALIGN,
+            lines: FxHashMap::default(),
+            reflows: FxHashMap::default(),
+            rendering: None,
+        }
+    }
+
+    fn settings(&self) -> FlowSettings {
+        FlowSettings {
+            width: self.dims.0 as i32,
+            height: self.dims.1 as i32,
+            margin: mm_to_px(self.margin_width as f32, self.dpi).round() as i32,
+            em: pt_to_px(self.font_size, self.dpi).round() as i32,
+            line_height: self.line_height,
+            text_align: self.text_align,
+        }
+    }
+
+    fn segment(&mut self, index: usize) -> &[Line] {
+        if !self.lines.contains_key(&index) {
+            let lines = self.text_lines(index)
+                            .or_else(|| self.bitmap_lines(index))
+                            .unwrap_or_default();
+            self.lines.insert(index, lines);
+        }
+        &self.lines[&index]
+    }
+
+    fn reflow(&mut self, index: usize) -> &Reflow {
+        if !self.reflows.contains_key(&index) {
+            let settings = self.settings();
+            let reflow = flow_lines(self.segment(index), &settings);
+            self.reflows.insert(index, reflow);
+        }
+        &self.reflows[&index]
+    }
+
+    // Returns the index of the original page and the index of the reflowed page.
+    fn locate(&mut self, offset: usize) -> Option<(usize, usize)> {
+        let index = offset / REFLOW_PAGE_SPAN;
+        if index >= self.doc.pages_count() {
+            return None;
+        }
+        let pages = &self.reflow(index).pages;
+        let page_index = pages.iter().rposition(|page| page.offset <= offset).unwrap_or(0);
+        Some((index, page_index))
+    }
+
+    fn offset(&mut self, index: usize, page_index: usize) -> usize {
+        index * REFLOW_PAGE_SPAN + self.reflow(index).pages[page_index].offset
+    }
+
+    fn invalidate(&mut self) {
+        self.reflows.clear();
+        self.rendering = None;
+    }
+
+    // The words are grouped in lines following the order of the text layer.
+    fn text_lines(&mut self, index: usize) -> Option<Vec<Line>> {
+        let (words, _) = self.doc.words(Location::Exact(index))?;
+        let mut lines: Vec<Line> = Vec::new();
+
+        for (i, word) in words.into_iter().enumerate() {
+            if word.text.trim().is_empty() {
+                continue;
+            }
+            let center_y = (word.rect.min.y + word.rect.max.y) / 2.0;
+            let entry = Word { rect: word.rect, text: Some((word.text, i)) };
+            match lines.last_mut() {
+                Some(line) if center_y >= line.rect.min.y && center_y <= line.rect.max.y &&
+                              word.rect.min.x >= line.rect.max.x - line.rect.height() => {
+                    line.rect = union(&line.rect, &word.rect);
+                    line.words.push(entry);
+                },
+                _ => lines.push(Line { rect: word.rect, words: vec![entry], paragraph: false, figure: false }),
+            }
+        }
+
+        if lines.is_empty() {
+            return None;
+        }
+
+        // The figures of born-digital documents.
+        let page_area = self.doc.dims(index).map_or(0.0, |(w, h)| w * h);
+        if let Some((images, _)) = self.doc.images(Location::Exact(index)) {
+            for rect in images.into_iter().filter(|r| r.width() * r.height() < 0.8 * page_area) {
+                let position = lines.iter().position(|line| line.rect.min.y > rect.min.y)
+                                    .unwrap_or(lines.len());
+                lines.insert(position, Line {
+                    rect,
+                    words: vec![Word { rect, text: None }],
+                    paragraph: true,
+                    figure: true,
+                });
+            }
+        }
+
+        align_words(&mut lines);
+        mark_paragraphs(&mut lines);
+        Some(lines)
+    }
+
+    fn bitmap_lines(&mut self, index: usize) -> Option<Vec<Line>> {
+        let (width, _) = self.doc.dims(index)?;
+        let scale = ANALYSIS_WIDTH / width;
+        let (pixmap, _) = self.doc.pixmap(Location::Exact(index), scale, 1)?;
+        let page = rect![0, 0, pixmap.width as i32, pixmap.height as i32];
+        let mut lines = segment_components(components(&pixmap), page);
+        for line in &mut lines {
+            line.rect /= scale;
+            for word in &mut line.words {
+                word.rect /= scale;
+            }
+        }
+        Some(lines)
+    }
+}
+
+fn union(a: &Boundary, b: &Boundary) -> Boundary {
+    bndr![a.min.x.min(b.min.x), a.min.y.min(b.min.y),
+          a.max.x.max(b.max.x), a.max.y.max(b.max.y)]
+}
+
+fn median(mut values: Vec<f32>) -> Option<f32> {
+    if values.is_empty() {
+        return None;
+    }
+    values.sort_by(|a, b| a.total_cmp(b));
+    Some(values[values.len() / 2])
+}
+
+// The words of a line share its vertical extent: this keeps them on the
+// same baseline once reflowed.
+fn align_words(lines: &mut [Line]) {
+    for line in lines.iter_mut().filter(|line| !line.figure) {
+        for word in &mut line.words {
+            word.rect.min.y = line.rect.min.y;
+            word.rect.max.y = line.rect.max.y;
+        }
+    }
+}
+
+// A paragraph starts after a short line, a wide vertical gap, or on an indented line.
+fn mark_paragraphs(lines: &mut [Line]) {
+    let text_lines = || lines.iter().filter(|line| !line.figure);
+    let em = match median(text_lines().map(|line| line.rect.height()).collect()) {
+        Some(em) => em,
+        None => return,
+    };
+    let left = text_lines().map(|line| line.rect.min.x).fold(f32::MAX, f32::min);
+    let right = text_lines().map(|line| line.rect.max.x).fold(f32::MIN, f32::max);
+    let gap = median(text_lines().zip(text_lines().skip(1))
+                                 .map(|(a, b)| b.rect.min.y - a.rect.max.y)
+                                 .filter(|gap| *gap >= 0.0)
+                                 .collect()).unwrap_or(0.0);
+    let mut previous: Option<Boundary> = None;
+
+    for line in lines.iter_mut() {
+        if line.figure {
+            line.paragraph = true;
+            previous = None;
+            continue;
+        }
+        let indented = line.rect.min.x - left > 0.8 * em;
+        line.paragraph = indented || previous.map_or(false, |prev| {
+            right - prev.max.x > 2.5 * em ||
+            line.rect.min.y - prev.max.y > gap + 0.5 * em
+        });
+        previous = Some(line.rect);
+    }
+}
+
+// Returns the bounding boxes of the 8-connected components of ink pixels.
+fn components(pixmap: &Pixmap) -> Vec<Rectangle> {
+    let width = pixmap.width as usize;
+    let height = pixmap.height as usize;
+    let samples = pixmap.samples;
+    let is_ink = |x: usize, y: usize| {
+        let addr = samples * (y * width + x);
+        let gray = if samples == 1 {
+            pixmap.data[addr]
+        } else {
+            ((pixmap.data[addr] as u32 * 299 + pixmap.data[addr+1] as u32 * 587 +
+              pixmap.data[addr+2] as u32 * 114) / 1000) as u8
+        };
+        gray < INK_THRESHOLD
+    };
+
+    if pixmap.data.len() < samples * width * height {
+        return Vec::new();
+    }
+
+    let mut labels = vec![0u32; width * height];
+    let mut parents = vec![0u32];
+
+    fn find(parents: &mut [u32], mut label: u32) -> u32 {
+        while parents[label as usize] != label {
+            let parent = parents[label as usize];
+            parents[label as usize] = parents[parent as usize];
+            label = parent;
+        }
+        label
+    }
+
+    for y in 0..height {
+        for x in 0..width {
+            if !is_ink(x, y) {
+                continue;
+            }
+            let i = y * width + x;
+            let mut neighbors = [0u32; 4];
+            if x > 0 {
+                neighbors[0] = labels[i - 1];
+            }
+            if y > 0 {
+                neighbors[1] = labels[i - width];
+                if x > 0 {
+                    neighbors[2] = labels[i - width - 1];
+                }
+                if x + 1 < width {
+                    neighbors[3] = labels[i - width + 1];
+                }
+            }
+            let mut label = 0;
+            for &neighbor in neighbors.iter().filter(|n| **n > 0) {
+                let root = find(&mut parents, neighbor);
+                if label == 0 {
+                    label = root;
+                } else if root != label {
+                    let (a, b) = (label.min(root), label.max(root));
+                    parents[b as usize] = a;
+                    label = a;
+                }
+            }
+            if label == 0 {
+                label = parents.len() as u32;
+                parents.push(label);
+            }
+            labels[i] = label;
+        }
+    }
+
+    let mut boxes: FxHashMap<u32, Rectangle> = FxHashMap::default();
+
+    for y in 0..height {
+        for x in 0..width {
+            let label = labels[y * width + x];
+            if label == 0 {
+                continue;
+            }
+            let root = find(&mut parents, label);
+            let pixel = rect![x as i32, y as i32, x as i32 + 1, y as i32 + 1];
+            boxes.entry(root)
+                 .and_modify(|rect| rect.absorb(&pixel))
+                 .or_insert(pixel);
+        }
+    }
+
+    let mut boxes = boxes.into_values().collect::<Vec<Rectangle>>();
+    boxes.sort_by_key(|rect| (rect.min.y, rect.min.x));
+    boxes
+}
+
+// Groups the connected components of a page into lines of words, and figures.
+fn segment_components(boxes: Vec<Rectangle>, page: Rectangle) -> Vec<Line> {
+    let page_width = page.width() as i32;
+    let page_height = page.height() as i32;
+    // Specks of dust and the shadows of the scanner are ignored.
+    let boxes = boxes.into_iter().filter(|rect| {
+        let (w, h) = (rect.width() as i32, rect.height() as i32);
+        w * h >= 4 && !(w > 9 * page_width / 10 && h > 9 * page_height / 10)
+    }).collect::<Vec<Rectangle>>();
+
+    let char_height = match median(boxes.iter().map(|rect| rect.height() as f32).collect()) {
+        Some(h) => h.max(2.0),
+        None => return Vec::new(),
+    };
+
+    let (mut figures, mut glyphs): (Vec<Rectangle>, Vec<Rectangle>) = boxes.into_iter().partition(|rect| {
+        rect.height() as f32 > 4.0 * char_height && rect.width() as f32 > 2.0 * char_height
+    });
+
+    // Overlapping figures are merged, and absorb the labels they contain.
+    let mut merged = true;
+    while merged {
+        merged = false;
+        'outer: for i in 0..figures.len() {
+            for j in i+1..figures.len() {
+                if figures[i].overlaps(&figures[j]) {
+                    let other = figures.remove(j);
+                    figures[i].absorb(&other);
+                    merged = true;
+                    break 'outer;
+                }
+            }
+        }
+    }
+    glyphs.retain(|rect| !figures.iter().any(|figure| figure.includes(rect.center())));
+
+    let mut lines = Vec::new();
+
+    for (glyphs, figures) in split_columns(glyphs, figures, char_height) {
+        let mut column = text_bands(glyphs, char_height);
+        for figure in figures {
+            let rect: Boundary = figure.into();
+            let position = column.iter().position(|line: &Line| line.rect.min.y > rect.min.y)
+                                 .unwrap_or(column.len());
+            column.insert(position, Line {
+                rect,
+                words: vec![Word { rect, text: None }],
+                paragraph: true,
+                figure: true,
+            });
+        }
+        align_words(&mut column);
+        mark_paragraphs(&mut column);
+        lines.append(&mut column);
+    }
+
+    lines
+}
+
+// Splits two-column pages at the widest vertical gap near the middle of the text.
+fn split_columns(glyphs: Vec<Rectangle>, figures: Vec<Rectangle>, char_height: f32) -> Vec<(Vec<Rectangle>, Vec<Rectangle>)> {
+    let mut intervals = glyphs.iter().map(|rect| (rect.min.x, rect.max.x)).collect::<Vec<(i32, i32)>>();
+    intervals.sort_unstable();
+    let left = intervals.first().map_or(0, |i| i.0);
+    let right = intervals.iter().map(|i| i.1).max().unwrap_or(0);
+    let mut best: Option<(i32, i32)> = None;
+    let mut end = left;
+
+    for (start, stop) in intervals {
+        let middle = (end + start) / 2;
+        if start - end > 0 && 10 * (middle - left) > 3 * (right - left) && 10 * (middle - left) < 7 * (right - left) &&
+           best.map_or(true, |(width, _)| start - end > width) {
+            best = Some((start - end, middle));
+        }
+        end = end.max(stop);
+    }
+
+    match best {
+        Some((width, middle)) if width as f32 > 1.5 * char_height => {
+            let (first, second): (Vec<Rectangle>, Vec<Rectangle>) = glyphs.into_iter().partition(|rect| rect.center().x < middle);
+            let (first_figures, second_figures) = figures.into_iter().partition(|rect| rect.center().x < middle);
+            vec![(first, first_figures), (second, second_figures)]
+        },
+        _ => vec![(glyphs, figures)],
+    }
+}
+
+// The lines are the bands of overlapping glyphs. The dots and accents
+// are attached to the band below them.
+fn text_bands(mut glyphs: Vec<Rectangle>, char_height: f32) -> Vec<Line> {
+    glyphs.sort_by_key(|rect| rect.min.y);
+    let mut bands: Vec<(i32, i32, Vec<Rectangle>)> = Vec::new();
+
+    for rect in glyphs {
+        match bands.last_mut() {
+            Some(band) if rect.min.y < band.1 => {
+                band.1 = band.1.max(rect.max.y);
+                band.2.push(rect);
+            },
+            _ => bands.push((rect.min.y, rect.max.y, vec![rect])),
+        }
+    }
+
+    let small = (0.6 * char_height).round() as i32;
+    let mut i = 0;
+    while i + 1 < bands.len() {
+        if bands[i].1 - bands[i].0 < small && bands[i+1].0 - bands[i].1 < small {
+            let (top, _, mut rects) = bands.remove(i);
+            bands[i].0 = top;
+            bands[i].2.append(&mut rects);
+        } else {
+            i += 1;
+        }
+    }
+
+    let word_gap = (0.4 * char_height).round() as i32;
+
+    bands.into_iter().map(|(top, bottom, mut rects)| {
+        rects.sort_by_key(|rect| rect.min.x);
+        let mut words: Vec<(i32, i32)> = Vec::new();
+        for rect in rects {
+            match words.last_mut() {
+                Some(word) if rect.min.x - word.1 <= word_gap => word.1 = word.1.max(rect.max.x),
+                _ => words.push((rect.min.x, rect.max.x)),
+            }
+        }
+        let words = words.into_iter().map(|(start, end)| {
+            Word { rect: rect![start, top, end, bottom].into(), text: None }
+        }).collect::<Vec<Word>>();
+        let rect = bndr![words[0].rect.min.x, top as f32,
+                         words[words.len() - 1].rect.max.x, bottom as f32];
+        Line { rect, words, paragraph: false, figure: false }
+    }).collect()
+}
+
+struct Flow<'a> {
+    settings: &'a FlowSettings,
+    scale: f32,
+    ordinal: usize,
+    pages: Vec<ReflowPage>,
+    page: Vec<Placement>,
+    line: Vec<Placement>,
+    line_width: i32,
+    indent: i32,
+    y: i32,
+}
+
+impl<'a> Flow<'a> {
+    fn size(&self, placement: &Placement) -> (i32, i32) {
+        let factor = self.scale * placement.ratio;
+        ((placement.rect.width() * factor).round() as i32,
+         (placement.rect.height() * factor).round() as i32)
+    }
+
+    fn content_width(&self) -> i32 {
+        (self.settings.width - 2 * self.settings.margin).max(1)
+    }
+
+    fn space(&self) -> i32 {
+        self.settings.em / 3
+    }
+
+    fn leading(&self) -> i32 {
+        ((self.settings.line_height - 1.0) * self.settings.em as f32).round() as i32
+    }
+
+    fn push_word(&mut self, word: &Word) {
+        let mut placement = Placement {
+            ordinal: self.ordinal,
+            rect: word.rect,
+            position: Point::default(),
+            ratio: 1.0,
+            text: word.text.clone(),
+            figure: false,
+        };
+        self.ordinal += 1;
+        let available = self.content_width() - self.indent;
+        let (mut width, _) = self.size(&placement);
+        if width > available {
+            placement.ratio = available as f32 / width as f32;
+            width = self.size(&placement).0;
+        }
+        let space = if self.line.is_empty() { 0 } else { self.space() };
+        if !self.line.is_empty() && self.indent + self.line_width + space + width > self.content_width() {
+            self.break_line(true);
+        }
+        let space = if self.line.is_empty() { 0 } else { self.space() };
+        self.line_width += space + width;
+        self.line.push(placement);
+    }
+
+    fn break_line(&mut self, justify: bool) {
+        if self.line.is_empty() {
+            return;
+        }
+        let line = std::mem::take(&mut self.line);
+        let sizes = line.iter().map(|p| self.size(p)).collect::<Vec<(i32, i32)>>();
+        let height = sizes.iter().map(|s| s.1).max().unwrap_or(0);
+        if self.y + height > self.settings.height - self.settings.margin && !self.page.is_empty() {
+            self.new_page();
+        }
+        let free = (self.content_width() - self.indent - self.line_width).max(0);
+        let mut space = self.space() as f32;
+        let mut x = (self.settings.margin + self.indent) as f32;
+        match self.settings.text_align {
+            TextAlign::Justify if justify && line.len() > 1 => space += free as f32 / (line.len() - 1) as f32,
+            TextAlign::Center => x += free as f32 / 2.0,
+            TextAlign::Right => x += free as f32,
+            _ => (),
+        }
+        for (mut placement, (width, _)) in line.into_iter().zip(sizes) {
+            placement.position = pt!(x.round() as i32, self.y);
+            self.page.push(placement);
+            x += width as f32 + space;
+        }
+        self.y += height + self.leading();
+        self.line_width = 0;
+        self.indent = 0;
+    }
+
+    fn push_figure(&mut self, rect: Boundary) {
+        self.break_line(false);
+        let mut placement = Placement {
+            ordinal: self.ordinal,
+            rect,
+            position: Point::default(),
+            ratio: 1.0,
+            text: None,
+            figure: true,
+        };
+        self.ordinal += 1;
+        let (width, height) = self.size(&placement);
+        let max_height = self.settings.height - 2 * self.settings.margin;
+        placement.ratio = (self.content_width() as f32 / width.max(1) as f32)
+                              .min(max_height as f32 / height.max(1) as f32)
+                              .min(1.0);
+        let (width, height) = self.size(&placement);
+        if self.y + height > self.settings.height - self.settings.margin && !self.page.is_empty() {
+            self.new_page();
+        }
+        placement.position = pt!(self.settings.margin + (self.content_width() - width) / 2, self.y);
+        self.page.push(placement);
+        self.y += height + self.leading();
+    }
+
+    fn new_page(&mut self) {
+        let placements = std::mem::take(&mut self.page);
+        let offset = placements.first().map_or(self.ordinal, |p| p.ordinal);
+        self.pages.push(ReflowPage { offset, placements });
+        self.y = self.settings.margin;
+    }
+}
+
+fn flow_lines(lines: &[Line], settings: &FlowSettings) -> Reflow {
+    let scale = median(lines.iter().filter(|line| !line.figure)
+                            .map(|line| line.rect.height()).collect())
+                    .map_or(1.0, |height| settings.em as f32 / height.max(1.0))
+                    .clamp(0.05, 20.0);
+
+    let mut flow = Flow {
+        settings,
+        scale,
+        ordinal: 0,
+        pages: Vec::new(),
+        page: Vec::new(),
+        line: Vec::new(),
+        line_width: 0,
+        indent: 0,
+        y: settings.margin,
+    };
+
+    for line in lines {
+        if line.figure {
+            flow.push_figure(line.rect);
+            continue;
+        }
+        if line.paragraph {
+            flow.break_line(false);
+            if flow.line.is_empty() && settings.text_align != TextAlign::Center {
+                flow.indent = settings.em;
+            }
+        }
+        for word in &line.words {
+            flow.push_word(word);
+        }
+    }
+
+    flow.break_line(false);
+
+    if !flow.page.is_empty() || flow.pages.is_empty() {
+        flow.new_page();
+    }
+
+    // The offsets of the reflowed pages must stay within the span of the original page:
+    // the pages of a very dense page are shifted back while keeping distinct offsets.
+    let mut limit = REFLOW_PAGE_SPAN - 1;
+    for page in flow.pages.iter_mut().rev() {
+        page.offset = page.offset.min(limit);
+        limit = page.offset.saturating_sub(1);
+    }
+
+    // The location of the first page is the location of the original page.
+    flow.pages[0].offset = 0;
+
+    Reflow { scale, pages: flow.pages }
+}
+
+// Copies a region of `source` into `target`, scaled by `ratio`.
+fn blit(source: &Pixmap, rect: Rectangle, ratio: f32, target: &mut Pixmap, position: Point) {
+    let samples = target.samples;
+    if source.samples != samples || source.data.is_empty() || target.data.is_empty() {
+        return;
+    }
+    let width = (rect.width() as f32 * ratio).round() as i32;
+    let height = (rect.height() as f32 * ratio).round() as i32;
+    for dy in 0..height {
+        let ty = position.y + dy;
+        let sy = rect.min.y + (dy as f32 / ratio) as i32;
+        if ty < 0 || ty >= target.height as i32 || sy < 0 || sy >= source.height as i32 {
+            continue;
+        }
+        for dx in 0..width {
+            let tx = position.x + dx;
+            let sx = rect.min.x + (dx as f32 / ratio) as i32;
+            if tx < 0 || tx >= target.width as i32 || sx < 0 || sx >= source.width as i32 {
+                continue;
+            }
+            let s = samples * (sy as usize * source.width as usize + sx as usize);
+            let t = samples * (ty as usize * target.width as usize + tx as usize);
+            target.data[t..t+samples].copy_from_slice(&source.data[s..s+samples]);
+        }
+    }
+}
+
+fn map_toc(entries: Vec<TocEntry>) -> Vec<TocEntry> {
+    entries.into_iter().map(|entry| TocEntry {
+        location: match entry.location {
+            Location::Exact(index) => Location::Exact(index * REFLOW_PAGE_SPAN),
+            location => location,
+        },
+        children: map_toc(entry.children),
+        .. entry
+    }).collect()
+}
+
+impl Document for ReflowDocument {
+    fn dims(&self, _index: usize) -> Option<(f32, f32)> {
+        Some((self.dims.0 as f32, self.dims.1 as f32))
+    }
+
+    fn pages_count(&self) -> usize {
+        self.doc.pages_count() * REFLOW_PAGE_SPAN
+    }
+
+    fn toc(&mut self) -> Option<Vec<TocEntry>> {
+        self.doc.toc().map(map_toc)
+    }
+
+    fn chapter<'a>(&mut self, offset: usize, toc: &'a [TocEntry]) -> Option<(&'a TocEntry, f32)> {
+        chapter(offset, self.pages_count(), toc)
+    }
+
+    fn chapter_relative<'a>(&mut self, offset: usize, dir: CycleDir, toc: &'a [TocEntry]) -> Option<&'a TocEntry> {
+        chapter_relative(offset, dir, toc)
+    }
+
+    fn resolve_location(&mut self, loc: Location) -> Option<usize> {
+        match loc {
+            Location::Exact(offset) => {
+                let (index, page_index) = self.locate(offset)?;
+                Some(self.offset(index, page_index))
+            },
+            Location::Previous(offset) => {
+                let (index, page_index) = self.locate(offset)?;
+                if page_index > 0 {
+                    Some(self.offset(index, page_index - 1))
+                } else if index > 0 {
+                    let last_index = self.reflow(index - 1).pages.len() - 1;
+                    Some(self.offset(index - 1, last_index))
+                } else {
+                    None
+                }
+            },
+            Location::Next(offset) => {
+                let (index, page_index) = self.locate(offset)?;
+                if page_index + 1 < self.reflow(index).pages.len() {
+                    Some(self.offset(index, page_index + 1))
+                } else if index + 1 < self.doc.pages_count() {
+                    Some(self.offset(index + 1, 0))
+                } else {
+                    None
+                }
+            },
+            loc => self.doc.resolve_location(loc)
+                       .map(|index| index * REFLOW_PAGE_SPAN),
+        }
+    }
+
+    fn words(&mut self, loc: Location) -> Option<(Vec<BoundedText>, usize)> {
+        let offset = self.resolve_location(loc)?;
+        let (index, page_index) = self.locate(offset)?;
+        let reflow = self.reflow(index);
+        let words = reflow.pages[page_index].placements.iter().filter_map(|p| {
+            p.text.as_ref().map(|(text, word_index)| {
+                let factor = reflow.scale * p.ratio;
+                let min = Vec2::from(p.position);
+                BoundedText {
+                    text: text.clone(),
+                    rect: Boundary::new(min, min + vec2!(p.rect.width() * factor, p.rect.height() * factor)),
+                    location: TextLocation::Static(index, *word_index),
+                }
+            })
+        }).collect();
+        Some((words, offset))
+    }
+
+    fn lines(&mut self, _loc: Location) -> Option<(Vec<BoundedText>, usize)> {
+        None
+    }
+
+    fn links(&mut self, _loc: Location) -> Option<(Vec<BoundedText>, usize)> {
+        None
+    }
+
+    fn images(&mut self, loc: Location) -> Option<(Vec<Boundary>, usize)> {
+        let offset = self.resolve_location(loc)?;
+        let (index, page_index) = self.locate(offset)?;
+        let reflow = self.reflow(index);
+        let images = reflow.pages[page_index].placements.iter().filter(|p| p.figure).map(|p| {
+            let factor = reflow.scale * p.ratio;
+            let min = Vec2::from(p.position);
+            Boundary::new(min, min + vec2!(p.rect.width() * factor, p.rect.height() * factor))
+        }).collect();
+        Some((images, offset))
+    }
+
+    fn pixmap(&mut self, loc: Location, scale: f32, samples: usize) -> Option<(Pixmap, usize)> {
+        let offset = self.resolve_location(loc)?;
+        let (index, page_index) = self.locate(offset)?;
+        let width = (self.dims.0 as f32 * scale).max(1.0) as u32;
+        let height = (self.dims.1 as f32 * scale).max(1.0) as u32;
+        let mut pixmap = Pixmap::try_new(width, height, samples)?;
+        let (render_scale, placements) = {
+            let reflow = self.reflow(index);
+            (reflow.scale * scale, reflow.pages[page_index].placements.clone())
+        };
+
+        if placements.is_empty() {
+            return Some((pixmap, offset));
+        }
+
+        let is_cached = self.rendering.as_ref().map_or(false, |(i, s, n, _)| {
+            *i == index && (*s - render_scale).abs() < f32::EPSILON && *n == samples
+        });
+
+        if !is_cached {
+            self.rendering = None;
+            let (source, _) = self.doc.pixmap(Location::Exact(index), render_scale, samples)?;
+            self.rendering = Some((index, render_scale, samples, source));
+        }
+
+        if let Some((_, _, _, source)) = self.rendering.as_ref() {
+            for placement in placements {
+                let rect = (placement.rect * render_scale).to_rect();
+                let position = Point::from(scale * Vec2::from(placement.position));
+                blit(source, rect, placement.ratio, &mut pixmap, position);
+            }
+        }
+
+        Some((pixmap, offset))
+    }
+
+    fn preview_pixmap(&mut self, width: f32, height: f32, samples: usize) -> Option<Pixmap> {
+        self.doc.preview_pixmap(width, height, samples)
+    }
+
+    fn layout(&mut self, width: u32, height: u32, font_size: f32, dpi: u16) {
+        self.doc.layout(width, height, font_size, dpi);
+        self.dims = (width, height);
+        self.font_size = font_size;
+        self.dpi = dpi;
+        self.invalidate();
+    }
+
+    fn set_text_align(&mut self, text_align: TextAlign) {
+        self.text_align = text_align;
+        self.invalidate();
+    }
+
+    fn set_font_family(&mut self, _family_name: &str, _search_path: &str) {
+    }
+
+    fn set_margin_width(&mut self, width: i32) {
+        self.margin_width = width;
+        self.invalidate();
+    }
+
+    fn set_line_height(&mut self, line_height: f32) {
+        self.line_height = line_height;
+        self.invalidate();
+    }
+
+    fn set_hyphen_penalty(&mut self, _hyphen_penalty: i32) {
+    }
+
+    fn set_stretch_tolerance(&mut self, _stretch_tolerance: f32) {
+    }
+
+    fn set_ignore_document_css(&mut self, _ignore: bool) {
+    }
+
+    fn title(&self) -> Option<String> {
+        self.doc.title()
+    }
+
+    fn author(&self) -> Option<String> {
+        self.doc.author()
+    }
+
+    fn metadata(&self, key: &str) -> Option<String> {
+        self.doc.metadata(key)
+    }
+
+    fn is_reflowable(&self) -> bool {
+        true
+    }
+
+    fn has_synthetic_page_numbers(&self) -> bool {
+        true
+    }
+
+    fn is_right_to_left(&self) -> bool {
+        self.doc.is_right_to_left()
+    }
+
+    fn set_right_to_left(&mut self, right_to_left: bool) {
+        self.doc.set_right_to_left(right_to_left);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_components() {
+        let mut pixmap = Pixmap::new(20, 10, 1);
+        for (x, y) in [(2, 2), (3, 3), (4, 2), (10, 5), (11, 5), (11, 6)] {
+            pixmap.data[y * 20 + x] = 0;
+        }
+        let boxes = components(&pixmap);
+        assert_eq!(boxes, vec![rect![2, 2, 5, 4], rect![10, 5, 12, 7]]);
+    }
+
+    #[test]
+    fn test_segment_components() {
+        // Two lines of two words, and the dot of an *i*.
+        let glyphs = |lefts: [i32; 4]| {
+            let mut boxes = Vec::new();
+            for top in [10, 40] {
+                for left in lefts {
+                    boxes.push(rect![left, top, left + 8, top + 10]);
+                }
+            }
+            boxes.push(rect![12, 5, 14, 7]);
+            boxes
+        };
+        let lines = segment_components(glyphs([10, 20, 35, 45]), rect![0, 0, 200, 100]);
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0].words.len(), 2);
+        assert_eq!(lines[0].rect.min.y, 5.0);
+        assert!(lines.iter().all(|line| !line.figure));
+        // The left column comes first.
+        let lines = segment_components(glyphs([10, 20, 50, 60]), rect![0, 0, 200, 100]);
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[1].rect.min.x, 10.0);
+        assert_eq!(lines[2].rect.min.x, 50.0);
+    }
+
+    #[test]
+    fn test_flow_lines() {
+        let words = (0..4).map(|i| {
+            Word { rect: bndr![i as f32 * 20.0, 0.0, i as f32 * 20.0 + 15.0, 10.0], text: None }
+        }).collect::<Vec<Word>>();
+        let line = Line { rect: bndr![0.0, 0.0, 75.0, 10.0], words, paragraph: true, figure: false };
+        let lines = vec![line; 3];
+        let settings = FlowSettings {
+            width: 70,
+            height: 55,
+            margin: 5,
+            em: 10,
+            line_height: 1.0,
+            text_align: TextAlign::Left,
+        };
+        let reflow = flow_lines(&lines, &settings);
+        assert_eq!(reflow.scale, 1.0);
+        // Each paragraph takes two lines.
+        assert_eq!(reflow.pages.len(), 2);
+        assert_eq!(reflow.pages[0].offset, 0);
+        assert_eq!(reflow.pages[1].offset, 8);
+        assert_eq!(reflow.pages[0].placements[0].position, pt!(15, 5));
+    }
+
+    #[test]
+    fn test_flow_dense_page() {
+        // Three words per reflowed page, more words than the span of a page.
+        let count = 3 * REFLOW_PAGE_SPAN / 2;
+        let words = (0..count).map(|i| {
+            Word { rect: bndr![i as f32, 0.0, i as f32 + 1.0, 10.0], text: None }
+        }).collect::<Vec<Word>>();
+        let line = Line { rect: bndr![0.0, 0.0, count as f32, 10.0], words, paragraph: false, figure: false };
+        let settings = FlowSettings {
+            width: 20,
+            height: 20,
+            margin: 5,
+            em: 10,
+            line_height: 1.0,
+            text_align: TextAlign::Left,
+        };
+        let reflow = flow_lines(&[line], &settings);
+        assert_eq!(reflow.pages.len(), count / 3);
+        assert_eq!(reflow.pages[1].offset, 3);
+        assert_eq!(reflow.pages[count / 3 - 1].offset, REFLOW_PAGE_SPAN - 1);
+        assert!(reflow.pages.windows(2).all(|w| w[0].offset < w[1].offset));
+    }
+}
@@ -0,0 +1,392 @@
+use std::fs;
+use std::fmt;
+use std::str::FromStr;
+use std::path::{Path, PathBuf};
+use chrono::{Local, NaiveDateTime};
+use septem::Roman;
+use serde::{Serialize, Deserialize};
+use anyhow::{Error, Context, format_err};
+use crate::document::{Document, BYTES_PER_PAGE, has_synthetic_page_numbers, open_book};
+use crate::document::pdf::PdfOpener;
+use crate::metadata::{Info, ReaderInfo};
+use crate::helpers::datetime_format;
+
+pub const EXPORT_FORMATS: [ExportFormat; 3] = [ExportFormat::Markdown, ExportFormat::Json, ExportFormat::Csv];
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ExportFormat {
+    Markdown,
+    Json,
+    Csv,
+}
+
+impl ExportFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            ExportFormat::Markdown => "md",
+            ExportFormat::Json => "json",
+            ExportFormat::Csv => "csv",
+        }
+    }
+}
+
+impl fmt::Display for ExportFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            ExportFormat::Markdown => write!(f, "Markdown"),
+            ExportFormat::Json => write!(f, "JSON"),
+            ExportFormat::Csv => write!(f, "CSV"),
+        }
+    }
+}
+
+impl FromStr for ExportFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "markdown" | "md" => Ok(ExportFormat::Markdown),
+            "json" => Ok(ExportFormat::Json),
+            "csv" => Ok(ExportFormat::Csv),
+            _ => Err(format_err!("unknown export format: {}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Highlight {
+    pub text: String,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub note: String,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub chapter: String,
+    pub page: String,
+    pub location: usize,
+    #[serde(with = "datetime_format")]
+    pub modified: NaiveDateTime,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Bookmark {
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub chapter: String,
+    pub page: String,
+    pub location: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BookAnnotations {
+    pub title: String,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub author: String,
+    pub path: PathBuf,
+    #[serde(skip)]
+    pub synthetic: bool,
+    pub highlights: Vec<Highlight>,
+    pub bookmarks: Vec<Bookmark>,
+}
+
+impl BookAnnotations {
+    pub fn new(info: &Info) -> BookAnnotations {
+        let synthetic = has_synthetic_page_numbers(&info.file.kind, info.reader.as_ref());
+        BookAnnotations::build(info, synthetic, |_| String::new())
+    }
+
+    // Uses the document to resolve the chapter titles.
+    pub fn from_document(info: &Info, doc: &mut dyn Document) -> BookAnnotations {
+        let synthetic = doc.has_synthetic_page_numbers();
+        let toc = doc.toc().unwrap_or_default();
+        BookAnnotations::build(info, synthetic, |location| {
+            doc.chapter(location, &toc)
+               .map(|(chap, _)| chap.title.clone())
+               .unwrap_or_default()
+        })
+    }
+
+    fn build<F>(info: &Info, synthetic: bool, mut chapter: F) -> BookAnnotations where F: FnMut(usize) -> String {
+        let mut book = BookAnnotations {
+            title: info.title(),
+            author: info.author.clone(),
+            path: info.file.path.clone(),
+            synthetic,
+            highlights: Vec::new(),
+            bookmarks: Vec::new(),
+        };
+
+        let reader = match info.reader.as_ref() {
+            Some(reader) => reader,
+            None => return book,
+        };
+
+        let mut annotations = reader.annotations.clone();
+        annotations.sort_by(|a, b| a.selection[0].cmp(&b.selection[0]));
+
+        for annot in annotations {
+            let location = annot.selection[0].location();
+            book.highlights.push(Highlight {
+                text: annot.text,
+                note: annot.note,
+                chapter: chapter(location),
+                page: page_label(reader, location, synthetic),
+                location,
+                modified: annot.modified,
+            });
+        }
+
+        for location in reader.bookmarks.iter().copied() {
+            book.bookmarks.push(Bookmark {
+                chapter: chapter(location),
+                page: page_label(reader, location, synthetic),
+                location,
+            });
+        }
+
+        book
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.highlights.is_empty() && self.bookmarks.is_empty()
+    }
+}
+
+// Names the page containing the given location, taking the user defined page names into account.
+pub fn page_label(reader: &ReaderInfo, location: usize, synthetic: bool) -> String {
+    if let Some((index, name)) = reader.page_names.range(..=location).next_back() {
+        if *index == location {
+            return name.clone();
+        }
+        if !synthetic {
+            let delta = (location - index) as u32;
+            if let Ok(number) = name.parse::<u32>() {
+                return (number + delta).to_string();
+            } else if let Ok(number) = Roman::from_str(name) {
+                let roman = Roman::from_unchecked(*number + delta);
+                return if name.chars().all(char::is_lowercase) {
+                    roman.to_lowercase()
+                } else {
+                    roman.to_uppercase()
+                };
+            }
+        }
+    }
+
+    if synthetic {
+        format!("{:.1}", location as f64 / BYTES_PER_PAGE)
+    } else {
+        format!("{}", location + 1)
+    }
+}
+
+pub fn to_markdown(books: &[BookAnnotations]) -> String {
+    let mut buf = String::new();
+
+    for book in books {
+        if !buf.is_empty() {
+            buf.push('\n');
+        }
+
+        buf.push_str(&format!("# {}\n\n", book.title));
+
+        if !book.author.is_empty() {
+            buf.push_str(&format!("*{}*\n\n", book.author));
+        }
+
+        let mut chapter = "";
+
+        for highlight in &book.highlights {
+            if !highlight.chapter.is_empty() && highlight.chapter != chapter {
+                chapter = &highlight.chapter;
+                buf.push_str(&format!("## {}\n\n", chapter));
+            }
+
+            for line in highlight.text.lines() {
+                buf.push_str(&format!("> {}\n", line));
+            }
+
+            buf.push('\n');
+
+            if !highlight.note.is_empty() {
+                buf.push_str(&format!("{}\n\n", highlight.note));
+            }
+
+            buf.push_str(&format!("— Page {}, {}\n\n",
+                                  highlight.page,
+                                  highlight.modified.format(datetime_format::FORMAT)));
+        }
+
+        if !book.bookmarks.is_empty() {
+            buf.push_str("## Bookmarks\n\n");
+
+            for bookmark in &book.bookmarks {
+                if bookmark.chapter.is_empty() {
+                    buf.push_str(&format!("- Page {}\n", bookmark.page));
+                } else {
+                    buf.push_str(&format!("- Page {} ({})\n", bookmark.page, bookmark.chapter));
+                }
+            }
+        }
+    }
+
+    buf
+}
+
+pub fn to_json(books: &[BookAnnotations]) -> Result<String, Error> {
+    serde_json::to_string_pretty(books).map_err(Into::into)
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+// Uses the column names expected by Readwise's CSV import.
+pub fn to_csv(books: &[BookAnnotations]) -> String {
+    let mut buf = "Highlight,Title,Author,Note,Location,Location Type,Date\n".to_string();
+
+    for book in books {
+        let location_type = if book.synthetic { "location" } else { "page" };
+
+        for highlight in &book.highlights {
+            let location = if book.synthetic {
+                highlight.location
+            } else {
+                highlight.location + 1
+            };
+            let fields = [csv_field(&highlight.text),
+                          csv_field(&book.title),
+                          csv_field(&book.author),
+                          csv_field(&highlight.note),
+                          location.to_string(),
+                          location_type.to_string(),
+                          highlight.modified.format(datetime_format::FORMAT).to_string()];
+            buf.push_str(&fields.join(","));
+            buf.push('\n');
+        }
+    }
+
+    buf
+}
+
+pub fn render(books: &[BookAnnotations], format: ExportFormat) -> Result<String, Error> {
+    match format {
+        ExportFormat::Markdown => Ok(to_markdown(books)),
+        ExportFormat::Json => to_json(books),
+        ExportFormat::Csv => Ok(to_csv(books)),
+    }
+}
+
+fn save(books: &[BookAnnotations], format: ExportFormat, path: &Path) -> Result<(), Error> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let text = render(books, format)?;
+    fs::write(path, text).with_context(|| format!("can't write {}", path.display()))
+}
+
+// Writes the annotations of a single book into `dir` and returns the path of the export.
+pub fn export_book<P: AsRef<Path>>(book: &BookAnnotations, format: ExportFormat, dir: P) -> Result<PathBuf, Error> {
+    let stem = book.path.file_stem()
+                   .map(|s| s.to_string_lossy().into_owned())
+                   .unwrap_or_else(|| book.title.clone());
+    let path = dir.as_ref().join(format!("{}.{}", stem, format.extension()));
+    save(std::slice::from_ref(book), format, &path)?;
+    Ok(path)
+}
+
+// Writes a copy of the PDF with the highlights, the notes and the handwritten strokes of the book.
+pub fn export_annotated_pdf<P: AsRef<Path>, Q: AsRef<Path>>(home: P, info: &Info, dir: Q) -> Result<PathBuf, Error> {
+    let reader = info.reader.as_ref().ok_or_else(|| format_err!("no annotations"))?;
+    let doc = PdfOpener::new().and_then(|o| o.open(home.as_ref().join(&info.file.path)))
+                              .ok_or_else(|| format_err!("can't open {}", info.file.path.display()))?;
+    let stem = info.file.path.file_stem()
+                   .map(|s| s.to_string_lossy().into_owned())
+                   .unwrap_or_else(|| info.title());
+    fs::create_dir_all(dir.as_ref())?;
+    let path = dir.as_ref().join(format!("{}-annotated.pdf", stem));
+    doc.save_annotated(&path, &reader.annotations, &reader.ink)?;
+    Ok(path)
+}
+
+// Writes the annotations of all the given books into a single dated file.
+pub fn export_books<P: AsRef<Path>>(books: &[BookAnnotations], format: ExportFormat, dir: P) -> Result<PathBuf, Error> {
+    let name = format!("library-{}.{}", Local::now().format("%Y-%m-%d"), format.extension());
+    let path = dir.as_ref().join(name);
+    save(books, format, &path)?;
+    Ok(path)
+}
+
+// Opens each document to resolve the chapter titles, the books without annotations nor bookmarks are skipped:
+// the ink is only exported within annotated PDFs.
+pub fn collect<P: AsRef<Path>>(home: P, infos: &[Info]) -> Vec<BookAnnotations> {
+    infos.iter().filter(|info| info.reader.as_ref().map_or(false, |r| {
+        !r.annotations.is_empty() || !r.bookmarks.is_empty()
+    })).map(|info| {
+        match open_book(home.as_ref().join(&info.file.path), info.reader.as_ref()) {
+            Some(mut doc) => BookAnnotations::from_document(info, doc.as_mut()),
+            None => BookAnnotations::new(info),
+        }
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::document::TextLocation;
+    use crate::metadata::Annotation;
+
+    fn book() -> BookAnnotations {
+        let mut reader = ReaderInfo::default();
+        reader.page_names.insert(4, "1".to_string());
+        reader.bookmarks.insert(7);
+        reader.annotations.push(Annotation {
+            text: "Call me \"Ishmael\", he said".to_string(),
+            note: "Opening".to_string(),
+            selection: [TextLocation::Static(5, 0), TextLocation::Static(5, 3)],
+            .. Default::default()
+        });
+        let info = Info {
+            title: "Moby Dick".to_string(),
+            author: "Herman Melville".to_string(),
+            file: crate::metadata::FileInfo {
+                path: PathBuf::from("moby-dick.pdf"),
+                kind: "pdf".to_string(),
+                size: 0,
+            },
+            reader: Some(reader),
+            .. Default::default()
+        };
+        BookAnnotations::new(&info)
+    }
+
+    #[test]
+    fn test_page_label() {
+        let mut reader = ReaderInfo::default();
+        reader.page_names.insert(2, "i".to_string());
+        reader.page_names.insert(10, "1".to_string());
+        assert_eq!(page_label(&reader, 0, false), "1");
+        assert_eq!(page_label(&reader, 4, false), "iii");
+        assert_eq!(page_label(&reader, 14, false), "5");
+        assert_eq!(page_label(&reader, 4096, true), "2.0");
+    }
+
+    #[test]
+    fn test_exports() {
+        let book = book();
+        assert_eq!(book.highlights[0].page, "2");
+        assert_eq!(book.bookmarks[0].page, "4");
+        let markdown = to_markdown(std::slice::from_ref(&book));
+        assert!(markdown.starts_with("# Moby Dick\n\n*Herman Melville*\n\n> Call me"));
+        assert!(markdown.contains("- Page 4\n"));
+        let csv = to_csv(std::slice::from_ref(&book));
+        assert_eq!(csv.lines().nth(1).unwrap().split(',').next(), Some("\"Call me \"\"Ishmael\"\""));
+        assert!(csv.lines().nth(1).unwrap().contains(",Moby Dick,Herman Melville,Opening,6,page,"));
+    }
+}
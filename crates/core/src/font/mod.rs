@@ -1051,6 +1051,8 @@ pub struct Font {
     // lowercase and uppercase x heights
     pub x_heights: (u32, u32),
     space_codepoint: u32,
+    // Shape the text from top to bottom.
+    vertical: bool,
 }
 
 impl FontOpener {
@@ -1079,7 +1081,7 @@ impl FontOpener {
             let x_heights = (0, 0);
             let space_codepoint = FT_Get_Char_Index(face, ' ' as libc::c_ulong);
             Ok(Font { lib: self.0.clone(), face, font,
-                      size: 0, dpi: 0, ellipsis, x_heights, space_codepoint })
+                      size: 0, dpi: 0, ellipsis, x_heights, space_codepoint, vertical: false })
         }
     }
 
@@ -1095,7 +1097,7 @@ impl FontOpener {
             let x_heights = (0, 0);
             let space_codepoint = FT_Get_Char_Index(face, ' ' as libc::c_ulong);
             Ok(Font { lib: self.0.clone(), face, font,
-                      size: 0, dpi: 0, ellipsis, x_heights, space_codepoint })
+                      size: 0, dpi: 0, ellipsis, x_heights, space_codepoint, vertical: false })
         }
     }
 }
@@ -1248,18 +1250,28 @@ impl Font {
     }
 
     #[inline]
-    unsafe fn patch(&mut self, txt: &str, features: &[HbFeature], render_plan: &mut RenderPlan, missing_glyphs: Vec<(usize, usize)>, buf: *mut HbBuffer) {
+    unsafe fn patch(&mut self, txt: &str, features: &[HbFeature], render_plan: &mut RenderPlan, missing_glyphs: Vec<(usize, usize)>, buf: *mut HbBuffer, direction: HbDirection) {
         let mut drift = 0;
         for (mut start, mut end) in missing_glyphs.into_iter() {
             start = (start as i32 + drift).max(0) as usize;
             end = (end as i32 + drift).max(0) as usize;
             hb_buffer_clear_contents(buf);
-            let start_index = render_plan.glyphs[start].cluster;
-            let end_index = render_plan.glyphs.get(end).map(|g| g.cluster)
-                                       .unwrap_or_else(|| txt.len());
+            // The clusters are decreasing when the direction is RTL.
+            let (start_index, end_index) = if direction == HB_DIRECTION_RTL {
+                (render_plan.glyphs[end-1].cluster,
+                 start.checked_sub(1).map(|i| render_plan.glyphs[i].cluster)
+                      .unwrap_or_else(|| txt.len()))
+            } else {
+                (render_plan.glyphs[start].cluster,
+                 render_plan.glyphs.get(end).map(|g| g.cluster)
+                            .unwrap_or_else(|| txt.len()))
+            };
             let chunk = &txt[start_index..end_index];
             hb_buffer_add_utf8(buf, chunk.as_ptr() as *const libc::c_char,
                                chunk.len() as libc::c_int, 0, -1);
+            if direction != HB_DIRECTION_LTR {
+                hb_buffer_set_direction(buf, direction);
+            }
             hb_buffer_guess_segment_properties(buf);
             let mut script = hb_buffer_get_script(buf);
             if script == HB_SCRIPT_INVALID || script == HB_SCRIPT_UNKNOWN {
@@ -1282,11 +1294,12 @@ impl Font {
             for i in 0..len {
                 let pos_i = &*pos.add(i);
                 let info_i = &*info.add(i);
-                render_plan.width += pos_i.x_advance >> 6;
+                let advance = glyph_advance(pos_i, direction);
+                render_plan.width += advance.x;
                 glyphs.push(GlyphPlan {
                     codepoint: info_i.codepoint,
                     cluster: start_index + info_i.cluster as usize,
-                    advance: pt!(pos_i.x_advance >> 6, pos_i.y_advance >> 6),
+                    advance,
                     offset: pt!(pos_i.x_offset >> 6, -pos_i.y_offset >> 6),
                 });
                 render_plan.scripts.insert(start+i, script);
@@ -1301,13 +1314,33 @@ impl Font {
     }
 
     pub fn plan<S: AsRef<str>>(&mut self, text: S, max_width: Option<i32>, features: Option<&[String]>) -> RenderPlan {
+        self.shape(text.as_ref(), max_width, features, HB_DIRECTION_LTR)
+    }
+
+    // The glyphs are given in visual order, and the clusters in reverse order.
+    pub fn plan_rtl<S: AsRef<str>>(&mut self, text: S, features: Option<&[String]>) -> RenderPlan {
+        self.shape(text.as_ref(), None, features, HB_DIRECTION_RTL)
+    }
+
+    pub fn set_vertical(&mut self, vertical: bool) {
+        self.vertical = vertical;
+    }
+
+    fn shape(&mut self, text: &str, max_width: Option<i32>, features: Option<&[String]>, direction: HbDirection) -> RenderPlan {
+        // Vertical text is set upright, from top to bottom.
+        let direction = if self.vertical && direction == HB_DIRECTION_LTR {
+            HB_DIRECTION_TTB
+        } else {
+            direction
+        };
+
         unsafe {
             let buf = hb_buffer_create();
-            hb_buffer_add_utf8(buf, text.as_ref().as_ptr() as *const libc::c_char,
-                               text.as_ref().len() as libc::c_int, 0, -1);
+            hb_buffer_add_utf8(buf, text.as_ptr() as *const libc::c_char,
+                               text.len() as libc::c_int, 0, -1);
 
             // If the direction is RTL, the clusters are given in reverse order.
-            hb_buffer_set_direction(buf, HB_DIRECTION_LTR);
+            hb_buffer_set_direction(buf, direction);
             hb_buffer_guess_segment_properties(buf);
 
             let features_vec: Vec<HbFeature> = features.map(|ftr|
@@ -1329,12 +1362,16 @@ impl Font {
             let len = hb_buffer_get_length(buf) as usize;
             let info = hb_buffer_get_glyph_infos(buf, ptr::null_mut());
             let pos = hb_buffer_get_glyph_positions(buf, ptr::null_mut());
-            let mut render_plan = RenderPlan::default();
+            let mut render_plan = RenderPlan {
+                vertical: direction == HB_DIRECTION_TTB,
+                .. Default::default()
+            };
             let mut missing_glyphs = Vec::new();
 
             for i in 0..len {
                 let pos_i = &*pos.add(i);
                 let info_i = &*info.add(i);
+                let advance = glyph_advance(pos_i, direction);
                 if info_i.codepoint == 0 {
                     if let Some((start, end)) = missing_glyphs.pop() {
                         if i == end {
@@ -1347,18 +1384,18 @@ impl Font {
                         missing_glyphs.push((i, i+1));
                     }
                 } else {
-                    render_plan.width += pos_i.x_advance >> 6;
+                    render_plan.width += advance.x;
                 }
                 let glyph = GlyphPlan {
                     codepoint: info_i.codepoint,
                     cluster: info_i.cluster as usize,
-                    advance: pt!(pos_i.x_advance >> 6, pos_i.y_advance >> 6),
+                    advance,
                     offset: pt!(pos_i.x_offset >> 6, -pos_i.y_offset >> 6),
                 };
                 render_plan.glyphs.push(glyph);
             }
 
-            self.patch(text.as_ref(), &features_vec, &mut render_plan, missing_glyphs, buf);
+            self.patch(text, &features_vec, &mut render_plan, missing_glyphs, buf, direction);
 
             hb_buffer_destroy(buf);
 
@@ -1545,7 +1582,11 @@ impl Font {
                     }
                 }
 
-                pos += glyph.advance;
+                pos += if render_plan.vertical {
+                    pt!(0, glyph.advance.x)
+                } else {
+                    glyph.advance
+                };
             }
 
             for (_, face) in fallback_faces {
@@ -1600,6 +1641,8 @@ pub struct RenderPlan {
     pub width: i32,
     scripts: FxHashMap<usize, HbScript>,
     glyphs: Vec<GlyphPlan>,
+    // The advances of vertical plans are downward.
+    vertical: bool,
 }
 
 impl Default for RenderPlan {
@@ -1608,6 +1651,7 @@ impl Default for RenderPlan {
             width: 0,
             scripts: FxHashMap::default(),
             glyphs: Vec::new(),
+            vertical: false,
         }
     }
 }
@@ -1627,6 +1671,7 @@ impl RenderPlan {
             width,
             scripts,
             glyphs,
+            vertical: self.vertical,
         }
     }
 
@@ -1659,6 +1704,7 @@ impl RenderPlan {
             width: next_width,
             scripts: next_scripts,
             glyphs: next_glyphs,
+            vertical: self.vertical,
         }
     }
 
@@ -1713,6 +1759,16 @@ impl Drop for Font {
     }
 }
 
+// The advance along the line: the vertical advances given by HarfBuzz are negative.
+#[inline]
+fn glyph_advance(pos: &HbGlyphPosition, direction: HbDirection) -> Point {
+    if direction == HB_DIRECTION_TTB {
+        pt!(-pos.y_advance >> 6, 0)
+    } else {
+        pt!(pos.x_advance >> 6, pos.y_advance >> 6)
+    }
+}
+
 #[inline]
 fn tag(c1: u8, c2: u8, c3: u8, c4: u8) -> u32 {
     ((c1 as u32) << 24) | ((c2 as u32) << 16) | ((c3 as u32) << 8) | c4 as u32
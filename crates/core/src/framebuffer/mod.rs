@@ -387,6 +387,15 @@ pub trait Framebuffer {
 
     fn draw_segment(&mut self, start: Point, end: Point, start_radius: f32, end_radius: f32, color: Color) {
         let rect = Rectangle::from_segment(start, end, start_radius.ceil() as i32, end_radius.ceil() as i32);
+        self.draw_clipped_segment(start, end, start_radius, end_radius, color, &rect);
+    }
+
+    // Only draws the part of the segment inside the given rectangle.
+    fn draw_clipped_segment(&mut self, start: Point, end: Point, start_radius: f32, end_radius: f32, color: Color, clip: &Rectangle) {
+        let rect = match Rectangle::from_segment(start, end, start_radius.ceil() as i32, end_radius.ceil() as i32).intersection(clip) {
+            Some(rect) => rect,
+            None => return,
+        };
         let a = vec2!(start.x as f32, start.y as f32) + 0.5;
         let b = vec2!(end.x as f32, end.y as f32) + 0.5;
 
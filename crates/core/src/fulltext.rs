@@ -0,0 +1,337 @@
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::collections::BTreeMap;
+use std::io::{BufReader, BufWriter, Write};
+use anyhow::{Context, Error};
+use flate2::Compression;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use serde::{Serialize, Deserialize};
+use unicode_normalization::UnicodeNormalization;
+use unicode_normalization::char::is_combining_mark;
+use percent_encoding::{utf8_percent_encode, percent_decode_str, NON_ALPHANUMERIC};
+use crate::document::{Document, Location};
+use crate::metadata::Info;
+
+const LIBRARY_SCHEME: &str = "library:";
+const SNIPPET_RADIUS: usize = 60;
+const MAX_MATCHES_PER_BOOK: usize = 50;
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct BookIndex {
+    // The location of each page. The text itself isn't stored: the snippets
+    // are extracted from the document when the results are listed.
+    locations: Vec<usize>,
+    // The indices of the pages where each term appears.
+    terms: BTreeMap<String, Vec<u32>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Match {
+    pub location: usize,
+    pub snippet: String,
+}
+
+impl BookIndex {
+    pub fn build(doc: &mut dyn Document) -> BookIndex {
+        let mut index = BookIndex::default();
+        let mut loc = Location::Exact(0);
+        let mut last_location = None;
+
+        while let Some(location) = doc.resolve_location(loc) {
+            if last_location.map_or(false, |last| location <= last) {
+                break;
+            }
+
+            if let Some(text) = page_text(doc, location) {
+                let page = index.locations.len() as u32;
+                for (start, end) in word_bounds(&text) {
+                    let pages = index.terms.entry(normalize(&text[start..end])).or_default();
+                    if pages.last() != Some(&page) {
+                        pages.push(page);
+                    }
+                }
+
+                index.locations.push(location);
+            }
+
+            last_location = Some(location);
+            loc = Location::Next(location);
+        }
+
+        index
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<BookIndex, Error> {
+        let file = File::open(path.as_ref())
+                        .with_context(|| format!("can't open file {}", path.as_ref().display()))?;
+        let reader = GzDecoder::new(BufReader::new(file));
+        serde_json::from_reader(reader)
+                   .with_context(|| format!("can't parse index from {}", path.as_ref().display()))
+    }
+
+    // The index is written next to its final path, and then renamed,
+    // so that an interrupted write doesn't leave a truncated index behind.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        let part_path = path.as_ref().with_extension("part");
+        let file = File::create(&part_path)
+                        .with_context(|| format!("can't create file {}", part_path.display()))?;
+        let mut writer = GzEncoder::new(BufWriter::new(file), Compression::default());
+        serde_json::to_writer(&mut writer, self)
+                   .with_context(|| format!("can't serialize index to {}", part_path.display()))?;
+        writer.finish()?.flush()?;
+        fs::rename(&part_path, path.as_ref())
+           .with_context(|| format!("can't rename {}", part_path.display()))
+    }
+
+    // Returns the locations of the pages that contain all the terms.
+    pub fn search(&self, query: &[Term]) -> Vec<usize> {
+        let mut pages: Option<Vec<u32>> = None;
+
+        for term in query {
+            let mut term_pages = match term {
+                Term::Word(word) => self.terms.get(word).cloned().unwrap_or_default(),
+                Term::Prefix(prefix) => {
+                    self.terms.range(prefix.clone()..)
+                        .take_while(|(word, _)| word.starts_with(prefix.as_str()))
+                        .flat_map(|(_, pages)| pages.iter().copied())
+                        .collect()
+                },
+            };
+            term_pages.sort_unstable();
+            term_pages.dedup();
+            pages = Some(match pages {
+                Some(pages) => pages.into_iter().filter(|page| term_pages.binary_search(page).is_ok()).collect(),
+                None => term_pages,
+            });
+        }
+
+        pages.unwrap_or_default().into_iter()
+             .take(MAX_MATCHES_PER_BOOK)
+             .map(|page| self.locations[page as usize])
+             .collect()
+    }
+}
+
+// Extracts the snippets of the given locations from the indexed document.
+pub fn matches(doc: &mut dyn Document, locations: &[usize], query: &[Term]) -> Vec<Match> {
+    locations.iter().map(|&location| {
+        let snippet = page_text(doc, location).map(|text| snippet(&text, &query[0]))
+                                              .unwrap_or_default();
+        Match { location, snippet }
+    }).collect()
+}
+
+fn page_text(doc: &mut dyn Document, location: usize) -> Option<String> {
+    let (words, _) = doc.words(Location::Exact(location))?;
+    let mut text = String::new();
+    for word in &words {
+        if text.ends_with('\u{00AD}') {
+            text.pop();
+        } else if !text.ends_with('-') && !text.is_empty() {
+            text.push(' ');
+        }
+        text += &word.text;
+    }
+    Some(text)
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Term {
+    Word(String),
+    Prefix(String),
+}
+
+impl Term {
+    fn is_match(&self, word: &str) -> bool {
+        match self {
+            Term::Word(w) => w == word,
+            Term::Prefix(p) => word.starts_with(p.as_str()),
+        }
+    }
+}
+
+// Words ending with an asterisk match any word they are a prefix of.
+pub fn parse_query(text: &str) -> Vec<Term> {
+    let mut terms = Vec::new();
+
+    for token in text.split_whitespace() {
+        let prefix = token.ends_with('*');
+        let token = normalize(token);
+        let bounds = word_bounds(&token).collect::<Vec<(usize, usize)>>();
+        for (i, &(start, end)) in bounds.iter().enumerate() {
+            let word = token[start..end].to_string();
+            if prefix && i == bounds.len() - 1 {
+                terms.push(Term::Prefix(word));
+            } else {
+                terms.push(Term::Word(word));
+            }
+        }
+    }
+
+    terms
+}
+
+pub fn results_as_html(query: &str, results: &[(Info, Vec<Match>)]) -> String {
+    let mut buf = "<html>\n\t<head>\n\t\t<title>Search Results</title>\n\t\t\
+                   <link rel=\"stylesheet\" type=\"text/css\" href=\"css/fulltext.css\"/>\n\t\
+                   </head>\n\t<body>\n".to_string();
+    buf.push_str(&format!("\t\t<h1>{}</h1>\n", escape(query)));
+    for (info, matches) in results {
+        let path = utf8_percent_encode(&info.file.path.to_string_lossy(), NON_ALPHANUMERIC).to_string();
+        let title = if info.title.is_empty() {
+            info.file.path.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default()
+        } else {
+            info.title.clone()
+        };
+        buf.push_str(&format!("\t\t<h2>{}</h2>\n", escape(&title)));
+        if !info.author.is_empty() {
+            buf.push_str(&format!("\t\t<p class=\"author\">{}</p>\n", escape(&info.author)));
+        }
+        buf.push_str("\t\t<ul>\n");
+        for m in matches {
+            buf.push_str(&format!("\t\t\t<li><a href=\"{}{}@{}\">{}</a></li>\n",
+                                  LIBRARY_SCHEME, path, m.location, escape(&m.snippet)));
+        }
+        buf.push_str("\t\t</ul>\n");
+    }
+    buf.push_str("\t</body>\n</html>");
+    buf
+}
+
+// Parses the links of the results: `library:PATH@LOCATION`.
+pub fn parse_library_uri(uri: &str) -> Option<(PathBuf, usize)> {
+    let (path, location) = uri.strip_prefix(LIBRARY_SCHEME)?.rsplit_once('@')?;
+    let location = location.parse::<usize>().ok()?;
+    let path = percent_decode_str(path).decode_utf8().ok()?;
+    Some((PathBuf::from(path.as_ref()), location))
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+fn normalize(word: &str) -> String {
+    word.nfkd().filter(|&c| !is_combining_mark(c))
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+fn word_bounds(text: &str) -> impl Iterator<Item=(usize, usize)> + '_ {
+    let mut start = None;
+    text.char_indices().chain(Some((text.len(), ' '))).filter_map(move |(i, c)| {
+        if c.is_alphanumeric() {
+            if start.is_none() {
+                start = Some(i);
+            }
+            None
+        } else {
+            start.take().map(|s| (s, i))
+        }
+    })
+}
+
+fn snippet(text: &str, term: &Term) -> String {
+    let (start, end) = word_bounds(text).find(|&(s, e)| term.is_match(&normalize(&text[s..e])))
+                                        .unwrap_or((0, 0));
+    let mut first = text[..start].char_indices().rev()
+                                 .nth(SNIPPET_RADIUS).map_or(0, |(i, _)| i);
+    let mut last = text[end..].char_indices()
+                              .nth(SNIPPET_RADIUS).map_or(text.len(), |(i, _)| end + i);
+
+    if first > 0 {
+        first = text[first..start].find(' ').map_or(first, |i| first + i + 1);
+    }
+
+    if last < text.len() {
+        last = text[end..last].rfind(' ').map_or(last, |i| end + i);
+    }
+
+    let mut snippet = String::new();
+    if first > 0 {
+        snippet.push('…');
+    }
+    snippet.push_str(&text[first..last]);
+    if last < text.len() {
+        snippet.push('…');
+    }
+    snippet
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::document::{BoundedText, TextLocation, TocEntry};
+    use crate::framebuffer::Pixmap;
+    use crate::geom::{Boundary, CycleDir};
+    use crate::metadata::TextAlign;
+
+    // A document whose pages are lists of words.
+    struct WordsDocument(Vec<Vec<&'static str>>);
+
+    impl Document for WordsDocument {
+        fn dims(&self, _index: usize) -> Option<(f32, f32)> { None }
+        fn pages_count(&self) -> usize { self.0.len() }
+        fn toc(&mut self) -> Option<Vec<TocEntry>> { None }
+        fn chapter<'a>(&mut self, _offset: usize, _toc: &'a [TocEntry]) -> Option<(&'a TocEntry, f32)> { None }
+        fn chapter_relative<'a>(&mut self, _offset: usize, _dir: CycleDir, _toc: &'a [TocEntry]) -> Option<&'a TocEntry> { None }
+        fn words(&mut self, loc: Location) -> Option<(Vec<BoundedText>, usize)> {
+            let index = self.resolve_location(loc)?;
+            let words = self.0[index].iter().enumerate().map(|(i, text)| BoundedText {
+                text: text.to_string(),
+                rect: Boundary::new(vec2!(0.0, 0.0), vec2!(0.0, 0.0)),
+                location: TextLocation::Static(index, i),
+            }).collect();
+            Some((words, index))
+        }
+        fn lines(&mut self, _loc: Location) -> Option<(Vec<BoundedText>, usize)> { None }
+        fn links(&mut self, _loc: Location) -> Option<(Vec<BoundedText>, usize)> { None }
+        fn images(&mut self, _loc: Location) -> Option<(Vec<Boundary>, usize)> { None }
+        fn pixmap(&mut self, _loc: Location, _scale: f32, _samples: usize) -> Option<(Pixmap, usize)> { None }
+        fn layout(&mut self, _width: u32, _height: u32, _font_size: f32, _dpi: u16) {}
+        fn set_font_family(&mut self, _family_name: &str, _search_path: &str) {}
+        fn set_margin_width(&mut self, _width: i32) {}
+        fn set_text_align(&mut self, _text_align: TextAlign) {}
+        fn set_line_height(&mut self, _line_height: f32) {}
+        fn set_hyphen_penalty(&mut self, _hyphen_penalty: i32) {}
+        fn set_stretch_tolerance(&mut self, _stretch_tolerance: f32) {}
+        fn set_ignore_document_css(&mut self, _ignore: bool) {}
+        fn title(&self) -> Option<String> { None }
+        fn author(&self) -> Option<String> { None }
+        fn metadata(&self, _key: &str) -> Option<String> { None }
+        fn is_reflowable(&self) -> bool { false }
+    }
+
+    #[test]
+    fn test_search() {
+        let mut doc = WordsDocument(vec![
+            vec!["Le", "café", "est", "servi."],
+            vec!["Tighten", "the", "bolts", "to", "12", "Nm."],
+            vec!["The", "cafeteria", "is", "clo\u{00AD}", "sed.", "Tightening", "torque:", "12", "Nm."],
+        ]);
+        let index = BookIndex::build(&mut doc);
+        let pages = ["Le café est servi.", "Tighten the bolts to 12 Nm.", "The cafeteria is closed. Tightening torque: 12 Nm."];
+        assert_eq!((0..3).filter_map(|i| page_text(&mut doc, i)).collect::<Vec<String>>(), pages);
+        // Only the postings are stored.
+        assert!(!serde_json::to_string(&index).unwrap().contains(pages[0]));
+
+        let locations = |query: &str| index.search(&parse_query(query));
+        assert_eq!(locations("CAFE"), vec![0]);
+        assert_eq!(locations("caf*"), vec![0, 2]);
+        assert_eq!(locations("12 nm"), vec![1, 2]);
+        assert_eq!(locations("closed"), vec![2]);
+        assert_eq!(locations("tighten* torque"), vec![2]);
+        assert_eq!(locations("bolts cafeteria"), Vec::<usize>::new());
+        let query = parse_query("torque");
+        let results = matches(&mut doc, &index.search(&query), &query);
+        assert_eq!(results[0].snippet, pages[2]);
+
+        let info = Info {
+            file: crate::metadata::FileInfo { path: PathBuf::from("Manuals/Bosch @ Home.pdf"), .. Default::default() },
+            .. Default::default()
+        };
+        let html = results_as_html("torque", &[(info, results)]);
+        let uri = html.split("href=\"").nth(2).and_then(|s| s.split('"').next()).unwrap();
+        assert_eq!(parse_library_uri(uri), Some((PathBuf::from("Manuals/Bosch @ Home.pdf"), 2)));
+    }
+}
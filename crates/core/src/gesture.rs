@@ -322,6 +322,8 @@ pub fn parse_gesture_events(rx: &Receiver<DeviceEvent>, ty: &Sender<Event>) {
                 let mut bt = buttons.lock().unwrap();
                 bt.remove(&code);
             },
+            // The views receive the stylus events as is: they never become gestures.
+            DeviceEvent::Pen { .. } => (),
             _ => (),
         }
     }
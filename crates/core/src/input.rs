@@ -1,6 +1,6 @@
 use crate::device::CURRENT_DEVICE;
 use crate::framebuffer::Display;
-use crate::geom::{LinearDir, Point};
+use crate::geom::{LinearDir, Point, Vec2};
 use crate::settings::ButtonScheme;
 use anyhow::{Context, Error};
 use libremarkable::device::{CURRENT_DEVICE as CURRENT_LIBREMARKABLE_DEVICE, rotate};
@@ -36,6 +36,8 @@ pub const SYN_MT_REPORT: u16 = 0x02;
 pub const ABS_X: u16 = ecodes::ABS_X; // reMarkable specific
 pub const ABS_Y: u16 = ecodes::ABS_Y; //  reMarkable specific
 pub const ABS_PRESSURE: u16 = ecodes::ABS_PRESSURE; // reMarkable MT Pressure
+pub const ABS_TILT_X: u16 = ecodes::ABS_TILT_X;
+pub const ABS_TILT_Y: u16 = ecodes::ABS_TILT_Y;
 pub const MSC_RAW: u16 = 0x03;
 pub const SYN_REPORT: u16 = 0x00;
 
@@ -68,6 +70,9 @@ pub const KEY_FORWARD: u16 = ecodes::KEY_RIGHT;
 pub const PEN_ERASE: u16 = ecodes::BTN_TOOL_RUBBER;
 pub const PEN_HIGHLIGHT: u16 = ecodes::BTN_TOOL_PEN;
 pub const SLEEP_COVER: [u16; 2] = [59, 35];
+// The ranges of the values reported by the digitizer.
+pub const PEN_MAX_PRESSURE: i32 = 4095;
+pub const PEN_MAX_TILT: i32 = 9000;
 // Synthetic touch button
 pub const BTN_TOUCH: u16 = 330;
 // ---
@@ -149,6 +154,23 @@ pub enum FingerStatus {
     Up,
 }
 
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum PenStatus {
+    // Above the screen, without touching it.
+    Hover,
+    Down,
+    Motion,
+    Up,
+    // Out of the range of the digitizer.
+    Leave,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum PenTool {
+    Pen,
+    Eraser,
+}
+
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub enum ButtonStatus {
     Pressed,
@@ -187,11 +209,6 @@ impl ButtonCode {
             KEY_LIGHT => ButtonCode::Light,
             KEY_BACKWARD => resolve_button_direction(LinearDir::Backward, rotation, button_scheme),
             KEY_FORWARD => resolve_button_direction(LinearDir::Forward, rotation, button_scheme),
-            // TODO: Attempt to add ButtonCode: highlight and/or erase
-            //PEN_ERASE => ButtonCode::Erase,
-            //PEN_HIGHLIGHT => ButtonCode::Highlight,
-            // Would work ↑ but usage doesnt seem to make sense this way.
-            // Most likely a dedicated buttont hat is used in another manner on kobo devices.
             _ => ButtonCode::Raw(code),
         }
     }
@@ -254,6 +271,15 @@ pub enum DeviceEvent {
         status: FingerStatus,
         position: Point,
     },
+    // The pressure is in [0, 1] and the tilt is in degrees, along both axes.
+    Pen {
+        time: f64,
+        status: PenStatus,
+        tool: PenTool,
+        position: Point,
+        pressure: f32,
+        tilt: Vec2,
+    },
     Button {
         time: f64,
         code: ButtonCode,
@@ -455,6 +481,71 @@ impl Default for EvFinger {
     }
 }
 
+// Same for the stylus
+#[derive(Debug)]
+struct EvPen {
+    pos: Point,
+    updated: bool, // Report motion or hover at SYN_REPORT?
+    pressure: i32,
+    tilt: (i32, i32),
+    tool: Option<PenTool>,
+    last_tool: Option<PenTool>,
+    last_contact: bool,
+}
+
+impl Default for EvPen {
+    fn default() -> EvPen {
+        EvPen {
+            pos: Point { x: -1, y: -1 },
+            updated: false,
+            pressure: 0,
+            tilt: (0, 0),
+            tool: None,
+            last_tool: None,
+            last_contact: false,
+        }
+    }
+}
+
+impl EvPen {
+    fn event(&self, time: f64, status: PenStatus) -> DeviceEvent {
+        DeviceEvent::Pen {
+            time,
+            status,
+            tool: self.tool.or(self.last_tool).unwrap_or(PenTool::Pen),
+            position: self.pos,
+            pressure: (self.pressure as f32 / PEN_MAX_PRESSURE as f32).clamp(0.0, 1.0),
+            tilt: vec2!(self.tilt.0 as f32 * 90.0 / PEN_MAX_TILT as f32,
+                        self.tilt.1 as f32 * 90.0 / PEN_MAX_TILT as f32),
+        }
+    }
+
+    // Sends the changes of the last report.
+    fn report(&mut self, time: f64, ty: &Sender<DeviceEvent>) {
+        let contact = self.pressure > 0;
+
+        if self.last_contact && !contact {
+            ty.send(self.event(time, PenStatus::Up)).ok();
+        } else if !self.last_contact && contact {
+            ty.send(self.event(time, PenStatus::Down)).ok();
+        } else if contact && self.updated {
+            ty.send(self.event(time, PenStatus::Motion)).ok();
+        }
+
+        if !contact && self.tool.is_some() && self.updated {
+            ty.send(self.event(time, PenStatus::Hover)).ok();
+        }
+
+        if self.tool.is_none() && self.last_tool.is_some() {
+            ty.send(self.event(time, PenStatus::Leave)).ok();
+        }
+
+        self.last_contact = contact;
+        self.last_tool = self.tool;
+        self.updated = false;
+    }
+}
+
 /// Apply the correct postion. The roation, scaling, etc. will be done
 /// through libremarkable::input::rotate which should make adding support
 /// for a future device generation trivial without any effort here.
@@ -462,7 +553,7 @@ fn update_proper_postion(
     input_device: InputDevice,
     input_coord: rotate::CoordinatePart,
     current_roation: i8,
-    finger: &mut EvFinger,
+    pos: &mut Point,
 ) {
     let (placement, scale, orig_size, orig_portrait_width, orig_portrait_height) =
         match input_device {
@@ -513,16 +604,15 @@ fn update_proper_postion(
     }
     .rotate_part(rotated_part, &rotated_size);
 
-    // Apply to finger pos and scale to fit framebuffer size
+    // Apply to the position and scale to fit framebuffer size
     match rotated_part {
         rotate::CoordinatePart::X(rotated_value) => {
-            finger.pos.x = (f32::from(rotated_value) * scale) as i32;
+            pos.x = (f32::from(rotated_value) * scale) as i32;
         }
         rotate::CoordinatePart::Y(rotated_value) => {
-            finger.pos.y = (f32::from(rotated_value) * scale) as i32;
+            pos.y = (f32::from(rotated_value) * scale) as i32;
         }
     }
-    finger.pos_updated = true;
 }
 
 pub fn parse_device_events(
@@ -534,7 +624,6 @@ pub fn parse_device_events(
     ignored_buttoncodes_rx: Receiver<Vec<ButtonCode>>,
 ) {
     let mut current_slot: i32 = 0; // Basically for which finger id to events are meant
-    const PEN_SLOT: i32 = 100; // Figer id for wacom pen
     let mut last_activity = -60;
     let Display {
         dims: _dims,
@@ -542,6 +631,7 @@ pub fn parse_device_events(
     } = display;
 
     let mut ev_fingers: HashMap<i32, EvFinger> = HashMap::new();
+    let mut ev_pen = EvPen::default();
 
     /*let mut tc = match CURRENT_DEVICE.proto {
         TouchProto::Single => SINGLE_TOUCH_CODES,
@@ -569,32 +659,44 @@ pub fn parse_device_events(
                     InputDevice::Wacom,
                     rotate::CoordinatePart::X(evt.value as u16),
                     rotation,
-                    ev_fingers.entry(PEN_SLOT).or_default(),
+                    &mut ev_pen.pos,
                 );
+                ev_pen.updated = true;
             } else if evt.code == ecodes::ABS_Y {
                 // (wacom)
                 update_proper_postion(
                     InputDevice::Wacom,
                     rotate::CoordinatePart::Y(evt.value as u16),
                     rotation,
-                    ev_fingers.entry(PEN_SLOT).or_default(),
+                    &mut ev_pen.pos,
                 );
+                ev_pen.updated = true;
+            } else if evt.code == ABS_TILT_X {
+                ev_pen.tilt.0 = evt.value;
+                ev_pen.updated = true;
+            } else if evt.code == ABS_TILT_Y {
+                ev_pen.tilt.1 = evt.value;
+                ev_pen.updated = true;
             } else if evt.code == ABS_MT_SLOT {
                 current_slot = evt.value;
             } else if evt.code == ABS_MT_POSITION_X {
+                let finger = ev_fingers.entry(current_slot).or_default();
                 update_proper_postion(
                     InputDevice::Multitouch,
                     rotate::CoordinatePart::X(evt.value as u16),
                     rotation,
-                    ev_fingers.entry(current_slot).or_default(),
+                    &mut finger.pos,
                 );
+                finger.pos_updated = true;
             } else if evt.code == ABS_MT_POSITION_Y {
+                let finger = ev_fingers.entry(current_slot).or_default();
                 update_proper_postion(
                     InputDevice::Multitouch,
                     rotate::CoordinatePart::Y(evt.value as u16),
                     rotation,
-                    ev_fingers.entry(current_slot).or_default(),
+                    &mut finger.pos,
                 );
+                finger.pos_updated = true;
             } else if evt.code == ABS_MT_PRESSURE {
                 // Pressure is sent after position and tracking id
                 // So its better to get a click with an actual pos for
@@ -613,7 +715,8 @@ pub fn parse_device_events(
                     ev_fingers.entry(current_slot).or_default().pressed = false;
                 }
             } else if evt.code == ecodes::ABS_PRESSURE {
-                ev_fingers.entry(PEN_SLOT).or_default().pressed = evt.value > 0;
+                ev_pen.pressure = evt.value;
+                ev_pen.updated = true;
             }
         } else if evt.kind == EV_SYN && evt.code == SYN_REPORT {
             // The absolute value accounts for the wrapping around that might occur,
@@ -624,6 +727,8 @@ pub fn parse_device_events(
             }
 
             if evt.code == SYN_REPORT {
+                ev_pen.report(seconds(evt.time), ty);
+
                 // Send new positions
                 for (slot, finger) in ev_fingers.iter_mut() {
                     if !finger.last_pressed && finger.pressed {
@@ -688,6 +793,14 @@ pub fn parse_device_events(
                     //}
                     rotation = next_rotation;
                 }
+            } else if evt.code == PEN_HIGHLIGHT || evt.code == PEN_ERASE {
+                // The tool entering or leaving the range of the digitizer
+                let tool = if evt.code == PEN_ERASE { PenTool::Eraser } else { PenTool::Pen };
+                if evt.value == VAL_PRESS {
+                    ev_pen.tool = Some(tool);
+                } else if evt.value == VAL_RELEASE && ev_pen.tool == Some(tool) {
+                    ev_pen.tool = None;
+                }
             } else if evt.code != BTN_TOUCH {
                 if let Some(button_status) = ButtonStatus::try_from_raw(evt.value) {
                     let code = ButtonCode::from_raw(evt.code, rotation, button_scheme);
@@ -716,3 +829,42 @@ pub fn parse_device_events(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pen_report() {
+        let (ty, ry) = mpsc::channel();
+        let mut pen = EvPen::default();
+        let statuses = |ry: &Receiver<DeviceEvent>| -> Vec<PenStatus> {
+            ry.try_iter().filter_map(|evt| match evt {
+                DeviceEvent::Pen { status, .. } => Some(status),
+                _ => None,
+            }).collect()
+        };
+
+        pen.tool = Some(PenTool::Pen);
+        pen.updated = true;
+        pen.report(0.0, &ty);
+        assert_eq!(statuses(&ry), vec![PenStatus::Hover]);
+
+        pen.pressure = PEN_MAX_PRESSURE / 2;
+        pen.updated = true;
+        pen.report(0.1, &ty);
+        pen.pos = pt!(10, 10);
+        pen.updated = true;
+        pen.report(0.2, &ty);
+        pen.report(0.3, &ty);
+        assert_eq!(statuses(&ry), vec![PenStatus::Down, PenStatus::Motion]);
+
+        pen.pressure = 0;
+        pen.tool = None;
+        pen.updated = true;
+        pen.report(0.4, &ty);
+        let events: Vec<DeviceEvent> = ry.try_iter().collect();
+        assert!(matches!(events[..], [DeviceEvent::Pen { status: PenStatus::Up, tool: PenTool::Pen, .. },
+                                      DeviceEvent::Pen { status: PenStatus::Leave, .. }]));
+    }
+}
@@ -0,0 +1,216 @@
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+use std::time::Duration;
+use anyhow::{Error, format_err};
+use serde::{Serialize, Deserialize};
+use reqwest::blocking::Client;
+use reqwest::header::ACCEPT;
+use crate::metadata::ReaderInfo;
+use crate::document::reflow::REFLOW_PAGE_SPAN;
+use crate::settings::ProgressSyncSettings;
+
+const MEDIA_TYPE: &str = "application/vnd.koreader.v1+json";
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+const SAMPLE_SIZE: u64 = 1024;
+const SAMPLES_COUNT: u64 = 12;
+
+// The progress record exchanged with a KOReader sync server.
+// `progress` is a page number for paginated documents. KOReader stores
+// an XPointer for reflowable documents: we can't resolve those, so we
+// only rely on `percentage` for them.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Progress {
+    pub document: String,
+    pub progress: String,
+    pub percentage: f32,
+    pub device: String,
+    pub device_id: String,
+    #[serde(skip_serializing)]
+    pub timestamp: i64,
+}
+
+impl Progress {
+    // Returns the location, in the document's own units, this progress points to.
+    pub fn location(&self, pages_count: usize, synthetic: bool) -> usize {
+        let last_location = pages_count.saturating_sub(1);
+
+        if !synthetic {
+            if let Ok(page) = self.progress.parse::<usize>() {
+                return page.saturating_sub(1).min(last_location);
+            }
+        }
+
+        ((self.percentage.clamp(0.0, 1.0) * pages_count as f32) as usize).min(last_location)
+    }
+}
+
+pub fn percentage(current_page: usize, pages_count: usize, synthetic: bool) -> f32 {
+    if pages_count == 0 {
+        return 0.0;
+    }
+
+    if synthetic {
+        current_page as f32 / pages_count as f32
+    } else {
+        (current_page + 1) as f32 / pages_count as f32
+    }
+}
+
+// Reflowed documents are synced with the numbers of their original pages.
+pub fn original_position(current_page: usize, pages_count: usize, synthetic: bool, reflow: bool) -> (usize, usize, bool) {
+    if reflow {
+        (current_page / REFLOW_PAGE_SPAN, pages_count / REFLOW_PAGE_SPAN, false)
+    } else {
+        (current_page, pages_count, synthetic)
+    }
+}
+
+// Computes the document hash used by KOReader: the MD5 digest of 1 KiB samples
+// read at the offsets 0 and 1024 × 4ⁱ for i in 0..=10.
+pub fn partial_md5<P: AsRef<Path>>(path: P) -> Result<String, Error> {
+    let mut file = File::open(path.as_ref())?;
+    let mut context = md5::Context::new();
+    let mut buf = Vec::with_capacity(SAMPLE_SIZE as usize);
+
+    for i in 0..SAMPLES_COUNT {
+        let offset = if i == 0 { 0 } else { SAMPLE_SIZE << (2 * (i - 1)) };
+        file.seek(SeekFrom::Start(offset))?;
+        buf.clear();
+        (&mut file).take(SAMPLE_SIZE).read_to_end(&mut buf)?;
+        if buf.is_empty() {
+            break;
+        }
+        context.consume(&buf);
+    }
+
+    Ok(format!("{:x}", context.compute()))
+}
+
+#[derive(Debug, Clone)]
+pub struct ProgressSync {
+    server: String,
+    username: String,
+    key: String,
+    device: String,
+    device_id: String,
+}
+
+impl ProgressSync {
+    pub fn new(settings: &ProgressSyncSettings) -> Option<ProgressSync> {
+        if settings.server.is_empty() || settings.username.is_empty() {
+            return None;
+        }
+
+        let device_id = format!("{:X}", md5::compute(format!("{}:{}", settings.device, settings.username)));
+
+        Some(ProgressSync {
+            server: settings.server.trim_end_matches('/').to_string(),
+            username: settings.username.clone(),
+            key: format!("{:x}", md5::compute(&settings.password)),
+            device: settings.device.clone(),
+            device_id,
+        })
+    }
+
+    // Returns true if the given progress was pushed by this device.
+    pub fn is_own(&self, progress: &Progress) -> bool {
+        progress.device_id == self.device_id
+    }
+
+    pub fn push(&self, document: &str, reader: &ReaderInfo, synthetic: bool) -> Result<(), Error> {
+        let (current_page, pages_count, synthetic) = original_position(reader.current_page, reader.pages_count,
+                                                                       synthetic, reader.reflow == Some(true));
+        let progress = Progress {
+            document: document.to_string(),
+            progress: if synthetic {
+                current_page.to_string()
+            } else {
+                (current_page + 1).to_string()
+            },
+            percentage: if reader.finished {
+                1.0
+            } else {
+                percentage(current_page, pages_count, synthetic)
+            },
+            device: self.device.clone(),
+            device_id: self.device_id.clone(),
+            timestamp: 0,
+        };
+
+        let response = self.client()?
+                           .put(format!("{}/syncs/progress", self.server))
+                           .header(ACCEPT, MEDIA_TYPE)
+                           .header("x-auth-user", &self.username)
+                           .header("x-auth-key", &self.key)
+                           .json(&progress)
+                           .send()?;
+
+        if !response.status().is_success() {
+            return Err(format_err!("unexpected status: {}", response.status()));
+        }
+
+        Ok(())
+    }
+
+    // Returns the last progress pushed for the given document, if any.
+    pub fn pull(&self, document: &str) -> Result<Option<Progress>, Error> {
+        let response = self.client()?
+                           .get(format!("{}/syncs/progress/{}", self.server, document))
+                           .header(ACCEPT, MEDIA_TYPE)
+                           .header("x-auth-user", &self.username)
+                           .header("x-auth-key", &self.key)
+                           .send()?;
+
+        if !response.status().is_success() {
+            return Err(format_err!("unexpected status: {}", response.status()));
+        }
+
+        let progress: Progress = response.json()?;
+
+        if progress.document.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(progress))
+        }
+    }
+
+    fn client(&self) -> Result<Client, Error> {
+        Client::builder().timeout(REQUEST_TIMEOUT)
+                         .build()
+                         .map_err(Into::into)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_location() {
+        let mut progress = Progress {
+            progress: "12".to_string(),
+            percentage: 0.5,
+            .. Default::default()
+        };
+        assert_eq!(progress.location(100, false), 11);
+        assert_eq!(progress.location(1000, true), 500);
+        progress.progress = "/body/DocFragment[3]/body/p[2]/text().0".to_string();
+        assert_eq!(progress.location(100, false), 50);
+        progress.percentage = 1.0;
+        assert_eq!(progress.location(100, false), 99);
+        assert_eq!(percentage(49, 100, false), 0.5);
+        assert_eq!(percentage(50, 100, true), 0.5);
+    }
+
+    #[test]
+    fn test_original_position() {
+        let span = REFLOW_PAGE_SPAN;
+        assert_eq!(original_position(12 * span + 7, 100 * span, true, true), (12, 100, false));
+        assert_eq!(original_position(12, 100, true, false), (12, 100, true));
+        let progress = Progress { progress: "13".to_string(), .. Default::default() };
+        let (_, pages_count, synthetic) = original_position(0, 100 * span, true, true);
+        assert_eq!(progress.location(pages_count, synthetic) * span, 12 * span);
+    }
+}
@@ -14,6 +14,11 @@ pub mod document;
 pub mod library;
 pub mod view;
 pub mod metadata;
+pub mod export;
+pub mod calibre;
+pub mod kosync;
+pub mod fulltext;
+pub mod statistics;
 pub mod rtc;
 pub mod settings;
 pub mod font;
@@ -1,4 +1,5 @@
-use std::fs::{self, File};
+use std::fs::{self, File, Metadata};
+use std::thread;
 use std::str::FromStr;
 use std::time::{SystemTime, Duration};
 use std::path::{PathBuf, Path};
@@ -12,13 +13,18 @@ use anyhow::{Error, bail, format_err};
 use crate::metadata::{Info, ReaderInfo, FileInfo, BookQuery, SimpleStatus, SortMethod};
 use crate::metadata::{sort, sorter, extract_metadata_from_document};
 use crate::settings::{LibraryMode, ImportSettings};
-use crate::document::file_kind;
+use crate::document::{file_kind, has_synthetic_page_numbers};
+use crate::kosync::{ProgressSync, partial_md5};
+use crate::statistics::{Statistics, Session};
+use crate::calibre::{CalibreDatabase, extract_metadata_from_calibre, DATABASE_FILENAME};
 use crate::helpers::{Fingerprint, Fp, save_json, load_json, IsHidden};
 
 pub const METADATA_FILENAME: &str = ".metadata.json";
 pub const FAT32_EPOCH_FILENAME: &str = ".fat32-epoch";
 pub const READING_STATES_DIRNAME: &str = ".reading-states";
 pub const THUMBNAIL_PREVIEWS_DIRNAME: &str = ".thumbnail-previews";
+pub const FULLTEXT_INDEX_DIRNAME: &str = ".fulltext-index";
+pub const STATISTICS_FILENAME: &str = ".statistics.json";
 
 pub struct Library {
     pub home: PathBuf,
@@ -32,6 +38,7 @@ pub struct Library {
     pub sort_method: SortMethod,
     pub reverse_order: bool,
     pub show_hidden: bool,
+    pub progress_sync: Option<ProgressSync>,
 }
 
 impl Library {
@@ -92,6 +99,11 @@ impl Library {
             fs::create_dir(&path).ok();
         }
 
+        let path = home.as_ref().join(FULLTEXT_INDEX_DIRNAME);
+        if !path.exists() {
+            fs::create_dir(&path).ok();
+        }
+
         let paths = if mode == LibraryMode::Database {
             db.iter().map(|(fp, info)| (info.file.path.clone(), *fp)).collect()
         } else {
@@ -120,6 +132,7 @@ impl Library {
             sort_method,
             reverse_order: sort_method.reverse_order(),
             show_hidden: false,
+            progress_sync: None,
         })
     }
 
@@ -186,26 +199,8 @@ impl Library {
                             continue;
                         }
 
-                        let kind = file_kind(&path).unwrap_or_default();
                         let md = entry.metadata().unwrap();
-                        let size = md.len();
-                        let fp = md.fingerprint(self.fat32_epoch).unwrap();
-                        let file = FileInfo {
-                            path: relat.to_path_buf(),
-                            kind,
-                            size,
-                        };
-                        let secs = (*fp >> 32) as i64;
-                        let nsecs = ((*fp & ((1<<32) - 1)) % 1_000_000_000) as u32;
-                        let added = DateTime::from_timestamp(secs, nsecs).unwrap().naive_utc();
-                        let info = Info {
-                            file,
-                            added,
-                            reader: self.reading_states.get(&fp).cloned(),
-                            .. Default::default()
-                        };
-
-                        files.push(info);
+                        files.push(self.filesystem_info(relat, &md));
                     }
                 }
 
@@ -216,11 +211,54 @@ impl Library {
         (files, dirs)
     }
 
+    pub fn info<P: AsRef<Path>>(&self, path: P) -> Option<Info> {
+        match self.mode {
+            LibraryMode::Database => {
+                self.paths.get(path.as_ref())
+                    .and_then(|fp| self.db.get(fp))
+                    .cloned()
+            },
+            LibraryMode::Filesystem => {
+                self.home.join(path.as_ref()).metadata().ok()
+                    .filter(|md| md.is_file())
+                    .map(|md| self.filesystem_info(path.as_ref(), &md))
+            },
+        }
+    }
+
+    fn filesystem_info(&self, relat: &Path, md: &Metadata) -> Info {
+        let kind = file_kind(self.home.join(relat)).unwrap_or_default();
+        let size = md.len();
+        let fp = md.fingerprint(self.fat32_epoch).unwrap();
+        let file = FileInfo {
+            path: relat.to_path_buf(),
+            kind,
+            size,
+        };
+        let secs = (*fp >> 32) as i64;
+        let nsecs = ((*fp & ((1<<32) - 1)) % 1_000_000_000) as u32;
+        let added = DateTime::from_timestamp(secs, nsecs).unwrap().naive_utc();
+        Info {
+            file,
+            added,
+            reader: self.reading_states.get(&fp).cloned(),
+            .. Default::default()
+        }
+    }
+
     pub fn import(&mut self, settings: &ImportSettings) {
         if self.mode == LibraryMode::Filesystem {
             return;
         }
 
+        let calibre_db = if settings.calibre_metadata && self.home.join(DATABASE_FILENAME).exists() {
+            CalibreDatabase::open(self.home.join(DATABASE_FILENAME))
+                           .map_err(|e| eprintln!("Can't open Calibre database: {:#}.", e))
+                           .ok()
+        } else {
+            None
+        };
+
         for entry in WalkDir::new(&self.home).min_depth(1).into_iter()
                              .filter_entry(|e| !e.is_hidden()) {
             if entry.is_err() {
@@ -252,8 +290,8 @@ impl Library {
             } else if let Some(fp2) = self.paths.get(relat).cloned() {
                 println!("Update fingerprint for {}: {} → {}.", relat.display(), fp2, fp);
                 let mut info = self.db.swap_remove(&fp2).unwrap();
-                if settings.sync_metadata && settings.metadata_kinds.contains(&info.file.kind) {
-                    extract_metadata_from_document(&self.home, &mut info);
+                if settings.sync_metadata {
+                    extract_metadata(&self.home, settings, calibre_db.as_ref(), &mut info);
                 }
                 self.db.insert(fp, info);
                 self.db[&fp].file.size = md.len();
@@ -265,6 +303,10 @@ impl Library {
                 if tpp.exists() {
                     fs::remove_file(tpp).ok();
                 }
+                let fip = self.fulltext_index_path(fp2);
+                if fip.exists() {
+                    fs::remove_file(fip).ok();
+                }
                 self.has_db_changed = true;
             } else {
                 let fp1 = self.fat32_epoch.checked_sub(Duration::from_secs(1))
@@ -294,6 +336,9 @@ impl Library {
                     let tp1 = self.thumbnail_preview_path(nfp);
                     let tp2 = self.thumbnail_preview_path(fp);
                     fs::rename(tp1, tp2).ok();
+                    let fi1 = self.fulltext_index_path(nfp);
+                    let fi2 = self.fulltext_index_path(fp);
+                    fs::rename(fi1, fi2).ok();
                     if relat != self.db[&fp].file.path {
                         println!("Update path for {}: {} → {}.",
                                  fp, self.db[&fp].file.path.display(), relat.display());
@@ -318,9 +363,7 @@ impl Library {
                         file,
                         .. Default::default()
                     };
-                    if settings.metadata_kinds.contains(&info.file.kind) {
-                        extract_metadata_from_document(&self.home, &mut info);
-                    }
+                    extract_metadata(&self.home, settings, calibre_db.as_ref(), &mut info);
                     self.db.insert(fp, info);
                     self.paths.insert(relat.to_path_buf(), fp);
                 }
@@ -350,8 +393,10 @@ impl Library {
 
             let reading_states_dir = home.join(READING_STATES_DIRNAME);
             let thumbnail_previews_dir = home.join(THUMBNAIL_PREVIEWS_DIRNAME);
+            let fulltext_index_dir = home.join(FULLTEXT_INDEX_DIRNAME);
             for entry in fs::read_dir(&reading_states_dir).unwrap()
-                            .chain(fs::read_dir(&thumbnail_previews_dir).unwrap()) {
+                            .chain(fs::read_dir(&thumbnail_previews_dir).unwrap())
+                            .chain(fs::read_dir(&fulltext_index_dir).unwrap()) {
                 if entry.is_err() {
                     continue;
                 }
@@ -439,6 +484,11 @@ impl Library {
             fs::remove_file(tpp)?;
         }
 
+        let fip = self.fulltext_index_path(fp);
+        if fip.exists() {
+            fs::remove_file(fip)?;
+        }
+
         if self.mode == LibraryMode::Database {
             self.paths.remove(path.as_ref());
             if self.db.shift_remove(&fp).is_some() {
@@ -496,6 +546,12 @@ impl Library {
             fs::copy(&tpp_src, &tpp_dest)?;
         }
 
+        let fip_src = self.fulltext_index_path(fp);
+        if fip_src.exists() {
+            let fip_dest = other.fulltext_index_path(fp);
+            fs::copy(&fip_src, &fip_dest)?;
+        }
+
         if other.mode == LibraryMode::Database {
             let info = self.db.get(&fp).cloned()
                            .or_else(||
@@ -571,6 +627,12 @@ impl Library {
             fs::rename(&tpp_src, &tpp_dest)?;
         }
 
+        let fip_src = self.fulltext_index_path(fp);
+        if fip_src.exists() {
+            let fip_dest = other.fulltext_index_path(fp);
+            fs::rename(&fip_src, &fip_dest)?;
+        }
+
         if other.mode == LibraryMode::Database {
             let info = self.db.shift_remove(&fp)
                            .or_else(||
@@ -640,8 +702,10 @@ impl Library {
 
         let reading_states_dir = self.home.join(READING_STATES_DIRNAME);
         let thumbnail_previews_dir = self.home.join(THUMBNAIL_PREVIEWS_DIRNAME);
+        let fulltext_index_dir = self.home.join(FULLTEXT_INDEX_DIRNAME);
         for entry in fs::read_dir(&reading_states_dir).unwrap()
-                        .chain(fs::read_dir(&thumbnail_previews_dir).unwrap()) {
+                        .chain(fs::read_dir(&thumbnail_previews_dir).unwrap())
+                        .chain(fs::read_dir(&fulltext_index_dir).unwrap()) {
             if entry.is_err() {
                 continue;
             }
@@ -702,6 +766,65 @@ impl Library {
                 self.reading_states.insert(fp, reader.clone());
             },
         }
+
+        if let Some(progress_sync) = self.progress_sync.clone() {
+            let path = self.home.join(path.as_ref());
+            let synthetic = reader.reflow == Some(true) ||
+                            file_kind(&path).map_or(false, |k| has_synthetic_page_numbers(&k, Some(reader)));
+            let reader = reader.clone();
+            thread::spawn(move || {
+                partial_md5(&path).and_then(|document| progress_sync.push(&document, &reader, synthetic))
+                                  .map_err(|e| eprintln!("Can't push reading progress: {:#}.", e))
+                                  .ok();
+            });
+        }
+    }
+
+    // Returns the documents that have annotations, bookmarks or ink.
+    pub fn annotated(&self) -> Vec<Info> {
+        let is_annotated = |r: &ReaderInfo| !r.annotations.is_empty() || !r.bookmarks.is_empty() || !r.ink.is_empty();
+
+        match self.mode {
+            LibraryMode::Database => {
+                self.db.values()
+                    .filter(|info| info.reader.as_ref().map_or(false, is_annotated))
+                    .cloned().collect()
+            },
+            LibraryMode::Filesystem => {
+                let mut files = Vec::new();
+
+                for entry in WalkDir::new(&self.home).min_depth(1)
+                                     .into_iter()
+                                     .filter_entry(|e| self.show_hidden || !e.is_hidden()) {
+                    if entry.is_err() {
+                        continue;
+                    }
+
+                    let entry = entry.unwrap();
+                    if entry.file_type().is_dir() {
+                        continue;
+                    }
+
+                    let path = entry.path();
+                    let md = entry.metadata().unwrap();
+                    let fp = md.fingerprint(self.fat32_epoch).unwrap();
+
+                    if let Some(reader_info) = self.reading_states.get(&fp).filter(|r| is_annotated(r)) {
+                        files.push(Info {
+                            file: FileInfo {
+                                path: path.strip_prefix(&self.home).unwrap_or(path).to_path_buf(),
+                                kind: file_kind(path).unwrap_or_default(),
+                                size: md.len(),
+                            },
+                            reader: Some(reader_info.clone()),
+                            .. Default::default()
+                        });
+                    }
+                }
+
+                files
+            },
+        }
     }
 
     pub fn thumbnail_preview<P: AsRef<Path>>(&self, path: P) -> PathBuf {
@@ -717,6 +840,27 @@ impl Library {
         }
     }
 
+    pub fn fulltext_index<P: AsRef<Path>>(&self, path: P) -> PathBuf {
+        let fp = self.paths.get(path.as_ref()).cloned().unwrap_or_else(|| {
+            self.home.join(path.as_ref())
+                .metadata().unwrap()
+                .fingerprint(self.fat32_epoch).unwrap()
+        });
+        self.fulltext_index_path(fp)
+    }
+
+    pub fn statistics(&self) -> Statistics {
+        Statistics::load(self.home.join(STATISTICS_FILENAME))
+    }
+
+    pub fn add_reading_session(&self, session: Session) {
+        let mut statistics = self.statistics();
+        statistics.add(session);
+        statistics.save(self.home.join(STATISTICS_FILENAME))
+                  .map_err(|e| eprintln!("Can't save reading statistics: {:#}.", e))
+                  .ok();
+    }
+
     pub fn set_status<P: AsRef<Path>>(&mut self, path: P, status: SimpleStatus) {
         let fp = self.paths.get(path.as_ref()).cloned().unwrap_or_else(|| {
             self.home.join(path.as_ref())
@@ -846,4 +990,21 @@ impl Library {
             .join(THUMBNAIL_PREVIEWS_DIRNAME)
             .join(format!("{}.png", fp))
     }
+
+    fn fulltext_index_path(&self, fp: Fp) -> PathBuf {
+        self.home
+            .join(FULLTEXT_INDEX_DIRNAME)
+            .join(format!("{}.gz", fp))
+    }
+}
+
+// The metadata maintained by Calibre takes precedence over the one embedded in the document.
+fn extract_metadata(home: &Path, settings: &ImportSettings, calibre_db: Option<&CalibreDatabase>, info: &mut Info) {
+    if settings.calibre_metadata && extract_metadata_from_calibre(home, calibre_db, info) {
+        return;
+    }
+
+    if settings.metadata_kinds.contains(&info.file.kind) {
+        extract_metadata_from_document(home, info);
+    }
 }
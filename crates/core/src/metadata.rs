@@ -11,16 +11,23 @@ use serde::{Serialize, Deserialize};
 use lazy_static::lazy_static;
 use titlecase::titlecase;
 use crate::geom::Point;
+use crate::color::{Color, BLACK};
 use crate::document::{Document, SimpleTocEntry, TextLocation};
 use crate::document::asciify;
 use crate::document::epub::EpubDocument;
 use crate::document::html::HtmlDocument;
+use crate::document::comic::ComicDocument;
+use crate::document::fb2::{read_fb2, metadata_from_fb2};
+use crate::document::html::xml::XmlParser;
 use crate::document::pdf::PdfOpener;
 use crate::document::djvu::DjvuOpener;
 use crate::helpers::datetime_format;
 
 pub const DEFAULT_CONTRAST_EXPONENT: f32 = 1.0;
 pub const DEFAULT_CONTRAST_GRAY: f32 = 224.0;
+// The version 0 predates the layout of plain text and FictionBook files by the HTML engine:
+// their locations were page indices.
+pub const READER_INFO_VERSION: u8 = 1;
 
 pub type Metadata = Vec<Info>;
 
@@ -103,6 +110,36 @@ impl Default for Annotation {
     }
 }
 
+// A stroke drawn with the stylus over a page, in the coordinates of the page at scale one.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct Stroke {
+    // The position and the pressure of each sample.
+    pub points: Vec<[f32; 3]>,
+    pub width: f32,
+    pub color: Color,
+    #[serde(with = "datetime_format")]
+    pub modified: NaiveDateTime,
+}
+
+impl Default for Stroke {
+    fn default() -> Self {
+        Stroke {
+            points: Vec::new(),
+            width: 1.0,
+            color: BLACK,
+            modified: Local::now().naive_local(),
+        }
+    }
+}
+
+impl Stroke {
+    // The radius of the stroke at a given pressure.
+    pub fn radius(&self, pressure: f32) -> f32 {
+        self.width * (0.5 + pressure) / 2.0
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Margin {
     pub top: f32,
@@ -194,6 +231,8 @@ impl fmt::Display for TextAlign {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(default, rename_all = "camelCase")]
 pub struct ReaderInfo {
+    #[serde(default)]
+    pub version: u8,
     #[serde(with = "datetime_format")]
     pub opened: NaiveDateTime,
     pub current_page: usize,
@@ -226,12 +265,18 @@ pub struct ReaderInfo {
     pub contrast_exponent: Option<f32>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub contrast_gray: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub right_to_left: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reflow: Option<bool>,
     #[serde(skip_serializing_if = "BTreeMap::is_empty")]
     pub page_names: BTreeMap<usize, String>,
     #[serde(skip_serializing_if = "BTreeSet::is_empty")]
     pub bookmarks: BTreeSet<usize>,
     #[serde(skip_serializing_if = "Vec::is_empty")]
     pub annotations: Vec<Annotation>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub ink: BTreeMap<usize, Vec<Stroke>>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Copy, Clone)]
@@ -269,6 +314,7 @@ impl ReaderInfo {
 impl Default for ReaderInfo {
     fn default() -> Self {
         ReaderInfo {
+            version: READER_INFO_VERSION,
             opened: Local::now().naive_local(),
             current_page: 0,
             pages_count: 1,
@@ -287,9 +333,12 @@ impl Default for ReaderInfo {
             line_height: None,
             contrast_exponent: None,
             contrast_gray: None,
+            right_to_left: None,
+            reflow: None,
             page_names: BTreeMap::new(),
             bookmarks: BTreeSet::new(),
             annotations: Vec::new(),
+            ink: BTreeMap::new(),
         }
     }
 }
@@ -475,6 +524,7 @@ pub struct BookQuery {
     pub bookmarks: Option<bool>,
     pub opened_after: Option<(bool, NaiveDateTime)>,
     pub added_after: Option<(bool, NaiveDateTime)>,
+    pub fulltext: Option<String>,
 }
 
 impl BookQuery {
@@ -501,6 +551,11 @@ impl BookQuery {
                         Some('e') => { buf.reverse(); query.edition = make_query(&buf.join(" ")); buf.clear(); },
                         Some('v') => { buf.reverse(); query.volume = make_query(&buf.join(" ")); buf.clear(); },
                         Some('n') => { buf.reverse(); query.number = make_query(&buf.join(" ")); buf.clear(); },
+                        Some('x') => {
+                            buf.reverse();
+                            query.fulltext = Some(buf.join(" ")).filter(|text| !text.trim().is_empty());
+                            buf.clear();
+                        },
                         Some('R') => query.reading = Some(!invert),
                         Some('N') => query.new = Some(!invert),
                         Some('F') => query.finished = Some(!invert),
@@ -546,7 +601,8 @@ impl BookQuery {
            query.annotations.is_none() &&
            query.bookmarks.is_none() &&
            query.opened_after.is_none() &&
-           query.added_after.is_none() {
+           query.added_after.is_none() &&
+           query.fulltext.is_none() {
             None
         } else {
             Some(query)
@@ -783,6 +839,28 @@ pub fn extract_metadata_from_document(prefix: &Path, info: &mut Info) {
                 Err(e) => eprintln!("Can't open {}: {:#}.", info.file.path.display(), e),
             }
         },
+        "cbz" | "cbr" => {
+            let doc = if info.file.kind == "cbz" {
+                ComicDocument::new(&path)
+            } else {
+                ComicDocument::new_rar(&path)
+            };
+            match doc {
+                Ok(doc) => {
+                    info.title = doc.title().unwrap_or_default();
+                    info.author = doc.author().unwrap_or_default();
+                    info.year = doc.year().unwrap_or_default();
+                    info.publisher = doc.publisher().unwrap_or_default();
+                    if let Some((title, index)) = doc.series() {
+                        info.series = title;
+                        info.number = index;
+                    }
+                    info.language = doc.language().unwrap_or_default();
+                    info.categories.append(&mut doc.categories());
+                },
+                Err(e) => eprintln!("Can't open {}: {:#}.", info.file.path.display(), e),
+            }
+        },
         "html" | "htm" => {
             match HtmlDocument::new(&path) {
                 Ok(doc) => {
@@ -793,6 +871,29 @@ pub fn extract_metadata_from_document(prefix: &Path, info: &mut Info) {
                 Err(e) => eprintln!("Can't open {}: {:#}.", info.file.path.display(), e),
             }
         },
+        "fb2" | "fb2.zip" => {
+            match read_fb2(&path) {
+                Ok(text) => {
+                    let metadata = metadata_from_fb2(&XmlParser::new(&text).parse());
+                    info.title = metadata.title;
+                    info.author = metadata.authors.join(", ");
+                    info.year = metadata.year;
+                    info.language = metadata.language;
+                    info.publisher = metadata.publisher;
+                    info.series = metadata.series;
+                    info.number = metadata.number;
+                    info.identifier = metadata.identifier;
+                    info.categories.extend(metadata.genres);
+                },
+                Err(e) => eprintln!("Can't open {}: {:#}.", info.file.path.display(), e),
+            }
+        },
+        "md" | "markdown" => {
+            match HtmlDocument::new_from_markdown(&path) {
+                Ok(doc) => info.title = doc.title().unwrap_or_default(),
+                Err(e) => eprintln!("Can't open {}: {:#}.", info.file.path.display(), e),
+            }
+        },
         "pdf" => {
             match PdfOpener::new().and_then(|o| o.open(path)) {
                 Some(doc) => {
@@ -933,3 +1034,38 @@ pub fn file_name_from_info(info: &Info) -> String {
         .replace('!', "")
         .replace(':', "")
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reader_info_version() {
+        let reader: ReaderInfo = serde_json::from_str(r#"{"opened":"2020-01-01 00:00:00","currentPage":3,"pagesCount":10}"#).unwrap();
+        assert_eq!(reader.version, 0);
+        assert_eq!(reader.current_page, 3);
+        let text = serde_json::to_string(&ReaderInfo::default()).unwrap();
+        let reader: ReaderInfo = serde_json::from_str(&text).unwrap();
+        assert_eq!(reader.version, READER_INFO_VERSION);
+    }
+
+    #[test]
+    fn test_ink_round_trip() {
+        let mut reader = ReaderInfo::default();
+        reader.ink.insert(4, vec![Stroke {
+            points: vec![[1.0, 2.0, 0.5], [3.5, 4.0, 0.25]],
+            width: 2.0,
+            .. Default::default()
+        }]);
+        let text = serde_json::to_string(&reader).unwrap();
+        assert!(text.contains(r#""ink":{"4":[{"points":[[1.0,2.0,0.5],[3.5,4.0,0.25]],"width":2.0,"#));
+        let reader: ReaderInfo = serde_json::from_str(&text).unwrap();
+        let strokes = &reader.ink[&4];
+        assert_eq!(strokes.len(), 1);
+        assert_eq!(strokes[0].points, vec![[1.0, 2.0, 0.5], [3.5, 4.0, 0.25]]);
+        assert_eq!(strokes[0].width, 2.0);
+        assert_eq!(strokes[0].color, BLACK);
+        let text = serde_json::to_string(&ReaderInfo::default()).unwrap();
+        assert!(!text.contains(r#""ink""#));
+    }
+}
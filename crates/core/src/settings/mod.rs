@@ -193,7 +193,10 @@ pub struct Settings {
     pub import: ImportSettings,
     pub dictionary: DictionarySettings,
     pub sketch: SketchSettings,
+    pub export: ExportSettings,
+    pub progress_sync: ProgressSyncSettings,
     pub calculator: CalculatorSettings,
+    pub handwriting: HandwritingSettings,
     pub battery: BatterySettings,
     pub frontlight_levels: LightLevels,
 }
@@ -241,6 +244,7 @@ pub struct ImportSettings {
     pub unshare_trigger: bool,
     pub startup_trigger: bool,
     pub sync_metadata: bool,
+    pub calibre_metadata: bool,
     pub metadata_kinds: FxHashSet<String>,
     pub allowed_kinds: FxHashSet<String>,
 }
@@ -272,6 +276,23 @@ pub struct SketchSettings {
     pub pen: Pen,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct ExportSettings {
+    pub save_path: PathBuf,
+}
+
+// The server is expected to speak the KOReader sync protocol.
+// Progress isn't synchronized when `server` or `username` is empty.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct ProgressSyncSettings {
+    pub server: String,
+    pub username: String,
+    pub password: String,
+    pub device: String,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(default, rename_all = "kebab-case")]
 pub struct CalculatorSettings {
@@ -280,6 +301,16 @@ pub struct CalculatorSettings {
     pub history_size: usize,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct HandwritingSettings {
+    // Show the handwriting pad instead of the keys of the keyboard.
+    pub enabled: bool,
+    pub language: String,
+    // The pause, in milliseconds, after which the ink is recognized.
+    pub delay: u64,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(default, rename_all = "kebab-case")]
 pub struct Pen {
@@ -314,6 +345,25 @@ impl Default for SketchSettings {
     }
 }
 
+impl Default for ExportSettings {
+    fn default() -> Self {
+        ExportSettings {
+            save_path: PathBuf::from("Annotations"),
+        }
+    }
+}
+
+impl Default for ProgressSyncSettings {
+    fn default() -> Self {
+        ProgressSyncSettings {
+            server: String::new(),
+            username: String::new(),
+            password: String::new(),
+            device: "Plato".to_string(),
+        }
+    }
+}
+
 impl Default for CalculatorSettings {
     fn default() -> Self {
         CalculatorSettings {
@@ -324,6 +374,16 @@ impl Default for CalculatorSettings {
     }
 }
 
+impl Default for HandwritingSettings {
+    fn default() -> Self {
+        HandwritingSettings {
+            enabled: false,
+            language: "en".to_string(),
+            delay: 800,
+        }
+    }
+}
+
 #[derive(Debug, Copy, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "kebab-case")]
 pub struct Columns {
@@ -531,7 +591,7 @@ impl Default for ReaderSettings {
             line_height: DEFAULT_LINE_HEIGHT,
             continuous_fit_to_width: true,
             ignore_document_css: false,
-            dithered_kinds: ["cbz", "png", "jpg", "jpeg"].iter().map(|k| k.to_string()).collect(),
+            dithered_kinds: ["cbz", "cbr", "png", "jpg", "jpeg"].iter().map(|k| k.to_string()).collect(),
             paragraph_breaker: ParagraphBreakerSettings::default(),
             refresh_rate: RefreshRateSettings::default(),
         }
@@ -544,9 +604,10 @@ impl Default for ImportSettings {
             unshare_trigger: true,
             startup_trigger: true,
             sync_metadata: true,
-            metadata_kinds: ["epub", "pdf", "djvu"].iter().map(|k| k.to_string()).collect(),
-            allowed_kinds: ["pdf", "djvu", "epub", "fb2", "txt",
-                            "xps", "oxps", "mobi", "cbz"].iter().map(|k| k.to_string()).collect(),
+            calibre_metadata: true,
+            metadata_kinds: ["epub", "pdf", "djvu", "cbz", "cbr", "md", "fb2", "fb2.zip"].iter().map(|k| k.to_string()).collect(),
+            allowed_kinds: ["pdf", "djvu", "epub", "fb2", "fb2.zip", "txt", "md",
+                            "xps", "oxps", "mobi", "cbz", "cbr"].iter().map(|k| k.to_string()).collect(),
         }
     }
 }
@@ -601,7 +662,10 @@ impl Default for Settings {
             import: ImportSettings::default(),
             dictionary: DictionarySettings::default(),
             sketch: SketchSettings::default(),
+            export: ExportSettings::default(),
+            progress_sync: ProgressSyncSettings::default(),
             calculator: CalculatorSettings::default(),
+            handwriting: HandwritingSettings::default(),
             battery: BatterySettings::default(),
             frontlight_levels: LightLevels::default(),
             frontlight_presets: Vec::new(),
@@ -0,0 +1,295 @@
+use std::path::{Path, PathBuf};
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use chrono::{Local, NaiveDate, NaiveDateTime, Datelike, Duration};
+use serde::{Serialize, Deserialize};
+use anyhow::Error;
+use crate::helpers::{load_json, save_json, datetime_format};
+
+// Intervals longer than this between two page turns aren't counted as reading time.
+const MAX_PAGE_DURATION: i64 = 600;
+// The time that has to be spent reading a book before estimates are given.
+const MIN_ESTIMATE_DURATION: u64 = 120;
+const RECENT_DAYS_COUNT: i64 = 7;
+const RECENT_WEEKS_COUNT: i64 = 8;
+const BOOKS_COUNT: usize = 10;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Session {
+    pub path: PathBuf,
+    pub title: String,
+    #[serde(with = "datetime_format")]
+    pub start: NaiveDateTime,
+    #[serde(with = "datetime_format")]
+    pub end: NaiveDateTime,
+    // The active reading time, in seconds.
+    pub duration: u64,
+    pub pages_turned: usize,
+    pub start_location: usize,
+    pub end_location: usize,
+    // The sum of the forward jumps between locations.
+    pub advance: usize,
+    #[serde(skip)]
+    last_turn: NaiveDateTime,
+}
+
+impl Session {
+    pub fn new(path: &Path, title: &str, location: usize) -> Session {
+        let now = Local::now().naive_local();
+        Session {
+            path: path.to_path_buf(),
+            title: title.to_string(),
+            start: now,
+            end: now,
+            duration: 0,
+            pages_turned: 0,
+            start_location: location,
+            end_location: location,
+            advance: 0,
+            last_turn: now,
+        }
+    }
+
+    pub fn turn_page(&mut self, from: usize, to: usize) {
+        self.tick();
+        self.pages_turned += 1;
+        self.advance += to.saturating_sub(from);
+        self.end_location = to;
+    }
+
+    pub fn finish(&mut self, location: usize) {
+        self.tick();
+        self.end_location = location;
+    }
+
+    fn tick(&mut self) {
+        let now = Local::now().naive_local();
+        let elapsed = (now - self.last_turn).num_seconds();
+        if (0..=MAX_PAGE_DURATION).contains(&elapsed) {
+            self.duration += elapsed as u64;
+        }
+        self.last_turn = now;
+        self.end = now;
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Totals {
+    pub duration: u64,
+    pub pages_turned: usize,
+    pub advance: usize,
+}
+
+impl Totals {
+    fn add(&mut self, session: &Session) {
+        self.duration += session.duration;
+        self.pages_turned += session.pages_turned;
+        self.advance += session.advance;
+    }
+
+    pub fn pages_per_hour(&self) -> Option<f32> {
+        if self.duration == 0 {
+            None
+        } else {
+            Some(3600.0 * self.pages_turned as f32 / self.duration as f32)
+        }
+    }
+
+    // Estimates the time needed to read `distance` more locations.
+    pub fn time_left(&self, distance: usize) -> Option<u64> {
+        if self.duration < MIN_ESTIMATE_DURATION || self.advance == 0 {
+            None
+        } else {
+            Some((distance as f64 * self.duration as f64 / self.advance as f64).round() as u64)
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct Statistics {
+    pub sessions: Vec<Session>,
+}
+
+impl Statistics {
+    pub fn load<P: AsRef<Path>>(path: P) -> Statistics {
+        if !path.as_ref().exists() {
+            return Statistics::default();
+        }
+        load_json(path).map_err(|e| eprintln!("Can't load reading statistics: {:#}.", e))
+                       .unwrap_or_default()
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        save_json(self, path)
+    }
+
+    // Sessions without any page turn aren't recorded.
+    pub fn add(&mut self, session: Session) {
+        if session.pages_turned > 0 {
+            self.sessions.push(session);
+        }
+    }
+
+    pub fn book_totals(&self, path: &Path) -> Totals {
+        let mut totals = Totals::default();
+        for session in self.sessions.iter().filter(|s| s.path == path) {
+            totals.add(session);
+        }
+        totals
+    }
+
+    pub fn daily_totals(&self) -> BTreeMap<NaiveDate, Totals> {
+        let mut totals: BTreeMap<NaiveDate, Totals> = BTreeMap::new();
+        for session in &self.sessions {
+            totals.entry(session.start.date()).or_default().add(session);
+        }
+        totals
+    }
+
+    // The keys are the ISO year and week numbers.
+    pub fn weekly_totals(&self) -> BTreeMap<(i32, u32), Totals> {
+        let mut totals: BTreeMap<(i32, u32), Totals> = BTreeMap::new();
+        for session in &self.sessions {
+            let week = session.start.date().iso_week();
+            totals.entry((week.year(), week.week())).or_default().add(session);
+        }
+        totals
+    }
+}
+
+pub fn format_duration(secs: u64) -> String {
+    let minutes = (secs + 30) / 60;
+    match (minutes / 60, minutes % 60) {
+        (0, m) => format!("{} min", m),
+        (h, 0) => format!("{} h", h),
+        (h, m) => format!("{} h {} min", h, m),
+    }
+}
+
+fn format_totals(totals: &Totals) -> String {
+    let mut text = format!("{} · {} pages", format_duration(totals.duration), totals.pages_turned);
+    if let Some(pph) = totals.pages_per_hour() {
+        text.push_str(&format!(" · {:.0} pages/h", pph));
+    }
+    text
+}
+
+fn push_row(buf: &mut String, key: &str, value: &str) {
+    buf.push_str("\t\t\t<tr>\n");
+    buf.push_str(&format!("\t\t\t\t<td class=\"key\">{}</td>\n", key));
+    buf.push_str(&format!("\t\t\t\t<td class=\"value\">{}</td>\n", value));
+    buf.push_str("\t\t\t</tr>\n");
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+pub fn statistics_as_html(statistics: &Statistics) -> String {
+    let mut buf = "<html>\n\t<head>\n\t\t<title>Reading Statistics</title>\n\t\t\
+                   <link rel=\"stylesheet\" type=\"text/css\" \
+                   href=\"css/statistics.css\"/>\n\t</head>\n\t<body>\n".to_string();
+
+    if statistics.sessions.is_empty() {
+        buf.push_str("\t\t<p>No reading sessions.</p>\n\t</body>\n</html>");
+        return buf;
+    }
+
+    let mut overall = Totals::default();
+    for session in &statistics.sessions {
+        overall.add(session);
+    }
+
+    buf.push_str("\t\t<h1>Overall</h1>\n\t\t<table>\n");
+    push_row(&mut buf, "Sessions", &statistics.sessions.len().to_string());
+    push_row(&mut buf, "Reading time", &format_duration(overall.duration));
+    push_row(&mut buf, "Pages turned", &overall.pages_turned.to_string());
+    if let Some(pph) = overall.pages_per_hour() {
+        push_row(&mut buf, "Pages per hour", &format!("{:.0}", pph));
+    }
+    buf.push_str("\t\t</table>\n");
+
+    let today = Local::now().date_naive();
+    let daily_totals = statistics.daily_totals();
+    buf.push_str("\t\t<h1>Last Days</h1>\n\t\t<table>\n");
+    for i in 0..RECENT_DAYS_COUNT {
+        let date = today - Duration::days(i);
+        let totals = daily_totals.get(&date).copied().unwrap_or_default();
+        push_row(&mut buf, &date.format("%a %-d %b").to_string(), &format_totals(&totals));
+    }
+    buf.push_str("\t\t</table>\n");
+
+    let weekly_totals = statistics.weekly_totals();
+    buf.push_str("\t\t<h1>Last Weeks</h1>\n\t\t<table>\n");
+    for i in 0..RECENT_WEEKS_COUNT {
+        let week = (today - Duration::weeks(i)).iso_week();
+        let totals = weekly_totals.get(&(week.year(), week.week())).copied().unwrap_or_default();
+        push_row(&mut buf, &format!("Week {} of {}", week.week(), week.year()), &format_totals(&totals));
+    }
+    buf.push_str("\t\t</table>\n");
+
+    let mut books: BTreeMap<&Path, (&str, Totals)> = BTreeMap::new();
+    for session in &statistics.sessions {
+        let entry = books.entry(&session.path).or_insert((&session.title, Totals::default()));
+        entry.0 = &session.title;
+        entry.1.add(session);
+    }
+    let mut books = books.into_iter().collect::<Vec<_>>();
+    books.sort_by_key(|(_, (_, totals))| Reverse(totals.duration));
+
+    buf.push_str("\t\t<h1>Books</h1>\n\t\t<table>\n");
+    for (path, (title, totals)) in books.into_iter().take(BOOKS_COUNT) {
+        let title = if title.is_empty() {
+            path.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default()
+        } else {
+            title.to_string()
+        };
+        push_row(&mut buf, &escape(&title), &format_totals(&totals));
+    }
+    buf.push_str("\t\t</table>\n");
+
+    buf.push_str("\t</body>\n</html>");
+    buf
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_totals() {
+        let start = NaiveDate::from_ymd_opt(2024, 12, 30).unwrap().and_hms_opt(21, 0, 0).unwrap();
+        let session = |path: &str, days: i64, duration: u64, pages_turned: usize| Session {
+            path: PathBuf::from(path),
+            title: String::new(),
+            start: start + Duration::days(days),
+            end: start + Duration::days(days),
+            duration,
+            pages_turned,
+            start_location: 0,
+            end_location: pages_turned,
+            advance: pages_turned,
+            last_turn: start,
+        };
+        let mut statistics = Statistics::default();
+        statistics.add(session("a.pdf", 0, 1800, 20));
+        statistics.add(session("b.pdf", 0, 0, 0));
+        statistics.add(session("a.pdf", 1, 1800, 40));
+        statistics.add(session("b.pdf", 7, 600, 5));
+        assert_eq!(statistics.sessions.len(), 3);
+
+        let daily_totals = statistics.daily_totals();
+        assert_eq!(daily_totals.len(), 3);
+        assert_eq!(daily_totals[&start.date()].pages_per_hour(), Some(40.0));
+
+        let weekly_totals = statistics.weekly_totals();
+        assert_eq!(weekly_totals[&(2025, 1)].pages_turned, 60);
+        assert_eq!(weekly_totals[&(2025, 2)].duration, 600);
+
+        let totals = statistics.book_totals(Path::new("a.pdf"));
+        assert_eq!(totals.time_left(30), Some(1800));
+        assert_eq!(Totals::default().time_left(30), None);
+        assert_eq!(format_duration(5430), "1 h 31 min");
+    }
+}
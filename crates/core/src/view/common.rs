@@ -135,6 +135,8 @@ pub fn toggle_main_menu(view: &mut dyn View, rect: Rectangle, enable: Option<boo
                                                    EntryId::About),
                                EntryKind::Command("System Info".to_string(),
                                                    EntryId::SystemInfo),
+                               EntryKind::Command("Reading Statistics".to_string(),
+                                                   EntryId::ReadingStatistics),
                                EntryKind::Separator,
                                /*EntryKind::CheckBox("Invert Colors".to_string(),
                                                    EntryId::ToggleInverted,
@@ -290,10 +292,14 @@ pub fn toggle_keyboard_layout_menu(view: &mut dyn View, rect: Rectangle, enable:
         if let Some(false) = enable {
             return;
         }
-        let entries = context.keyboard_layouts.keys()
-                             .map(|s| EntryKind::Command(s.to_string(),
-                                                         EntryId::SetKeyboardLayout(s.to_string())))
-                             .collect::<Vec<EntryKind>>();
+        let mut entries = context.keyboard_layouts.keys()
+                                 .map(|s| EntryKind::Command(s.to_string(),
+                                                             EntryId::SetKeyboardLayout(s.to_string())))
+                                 .collect::<Vec<EntryKind>>();
+        entries.push(EntryKind::Separator);
+        entries.push(EntryKind::CheckBox("Handwriting".to_string(),
+                                         EntryId::ToggleHandwriting,
+                                         context.settings.handwriting.enabled));
         let keyboard_layout_menu = Menu::new(rect, ViewId::KeyboardLayoutMenu, MenuKind::Contextual, entries, context);
         rq.add(RenderData::new(keyboard_layout_menu.id(), *keyboard_layout_menu.rect(), UpdateMode::Gui));
         view.children_mut().push(Box::new(keyboard_layout_menu) as Box<dyn View>);
@@ -0,0 +1,385 @@
+pub mod recognizer;
+
+use std::thread;
+use std::time::Duration;
+use crate::device::CURRENT_DEVICE;
+use crate::framebuffer::{Framebuffer, UpdateMode};
+use crate::gesture::GestureEvent;
+use crate::input::{DeviceEvent, FingerStatus, PenStatus, PenTool};
+use crate::geom::{Point, Rectangle, LinearDir};
+use crate::font::{Fonts, font_from_style, NORMAL_STYLE};
+use crate::color::{BLACK, WHITE, KEYBOARD_BG, SEPARATOR_NORMAL, TEXT_NORMAL};
+use crate::unit::scale_by_dpi;
+use crate::context::Context;
+use super::{View, Event, Hub, Bus, Id, ID_FEEDER, RenderQueue, RenderData, KeyboardEvent, TextKind};
+use super::{SMALL_BAR_HEIGHT, THICKNESS_SMALL};
+use super::key::{Key, KeyKind};
+use super::keyboard::PADDING_RATIO;
+use self::recognizer::{Recognizer, Request};
+
+const CANDIDATES_COUNT: usize = 4;
+const INK_RADIUS: f32 = 2.5;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Source {
+    Finger(i32),
+    Stylus,
+}
+
+// Replaces the keys of the keyboard: the words written in the writing area
+// are recognized after a short pause and sent as keyboard events.
+pub struct HandwritingPad {
+    id: Id,
+    rect: Rectangle,
+    children: Vec<Box<dyn View>>,
+    bar_rect: Rectangle,
+    area_rect: Rectangle,
+    strokes: Vec<Vec<Point>>,
+    source: Option<Source>,
+    // The number of strokes submitted to the recognizer.
+    pending: usize,
+    generation: u32,
+    candidates: Vec<String>,
+    // The last word sent, it can be replaced by another candidate.
+    committed: Option<String>,
+    recognizer: Option<Recognizer>,
+}
+
+impl HandwritingPad {
+    pub fn new(rect: Rectangle) -> HandwritingPad {
+        let id = ID_FEEDER.next();
+        let (bar_rect, area_rect, key_rects) = layout(rect);
+        let kinds = [KeyKind::Delete(LinearDir::Backward), KeyKind::Move(LinearDir::Backward),
+                     KeyKind::Output(' '), KeyKind::Move(LinearDir::Forward), KeyKind::Return];
+        let children = kinds.iter().zip(key_rects)
+                            .map(|(kind, key_rect)| Box::new(Key::new(key_rect, *kind)) as Box<dyn View>)
+                            .collect();
+
+        HandwritingPad {
+            id,
+            rect,
+            children,
+            bar_rect,
+            area_rect,
+            strokes: Vec::new(),
+            source: None,
+            pending: 0,
+            generation: 0,
+            candidates: Vec::new(),
+            committed: None,
+            recognizer: None,
+        }
+    }
+
+    fn begin(&mut self, source: Source, position: Point, rq: &mut RenderQueue) {
+        self.source = Some(source);
+        self.generation = self.generation.wrapping_add(1);
+        self.strokes.push(vec![position]);
+        self.add_segment(position, position, rq);
+    }
+
+    fn extend(&mut self, position: Point, rq: &mut RenderQueue) {
+        let (min, max) = (self.area_rect.min, self.area_rect.max);
+        let position = pt!(position.x.clamp(min.x, max.x - 1), position.y.clamp(min.y, max.y - 1));
+        let last = match self.strokes.last_mut() {
+            Some(stroke) => {
+                let last = stroke[stroke.len() - 1];
+                stroke.push(position);
+                last
+            },
+            None => return,
+        };
+        self.add_segment(last, position, rq);
+    }
+
+    fn finish(&mut self, hub: &Hub, context: &Context) {
+        self.source = None;
+        let hub2 = hub.clone();
+        let (id, generation) = (self.id, self.generation);
+        let delay = Duration::from_millis(context.settings.handwriting.delay);
+        thread::spawn(move || {
+            thread::sleep(delay);
+            hub2.send(Event::RecognizeInk(id, generation)).ok();
+        });
+    }
+
+    fn add_segment(&self, start: Point, end: Point, rq: &mut RenderQueue) {
+        let radius = self.ink_radius();
+        let rect = Rectangle::from_segment(start, end, radius.ceil() as i32, radius.ceil() as i32);
+        if let Some(render_rect) = rect.intersection(&self.area_rect) {
+            rq.add(RenderData::no_wait(self.id, render_rect, UpdateMode::FastMono));
+        }
+    }
+
+    fn ink_radius(&self) -> f32 {
+        scale_by_dpi(INK_RADIUS, CURRENT_DEVICE.dpi)
+    }
+
+    fn clear_ink(&mut self, rq: &mut RenderQueue) {
+        self.strokes.drain(self.pending..);
+        rq.add(RenderData::new(self.id, self.area_rect, UpdateMode::Gui));
+    }
+
+    fn recognize(&mut self, hub: &Hub, context: &Context) {
+        if self.pending > 0 || self.strokes.is_empty() {
+            return;
+        }
+
+        if self.recognizer.is_none() {
+            match Recognizer::new(self.id, hub) {
+                Ok(recognizer) => self.recognizer = Some(recognizer),
+                Err(e) => {
+                    let msg = format!("Can't start the handwriting recognizer: {:#}.", e);
+                    hub.send(Event::Notify(msg)).ok();
+                    return;
+                },
+            }
+        }
+
+        let origin = self.area_rect.min;
+        let request = Request {
+            language: context.settings.handwriting.language.clone(),
+            width: self.area_rect.width(),
+            height: self.area_rect.height(),
+            strokes: self.strokes.iter().map(|stroke| {
+                stroke.iter().map(|pt| [(pt.x - origin.x) as f32, (pt.y - origin.y) as f32]).collect()
+            }).collect(),
+        };
+
+        if let Some(recognizer) = self.recognizer.as_mut() {
+            if let Err(e) = recognizer.recognize(&request) {
+                let msg = format!("Can't recognize handwriting: {:#}.", e);
+                hub.send(Event::Notify(msg)).ok();
+                self.recognizer = None;
+                return;
+            }
+            self.pending = self.strokes.len();
+        }
+    }
+
+    // Sends the given word, either after the last one or in its place.
+    fn commit(&mut self, word: &str, replace: bool, hub: &Hub) {
+        if let Some(last) = self.committed.take() {
+            if replace {
+                for _ in 0..last.chars().count() {
+                    hub.send(Event::Keyboard(KeyboardEvent::Delete { target: TextKind::Char, dir: LinearDir::Backward })).ok();
+                }
+            } else {
+                hub.send(Event::Keyboard(KeyboardEvent::Append(' '))).ok();
+            }
+        }
+        for ch in word.chars() {
+            hub.send(Event::Keyboard(KeyboardEvent::Append(ch))).ok();
+        }
+        self.committed = Some(word.to_string());
+    }
+}
+
+impl View for HandwritingPad {
+    fn handle_event(&mut self, evt: &Event, hub: &Hub, _bus: &mut Bus, rq: &mut RenderQueue, context: &mut Context) -> bool {
+        match *evt {
+            Event::Device(DeviceEvent::Pen { status, tool, position, .. }) => {
+                match status {
+                    PenStatus::Down if self.area_rect.includes(position) => {
+                        if tool == PenTool::Eraser {
+                            self.clear_ink(rq);
+                        } else {
+                            self.begin(Source::Stylus, position, rq);
+                        }
+                        true
+                    },
+                    PenStatus::Motion if self.source == Some(Source::Stylus) => {
+                        self.extend(position, rq);
+                        true
+                    },
+                    PenStatus::Up if self.source == Some(Source::Stylus) => {
+                        self.finish(hub, context);
+                        true
+                    },
+                    _ => self.rect.includes(position),
+                }
+            },
+            Event::Device(DeviceEvent::Finger { id, status, position, .. }) => {
+                match status {
+                    FingerStatus::Down if self.source.is_none() && self.area_rect.includes(position) => {
+                        self.begin(Source::Finger(id), position, rq);
+                        true
+                    },
+                    FingerStatus::Motion if self.source == Some(Source::Finger(id)) => {
+                        self.extend(position, rq);
+                        true
+                    },
+                    FingerStatus::Up if self.source == Some(Source::Finger(id)) => {
+                        self.finish(hub, context);
+                        true
+                    },
+                    _ => false,
+                }
+            },
+            Event::Gesture(GestureEvent::Tap(center)) if self.bar_rect.includes(center) => {
+                let index = ((center.x - self.bar_rect.min.x) as usize * CANDIDATES_COUNT) / self.bar_rect.width() as usize;
+                if self.committed.is_some() && index > 0 {
+                    if let Some(word) = self.candidates.get(index).cloned() {
+                        self.commit(&word, true, hub);
+                        self.candidates.swap(0, index);
+                        rq.add(RenderData::new(self.id, self.bar_rect, UpdateMode::Gui));
+                    }
+                }
+                true
+            },
+            Event::RecognizeInk(id, generation) if id == self.id => {
+                if generation == self.generation && self.source.is_none() {
+                    self.recognize(hub, context);
+                }
+                true
+            },
+            Event::Recognition(id, ref candidates) if id == self.id => {
+                self.strokes.drain(..self.pending);
+                self.pending = 0;
+                if let Some(word) = candidates.first() {
+                    self.commit(word, false, hub);
+                }
+                self.candidates = candidates.iter().take(CANDIDATES_COUNT).cloned().collect();
+                rq.add(RenderData::new(self.id, self.bar_rect, UpdateMode::Gui));
+                rq.add(RenderData::new(self.id, self.area_rect, UpdateMode::Gui));
+                // Words written while the previous one was being recognized.
+                if self.source.is_none() {
+                    self.recognize(hub, context);
+                }
+                true
+            },
+            // The strokes that were being recognized can be submitted again.
+            Event::RecognizerExited(id) if id == self.id => {
+                self.recognizer = None;
+                self.pending = 0;
+                let msg = "Can't recognize handwriting: the recognizer exited.".to_string();
+                hub.send(Event::Notify(msg)).ok();
+                true
+            },
+            // Sent by the keys, before they reach the keyboard.
+            Event::Key(..) => {
+                self.committed = None;
+                if !self.candidates.is_empty() {
+                    self.candidates.clear();
+                    rq.add(RenderData::new(self.id, self.bar_rect, UpdateMode::Gui));
+                }
+                false
+            },
+            _ => false,
+        }
+    }
+
+    fn render(&self, fb: &mut dyn Framebuffer, rect: Rectangle, fonts: &mut Fonts) {
+        let dpi = CURRENT_DEVICE.dpi;
+
+        if let Some(region) = rect.intersection(&self.rect) {
+            fb.draw_rectangle(&region, KEYBOARD_BG);
+        }
+
+        if let Some(region) = rect.intersection(&self.area_rect) {
+            fb.draw_rectangle(&region, WHITE);
+            let thickness = scale_by_dpi(THICKNESS_SMALL, dpi) as i32;
+            let y = self.area_rect.min.y + 2 * self.area_rect.height() as i32 / 3;
+            if let Some(baseline) = rect![self.area_rect.min.x, y, self.area_rect.max.x, y + thickness].intersection(&region) {
+                fb.draw_rectangle(&baseline, SEPARATOR_NORMAL);
+            }
+            let radius = self.ink_radius();
+            for stroke in &self.strokes {
+                if let [pt] = stroke[..] {
+                    fb.draw_clipped_segment(pt, pt, radius, radius, BLACK, &region);
+                }
+                for pts in stroke.windows(2) {
+                    fb.draw_clipped_segment(pts[0], pts[1], radius, radius, BLACK, &region);
+                }
+            }
+        }
+
+        if rect.overlaps(&self.bar_rect) {
+            fb.draw_rectangle(&self.bar_rect, TEXT_NORMAL[0]);
+            let font = font_from_style(fonts, &NORMAL_STYLE, dpi);
+            let x_height = font.x_heights.0 as i32;
+            let padding = font.em() as i32;
+            let cell_width = self.bar_rect.width() as i32 / CANDIDATES_COUNT as i32;
+            let thickness = scale_by_dpi(THICKNESS_SMALL, dpi) as i32;
+            for (i, candidate) in self.candidates.iter().enumerate() {
+                let x = self.bar_rect.min.x + i as i32 * cell_width;
+                if i > 0 {
+                    fb.draw_rectangle(&rect![x, self.bar_rect.min.y, x + thickness, self.bar_rect.max.y],
+                                      SEPARATOR_NORMAL);
+                }
+                let plan = font.plan(candidate, Some(cell_width - padding), None);
+                let dx = (cell_width - plan.width) / 2;
+                let dy = (self.bar_rect.height() as i32 - x_height) / 2;
+                font.render(fb, TEXT_NORMAL[1], &plan, pt!(x + dx, self.bar_rect.max.y - dy));
+            }
+        }
+    }
+
+    fn render_rect(&self, rect: &Rectangle) -> Rectangle {
+        rect.intersection(&self.rect)
+            .unwrap_or(self.rect)
+    }
+
+    fn resize(&mut self, rect: Rectangle, hub: &Hub, rq: &mut RenderQueue, context: &mut Context) {
+        let (bar_rect, area_rect, key_rects) = layout(rect);
+        for (child, key_rect) in self.children.iter_mut().zip(key_rects) {
+            child.resize(key_rect, hub, rq, context);
+        }
+        self.strokes.clear();
+        self.pending = 0;
+        self.bar_rect = bar_rect;
+        self.area_rect = area_rect;
+        self.rect = rect;
+    }
+
+    fn is_background(&self) -> bool {
+        true
+    }
+
+    fn rect(&self) -> &Rectangle {
+        &self.rect
+    }
+
+    fn rect_mut(&mut self) -> &mut Rectangle {
+        &mut self.rect
+    }
+
+    fn children(&self) -> &Vec<Box<dyn View>> {
+        &self.children
+    }
+
+    fn children_mut(&mut self) -> &mut Vec<Box<dyn View>> {
+        &mut self.children
+    }
+
+    fn id(&self) -> Id {
+        self.id
+    }
+}
+
+// The candidates bar, the writing area and the keys, from top to bottom.
+fn layout(rect: Rectangle) -> (Rectangle, Rectangle, Vec<Rectangle>) {
+    let dpi = CURRENT_DEVICE.dpi;
+    let key_height = scale_by_dpi(SMALL_BAR_HEIGHT, dpi);
+    let bar_height = (2.0 * key_height / 3.0).round() as i32;
+    let padding = (PADDING_RATIO * key_height).round() as i32;
+    let key_height = key_height.round() as i32;
+
+    let bar_rect = rect![rect.min.x + padding, rect.min.y + padding,
+                         rect.max.x - padding, rect.min.y + padding + bar_height];
+    let keys_y = rect.max.y - padding - key_height;
+    let area_rect = rect![rect.min.x + padding, bar_rect.max.y + padding,
+                          rect.max.x - padding, keys_y - padding];
+
+    // Delete, move backward, space, move forward and return.
+    let widths = [1.5, 1.0, 5.0, 1.0, 1.5];
+    let unit = (rect.width() as f32 - (widths.len() + 1) as f32 * padding as f32) / widths.iter().sum::<f32>();
+    let mut x = (rect.min.x + padding) as f32;
+    let key_rects = widths.iter().map(|w| {
+        let key_rect = rect![x.round() as i32, keys_y, (x + w * unit).round() as i32, keys_y + key_height];
+        x += w * unit + padding as f32;
+        key_rect
+    }).collect();
+
+    (bar_rect, area_rect, key_rects)
+}
@@ -0,0 +1,113 @@
+use std::thread;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::path::Path;
+use std::io::Write;
+use std::io::{BufRead, BufReader};
+use std::process::{Command, Child, Stdio};
+use serde::Serialize;
+use anyhow::{Error, format_err};
+use crate::view::{Event, Hub, Id};
+
+const APP_DIR: &str = "bin/handwriting";
+const APP_NAME: &str = "recognizer";
+
+// The recognizer reads one request per line, as a JSON object, and answers
+// each of them, in order, with one line holding the JSON array of the
+// candidate transcriptions, best first.
+#[derive(Debug, Clone, Serialize)]
+pub struct Request {
+    pub language: String,
+    pub width: u32,
+    pub height: u32,
+    // The points of each stroke, in pixels, in the order they were drawn.
+    pub strokes: Vec<Vec<[f32; 2]>>,
+}
+
+pub struct Recognizer {
+    process: Child,
+    // Set when the process is stopped on purpose.
+    stopped: Arc<AtomicBool>,
+}
+
+impl Recognizer {
+    // The answers are sent as `Event::Recognition(id, candidates)`, an unexpected
+    // exit of the process as `Event::RecognizerExited(id)`.
+    pub fn new(id: Id, hub: &Hub) -> Result<Recognizer, Error> {
+        let path = Path::new(APP_DIR).join(APP_NAME).canonicalize()?;
+        let mut process = Command::new(path)
+                                 .current_dir(APP_DIR)
+                                 .stdin(Stdio::piped())
+                                 .stdout(Stdio::piped())
+                                 .stderr(Stdio::inherit())
+                                 .spawn()?;
+        let stdout = process.stdout.take()
+                            .ok_or_else(|| format_err!("can't take stdout"))?;
+
+        let hub2 = hub.clone();
+        let stopped = Arc::new(AtomicBool::new(false));
+        let stopped2 = stopped.clone();
+        thread::spawn(move || {
+            let reader = BufReader::new(stdout);
+            for line_res in reader.lines() {
+                if let Ok(line) = line_res {
+                    // Every request gets an answer, so that the answers stay in order.
+                    let candidates = parse_candidates(&line)
+                                         .map_err(|e| eprintln!("Can't parse recognizer output: {:#}.", e))
+                                         .unwrap_or_default();
+                    hub2.send(Event::Recognition(id, candidates)).ok();
+                } else {
+                    break;
+                }
+            }
+            if !stopped2.load(Ordering::Relaxed) {
+                hub2.send(Event::RecognizerExited(id)).ok();
+            }
+        });
+
+        Ok(Recognizer { process, stopped })
+    }
+
+    pub fn recognize(&mut self, request: &Request) -> Result<(), Error> {
+        let stdin = self.process.stdin.as_mut()
+                        .ok_or_else(|| format_err!("can't access stdin"))?;
+        writeln!(stdin, "{}", serde_json::to_string(request)?)?;
+        Ok(())
+    }
+}
+
+impl Drop for Recognizer {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::Relaxed);
+        unsafe { libc::kill(self.process.id() as libc::pid_t, libc::SIGTERM) };
+        self.process.wait().map_err(|e| eprintln!("Can't wait for child process: {:#}.", e)).ok();
+    }
+}
+
+fn parse_candidates(line: &str) -> Result<Vec<String>, Error> {
+    let candidates: Vec<String> = serde_json::from_str(line)?;
+    Ok(candidates.into_iter()
+                 .map(|c| c.trim().to_string())
+                 .filter(|c| !c.is_empty())
+                 .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_protocol() {
+        let request = Request {
+            language: "en".to_string(),
+            width: 200,
+            height: 100,
+            strokes: vec![vec![[1.0, 2.0], [3.5, 4.0]], vec![[5.0, 6.0]]],
+        };
+        assert_eq!(serde_json::to_string(&request).unwrap(),
+                   r#"{"language":"en","width":200,"height":100,"strokes":[[[1.0,2.0],[3.5,4.0]],[[5.0,6.0]]]}"#);
+        assert_eq!(parse_candidates(r#"["hello", " hallo ", "", "he\nllo"]"#).unwrap(),
+                   vec!["hello".to_string(), "hallo".to_string(), "he\nllo".to_string()]);
+        assert!(parse_candidates("hello").is_err());
+    }
+}
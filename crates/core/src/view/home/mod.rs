@@ -12,6 +12,8 @@ use std::mem;
 use std::thread;
 use std::io::Write;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::process::{Command, Child, Stdio};
 use std::io::{BufRead, BufReader};
 use fxhash::FxHashMap;
@@ -19,8 +21,11 @@ use rand_core::RngCore;
 use serde_json::{json, Value as JsonValue};
 use anyhow::{Error, format_err};
 use crate::library::Library;
+use crate::document::open_book;
+use crate::export::{self, BookAnnotations, ExportFormat, EXPORT_FORMATS};
+use crate::fulltext::{BookIndex, parse_query, matches, results_as_html};
 use crate::framebuffer::{Framebuffer, UpdateMode};
-use crate::metadata::{Info, Metadata, SortMethod, BookQuery, SimpleStatus, sort};
+use crate::metadata::{Info, ReaderInfo, Metadata, SortMethod, BookQuery, SimpleStatus, sort};
 use crate::view::{View, Event, Hub, Bus, RenderQueue, RenderData};
 use crate::view::{Id, ID_FEEDER, ViewId, EntryId, EntryKind};
 use crate::view::{SMALL_BAR_HEIGHT, BIG_BAR_HEIGHT, THICKNESS_MEDIUM};
@@ -66,6 +71,7 @@ pub struct Home {
     current_directory: PathBuf,
     target_document: Option<PathBuf>,
     background_fetchers: FxHashMap<u32, Fetcher>,
+    indexing: Arc<AtomicBool>,
 }
 
 #[derive(Debug)]
@@ -203,6 +209,7 @@ impl Home {
             current_directory,
             target_document: None,
             background_fetchers: FxHashMap::default(),
+            indexing: Arc::new(AtomicBool::new(false)),
         })
     }
 
@@ -927,6 +934,15 @@ impl Home {
                                                                     EntryId::SetStatus(path.clone(), *s)))
                                  .collect();
             entries.push(EntryKind::SubMenu("Mark As".to_string(), submenu));
+
+            if info.reader.as_ref().map_or(false, |r| !r.annotations.is_empty() || !r.bookmarks.is_empty()) {
+                let export = EXPORT_FORMATS.iter().map(|format| {
+                    EntryKind::Command(format.to_string(),
+                                       EntryId::ExportBookAnnotations(path.clone(), *format))
+                }).collect::<Vec<EntryKind>>();
+                entries.push(EntryKind::SubMenu("Export Annotations".to_string(), export));
+            }
+
             entries.push(EntryKind::Separator);
 
             let selected_library = context.settings.selected_library;
@@ -1030,6 +1046,12 @@ impl Home {
                                              EntryId::ThumbnailPreviews,
                                              library_settings.thumbnail_previews));
 
+            let export = EXPORT_FORMATS.iter().map(|format| {
+                EntryKind::Command(format.to_string(), EntryId::ExportLibraryAnnotations(*format))
+            }).collect::<Vec<EntryKind>>();
+            entries.push(EntryKind::SubMenu("Export Annotations".to_string(), export));
+            entries.push(EntryKind::Command("Update Full-Text Index".to_string(), EntryId::UpdateFulltextIndex));
+
             let trash_path = context.library.home.join(TRASH_DIRNAME);
             if let Ok(trash) = Library::new(trash_path, LibraryMode::Database)
                                        .map_err(|e| eprintln!("Can't inspect trash: {:#?}.", e)) {
@@ -1092,6 +1114,162 @@ impl Home {
         self.children.push(Box::new(notif) as Box<dyn View>);
     }
 
+    fn export_annotations(&mut self, path: Option<&Path>, format: ExportFormat, hub: &Hub, rq: &mut RenderQueue, context: &mut Context) {
+        let home = context.library.home.clone();
+        let save_path = home.join(&context.settings.export.save_path);
+
+        if let Some(path) = path {
+            let result = self.visible_books.iter().find(|info| info.file.path == path)
+                .ok_or_else(|| format_err!("unknown document: {}", path.display()))
+                .and_then(|info| {
+                    let book = match open_book(home.join(path), info.reader.as_ref()) {
+                        Some(mut doc) => BookAnnotations::from_document(info, doc.as_mut()),
+                        None => BookAnnotations::new(info),
+                    };
+                    export::export_book(&book, format, &save_path)
+                });
+            let notif = Notification::new(export_message(result, &home), hub, rq, context);
+            self.children.push(Box::new(notif) as Box<dyn View>);
+            return;
+        }
+
+        let infos = context.library.annotated();
+        if infos.is_empty() {
+            let notif = Notification::new(export_message(Err(format_err!("no annotations")), &home),
+                                          hub, rq, context);
+            self.children.push(Box::new(notif) as Box<dyn View>);
+            return;
+        }
+
+        let notif = Notification::new(format!("Exporting the annotations of {} documents.", infos.len()),
+                                      hub, rq, context);
+        self.children.push(Box::new(notif) as Box<dyn View>);
+
+        // Every annotated document is opened to find the chapters and the page labels.
+        let hub2 = hub.clone();
+        thread::spawn(move || {
+            let books = export::collect(&home, &infos);
+            let result = if books.is_empty() {
+                Err(format_err!("no annotations"))
+            } else {
+                export::export_books(&books, format, &save_path)
+            };
+            hub2.send(Event::Notify(export_message(result, &home))).ok();
+        });
+    }
+
+    fn update_fulltext_index(&mut self, hub: &Hub, rq: &mut RenderQueue, context: &mut Context) {
+        if self.indexing.load(Ordering::Relaxed) {
+            let notif = Notification::new("The full-text index is already being updated.".to_string(),
+                                          hub, rq, context);
+            self.children.push(Box::new(notif) as Box<dyn View>);
+            return;
+        }
+
+        // In filesystem mode, a query is needed to list the documents of the subdirectories.
+        let home = context.library.home.clone();
+        let (files, _) = context.library.list(&home, Some(&BookQuery::default()), false);
+        let books = files.iter().filter_map(|info| {
+            let index_path = context.library.fulltext_index(&info.file.path);
+            if index_path.exists() {
+                None
+            } else {
+                Some((home.join(&info.file.path), index_path, info.reader.clone()))
+            }
+        }).collect::<Vec<(PathBuf, PathBuf, Option<ReaderInfo>)>>();
+
+        if books.is_empty() {
+            let notif = Notification::new("The full-text index is up to date.".to_string(),
+                                          hub, rq, context);
+            self.children.push(Box::new(notif) as Box<dyn View>);
+            return;
+        }
+
+        let notif = Notification::new(format!("Indexing {} documents.", books.len()),
+                                      hub, rq, context);
+        self.children.push(Box::new(notif) as Box<dyn View>);
+
+        let hub2 = hub.clone();
+        let indexing = Arc::clone(&self.indexing);
+        let (width, height) = context.display.dims;
+        let font_size = context.settings.reader.font_size;
+        indexing.store(true, Ordering::Relaxed);
+
+        thread::spawn(move || {
+            let mut count = 0;
+            for (path, index_path, reader) in books {
+                if let Some(mut doc) = open_book(&path, reader.as_ref()) {
+                    doc.layout(width, height, font_size, CURRENT_DEVICE.dpi);
+                    let index = BookIndex::build(doc.as_mut());
+                    if let Err(e) = index.save(&index_path) {
+                        eprintln!("Can't save full-text index: {:#}.", e);
+                    } else {
+                        count += 1;
+                    }
+                }
+            }
+            indexing.store(false, Ordering::Relaxed);
+            hub2.send(Event::Notify(format!("Indexed {} documents.", count))).ok();
+        });
+    }
+
+    fn search_fulltext(&mut self, text: &str, query: &BookQuery, hub: &Hub, rq: &mut RenderQueue, context: &mut Context) {
+        let terms = parse_query(text);
+
+        if terms.is_empty() {
+            let notif = Notification::new("Invalid search query.".to_string(),
+                                          hub, rq, context);
+            self.children.push(Box::new(notif) as Box<dyn View>);
+            return;
+        }
+
+        let (files, _) = context.library.list(&self.current_directory, Some(query), false);
+        let books = files.into_iter().map(|info| {
+            let index_path = context.library.fulltext_index(&info.file.path);
+            (info, index_path)
+        }).collect::<Vec<(Info, PathBuf)>>();
+
+        let hub2 = hub.clone();
+        let text = text.to_string();
+        let home = context.library.home.clone();
+        let (width, height) = context.display.dims;
+        let font_size = context.settings.reader.font_size;
+
+        thread::spawn(move || {
+            let mut results = Vec::new();
+            let mut missing = 0;
+            for (info, index_path) in books {
+                if !index_path.exists() {
+                    missing += 1;
+                    continue;
+                }
+                match BookIndex::load(&index_path) {
+                    Ok(index) => {
+                        let locations = index.search(&terms);
+                        if locations.is_empty() {
+                            continue;
+                        }
+                        // The document is laid out as it was when it was indexed.
+                        if let Some(mut doc) = open_book(home.join(&info.file.path), info.reader.as_ref()) {
+                            doc.layout(width, height, font_size, CURRENT_DEVICE.dpi);
+                            let matches = matches(doc.as_mut(), &locations, &terms);
+                            results.push((info, matches));
+                        }
+                    },
+                    Err(e) => eprintln!("Can't load full-text index: {:#}.", e),
+                }
+            }
+            if missing > 0 {
+                hub2.send(Event::Notify(format!("{} documents aren't indexed.", missing))).ok();
+            }
+            if results.is_empty() {
+                hub2.send(Event::Notify("No full-text search results.".to_string())).ok();
+            } else {
+                hub2.send(Event::OpenHtml(results_as_html(&text, &results), None)).ok();
+            }
+        });
+    }
+
     fn rename(&mut self, path: &Path, file_name: &str, hub: &Hub, rq: &mut RenderQueue, context: &mut Context) -> Result<(), Error> {
         context.library.rename(path, file_name)?;
         self.refresh_visibles(true, false, hub, rq, context);
@@ -1192,7 +1370,8 @@ impl Home {
             return;
         }
 
-        let library = library.unwrap();
+        let mut library = library.unwrap();
+        library.progress_sync = context.library.progress_sync.clone();
 
         let old_path = mem::take(&mut self.current_directory);
         self.terminate_fetchers(&old_path, false, hub, context);
@@ -1392,6 +1571,14 @@ impl Home {
     }
 }
 
+fn export_message(result: Result<PathBuf, Error>, home: &Path) -> String {
+    match result {
+        Err(e) => format!("Can't export annotations: {:#}.", e),
+        Ok(path) => format!("Exported annotations to {}.",
+                            path.strip_prefix(home).unwrap_or(&path).display()),
+    }
+}
+
 impl View for Home {
     fn handle_event(&mut self, evt: &Event, hub: &Hub, _bus: &mut Bus, rq: &mut RenderQueue, context: &mut Context) -> bool {
         match *evt {
@@ -1551,6 +1738,18 @@ impl View for Home {
                 self.add_document(*info.clone(), hub, rq, context);
                 true
             },
+            Event::Select(EntryId::ExportBookAnnotations(ref path, format)) => {
+                self.export_annotations(Some(path.as_path()), format, hub, rq, context);
+                true
+            },
+            Event::Select(EntryId::ExportLibraryAnnotations(format)) => {
+                self.export_annotations(None, format, hub, rq, context);
+                true
+            },
+            Event::Select(EntryId::UpdateFulltextIndex) => {
+                self.update_fulltext_index(hub, rq, context);
+                true
+            },
             Event::Select(EntryId::SetStatus(ref path, status)) => {
                 self.set_status(path, status, hub, rq, context);
                 true
@@ -1579,7 +1778,13 @@ impl View for Home {
                 true
             },
             Event::Submit(ViewId::HomeSearchInput, ref text) => {
-                self.query = BookQuery::new(text);
+                let query = BookQuery::new(text);
+                if let Some(fulltext) = query.as_ref().and_then(|q| q.fulltext.clone()) {
+                    self.toggle_keyboard(false, false, None, hub, rq, context);
+                    self.search_fulltext(&fulltext, query.as_ref().unwrap(), hub, rq, context);
+                    return true;
+                }
+                self.query = query;
                 if self.query.is_some() {
                     self.toggle_keyboard(false, false, None, hub, rq, context);
                     // Render the search bar and its separator.
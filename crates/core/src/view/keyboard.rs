@@ -7,6 +7,7 @@ use crate::gesture::GestureEvent;
 use crate::input::DeviceEvent;
 use super::{View, Event, Hub, Bus, Id, ID_FEEDER, RenderQueue, RenderData, KeyboardEvent, EntryId, TextKind};
 use super::key::{Key, KeyKind};
+use super::handwriting::HandwritingPad;
 use super::BIG_BAR_HEIGHT;
 use crate::color::KEYBOARD_BG;
 use crate::font::Fonts;
@@ -14,7 +15,7 @@ use crate::context::Context;
 use crate::geom::Rectangle;
 use crate::unit::scale_by_dpi;
 
-const PADDING_RATIO: f32 = 0.06;
+pub const PADDING_RATIO: f32 = 0.06;
 
 #[derive(Debug, Clone, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -39,6 +40,7 @@ pub struct Keyboard {
     layout: Layout,
     state: State,
     combine_buffer: String,
+    handwriting: bool,
 }
 
 impl Keyboard {
@@ -83,30 +85,36 @@ impl Keyboard {
 
         let start_y = rect.min.y as f32 + padding + (rect.height() as f32 - rows_height) / 2.0;
 
-        for (i, row) in layout.keys.iter().enumerate() {
-            let y = start_y + i as f32 * (padding + key_height);
-            let row_width = (layout.widths[i].len() + 1) as f32 * padding + layout.widths[i].iter().sum::<f32>() * key_height;
-            let start_x = rect.min.x as f32 + padding + (rect.width() as f32 - row_width) / 2.0;
-            let mut dx = 0.0;
-            let mut dj = 0;
-
-            for (j, kind) in row.iter().enumerate() {
-                let key_width = layout.widths[i][j] * key_height;
-                let x = start_x + dx;
-                dx += key_width + padding;
-                let key_rect = rect![x.round() as i32,
-                                     y.round() as i32,
-                                     (x + key_width).round() as i32,
-                                     (y + key_height).round() as i32];
-                let kind = match kind {
-                    KeyKind::Output(c) if *c != ' ' => KeyKind::Output(layout.outputs[level][i][j-dj]),
-                    _ => { dj = j + 1; *kind },
-                };
-                let mut key = Key::new(key_rect, kind);
-                if number && kind == KeyKind::Alternate {
-                    key.lock();
+        let handwriting = context.settings.handwriting.enabled;
+
+        if handwriting {
+            children.push(Box::new(HandwritingPad::new(*rect)) as Box<dyn View>);
+        } else {
+            for (i, row) in layout.keys.iter().enumerate() {
+                let y = start_y + i as f32 * (padding + key_height);
+                let row_width = (layout.widths[i].len() + 1) as f32 * padding + layout.widths[i].iter().sum::<f32>() * key_height;
+                let start_x = rect.min.x as f32 + padding + (rect.width() as f32 - row_width) / 2.0;
+                let mut dx = 0.0;
+                let mut dj = 0;
+
+                for (j, kind) in row.iter().enumerate() {
+                    let key_width = layout.widths[i][j] * key_height;
+                    let x = start_x + dx;
+                    dx += key_width + padding;
+                    let key_rect = rect![x.round() as i32,
+                                         y.round() as i32,
+                                         (x + key_width).round() as i32,
+                                         (y + key_height).round() as i32];
+                    let kind = match kind {
+                        KeyKind::Output(c) if *c != ' ' => KeyKind::Output(layout.outputs[level][i][j-dj]),
+                        _ => { dj = j + 1; *kind },
+                    };
+                    let mut key = Key::new(key_rect, kind);
+                    if number && kind == KeyKind::Alternate {
+                        key.lock();
+                    }
+                    children.push(Box::new(key) as Box<dyn View>);
                 }
-                children.push(Box::new(key) as Box<dyn View>);
             }
         }
 
@@ -117,6 +125,7 @@ impl Keyboard {
             layout,
             state,
             combine_buffer: String::new(),
+            handwriting,
         }
     }
 
@@ -242,8 +251,9 @@ impl View for Keyboard {
                 true
             },
             Event::Select(EntryId::SetKeyboardLayout(ref name)) => {
-                if *name != context.settings.keyboard_layout {
+                if *name != context.settings.keyboard_layout || self.handwriting {
                     context.settings.keyboard_layout = name.to_string();
+                    context.settings.handwriting.enabled = false;
                     // FIXME: the keyboard's height might change, in which case,
                     // we shall notify the root view.
                     *self = Keyboard::new(&mut self.rect, self.state.alternate == 2, context);
@@ -251,6 +261,12 @@ impl View for Keyboard {
                 }
                 true
             },
+            Event::Select(EntryId::ToggleHandwriting) => {
+                context.settings.handwriting.enabled = !self.handwriting;
+                *self = Keyboard::new(&mut self.rect, self.state.alternate == 2, context);
+                rq.add(RenderData::new(self.id, self.rect, UpdateMode::Gui));
+                true
+            },
             Event::Gesture(GestureEvent::Tap(center)) |
             Event::Gesture(GestureEvent::HoldFingerShort(center, ..)) if self.rect.includes(center) => true,
             Event::Gesture(GestureEvent::Swipe { start, .. }) if self.rect.includes(start) => true,
@@ -264,6 +280,9 @@ impl View for Keyboard {
                   Event::Key(..) |
                   Event::Gesture(..) |
                   Event::Device(DeviceEvent::Finger { .. }) |
+                  Event::Device(DeviceEvent::Pen { .. }) |
+                  Event::RecognizeInk(..) |
+                  Event::Recognition(..) |
                   Event::Select(..))
     }
 
@@ -301,25 +320,29 @@ impl View for Keyboard {
         let height_gap = (rect.height() - rows_height.round() as u32) / big_height as u32;
         rect.min.y += height_gap as i32 * big_height;
 
-        let start_y = rect.min.y as f32 + padding + (rect.height() as f32 - rows_height) / 2.0;
-        let mut index = 0;
-
-        for (i, row) in self.layout.keys.iter().enumerate() {
-            let y = start_y + i as f32 * (padding + key_height);
-            let row_width = (self.layout.widths[i].len() + 1) as f32 * padding + self.layout.widths[i].iter().sum::<f32>() * key_height;
-            let start_x = rect.min.x as f32 + padding + (rect.width() as f32 - row_width) / 2.0;
-            let mut dx = 0.0;
-
-            for j in 0..row.len() {
-                let key_width = self.layout.widths[i][j] * key_height;
-                let x = start_x + dx;
-                dx += key_width + padding;
-                let key_rect = rect![x.round() as i32,
-                                     y.round() as i32,
-                                     (x + key_width).round() as i32,
-                                     (y + key_height).round() as i32];
-                self.children[index].resize(key_rect, hub, rq, context);
-                index += 1;
+        if self.handwriting {
+            self.children[0].resize(rect, hub, rq, context);
+        } else {
+            let start_y = rect.min.y as f32 + padding + (rect.height() as f32 - rows_height) / 2.0;
+            let mut index = 0;
+
+            for (i, row) in self.layout.keys.iter().enumerate() {
+                let y = start_y + i as f32 * (padding + key_height);
+                let row_width = (self.layout.widths[i].len() + 1) as f32 * padding + self.layout.widths[i].iter().sum::<f32>() * key_height;
+                let start_x = rect.min.x as f32 + padding + (rect.width() as f32 - row_width) / 2.0;
+                let mut dx = 0.0;
+
+                for j in 0..row.len() {
+                    let key_width = self.layout.widths[i][j] * key_height;
+                    let x = start_x + dx;
+                    dx += key_width + padding;
+                    let key_rect = rect![x.round() as i32,
+                                         y.round() as i32,
+                                         (x + key_width).round() as i32,
+                                         (y + key_height).round() as i32];
+                    self.children[index].resize(key_rect, hub, rq, context);
+                    index += 1;
+                }
             }
         }
 
@@ -40,6 +40,7 @@ pub mod reader;
 pub mod dictionary;
 pub mod calculator;
 pub mod sketch;
+pub mod handwriting;
 pub mod touch_events;
 pub mod rotation_values;
 
@@ -57,6 +58,10 @@ use crate::color::Color;
 use crate::document::{Location, TextLocation};
 use crate::settings::{ButtonScheme, FirstColumn, SecondColumn, RotationLock, RefreshQuality, InputSource};
 use crate::metadata::{Info, ZoomMode, ScrollMode, SortMethod, TextAlign, SimpleStatus, PageScheme, Margin};
+use crate::export::ExportFormat;
+use crate::view::sketch::Tool as SketchTool;
+use crate::view::sketch::notebook::{Template, NotebookFormat};
+use crate::kosync::Progress;
 use crate::geom::{LinearDir, CycleDir, Rectangle, Boundary};
 use crate::framebuffer::{Framebuffer, UpdateMode};
 use crate::input::{DeviceEvent, FingerStatus, ButtonCode};
@@ -325,6 +330,9 @@ pub enum Event {
     TogglePresetMenu(Rectangle, usize),
     SubMenu(Rectangle, Vec<EntryKind>),
     ProcessLine(LineOrigin, String),
+    RecognizeInk(Id, u32),
+    Recognition(Id, Vec<String>),
+    RecognizerExited(Id),
     History(CycleDir, bool),
     Toggle(ViewId),
     Show(ViewId),
@@ -332,6 +340,7 @@ pub enum Event {
     CloseSub(ViewId),
     Search(String),
     SearchResult(usize, Vec<Boundary>),
+    RemoteProgress(Box<Progress>),
     FetcherAddDocument(u32, Box<Info>),
     FetcherRemoveDocument(u32, PathBuf),
     FetcherSearch {
@@ -431,7 +440,10 @@ pub enum ViewId {
     Keyboard,
     AboutDialog,
     ShareDialog,
+    ProgressSyncDialog,
     MarginCropper,
+    Footnote,
+    TableViewer,
     TopBottomBars,
     TableOfContents,
     MessageNotif(Id),
@@ -508,6 +520,7 @@ pub enum EntryKind {
 pub enum EntryId {
     About,
     SystemInfo,
+    ReadingStatistics,
     LoadLibrary(usize),
     Load(PathBuf),
     Flush,
@@ -525,6 +538,9 @@ pub enum EntryId {
     SelectDirectory(PathBuf),
     ToggleSelectDirectory(PathBuf),
     SetStatus(PathBuf, SimpleStatus),
+    ExportBookAnnotations(PathBuf, ExportFormat),
+    ExportLibraryAnnotations(ExportFormat),
+    UpdateFulltextIndex,
     SearchAuthor(String),
     RemovePreset(usize),
     FirstColumn(FirstColumn),
@@ -546,6 +562,8 @@ pub enum EntryId {
     RemoveAnnotation([TextLocation; 2]),
     EditAnnotationNote([TextLocation; 2]),
     RemoveAnnotationNote([TextLocation; 2]),
+    ExportAnnotations(ExportFormat),
+    ExportAnnotatedPdf,
     GoTo(usize),
     GoToSelectedPageName,
     SearchDirection(LinearDir),
@@ -561,6 +579,7 @@ pub enum EntryId {
     SetSearchTarget(Option<String>),
     SetInputText(ViewId, String),
     SetKeyboardLayout(String),
+    ToggleHandwriting,
     ToggleShowHidden,
     ToggleFuzzy,
     ToggleInverted,
@@ -569,12 +588,23 @@ pub enum EntryId {
     ToggleInputSource(InputSource),
     ToggleIgnoreButtonCode(ButtonCode),
     ToggleDithered,
+    ToggleRightToLeft,
+    ToggleReflow,
+    EraseInk,
     ToggleWifi,
     Rotate(i8),
     Launch(AppCmd),
     SetPenSize(i32),
     SetPenColor(Color),
     TogglePenDynamism,
+    SetSketchTool(SketchTool),
+    SetTemplate(Template),
+    AddPage,
+    RemovePage,
+    Undo,
+    Redo,
+    ExportSketch(NotebookFormat),
+    ConvertSketchToText,
     ReloadDictionaries,
     New,
     Refresh,
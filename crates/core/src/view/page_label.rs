@@ -4,6 +4,7 @@ use crate::color::{BLACK, WHITE};
 use crate::gesture::GestureEvent;
 use crate::geom::{Rectangle};
 use crate::document::BYTES_PER_PAGE;
+use crate::statistics::format_duration;
 use crate::framebuffer::{Framebuffer, UpdateMode};
 use super::{View, Event, Hub, Bus, Id, ID_FEEDER, RenderQueue, RenderData, ViewId};
 use crate::context::Context;
@@ -15,6 +16,7 @@ pub struct PageLabel {
     current_page: usize,
     pages_count: usize,
     synthetic: bool,
+    time_left: Option<u64>,
 }
 
 impl PageLabel {
@@ -26,6 +28,7 @@ impl PageLabel {
             current_page,
             pages_count,
             synthetic,
+            time_left: None,
         }
     }
 
@@ -44,6 +47,13 @@ impl PageLabel {
         }
     }
 
+    pub fn update_time_left(&mut self, time_left: Option<u64>, rq: &mut RenderQueue) {
+        if self.time_left != time_left {
+            self.time_left = time_left;
+            rq.add(RenderData::new(self.id, self.rect, UpdateMode::Gui));
+        }
+    }
+
     pub fn text(&self, size: u8) -> String {
         if self.pages_count == 0 {
             return "No pages".to_string();
@@ -56,12 +66,16 @@ impl PageLabel {
              self.pages_count as f64, 0)
         };
         let percent = 100.0 * self.current_page as f32 / self.pages_count as f32;
-        match size {
+        let text = match size {
             0 => format!("Page {1:.0$} of {2:.0$} ({3:.1}%)", precision, current_page, pages_count, percent),
             1 => format!("P. {1:.0$} of {2:.0$} ({3:.1}%)", precision, current_page, pages_count, percent),
             2 => format!("{1:.0$}/{2:.0$} ({3:.1}%)", precision, current_page, pages_count, percent),
             3 => format!("{1:.0$} ({2:.1}%)", precision, current_page, percent),
             _ => format!("{:.1}%", percent),
+        };
+        match self.time_left {
+            Some(secs) if size < 3 => format!("{} · {} left", text, format_duration(secs)),
+            _ => text,
         }
     }
 }
@@ -97,6 +97,14 @@ impl BottomBar {
         page_label.update(current_page, pages_count, rq);
     }
 
+    // The estimated times needed to finish the current chapter and the book.
+    pub fn update_time_left(&mut self, chapter_left: Option<u64>, book_left: Option<u64>, rq: &mut RenderQueue) {
+        let chapter_label = self.child_mut(1).downcast_mut::<ChapterLabel>().unwrap();
+        chapter_label.update_time_left(chapter_left, rq);
+        let page_label = self.child_mut(2).downcast_mut::<PageLabel>().unwrap();
+        page_label.update_time_left(book_left, rq);
+    }
+
     pub fn update_icons(&mut self, neighbors: &Neighbors, rq: &mut RenderQueue) {
         let is_prev_disabled = neighbors.previous_page.is_none();
 
@@ -0,0 +1,146 @@
+use crate::framebuffer::{Framebuffer, UpdateMode, Pixmap};
+use crate::document::{Document, Location};
+use crate::document::html::HtmlDocument;
+use crate::document::html::engine::ResourceFetcher;
+use crate::gesture::GestureEvent;
+use crate::input::DeviceEvent;
+use crate::font::Fonts;
+use crate::geom::{Rectangle, Dir, CycleDir, BorderSpec};
+use crate::view::{View, Event, Hub, Bus, Id, ID_FEEDER, RenderQueue, RenderData, ViewId};
+use crate::view::{SMALL_BAR_HEIGHT, THICKNESS_LARGE};
+use crate::unit::scale_by_dpi;
+use crate::color::{BLACK, WHITE};
+use crate::device::CURRENT_DEVICE;
+use crate::context::Context;
+use super::render_html;
+
+// Displays a note at the bottom of the screen, above the page being read.
+pub struct Footnote {
+    id: Id,
+    rect: Rectangle,
+    children: Vec<Box<dyn View>>,
+    doc: HtmlDocument,
+    pixmap: Pixmap,
+    location: usize,
+}
+
+impl Footnote {
+    pub fn new(html: &str, resources: Box<dyn ResourceFetcher>, font_size: f32, font_family: &str, rq: &mut RenderQueue, context: &mut Context) -> Option<Footnote> {
+        let id = ID_FEEDER.next();
+        let dpi = CURRENT_DEVICE.dpi;
+        let (width, height) = context.display.dims;
+        let padding = scale_by_dpi(SMALL_BAR_HEIGHT, dpi) as i32 / 2;
+        let thickness = scale_by_dpi(THICKNESS_LARGE, dpi) as i32;
+
+        let max_rect = rect![padding, height as i32 / 2,
+                             width as i32 - padding, height as i32 - padding];
+
+        let dims = (max_rect.width() - 2 * thickness as u32,
+                    max_rect.height() - 2 * thickness as u32);
+        let (mut doc, pixmap, frame, location) = render_html(html, resources, dims, font_size, font_family, context)?;
+
+        // Notes that fit on a single page only take the space they need: the blank space
+        // at the bottom of the page is replaced by a margin as high as the top one.
+        let content_height = if doc.resolve_location(Location::Next(location)).is_some() {
+            pixmap.height as i32
+        } else {
+            (frame.max.y + frame.min.y).min(pixmap.height as i32)
+        };
+
+        let rect = rect![max_rect.min.x, max_rect.max.y - content_height - 2 * thickness,
+                         max_rect.max.x, max_rect.max.y];
+
+        rq.add(RenderData::new(id, rect, UpdateMode::Gui));
+
+        Some(Footnote {
+            id,
+            rect,
+            children: Vec::new(),
+            doc,
+            pixmap,
+            location,
+        })
+    }
+
+    fn go_to_neighbor(&mut self, dir: CycleDir, rq: &mut RenderQueue) -> bool {
+        let loc = match dir {
+            CycleDir::Next => Location::Next(self.location),
+            CycleDir::Previous => Location::Previous(self.location),
+        };
+        if let Some((pixmap, location)) = self.doc.pixmap(loc, 1.0, CURRENT_DEVICE.color_samples()) {
+            self.pixmap = pixmap;
+            self.location = location;
+            rq.add(RenderData::new(self.id, self.rect, UpdateMode::Gui));
+            true
+        } else {
+            false
+        }
+    }
+}
+
+impl View for Footnote {
+    fn handle_event(&mut self, evt: &Event, hub: &Hub, _bus: &mut Bus, rq: &mut RenderQueue, _context: &mut Context) -> bool {
+        match *evt {
+            Event::Gesture(GestureEvent::Tap(center)) => {
+                if !self.rect.includes(center) || !self.go_to_neighbor(CycleDir::Next, rq) {
+                    hub.send(Event::Close(ViewId::Footnote)).ok();
+                }
+                true
+            },
+            Event::Gesture(GestureEvent::Swipe { dir, start, .. }) if self.rect.includes(start) => {
+                match dir {
+                    Dir::West => { self.go_to_neighbor(CycleDir::Next, rq); },
+                    Dir::East => { self.go_to_neighbor(CycleDir::Previous, rq); },
+                    _ => { hub.send(Event::Close(ViewId::Footnote)).ok(); },
+                }
+                true
+            },
+            Event::Device(DeviceEvent::Finger { position, .. }) if self.rect.includes(position) => true,
+            _ => false,
+        }
+    }
+
+    fn render(&self, fb: &mut dyn Framebuffer, _rect: Rectangle, _fonts: &mut Fonts) {
+        let dpi = CURRENT_DEVICE.dpi;
+        let thickness = scale_by_dpi(THICKNESS_LARGE, dpi) as u16;
+
+        fb.draw_rectangle_outline(&self.rect, &BorderSpec { thickness, color: BLACK });
+
+        let inner_rect = rect![self.rect.min + pt!(thickness as i32),
+                               self.rect.max - pt!(thickness as i32)];
+        let frame = rect![0, 0,
+                          inner_rect.width().min(self.pixmap.width) as i32,
+                          inner_rect.height().min(self.pixmap.height) as i32];
+        fb.draw_rectangle(&inner_rect, WHITE);
+        fb.draw_framed_pixmap(&self.pixmap, &frame, inner_rect.min);
+    }
+
+    fn resize(&mut self, rect: Rectangle, hub: &Hub, _rq: &mut RenderQueue, _context: &mut Context) {
+        self.rect = rect;
+        hub.send(Event::Close(ViewId::Footnote)).ok();
+    }
+
+    fn rect(&self) -> &Rectangle {
+        &self.rect
+    }
+
+    fn rect_mut(&mut self) -> &mut Rectangle {
+        &mut self.rect
+    }
+
+    fn children(&self) -> &Vec<Box<dyn View>> {
+        &self.children
+    }
+
+    fn children_mut(&mut self) -> &mut Vec<Box<dyn View>> {
+        &mut self.children
+    }
+
+    fn id(&self) -> Id {
+        self.id
+    }
+
+    fn view_id(&self) -> Option<ViewId> {
+        Some(ViewId::Footnote)
+    }
+}
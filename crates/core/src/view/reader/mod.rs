@@ -4,6 +4,8 @@ mod results_bar;
 mod margin_cropper;
 mod chapter_label;
 mod results_label;
+mod footnote;
+mod table_viewer;
 
 use std::thread;
 use std::sync::{Arc, Mutex};
@@ -19,7 +21,7 @@ use regex::Regex;
 use septem::prelude::*;
 use septem::{Roman, Digit};
 use rand_core::RngCore;
-use crate::input::{DeviceEvent, FingerStatus, ButtonCode, ButtonStatus};
+use crate::input::{DeviceEvent, FingerStatus, ButtonCode, ButtonStatus, PenStatus, PenTool};
 use crate::framebuffer::{Framebuffer, UpdateMode, Pixmap};
 use crate::view::{View, Event, AppCmd, Hub, Bus, RenderQueue, RenderData};
 use crate::view::{ViewId, Id, ID_FEEDER, EntryKind, EntryId, SliderId};
@@ -30,6 +32,8 @@ use crate::helpers::AsciiExtension;
 use crate::font::Fonts;
 use crate::font::family_names;
 use self::margin_cropper::{MarginCropper, BUTTON_DIAMETER};
+use self::footnote::Footnote;
+use self::table_viewer::TableViewer;
 use super::top_bar::TopBar;
 use self::tool_bar::ToolBar;
 use self::bottom_bar::BottomBar;
@@ -43,17 +47,24 @@ use crate::view::keyboard::Keyboard;
 use crate::view::menu::{Menu, MenuKind};
 use crate::view::menu_entry::MenuEntry;
 use crate::view::notification::Notification;
+use crate::view::dialog::Dialog;
 use crate::settings::{guess_frontlight, FinishedAction, SouthEastCornerAction, BottomRightGestureAction, SouthStripAction, WestStripAction, EastStripAction};
 use crate::settings::{DEFAULT_FONT_FAMILY, DEFAULT_TEXT_ALIGN, DEFAULT_LINE_HEIGHT, DEFAULT_MARGIN_WIDTH};
 use crate::settings::{HYPHEN_PENALTY, STRETCH_TOLERANCE};
 use crate::frontlight::LightLevels;
 use crate::gesture::GestureEvent;
-use crate::document::{Document, open, Location, TextLocation, BoundedText, Neighbors, BYTES_PER_PAGE};
+use crate::document::{Document, open_book, Location, TextLocation, BoundedText, Neighbors, BYTES_PER_PAGE, TABLE_URI};
 use crate::document::{TocEntry, SimpleTocEntry, TocLocation, toc_as_html, annotations_as_html, bookmarks_as_html};
 use crate::document::html::HtmlDocument;
-use crate::metadata::{Info, FileInfo, ReaderInfo, Annotation, TextAlign, ZoomMode, ScrollMode, PageScheme};
+use crate::document::html::engine::ResourceFetcher;
+use crate::document::reflow::{ReflowDocument, REFLOW_PAGE_SPAN};
+use crate::metadata::{Info, FileInfo, ReaderInfo, Annotation, Stroke, TextAlign, ZoomMode, ScrollMode, PageScheme};
 use crate::metadata::{Margin, CroppingMargins, make_query};
 use crate::metadata::{DEFAULT_CONTRAST_EXPONENT, DEFAULT_CONTRAST_GRAY};
+use crate::export::{BookAnnotations, EXPORT_FORMATS, export_book, export_annotated_pdf};
+use crate::kosync::{partial_md5, percentage, original_position};
+use crate::statistics::{Session, Totals};
+use crate::fulltext::parse_library_uri;
 use crate::geom::{Point, Vec2, Rectangle, Boundary, CornerSpec, BorderSpec};
 use crate::geom::{Dir, DiagDir, CycleDir, LinearDir, Axis, Region, halves};
 use crate::color::{BLACK, WHITE};
@@ -63,6 +74,7 @@ const HISTORY_SIZE: usize = 32;
 const RECT_DIST_JITTER: f32 = 24.0;
 const ANNOTATION_DRIFT: u8 =  0x44;
 const HIGHLIGHT_DRIFT: u8 =  0x22;
+const ERASER_RADIUS: f32 = 12.0;
 const MEM_SCHEME: &str = "mem:";
 
 pub struct Reader {
@@ -80,6 +92,7 @@ pub struct Reader {
     search_direction: LinearDir,
     held_buttons: FxHashSet<ButtonCode>,
     selection: Option<Selection>,
+    stroke: Option<(usize, Stroke)>,                 // The stroke being drawn, and its page.
     target_annotation: Option<[TextLocation; 2]>,
     history: VecDeque<usize>,
     state: State,
@@ -90,7 +103,10 @@ pub struct Reader {
     contrast: Contrast,
     synthetic: bool,
     page_turns: usize,
+    session: Option<Session>,
+    reading_totals: Totals,    // Reading totals of the previous sessions.
     reflowable: bool,
+    right_to_left: bool,
     ephemeral: bool,
     finished: bool,
 }
@@ -179,6 +195,17 @@ impl Default for Contrast {
     }
 }
 
+fn draw_stroke(fb: &mut dyn Framebuffer, stroke: &Stroke, chunk: &RenderChunk, clip: &Rectangle) {
+    let to_screen = |p: &[f32; 3]| Point::from(chunk.scale * vec2!(p[0], p[1])) - chunk.frame.min + chunk.position;
+    let radius = |p: &[f32; 3]| (chunk.scale * stroke.radius(p[2])).max(0.5);
+    if let [p] = stroke.points[..] {
+        fb.draw_clipped_segment(to_screen(&p), to_screen(&p), radius(&p), radius(&p), stroke.color, clip);
+    }
+    for w in stroke.points.windows(2) {
+        fb.draw_clipped_segment(to_screen(&w[0]), to_screen(&w[1]), radius(&w[0]), radius(&w[1]), stroke.color, clip);
+    }
+}
+
 fn scaling_factor(rect: &Rectangle, cropping_margin: &Margin, screen_margin_width: i32, dims: (f32, f32), zoom_mode: ZoomMode) -> f32 {
     if let ZoomMode::Custom(sf) = zoom_mode {
         return sf;
@@ -205,6 +232,46 @@ fn build_pixmap(rect: &Rectangle, doc: &mut dyn Document, location: usize) -> (P
     doc.pixmap(Location::Exact(location), scale, CURRENT_DEVICE.color_samples()).unwrap()
 }
 
+// Lays out the HTML of a note or a table with the given font, and renders its first page.
+fn render_html(html: &str, fetcher: Box<dyn ResourceFetcher>, dims: (u32, u32), font_size: f32, font_family: &str, context: &Context) -> Option<(HtmlDocument, Pixmap, Rectangle, usize)> {
+    let mut doc = HtmlDocument::new_with_fetcher(html, fetcher);
+    doc.layout(dims.0, dims.1, font_size, CURRENT_DEVICE.dpi);
+
+    if font_family != DEFAULT_FONT_FAMILY {
+        doc.set_font_family(font_family, &context.settings.reader.font_path);
+    }
+
+    let (pixmap, frame, location) = render_html_page(&mut doc, Location::Exact(0), 1.0)?;
+    Some((doc, pixmap, frame, location))
+}
+
+// Renders a page along with the smallest rectangle containing its non-blank pixels.
+fn render_html_page(doc: &mut HtmlDocument, loc: Location, scale: f32) -> Option<(Pixmap, Rectangle, usize)> {
+    let (pixmap, location) = doc.pixmap(loc, scale, CURRENT_DEVICE.color_samples())?;
+    let frame = content_frame(&pixmap).unwrap_or_else(|| rect![0, 0, pixmap.width as i32, pixmap.height as i32]);
+    Some((pixmap, frame, location))
+}
+
+fn content_frame(pixmap: &Pixmap) -> Option<Rectangle> {
+    let row_len = pixmap.width as usize * pixmap.samples;
+    let mut frame: Option<Rectangle> = None;
+    for (y, row) in pixmap.data.chunks(row_len).enumerate() {
+        let first = row.iter().position(|&v| v != WHITE.gray());
+        let last = row.iter().rposition(|&v| v != WHITE.gray());
+        if let (Some(first), Some(last)) = (first, last) {
+            let y = y as i32;
+            let r = rect![(first / pixmap.samples) as i32, y,
+                          (last / pixmap.samples) as i32 + 1, y + 1];
+            if let Some(f) = frame.as_mut() {
+                f.absorb(&r);
+            } else {
+                frame = Some(r);
+            }
+        }
+    }
+    frame
+}
+
 fn find_cut(frame: &Rectangle, y_pos: i32, scale: f32, dir: LinearDir, lines: &[BoundedText]) -> Option<i32> {
     let y_pos_u = y_pos as f32 / scale;
     let frame_u = frame.to_boundary() / scale;
@@ -244,13 +311,21 @@ impl Reader {
         let settings = &context.settings;
         let path = context.library.home.join(&info.file.path);
 
-        open(&path).and_then(|mut doc| {
+        open_book(&path, info.reader.as_ref()).and_then(|mut doc| {
+            if !doc.is_reflowable() && info.reader.as_ref().and_then(|r| r.reflow) == Some(true) {
+                doc = Box::new(ReflowDocument::new(doc));
+            }
+
             let (width, height) = context.display.dims;
             let font_size = info.reader.as_ref().and_then(|r| r.font_size)
                                 .unwrap_or(settings.reader.font_size);
 
             doc.layout(width, height, font_size, CURRENT_DEVICE.dpi);
 
+            let right_to_left = info.reader.as_ref().and_then(|r| r.right_to_left)
+                                    .unwrap_or_else(|| doc.is_right_to_left());
+            doc.set_right_to_left(right_to_left);
+
             let margin_width = info.reader.as_ref().and_then(|r| r.margin_width)
                                    .unwrap_or(settings.reader.margin_width);
 
@@ -360,6 +435,25 @@ impl Reader {
 
             println!("{}", info.file.path.display());
 
+            let session = Session::new(&info.file.path, &info.title, current_page);
+            let reading_totals = context.library.statistics().book_totals(&info.file.path);
+
+            if context.online {
+                if let Some(progress_sync) = context.library.progress_sync.clone() {
+                    let hub2 = hub.clone();
+                    let path = path.clone();
+                    thread::spawn(move || {
+                        match partial_md5(&path).and_then(|document| progress_sync.pull(&document)) {
+                            Ok(Some(progress)) if !progress_sync.is_own(&progress) => {
+                                hub2.send(Event::RemoteProgress(Box::new(progress))).ok();
+                            },
+                            Err(e) => eprintln!("Can't pull reading progress: {:#}.", e),
+                            _ => (),
+                        }
+                    });
+                }
+            }
+
             hub.send(Event::Update(UpdateMode::Partial)).ok();
 
             Some(Reader {
@@ -377,6 +471,7 @@ impl Reader {
                 search_direction: LinearDir::Forward,
                 held_buttons: FxHashSet::default(),
                 selection: None,
+                stroke: None,
                 target_annotation: None,
                 history: VecDeque::new(),
                 state: State::Idle,
@@ -386,9 +481,12 @@ impl Reader {
                 view_port,
                 synthetic,
                 page_turns: 0,
+                session: Some(session),
+                reading_totals,
                 contrast,
                 ephemeral: false,
                 reflowable,
+                right_to_left,
                 finished: false,
             })
         })
@@ -442,6 +540,7 @@ impl Reader {
             search_direction: LinearDir::Forward,
             held_buttons: FxHashSet::default(),
             selection: None,
+            stroke: None,
             target_annotation: None,
             history: VecDeque::new(),
             state: State::Idle,
@@ -451,9 +550,12 @@ impl Reader {
             view_port: ViewPort::default(),
             synthetic: true,
             page_turns: 0,
+            session: None,
+            reading_totals: Totals::default(),
             contrast: Contrast::default(),
             ephemeral: true,
             reflowable: true,
+            right_to_left: false,
             finished: false,
         }
     }
@@ -517,6 +619,10 @@ impl Reader {
                 s.current_page = s.highlights.range(..=location).count().saturating_sub(1);
             }
 
+            if let Some(ref mut session) = self.session {
+                session.turn_page(self.current_page, location);
+            }
+
             self.current_page = location;
             self.view_port.page_offset = pt!(0);
             self.selection = None;
@@ -851,6 +957,10 @@ impl Reader {
                     s.current_page = s.highlights.range(..=location).count().saturating_sub(1);
                 }
 
+                if let Some(ref mut session) = self.session {
+                    session.turn_page(current_page, location);
+                }
+
                 self.current_page = location;
                 self.selection = None;
                 self.state = State::Idle;
@@ -942,6 +1052,7 @@ impl Reader {
                                .unwrap_or_default();
             let progress = chapter.map(|(_, p)| p)
                                   .unwrap_or_default();
+            let (chapter_left, book_left) = self.time_left(doc.as_mut(), rtoc.as_deref());
             let bottom_bar = self.children[index].as_mut().downcast_mut::<BottomBar>().unwrap();
             let neighbors = Neighbors {
                 previous_page: doc.resolve_location(Location::Previous(current_page)),
@@ -949,10 +1060,30 @@ impl Reader {
             };
             bottom_bar.update_chapter_label(title, progress, rq);
             bottom_bar.update_page_label(self.current_page, self.pages_count, rq);
+            bottom_bar.update_time_left(chapter_left, book_left, rq);
             bottom_bar.update_icons(&neighbors, rq);
         }
     }
 
+    // Estimates the time needed to finish the current chapter and the book
+    // from the reading speed observed in the sessions spent on this book.
+    fn time_left(&self, doc: &mut dyn Document, toc: Option<&[TocEntry]>) -> (Option<u64>, Option<u64>) {
+        let mut totals = self.reading_totals;
+        if let Some(ref session) = self.session {
+            totals.duration += session.duration;
+            totals.advance += session.advance;
+        }
+        let chapter_left = toc.and_then(|toc| {
+            doc.chapter(self.current_page, toc)?;
+            let chapter_end = doc.chapter_relative(self.current_page, CycleDir::Next, toc)
+                                 .and_then(|chap| doc.resolve_location(chap.location.clone()))
+                                 .unwrap_or(self.pages_count);
+            totals.time_left(chapter_end.saturating_sub(self.current_page))
+        });
+        let book_left = totals.time_left(self.pages_count.saturating_sub(self.current_page));
+        (chapter_left, book_left)
+    }
+
     fn update_tool_bar(&mut self, rq: &mut RenderQueue, context: &mut Context) {
         if let Some(index) = locate::<ToolBar>(self) {
             let tool_bar = self.children[index].as_mut().downcast_mut::<ToolBar>().unwrap();
@@ -1596,16 +1727,19 @@ impl Reader {
                 next_page: doc.resolve_location(Location::Next(self.current_page)),
             };
 
-            let bottom_bar = BottomBar::new(rect![self.rect.min.x,
-                                                  self.rect.max.y - small_height + big_thickness,
-                                                  self.rect.max.x,
-                                                  self.rect.max.y],
-                                            doc.as_mut(),
-                                            self.toc(),
-                                            self.current_page,
-                                            self.pages_count,
-                                            &neighbors,
-                                            self.synthetic);
+            let rtoc = self.toc().or_else(|| doc.toc());
+            let (chapter_left, book_left) = self.time_left(doc.as_mut(), rtoc.as_deref());
+            let mut bottom_bar = BottomBar::new(rect![self.rect.min.x,
+                                                      self.rect.max.y - small_height + big_thickness,
+                                                      self.rect.max.x,
+                                                      self.rect.max.y],
+                                                doc.as_mut(),
+                                                rtoc,
+                                                self.current_page,
+                                                self.pages_count,
+                                                &neighbors,
+                                                self.synthetic);
+            bottom_bar.update_time_left(chapter_left, book_left, rq);
             self.children.insert(index, Box::new(bottom_bar) as Box<dyn View>);
 
             for i in 0..=index {
@@ -1760,6 +1894,9 @@ impl Reader {
                 entries.push(EntryKind::Command("Remove Note".to_string(), EntryId::RemoveAnnotationNote(sel)));
             }
 
+            entries.push(EntryKind::Separator);
+            entries.push(EntryKind::SubMenu("Export Annotations".to_string(), export_entries(self.info.file.kind == "pdf")));
+
             let selection_menu = Menu::new(rect, ViewId::AnnotationMenu, MenuKind::Contextual, entries, context);
             rq.add(RenderData::new(selection_menu.id(), *selection_menu.rect(), UpdateMode::Gui));
             self.children.push(Box::new(selection_menu) as Box<dyn View>);
@@ -1850,18 +1987,38 @@ impl Reader {
                 entries.push(EntryKind::Command("Save".to_string(), EntryId::Save));
             }
 
-            if self.info.reader.as_ref().map_or(false, |r| !r.annotations.is_empty()) {
+            if self.info.reader.as_ref().map_or(false, |r| !r.annotations.is_empty() || !r.ink.is_empty()) {
                 entries.push(EntryKind::Command("Annotations".to_string(), EntryId::Annotations));
             }
 
+            if self.info.reader.as_ref().map_or(false, |r| self.chunks.iter().any(|c| r.ink.contains_key(&c.location))) {
+                entries.push(EntryKind::Command("Erase Ink".to_string(), EntryId::EraseInk));
+            }
+
             if self.info.reader.as_ref().map_or(false, |r| !r.bookmarks.is_empty()) {
                 entries.push(EntryKind::Command("Bookmarks".to_string(), EntryId::Bookmarks));
             }
 
+            if self.info.reader.as_ref().map_or(false, |r| !r.annotations.is_empty() || !r.bookmarks.is_empty() || !r.ink.is_empty()) {
+                entries.push(EntryKind::SubMenu("Export Annotations".to_string(), export_entries(self.info.file.kind == "pdf")));
+            }
+
             if !entries.is_empty() {
                 entries.push(EntryKind::Separator);
             }
 
+            entries.push(EntryKind::CheckBox("Right to Left".to_string(),
+                                             EntryId::ToggleRightToLeft,
+                                             self.right_to_left));
+
+            let reflow = self.info.reader.as_ref().and_then(|r| r.reflow) == Some(true);
+
+            if !self.reflowable || reflow {
+                entries.push(EntryKind::CheckBox("Reflow".to_string(),
+                                                 EntryId::ToggleReflow,
+                                                 reflow));
+            }
+
             entries.push(EntryKind::CheckBox("Apply Dithering".to_string(),
                                              EntryId::ToggleDithered,
                                              context.fb.dithered()));
@@ -2618,6 +2775,13 @@ impl Reader {
         rq.add(RenderData::new(self.id, self.rect, UpdateMode::Gui));
     }
 
+    // The font size and family of the notes and the tables shown over the pages.
+    fn popup_font(&self, context: &Context) -> (f32, String) {
+        let reader = self.info.reader.as_ref();
+        (reader.and_then(|r| r.font_size).unwrap_or(context.settings.reader.font_size),
+         reader.and_then(|r| r.font_family.clone()).unwrap_or_else(|| context.settings.reader.font_family.clone()))
+    }
+
     fn quit(&mut self, context: &mut Context) {
         if let Some(ref mut s) = self.search {
             s.running.store(false, AtomicOrdering::Relaxed);
@@ -2627,6 +2791,11 @@ impl Reader {
             return;
         }
 
+        if let Some(mut session) = self.session.take() {
+            session.finish(self.current_page);
+            context.library.add_reading_session(session);
+        }
+
         if let Some(ref mut r) = self.info.reader {
             r.current_page = self.current_page;
             r.pages_count = self.pages_count;
@@ -2665,6 +2834,60 @@ impl Reader {
         }
     }
 
+    // The location of the page under the given point, the point in the coordinates of that page and the scale of the page.
+    fn page_point(&self, position: Point) -> Option<(usize, Vec2, f32)> {
+        self.chunks.iter().find(|chunk| {
+            let chunk_rect = chunk.frame - chunk.frame.min + chunk.position;
+            chunk_rect.includes(position)
+        }).map(|chunk| {
+            let pt = Vec2::from(position - chunk.position + chunk.frame.min) / chunk.scale;
+            (chunk.location, pt, chunk.scale)
+        })
+    }
+
+    fn extend_stroke(&mut self, position: Point, pressure: f32, rq: &mut RenderQueue) {
+        let (location, stroke) = match self.stroke.as_mut() {
+            Some((location, stroke)) => (*location, stroke),
+            None => return,
+        };
+
+        if let Some(chunk) = self.chunks.iter().find(|chunk| chunk.location == location) {
+            let pt = Vec2::from(position - chunk.position + chunk.frame.min) / chunk.scale;
+            let last = stroke.points.last().map_or(position, |p| {
+                Point::from(chunk.scale * vec2!(p[0], p[1])) - chunk.frame.min + chunk.position
+            });
+            stroke.points.push([pt.x, pt.y, pressure]);
+            let radius = (chunk.scale * stroke.width).ceil() as i32;
+            if let Some(rect) = Rectangle::from_segment(last, position, radius, radius).intersection(&self.rect) {
+                rq.add(RenderData::no_wait(self.id, rect, UpdateMode::FastMono));
+            }
+        }
+    }
+
+    // Removes the strokes touched by the eraser.
+    fn erase_ink(&mut self, position: Point, rq: &mut RenderQueue) {
+        let (location, pt, scale) = match self.page_point(position) {
+            Some(v) => v,
+            None => return,
+        };
+        let radius = scale_by_dpi(ERASER_RADIUS, CURRENT_DEVICE.dpi) / scale;
+
+        if let Some(r) = self.info.reader.as_mut() {
+            if let Some(strokes) = r.ink.get_mut(&location) {
+                let count = strokes.len();
+                strokes.retain(|stroke| {
+                    !stroke.points.iter().any(|p| (vec2!(p[0], p[1]) - pt).length() <= radius + stroke.width / 2.0)
+                });
+                if strokes.len() != count {
+                    if strokes.is_empty() {
+                        r.ink.remove(&location);
+                    }
+                    rq.add(RenderData::new(self.id, self.rect, UpdateMode::Gui));
+                }
+            }
+        }
+    }
+
     fn scale_page(&mut self, center: Point, factor: f32, hub: &Hub, rq: &mut RenderQueue, context: &mut Context) {
         if self.cache.is_empty() {
             return;
@@ -2704,6 +2927,7 @@ impl View for Reader {
             Event::Gesture(GestureEvent::Swipe { dir, start, end }) if self.rect.includes(start) => {
                 match self.view_port.zoom_mode {
                     ZoomMode::FitToPage | ZoomMode::FitToWidth => {
+                        let dir = if self.right_to_left && dir.axis() == Axis::Horizontal { dir.opposite() } else { dir };
                         match dir {
                             Dir::West => self.go_to_neighbor(CycleDir::Next, hub, rq, context),
                             Dir::East => self.go_to_neighbor(CycleDir::Previous, hub, rq, context),
@@ -2864,6 +3088,35 @@ impl View for Reader {
                 }
                 true
             },
+            Event::Device(DeviceEvent::Pen { status: PenStatus::Down, tool: PenTool::Pen, position, pressure, .. }) if !self.reflowable && self.state == State::Idle => {
+                if let Some((location, pt, scale)) = self.page_point(position) {
+                    let pen = &context.settings.sketch.pen;
+                    self.stroke = Some((location, Stroke {
+                        points: vec![[pt.x, pt.y, pressure]],
+                        width: pen.size as f32 / scale,
+                        color: pen.color,
+                        .. Default::default()
+                    }));
+                }
+                true
+            },
+            Event::Device(DeviceEvent::Pen { status: PenStatus::Motion, tool: PenTool::Pen, position, pressure, .. }) if self.stroke.is_some() => {
+                self.extend_stroke(position, pressure, rq);
+                true
+            },
+            Event::Device(DeviceEvent::Pen { status: PenStatus::Up, tool: PenTool::Pen, position, pressure, .. }) if self.stroke.is_some() => {
+                self.extend_stroke(position, pressure, rq);
+                if let Some((location, stroke)) = self.stroke.take() {
+                    if let Some(r) = self.info.reader.as_mut() {
+                        r.ink.entry(location).or_default().push(stroke);
+                    }
+                }
+                true
+            },
+            Event::Device(DeviceEvent::Pen { status: PenStatus::Down | PenStatus::Motion, tool: PenTool::Eraser, position, .. }) if !self.reflowable => {
+                self.erase_ink(position, rq);
+                true
+            },
             Event::Device(DeviceEvent::Finger { position, status: FingerStatus::Motion, id, .. }) if self.state == State::Selection(id) => {
                 let mut nearest_word = None;
                 let mut dmin = u32::MAX;
@@ -3101,7 +3354,15 @@ impl View for Reader {
                     let pdf_page = Regex::new(r"^#page=(\d+).*$").unwrap();
                     let djvu_page = Regex::new(r"^#([+-])?(\d+)$").unwrap();
                     let toc_page = Regex::new(r"^@(.+)$").unwrap();
-                    if let Some(caps) = toc_page.captures(&link.text) {
+                    if link.text == TABLE_URI {
+                        let table = self.doc.lock().unwrap().table(link.location.location());
+                        if let Some((html, resources)) = table {
+                            let (font_size, font_family) = self.popup_font(context);
+                            if let Some(table_viewer) = TableViewer::new(&html, resources, font_size, &font_family, rq, context) {
+                                self.children.push(Box::new(table_viewer) as Box<dyn View>);
+                            }
+                        }
+                    } else if let Some(caps) = toc_page.captures(&link.text) {
                         let loc_opt = if caps[1].chars().all(|c| c.is_digit(10)) {
                             caps[1].parse::<usize>()
                                    .map(Location::Exact)
@@ -3128,10 +3389,27 @@ impl View for Reader {
                             }
                             self.go_to_page(index, true, hub, rq, context);
                         }
+                    } else if let Some((path, location)) = parse_library_uri(&link.text) {
+                        if let Some(mut info) = context.library.info(&path) {
+                            let r = info.reader.get_or_insert_with(ReaderInfo::default);
+                            r.current_page = location;
+                            r.page_offset = None;
+                            r.finished = false;
+                            self.quit(context);
+                            hub.send(Event::Back).ok();
+                            hub.send(Event::Open(Box::new(info))).ok();
+                        } else {
+                            eprintln!("Can't find document: {}.", path.display());
+                        }
                     } else {
                         let mut doc = self.doc.lock().unwrap();
                         let loc = Location::LocalUri(self.current_page, link.text.clone());
-                        if let Some(location) = doc.resolve_location(loc) {
+                        if let Some((html, resources)) = doc.footnote(link.location.location(), &link.text) {
+                            let (font_size, font_family) = self.popup_font(context);
+                            if let Some(footnote) = Footnote::new(&html, resources, font_size, &font_family, rq, context) {
+                                self.children.push(Box::new(footnote) as Box<dyn View>);
+                            }
+                        } else if let Some(location) = doc.resolve_location(loc) {
                             hub.send(Event::GoTo(location)).ok();
                         } else {
                             if link.text.starts_with("https:") || link.text.starts_with("http:") {
@@ -3220,6 +3498,7 @@ impl View for Reader {
                         }
                     },
                     Region::Strip(dir) => {
+                        let dir = if self.right_to_left && dir.axis() == Axis::Horizontal { dir.opposite() } else { dir };
                         match dir {
                             Dir::West => {
                                 if self.search.is_none() {
@@ -3618,17 +3897,46 @@ impl View for Reader {
                                      .map(|annot| annot.selection[0]).collect::<Vec<TextLocation>>();
                 starts.sort();
                 let active_range = starts.first().cloned().zip(starts.last().cloned());
-                if let Some(mut annotations) = self.info.reader.as_ref().map(|r| &r.annotations).cloned() {
+                if let Some(r) = self.info.reader.as_ref() {
+                    let mut annotations = r.annotations.clone();
                     annotations.sort_by(|a, b| a.selection[0].cmp(&b.selection[0]));
-                    let html = annotations_as_html(&annotations, active_range);
+                    let html = annotations_as_html(&annotations, &r.ink, active_range);
                     let link_uri = annotations.iter()
-                                              .filter(|annot| annot.selection[0].location() <= self.current_page)
-                                              .max_by_key(|annot| annot.selection[0])
-                                              .map(|annot| format!("@{}", annot.selection[0].location()));
+                                              .map(|annot| annot.selection[0].location())
+                                              .chain(r.ink.keys().cloned())
+                                              .filter(|location| *location <= self.current_page)
+                                              .max()
+                                              .map(|location| format!("@{}", location));
                     hub.send(Event::OpenHtml(html, link_uri)).ok();
                 }
                 true
             },
+            Event::Select(EntryId::ExportAnnotations(format)) => {
+                let book = {
+                    let mut doc = self.doc.lock().unwrap();
+                    BookAnnotations::from_document(&self.info, doc.as_mut())
+                };
+                let save_path = context.library.home.join(&context.settings.export.save_path);
+                let msg = match export_book(&book, format, &save_path) {
+                    Err(e) => format!("Can't export annotations: {:#}.", e),
+                    Ok(path) => format!("Exported annotations to {}.",
+                                        path.strip_prefix(&context.library.home).unwrap_or(&path).display()),
+                };
+                let notif = Notification::new(msg, hub, rq, context);
+                self.children.push(Box::new(notif) as Box<dyn View>);
+                true
+            },
+            Event::Select(EntryId::ExportAnnotatedPdf) => {
+                let save_path = context.library.home.join(&context.settings.export.save_path);
+                let msg = match export_annotated_pdf(&context.library.home, &self.info, &save_path) {
+                    Err(e) => format!("Can't export annotated PDF: {:#}.", e),
+                    Ok(path) => format!("Exported annotated PDF to {}.",
+                                        path.strip_prefix(&context.library.home).unwrap_or(&path).display()),
+                };
+                let notif = Notification::new(msg, hub, rq, context);
+                self.children.push(Box::new(notif) as Box<dyn View>);
+                true
+            },
             Event::Select(EntryId::Bookmarks) => {
                 self.toggle_bars(Some(false), hub, rq, context);
                 if let Some(bookmarks) = self.info.reader.as_ref().map(|r| &r.bookmarks) {
@@ -3651,6 +3959,44 @@ impl View for Reader {
                 self.toggle_margin_cropper(false, hub, rq, context);
                 true
             },
+            Event::Close(ViewId::Footnote) => {
+                if let Some(index) = locate::<Footnote>(self) {
+                    rq.add(RenderData::expose(*self.child(index).rect(), UpdateMode::Gui));
+                    self.children.remove(index);
+                }
+                true
+            },
+            Event::Close(ViewId::TableViewer) => {
+                if let Some(index) = locate::<TableViewer>(self) {
+                    rq.add(RenderData::expose(*self.child(index).rect(), UpdateMode::Full));
+                    self.children.remove(index);
+                }
+                true
+            },
+            Event::RemoteProgress(ref progress) => {
+                let reflow = self.info.reader.as_ref().and_then(|r| r.reflow) == Some(true);
+                let (current_page, pages_count, synthetic) = original_position(self.current_page, self.pages_count,
+                                                                               self.synthetic, reflow);
+                if progress.percentage <= percentage(current_page, pages_count, synthetic) {
+                    return true;
+                }
+                let location = {
+                    let location = progress.location(pages_count, synthetic);
+                    let location = if reflow { location * REFLOW_PAGE_SPAN } else { location };
+                    let mut doc = self.doc.lock().unwrap();
+                    doc.resolve_location(Location::Exact(location))
+                };
+                if let Some(location) = location.filter(|&location| location != self.current_page) {
+                    let dialog = Dialog::new(ViewId::ProgressSyncDialog,
+                                             Some(Event::GoTo(location)),
+                                             format!("Go to {:.0}%, reached on {}?",
+                                                     100.0 * progress.percentage, progress.device),
+                                             context);
+                    rq.add(RenderData::new(dialog.id(), *dialog.rect(), UpdateMode::Gui));
+                    self.children.push(Box::new(dialog) as Box<dyn View>);
+                }
+                true
+            },
             Event::SearchResult(location, ref rects) => {
                 if self.search.is_none() {
                     return true;
@@ -3799,6 +4145,47 @@ impl View for Reader {
                 self.set_scroll_mode(scroll_mode, hub, rq, context);
                 true
             },
+            Event::Select(EntryId::ToggleRightToLeft) => {
+                self.right_to_left = !self.right_to_left;
+                self.doc.lock().unwrap().set_right_to_left(self.right_to_left);
+                if let Some(ref mut r) = self.info.reader {
+                    r.right_to_left = Some(self.right_to_left);
+                }
+                self.cache.clear();
+                self.update(None, hub, rq, context);
+                true
+            },
+            Event::Select(EntryId::EraseInk) => {
+                if let Some(r) = self.info.reader.as_mut() {
+                    for chunk in &self.chunks {
+                        r.ink.remove(&chunk.location);
+                    }
+                }
+                rq.add(RenderData::new(self.id, self.rect, UpdateMode::Gui));
+                true
+            },
+            Event::Select(EntryId::ToggleReflow) => {
+                self.quit(context);
+                let mut info = self.info.clone();
+                if let Some(ref mut r) = info.reader {
+                    // The locations of the reflowed pages are derived from the original pages.
+                    let reflow = r.reflow != Some(true);
+                    let convert = |location: usize| if reflow { location * REFLOW_PAGE_SPAN } else { location / REFLOW_PAGE_SPAN };
+                    r.current_page = convert(r.current_page);
+                    r.bookmarks = r.bookmarks.iter().map(|&b| convert(b)).collect();
+                    // The strokes of the reflowed pages of an original page end up on that page.
+                    let ink = std::mem::take(&mut r.ink);
+                    for (location, strokes) in ink {
+                        r.ink.entry(convert(location)).or_default().extend(strokes);
+                    }
+                    r.page_offset = None;
+                    r.zoom_mode = None;
+                    r.reflow = if reflow { Some(true) } else { None };
+                }
+                hub.send(Event::Back).ok();
+                hub.send(Event::Open(Box::new(info))).ok();
+                true
+            },
             Event::Select(EntryId::Save) => {
                 let name = format!("{}-{}.{}", self.info.title.to_lowercase().replace(' ', "_"),
                                    Local::now().format("%Y%m%d_%H%M%S"),
@@ -3975,6 +4362,16 @@ impl View for Reader {
                     }
                 }
 
+                if let Some(strokes) = self.info.reader.as_ref().and_then(|r| r.ink.get(&chunk.location)) {
+                    for stroke in strokes {
+                        draw_stroke(fb, stroke, chunk, &region_rect);
+                    }
+                }
+
+                if let Some((_, stroke)) = self.stroke.as_ref().filter(|(location, _)| *location == chunk.location) {
+                    draw_stroke(fb, stroke, chunk, &region_rect);
+                }
+
                 if let Some(annotations) = self.annotations.get(&chunk.location) {
                     for annot in annotations {
                         let drift = if annot.note.is_empty() { HIGHLIGHT_DRIFT } else { ANNOTATION_DRIFT };
@@ -4215,3 +4612,14 @@ impl View for Reader {
         self.id
     }
 }
+
+fn export_entries(pdf: bool) -> Vec<EntryKind> {
+    let mut entries = EXPORT_FORMATS.iter()
+                                    .map(|format| EntryKind::Command(format.to_string(), EntryId::ExportAnnotations(*format)))
+                                    .collect::<Vec<EntryKind>>();
+    if pdf {
+        entries.push(EntryKind::Separator);
+        entries.push(EntryKind::Command("Annotated PDF".to_string(), EntryId::ExportAnnotatedPdf));
+    }
+    entries
+}
@@ -0,0 +1,196 @@
+use crate::framebuffer::{Framebuffer, UpdateMode, Pixmap};
+use crate::document::Location;
+use crate::document::html::HtmlDocument;
+use crate::document::html::engine::ResourceFetcher;
+use crate::gesture::GestureEvent;
+use crate::input::DeviceEvent;
+use crate::font::Fonts;
+use crate::geom::{Point, Vec2, Rectangle, Dir, Axis, CycleDir};
+use crate::view::{View, Event, Hub, Bus, Id, ID_FEEDER, RenderQueue, RenderData, ViewId};
+use crate::color::WHITE;
+use crate::context::Context;
+use super::{render_html, render_html_page};
+
+// The width of the pages on which the table is laid out, relative to the width of the screen.
+const WIDTH_FACTOR: u32 = 3;
+const MAX_SCALE: f32 = 2.0;
+
+// Displays a table too wide for its page over the whole screen.
+// Swipes scroll through the table, pinches and spreads zoom in and out.
+pub struct TableViewer {
+    id: Id,
+    rect: Rectangle,
+    children: Vec<Box<dyn View>>,
+    doc: HtmlDocument,
+    pixmap: Pixmap,
+    // The part of the pixmap covered by the table.
+    frame: Rectangle,
+    location: usize,
+    scale: f32,
+    min_scale: f32,
+    // The position of the visible area, relative to the frame.
+    offset: Point,
+}
+
+impl TableViewer {
+    pub fn new(html: &str, resources: Box<dyn ResourceFetcher>, font_size: f32, font_family: &str, rq: &mut RenderQueue, context: &mut Context) -> Option<TableViewer> {
+        let id = ID_FEEDER.next();
+        let (width, height) = context.display.dims;
+        let rect = rect![0, 0, width as i32, height as i32];
+
+        let (doc, pixmap, frame, location) = render_html(html, resources, (WIDTH_FACTOR * width, height),
+                                                         font_size, font_family, context)?;
+        let min_scale = (width as f32 / frame.width().max(1) as f32).min(1.0);
+
+        rq.add(RenderData::new(id, rect, UpdateMode::Full));
+
+        Some(TableViewer {
+            id,
+            rect,
+            children: Vec::new(),
+            doc,
+            pixmap,
+            frame,
+            location,
+            scale: 1.0,
+            min_scale,
+            offset: Point::default(),
+        })
+    }
+
+    fn max_offset(&self) -> Point {
+        pt!((self.frame.width() as i32 - self.rect.width() as i32).max(0),
+            (self.frame.height() as i32 - self.rect.height() as i32).max(0))
+    }
+
+    fn clamp_offset(&mut self) {
+        let max_offset = self.max_offset();
+        self.offset.x = self.offset.x.clamp(0, max_offset.x);
+        self.offset.y = self.offset.y.clamp(0, max_offset.y);
+    }
+
+    fn scroll(&mut self, delta: Point, rq: &mut RenderQueue) {
+        let offset = self.offset;
+        self.offset += delta;
+        self.clamp_offset();
+        if self.offset != offset {
+            rq.add(RenderData::new(self.id, self.rect, UpdateMode::Partial));
+        }
+    }
+
+    fn go_to_neighbor(&mut self, dir: CycleDir, rq: &mut RenderQueue) -> bool {
+        let loc = match dir {
+            CycleDir::Next => Location::Next(self.location),
+            CycleDir::Previous => Location::Previous(self.location),
+        };
+        if let Some((pixmap, frame, location)) = render_html_page(&mut self.doc, loc, self.scale) {
+            self.pixmap = pixmap;
+            self.frame = frame;
+            self.location = location;
+            self.offset.y = if dir == CycleDir::Next { 0 } else { self.max_offset().y };
+            self.clamp_offset();
+            rq.add(RenderData::new(self.id, self.rect, UpdateMode::Partial));
+            true
+        } else {
+            false
+        }
+    }
+
+    fn zoom(&mut self, center: Point, factor: f32, rq: &mut RenderQueue) {
+        let scale = (self.scale * factor).clamp(self.min_scale, MAX_SCALE);
+        if (scale - self.scale).abs() < f32::EPSILON {
+            return;
+        }
+        if let Some((pixmap, frame, location)) = render_html_page(&mut self.doc, Location::Exact(self.location), scale) {
+            // Keep the point under the center of the gesture in place.
+            let anchor = center - self.rect.min;
+            let ratio = scale / self.scale;
+            self.offset = Point::from(ratio * Vec2::from(self.offset + anchor)) - anchor;
+            self.pixmap = pixmap;
+            self.frame = frame;
+            self.location = location;
+            self.scale = scale;
+            self.clamp_offset();
+            rq.add(RenderData::new(self.id, self.rect, UpdateMode::Gui));
+        }
+    }
+}
+
+impl View for TableViewer {
+    fn handle_event(&mut self, evt: &Event, hub: &Hub, _bus: &mut Bus, rq: &mut RenderQueue, _context: &mut Context) -> bool {
+        match *evt {
+            Event::Gesture(GestureEvent::Tap(..)) => {
+                hub.send(Event::Close(ViewId::TableViewer)).ok();
+                true
+            },
+            Event::Gesture(GestureEvent::Swipe { dir, start, end }) => {
+                let max_offset = self.max_offset();
+                match dir {
+                    Dir::North if self.offset.y == max_offset.y => {
+                        self.go_to_neighbor(CycleDir::Next, rq);
+                    },
+                    Dir::South if self.offset.y == 0 => {
+                        self.go_to_neighbor(CycleDir::Previous, rq);
+                    },
+                    _ => self.scroll(start - end, rq),
+                }
+                true
+            },
+            Event::Gesture(GestureEvent::Spread { axis: Axis::Diagonal, center, factor }) |
+            Event::Gesture(GestureEvent::Pinch { axis: Axis::Diagonal, center, factor }) if factor.is_finite() => {
+                self.zoom(center, factor, rq);
+                true
+            },
+            Event::Gesture(..) => true,
+            Event::Device(DeviceEvent::Finger { .. }) => true,
+            _ => false,
+        }
+    }
+
+    fn render(&self, fb: &mut dyn Framebuffer, _rect: Rectangle, _fonts: &mut Fonts) {
+        fb.draw_rectangle(&self.rect, WHITE);
+
+        let width = (self.frame.width() as i32 - self.offset.x).min(self.rect.width() as i32);
+        let height = (self.frame.height() as i32 - self.offset.y).min(self.rect.height() as i32);
+
+        if width <= 0 || height <= 0 {
+            return;
+        }
+
+        // Tables smaller than the screen are centered.
+        let shift = pt!((self.rect.width() as i32 - width) / 2,
+                        (self.rect.height() as i32 - height) / 2);
+        let min = self.frame.min + self.offset;
+        let frame = rect![min, min + pt!(width, height)];
+        fb.draw_framed_pixmap(&self.pixmap, &frame, self.rect.min + shift);
+    }
+
+    fn resize(&mut self, rect: Rectangle, hub: &Hub, _rq: &mut RenderQueue, _context: &mut Context) {
+        self.rect = rect;
+        hub.send(Event::Close(ViewId::TableViewer)).ok();
+    }
+
+    fn rect(&self) -> &Rectangle {
+        &self.rect
+    }
+
+    fn rect_mut(&mut self) -> &mut Rectangle {
+        &mut self.rect
+    }
+
+    fn children(&self) -> &Vec<Box<dyn View>> {
+        &self.children
+    }
+
+    fn children_mut(&mut self) -> &mut Vec<Box<dyn View>> {
+        &mut self.children
+    }
+
+    fn id(&self) -> Id {
+        self.id
+    }
+
+    fn view_id(&self) -> Option<ViewId> {
+        Some(ViewId::TableViewer)
+    }
+}
@@ -1,17 +1,23 @@
-use std::fs::{self, File};
+pub mod notebook;
+
+use std::fs;
+use std::ffi::OsStr;
 use std::path::PathBuf;
-use fxhash::FxHashMap;
+use std::collections::VecDeque;
+use fxhash::FxHashSet;
 use chrono::Local;
 use walkdir::WalkDir;
 use globset::Glob;
 use anyhow::Error;
 use crate::device::CURRENT_DEVICE;
-use crate::geom::{Point, Rectangle, CornerSpec};
-use crate::input::{DeviceEvent, FingerStatus};
+use crate::geom::{Point, Vec2, Boundary, Rectangle, CornerSpec, BorderSpec, Dir};
+use crate::input::{DeviceEvent, FingerStatus, PenStatus, PenTool};
+use crate::gesture::GestureEvent;
 use crate::view::icon::{Icon, ICONS_PIXMAPS};
 use crate::view::notification::Notification;
 use crate::view::menu::{Menu, MenuKind};
 use crate::view::common::{locate_by_id};
+use crate::view::handwriting::recognizer::{Recognizer, Request};
 use crate::view::{View, Event, Hub, Bus, RenderQueue, RenderData};
 use crate::view::{EntryKind, EntryId, ViewId, Id, ID_FEEDER};
 use crate::view::{SMALL_BAR_HEIGHT, BORDER_RADIUS_SMALL};
@@ -20,24 +26,44 @@ use crate::settings::{ImportSettings, Pen};
 use crate::helpers::IsHidden;
 use crate::font::Fonts;
 use crate::unit::scale_by_dpi;
-use crate::color::{Color, BLACK, WHITE};
+use crate::color::{Color, BLACK, WHITE, GRAY07, GRAY11};
 use crate::context::Context;
+use self::notebook::{Notebook, Stroke, StrokePoint, Template, NotebookFormat};
 
-const FILENAME_PATTERN: &str = "sketch-%Y%m%d_%H%M%S.png";
+const FILENAME_PATTERN: &str = "sketch-%Y%m%d_%H%M%S.json";
 const ICON_NAME: &str = "enclosed_menu";
 // https://oeis.org/A000041
 const PEN_SIZES: [i32; 12] = [1, 2, 3, 5, 7, 11, 15, 22, 30, 42, 56, 77];
+const TEMPLATES: [Template; 4] = [Template::Blank, Template::Lined, Template::Grid, Template::Dotted];
+const ERASER_RADIUS: f32 = 12.0;
+const MAX_SCALE: f32 = 4.0;
+// The pressure of the strokes drawn with a finger.
+const FINGER_PRESSURE: f32 = 0.5;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Tool {
+    Pen,
+    Eraser,
+    Lasso,
+}
 
-struct TouchState {
-    pt: Point,
-    time: f64,
-    radius: f32,
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Source {
+    Finger(i32),
+    Stylus,
 }
 
-impl TouchState {
-    fn new(pt: Point, time: f64, radius: f32) -> TouchState {
-        TouchState { pt, time, radius }
-    }
+// The positions are in page coordinates.
+enum Interaction {
+    Draw { stroke: Stroke, radius: f32 },
+    Erase,
+    Lasso(Vec<Vec2>),
+    Move { start: Vec2, delta: Vec2 },
+}
+
+struct Selection {
+    indices: Vec<usize>,
+    boundary: Boundary,
 }
 
 pub struct Sketch {
@@ -45,10 +71,23 @@ pub struct Sketch {
     rect: Rectangle,
     children: Vec<Box<dyn View>>,
     pixmap: Pixmap,
-    fingers: FxHashMap<i32, TouchState>,
+    notebook: Notebook,
+    current_page: usize,
+    // The zoom factor and the page coordinates of the top left corner of the screen.
+    scale: f32,
+    offset: Vec2,
+    fingers: FxHashSet<i32>,
+    interaction: Option<(Source, Interaction)>,
+    selection: Option<Selection>,
+    tool: Tool,
     pen: Pen,
     save_path: PathBuf,
     filename: String,
+    recognizer: Option<Recognizer>,
+    // The pages whose text is being recognized.
+    recognitions: VecDeque<usize>,
+    // The background of the current page, by name.
+    background: Option<(String, Pixmap)>,
 }
 
 impl Sketch {
@@ -78,10 +117,20 @@ impl Sketch {
             rect,
             children,
             pixmap: Pixmap::new(rect.width(), rect.height(), 1),
-            fingers: FxHashMap::default(),
+            notebook: Notebook::new(rect.width(), rect.height(), dpi, Template::Blank),
+            current_page: 0,
+            scale: 1.0,
+            offset: vec2!(0.0, 0.0),
+            fingers: FxHashSet::default(),
+            interaction: None,
+            selection: None,
+            tool: Tool::Pen,
             pen: context.settings.sketch.pen.clone(),
             save_path,
             filename: Local::now().format(FILENAME_PATTERN).to_string(),
+            recognizer: None,
+            recognitions: VecDeque::new(),
+            background: None,
         }
     }
 
@@ -98,15 +147,31 @@ impl Sketch {
                 return;
             }
 
-            let glob = Glob::new("**/*.png").unwrap().compile_matcher();
+            // The images are the sketches saved before the notebooks: they're
+            // hidden once they've been imported.
+            let glob = Glob::new("**/*.{json,png}").unwrap().compile_matcher();
             let mut loadables: Vec<PathBuf> =
                 WalkDir::new(&self.save_path).min_depth(1).into_iter()
                         .filter_map(|e| e.ok().filter(|e| !e.is_hidden())
                                          .and_then(|e| e.path().file_name().map(PathBuf::from)))
                         .filter(|p| glob.is_match(p))
+                        .filter(|p| p.extension() != Some(OsStr::new("png")) ||
+                                    !self.save_path.join(p.with_extension("json")).exists())
                         .collect();
             loadables.sort_by(|a, b| b.cmp(a));
 
+            let tools = vec![
+                EntryKind::RadioButton("Pen".to_string(),
+                                       EntryId::SetSketchTool(Tool::Pen),
+                                       self.tool == Tool::Pen),
+                EntryKind::RadioButton("Eraser".to_string(),
+                                       EntryId::SetSketchTool(Tool::Eraser),
+                                       self.tool == Tool::Eraser),
+                EntryKind::RadioButton("Lasso".to_string(),
+                                       EntryId::SetSketchTool(Tool::Lasso),
+                                       self.tool == Tool::Lasso),
+            ];
+
             let mut sizes = vec![
                 EntryKind::CheckBox("Dynamic".to_string(),
                                     EntryId::TogglePenDynamism,
@@ -140,35 +205,326 @@ impl Sketch {
                                                    self.pen.color == color));
             }
 
+            let template = self.notebook.pages[self.current_page].template;
+            let templates = TEMPLATES.iter().map(|t| {
+                EntryKind::RadioButton(t.to_string(), EntryId::SetTemplate(*t), template == *t)
+            }).collect();
+
+            let mut pages = (0..self.notebook.pages.len()).map(|i| {
+                EntryKind::RadioButton(format!("Page {}", i + 1), EntryId::GoTo(i), self.current_page == i)
+            }).collect::<Vec<EntryKind>>();
+            pages.push(EntryKind::Separator);
+            pages.push(EntryKind::Command("Add Page".to_string(), EntryId::AddPage));
+            if self.notebook.pages.len() > 1 {
+                pages.push(EntryKind::Command("Remove Page".to_string(), EntryId::RemovePage));
+            }
+
+            let exports = [NotebookFormat::Svg, NotebookFormat::Pdf].iter().map(|format| {
+                EntryKind::Command(format.to_string(), EntryId::ExportSketch(*format))
+            }).collect();
+
             let mut entries = vec![
+                EntryKind::SubMenu("Tool".to_string(), tools),
                 EntryKind::SubMenu("Size".to_string(), sizes),
                 EntryKind::SubMenu("Color".to_string(), colors),
+                EntryKind::SubMenu("Template".to_string(), templates),
+                EntryKind::SubMenu("Pages".to_string(), pages),
                 EntryKind::Separator,
-                EntryKind::Command("Save".to_string(), EntryId::Save),
-                EntryKind::Command("Refresh".to_string(), EntryId::Refresh),
-                EntryKind::Command("New".to_string(), EntryId::New),
-                EntryKind::Command("Quit".to_string(), EntryId::Quit),
             ];
 
+            if self.notebook.can_undo() {
+                entries.push(EntryKind::Command("Undo".to_string(), EntryId::Undo));
+            }
+
+            if self.notebook.can_redo() {
+                entries.push(EntryKind::Command("Redo".to_string(), EntryId::Redo));
+            }
+
+            if self.notebook.can_undo() || self.notebook.can_redo() {
+                entries.push(EntryKind::Separator);
+            }
+
+            entries.push(EntryKind::Command("Save".to_string(), EntryId::Save));
+            entries.push(EntryKind::SubMenu("Export".to_string(), exports));
+            entries.push(EntryKind::Command("Convert to Text".to_string(), EntryId::ConvertSketchToText));
+            entries.push(EntryKind::Command("Refresh".to_string(), EntryId::Refresh));
+            entries.push(EntryKind::Command("New".to_string(), EntryId::New));
+
             if !loadables.is_empty() {
-                entries.insert(entries.len() - 1, EntryKind::SubMenu("Load".to_string(),
+                entries.push(EntryKind::SubMenu("Load".to_string(),
                     loadables.into_iter().map(|e|
                         EntryKind::Command(e.to_string_lossy().into_owned(),
                                            EntryId::Load(e))).collect()));
             }
 
+            entries.push(EntryKind::Command("Quit".to_string(), EntryId::Quit));
+
             let sketch_menu = Menu::new(rect, ViewId::SketchMenu, MenuKind::Contextual, entries, context);
             rq.add(RenderData::new(sketch_menu.id(), *sketch_menu.rect(), UpdateMode::Gui));
             self.children.push(Box::new(sketch_menu) as Box<dyn View>);
         }
     }
 
+    fn to_page(&self, pt: Point) -> Vec2 {
+        Vec2::from(pt - self.rect.min) / self.scale + self.offset
+    }
+
+    fn to_pixmap(&self, v: Vec2) -> Point {
+        to_pixmap(v, self.scale, self.offset)
+    }
+
+    fn pixmap_rect(&self) -> Rectangle {
+        rect![0, 0, self.pixmap.width as i32, self.pixmap.height as i32]
+    }
+
+    fn screen_rect(&self, bnd: &Boundary) -> Option<Rectangle> {
+        let rect = rect![self.to_pixmap(bnd.min), self.to_pixmap(bnd.max) + pt!(1, 1)] + self.rect.min;
+        rect.intersection(&self.rect)
+    }
+
+    // Redraws the template, the strokes and the selection of the current page.
+    fn redraw(&mut self) {
+        let (scale, offset) = (self.scale, self.offset);
+        let clip = self.pixmap_rect();
+        self.pixmap.clear(WHITE);
+
+        let name = self.notebook.pages[self.current_page].background.as_ref();
+        if name != self.background.as_ref().map(|(name, _)| name) {
+            self.background = name.and_then(|name| {
+                Pixmap::from_png(self.save_path.join(name))
+                       .map_err(|e| eprintln!("Can't load background {}: {:#}.", name, e))
+                       .ok().map(|pixmap| (name.clone(), pixmap))
+            });
+        }
+
+        if let Some((_, background)) = self.background.as_ref() {
+            draw_background(&mut self.pixmap, background, scale, offset);
+        }
+
+        for [a, b] in self.notebook.template_lines(self.current_page) {
+            let (a, b) = (to_pixmap(a, scale, offset), to_pixmap(b, scale, offset));
+            if let Some(r) = rect![a, b + pt!(1, 1)].intersection(&clip) {
+                self.pixmap.draw_rectangle(&r, GRAY11);
+            }
+        }
+
+        let dot_radius = (scale * scale_by_dpi(1.5, CURRENT_DEVICE.dpi)).ceil() as i32;
+        for p in self.notebook.template_dots(self.current_page) {
+            let center = to_pixmap(p, scale, offset);
+            if clip.includes(center) {
+                self.pixmap.draw_disk(center, dot_radius, GRAY11);
+            }
+        }
+
+        for stroke in &self.notebook.pages[self.current_page].strokes {
+            draw_stroke(&mut self.pixmap, stroke, scale, offset);
+        }
+
+        if let Some(sel) = self.selection.as_ref() {
+            let rect = rect![to_pixmap(sel.boundary.min, scale, offset), to_pixmap(sel.boundary.max, scale, offset)];
+            if rect.intersection(&clip).is_some() {
+                let thickness = scale_by_dpi(2.0, CURRENT_DEVICE.dpi).max(1.0) as u16;
+                self.pixmap.draw_rectangle_outline(&rect, &BorderSpec { thickness, color: GRAY07 });
+            }
+        }
+    }
+
+    fn refresh(&mut self, rq: &mut RenderQueue) {
+        self.redraw();
+        rq.add(RenderData::new(self.id, self.rect, UpdateMode::Gui));
+    }
+
+    fn go_to_page(&mut self, index: usize, rq: &mut RenderQueue) {
+        self.current_page = index.min(self.notebook.pages.len() - 1);
+        self.selection = None;
+        self.refresh(rq);
+    }
+
+    fn clamp_offset(&mut self) {
+        let max_x = (self.notebook.width as f32 - self.rect.width() as f32 / self.scale).max(0.0);
+        let max_y = (self.notebook.height as f32 - self.rect.height() as f32 / self.scale).max(0.0);
+        self.offset = vec2!(self.offset.x.clamp(0.0, max_x), self.offset.y.clamp(0.0, max_y));
+    }
+
+    fn zoom(&mut self, center: Point, factor: f32, rq: &mut RenderQueue) {
+        let scale = (self.scale * factor).clamp(1.0, MAX_SCALE);
+        if (scale - self.scale).abs() < f32::EPSILON {
+            return;
+        }
+        // Keep the point under the center of the gesture in place.
+        let anchor = self.to_page(center);
+        self.scale = scale;
+        self.offset = anchor - Vec2::from(center - self.rect.min) / scale;
+        self.clamp_offset();
+        self.refresh(rq);
+    }
+
+    fn pan(&mut self, delta: Vec2, rq: &mut RenderQueue) {
+        self.offset += delta / self.scale;
+        self.clamp_offset();
+        self.refresh(rq);
+    }
+
+    fn begin(&mut self, source: Source, tool: Tool, position: Point, time: f64, pressure: f32, rq: &mut RenderQueue) {
+        let pt = self.to_page(position);
+        let interaction = match tool {
+            Tool::Pen => {
+                let mut stroke = Stroke::new(self.pen.clone());
+                stroke.points.push(StrokePoint { x: pt.x, y: pt.y, pressure, time });
+                let radius = stroke.base_radius();
+                Interaction::Draw { stroke, radius }
+            },
+            Tool::Eraser => {
+                self.erase(pt, rq);
+                Interaction::Erase
+            },
+            Tool::Lasso => {
+                if self.selection.as_ref().map_or(false, |sel| {
+                    sel.boundary.contains(&Boundary::new(pt, pt))
+                }) {
+                    Interaction::Move { start: pt, delta: vec2!(0.0, 0.0) }
+                } else {
+                    if self.selection.take().is_some() {
+                        self.refresh(rq);
+                    }
+                    Interaction::Lasso(vec![pt])
+                }
+            },
+        };
+        self.interaction = Some((source, interaction));
+    }
+
+    fn extend(&mut self, position: Point, time: f64, pressure: f32, rq: &mut RenderQueue) {
+        let pt = self.to_page(position);
+        let mut interaction = match self.interaction.take() {
+            Some(v) => v,
+            None => return,
+        };
+
+        let segment = match interaction.1 {
+            Interaction::Draw { ref mut stroke, ref mut radius } => {
+                let last = stroke.points[stroke.points.len() - 1];
+                let start_radius = *radius * (0.5 + last.pressure);
+                stroke.points.push(StrokePoint { x: pt.x, y: pt.y, pressure, time });
+                *radius = stroke.next_radius(stroke.points.len() - 1, *radius);
+                Some((last.position(), start_radius, *radius * (0.5 + pressure), stroke.pen.color))
+            },
+            Interaction::Lasso(ref mut polygon) => {
+                let last = polygon[polygon.len() - 1];
+                polygon.push(pt);
+                Some((last, 0.5 / self.scale, 0.5 / self.scale, GRAY07))
+            },
+            Interaction::Erase => {
+                self.erase(pt, rq);
+                None
+            },
+            Interaction::Move { start, ref mut delta } => {
+                *delta = pt - start;
+                None
+            },
+        };
+
+        if let Some((last, start_radius, end_radius, color)) = segment {
+            let (a, b) = (self.to_pixmap(last), self.to_pixmap(pt));
+            let (start_radius, end_radius) = (self.scale * start_radius, self.scale * end_radius);
+            let clip = self.pixmap_rect();
+            self.pixmap.draw_clipped_segment(a, b, start_radius, end_radius, color, &clip);
+            let rect = Rectangle::from_segment(a, b, start_radius.ceil() as i32, end_radius.ceil() as i32) + self.rect.min;
+            if let Some(render_rect) = rect.intersection(&self.rect) {
+                rq.add(RenderData::no_wait(self.id, render_rect, UpdateMode::FastMono));
+            }
+        }
+
+        self.interaction = Some(interaction);
+    }
+
+    fn finish(&mut self, position: Point, time: f64, pressure: f32, rq: &mut RenderQueue) {
+        self.extend(position, time, pressure, rq);
+        let interaction = match self.interaction.take() {
+            Some((_, interaction)) => interaction,
+            None => return,
+        };
+
+        match interaction {
+            Interaction::Draw { mut stroke, .. } => {
+                // A tap draws a dot.
+                let first = stroke.points[0];
+                if stroke.points.iter().all(|p| p.x == first.x && p.y == first.y) {
+                    stroke.points.truncate(1);
+                    draw_stroke(&mut self.pixmap, &stroke, self.scale, self.offset);
+                    if let Some(rect) = stroke.boundary().and_then(|bnd| self.screen_rect(&bnd)) {
+                        rq.add(RenderData::no_wait(self.id, rect, UpdateMode::FastMono));
+                    }
+                }
+                self.notebook.add_stroke(self.current_page, stroke);
+            },
+            Interaction::Lasso(polygon) => {
+                let indices = self.notebook.select(self.current_page, &polygon);
+                let strokes = &self.notebook.pages[self.current_page].strokes;
+                self.selection = indices.iter().filter_map(|i| strokes[*i].boundary())
+                                        .reduce(|a, b| Boundary::new(vec2!(a.min.x.min(b.min.x), a.min.y.min(b.min.y)),
+                                                                     vec2!(a.max.x.max(b.max.x), a.max.y.max(b.max.y))))
+                                        .map(|boundary| Selection { indices, boundary });
+                self.refresh(rq);
+            },
+            Interaction::Move { delta, .. } => {
+                if let Some(sel) = self.selection.as_mut() {
+                    self.notebook.translate(self.current_page, &sel.indices, delta);
+                    sel.boundary = Boundary::new(sel.boundary.min + delta, sel.boundary.max + delta);
+                }
+                self.refresh(rq);
+            },
+            Interaction::Erase => (),
+        }
+    }
+
+    // Aborts the current interaction and discards what it drew.
+    fn cancel(&mut self, rq: &mut RenderQueue) {
+        if self.interaction.take().is_some() {
+            self.refresh(rq);
+        }
+    }
+
+    fn erase(&mut self, pt: Vec2, rq: &mut RenderQueue) {
+        let radius = scale_by_dpi(ERASER_RADIUS, CURRENT_DEVICE.dpi) / self.scale;
+        if let Some(bnd) = self.notebook.erase(self.current_page, pt, radius) {
+            // The indices of the selected strokes might have changed.
+            self.selection = None;
+            self.redraw();
+            if let Some(rect) = self.screen_rect(&bnd) {
+                rq.add(RenderData::new(self.id, rect, UpdateMode::Gui));
+            }
+        }
+    }
+
+    fn history(&mut self, redo: bool, rq: &mut RenderQueue) {
+        let page = if redo {
+            self.notebook.redo()
+        } else {
+            self.notebook.undo()
+        };
+        if let Some(index) = page {
+            self.go_to_page(index, rq);
+        }
+    }
+
+    // An image becomes the background of the first page of a new notebook.
     fn load(&mut self, filename: &PathBuf) -> Result<(), Error> {
         let path = self.save_path.join(filename);
-        let decoder = png::Decoder::new(File::open(path)?);
-        let mut reader = decoder.read_info()?;
-        reader.next_frame(self.pixmap.data_mut())?;
-        self.filename = filename.to_string_lossy().into_owned();
+        if filename.extension() == Some(OsStr::new("png")) {
+            let pixmap = Pixmap::from_png(&path)?;
+            let mut notebook = Notebook::new(pixmap.width, pixmap.height, CURRENT_DEVICE.dpi, Template::Blank);
+            notebook.pages[0].background = Some(filename.to_string_lossy().into_owned());
+            self.notebook = notebook;
+            self.filename = filename.with_extension("json").to_string_lossy().into_owned();
+        } else {
+            self.notebook = Notebook::load(path)?;
+            self.filename = filename.to_string_lossy().into_owned();
+        }
+        self.recognitions.clear();
+        self.recognizer = None;
+        self.scale = 1.0;
+        self.offset = vec2!(0.0, 0.0);
         Ok(())
     }
 
@@ -177,77 +533,216 @@ impl Sketch {
             fs::create_dir_all(&self.save_path)?;
         }
         let path = self.save_path.join(&self.filename);
-        self.pixmap.save(&path.to_string_lossy().into_owned())?;
+        self.notebook.save(path)
+    }
+
+    fn export(&self, format: NotebookFormat) -> Result<String, Error> {
+        if !self.save_path.exists() {
+            fs::create_dir_all(&self.save_path)?;
+        }
+        let stem = self.filename.trim_end_matches(".json");
+        match format {
+            NotebookFormat::Svg => {
+                let count = self.notebook.pages.len();
+                for index in 0..count {
+                    let name = if count == 1 {
+                        format!("{}.svg", stem)
+                    } else {
+                        format!("{}-{:02}.svg", stem, index + 1)
+                    };
+                    fs::write(self.save_path.join(name), self.notebook.to_svg(index))?;
+                }
+                Ok(format!("{}.svg", stem))
+            },
+            NotebookFormat::Pdf => {
+                let name = format!("{}.{}", stem, format.extension());
+                fs::write(self.save_path.join(&name), self.notebook.to_pdf(&self.save_path))?;
+                Ok(name)
+            },
+        }
+    }
+
+    // Recognizes the handwriting of the pages that don't have text yet.
+    fn convert_to_text(&mut self, hub: &Hub, context: &Context) -> Result<(), Error> {
+        if !self.recognitions.is_empty() {
+            return Ok(());
+        }
+
+        if self.recognizer.is_none() {
+            self.recognizer = Some(Recognizer::new(self.id, hub)?);
+        }
+
+        for (index, page) in self.notebook.pages.iter().enumerate() {
+            if page.text.is_some() || page.strokes.is_empty() {
+                continue;
+            }
+            let request = Request {
+                language: context.settings.handwriting.language.clone(),
+                width: self.notebook.width,
+                height: self.notebook.height,
+                strokes: page.strokes.iter().map(|stroke| {
+                    stroke.points.iter().map(|pt| [pt.x, pt.y]).collect()
+                }).collect(),
+            };
+            if let Some(recognizer) = self.recognizer.as_mut() {
+                recognizer.recognize(&request)?;
+            }
+            self.recognitions.push_back(index);
+        }
+
         Ok(())
     }
 
+    fn write_text(&self) -> Result<String, Error> {
+        if !self.save_path.exists() {
+            fs::create_dir_all(&self.save_path)?;
+        }
+        let name = format!("{}.txt", self.filename.trim_end_matches(".json"));
+        fs::write(self.save_path.join(&name), self.notebook.to_text())?;
+        Ok(name)
+    }
+
+    fn export_text(&mut self, hub: &Hub, rq: &mut RenderQueue, context: &mut Context) {
+        let msg = match self.write_text() {
+            Err(e) => format!("Can't export text: {:#}.", e),
+            Ok(name) => format!("Converted to {}.", name),
+        };
+        let notif = Notification::new(msg, hub, rq, context);
+        self.children.push(Box::new(notif) as Box<dyn View>);
+    }
+
     fn quit(&self, context: &mut Context) {
         let import_settings = ImportSettings {
-            allowed_kinds: ["png".to_string()].iter().cloned().collect(),
+            allowed_kinds: ["pdf".to_string()].iter().cloned().collect(),
             .. Default::default()
         };
         context.library.import(&import_settings);
     }
 }
 
-#[inline]
-fn draw_segment(pixmap: &mut Pixmap, ts: &mut TouchState, position: Point, time: f64, pen: &Pen, id: Id, fb_rect: &Rectangle, rq: &mut RenderQueue) {
-    let (start_radius, end_radius) = if pen.dynamic {
-        if time > ts.time {
-            let d = vec2!((position.x - ts.pt.x) as f32,
-                          (position.y - ts.pt.y) as f32).length();
-            let speed = d / (time - ts.time) as f32;
-            let base_radius = pen.size as f32 / 2.0;
-            let radius = base_radius * (1.0 + (pen.amplitude/base_radius) * speed.clamp(pen.min_speed, pen.max_speed) / (pen.max_speed - pen.min_speed));
-            (ts.radius, radius)
-        } else {
-            (ts.radius, ts.radius)
-        }
-    } else {
-        let radius = pen.size as f32 / 2.0;
-        (radius, radius)
-    };
-
-    let rect = Rectangle::from_segment(ts.pt, position,
-                                       start_radius.ceil() as i32,
-                                       end_radius.ceil() as i32);
-
-    pixmap.draw_segment(ts.pt, position, start_radius, end_radius, pen.color);
+// The pixmap covers the rectangle of the view.
+fn to_pixmap(v: Vec2, scale: f32, offset: Vec2) -> Point {
+    Point::from((v - offset) * scale)
+}
 
-    if let Some(render_rect) = rect.intersection(fb_rect) {
-        rq.add(RenderData::no_wait(id, render_rect, UpdateMode::FastMono));
+// The background is drawn at the scale of the page.
+fn draw_background(pixmap: &mut Pixmap, background: &Pixmap, scale: f32, offset: Vec2) {
+    for y in 0..pixmap.height {
+        let v = (offset.y + y as f32 / scale) as u32;
+        if v >= background.height {
+            break;
+        }
+        for x in 0..pixmap.width {
+            let u = (offset.x + x as f32 / scale) as u32;
+            if u >= background.width {
+                break;
+            }
+            let addr = (y * pixmap.width + x) as usize;
+            pixmap.data[addr] = background.get_pixel(u, v).gray();
+        }
     }
+}
 
-    ts.pt = position;
-    ts.time = time;
-    ts.radius = end_radius;
+fn draw_stroke(pixmap: &mut Pixmap, stroke: &Stroke, scale: f32, offset: Vec2) {
+    let clip = rect![0, 0, pixmap.width as i32, pixmap.height as i32];
+    let radii = stroke.radii();
+    if let [pt] = stroke.points[..] {
+        let center = to_pixmap(pt.position(), scale, offset);
+        pixmap.draw_disk(center, (scale * radii[0]).round().max(1.0) as i32, stroke.pen.color);
+    }
+    for (pts, rds) in stroke.points.windows(2).zip(radii.windows(2)) {
+        pixmap.draw_clipped_segment(to_pixmap(pts[0].position(), scale, offset),
+                                    to_pixmap(pts[1].position(), scale, offset),
+                                    scale * rds[0], scale * rds[1], stroke.pen.color, &clip);
+    }
 }
 
 impl View for Sketch {
     fn handle_event(&mut self, evt: &Event, hub: &Hub, _bus: &mut Bus, rq: &mut RenderQueue, context: &mut Context) -> bool {
         match *evt {
-            Event::Device(DeviceEvent::Finger { status: FingerStatus::Motion, id, position, time }) => {
-                if let Some(ts) = self.fingers.get_mut(&id) {
-                    draw_segment(&mut self.pixmap, ts, position, time, &self.pen, self.id, &self.rect, rq);
+            Event::Device(DeviceEvent::Finger { status: FingerStatus::Down, id, position, time }) => {
+                self.fingers.insert(id);
+                // Several fingers zoom and pan.
+                if self.fingers.len() > 1 {
+                    if matches!(self.interaction, Some((Source::Finger(..), _))) {
+                        self.cancel(rq);
+                    }
+                } else if self.interaction.is_none() {
+                    self.begin(Source::Finger(id), self.tool, position, time, FINGER_PRESSURE, rq);
                 }
                 true
             },
-            Event::Device(DeviceEvent::Finger { status: FingerStatus::Down, id, position, time }) => {
-                let radius = self.pen.size as f32 / 2.0;
-                self.fingers.insert(id, TouchState::new(position, time, radius));
+            Event::Device(DeviceEvent::Finger { status: FingerStatus::Motion, id, position, time }) => {
+                if self.interaction.as_ref().map_or(false, |(source, _)| *source == Source::Finger(id)) {
+                    self.extend(position, time, FINGER_PRESSURE, rq);
+                }
                 true
             },
             Event::Device(DeviceEvent::Finger { status: FingerStatus::Up, id, position, time }) => {
-                if let Some(ts) = self.fingers.get_mut(&id) {
-                    draw_segment(&mut self.pixmap, ts, position, time, &self.pen, self.id, &self.rect, rq);
-                }
                 self.fingers.remove(&id);
+                if self.interaction.as_ref().map_or(false, |(source, _)| *source == Source::Finger(id)) {
+                    self.finish(position, time, FINGER_PRESSURE, rq);
+                }
+                true
+            },
+            Event::Device(DeviceEvent::Pen { status: PenStatus::Down, tool, position, time, pressure, .. }) => {
+                // The stylus takes precedence over the fingers.
+                if matches!(self.interaction, Some((Source::Finger(..), _))) {
+                    self.cancel(rq);
+                }
+                let tool = if tool == PenTool::Eraser { Tool::Eraser } else { self.tool };
+                self.begin(Source::Stylus, tool, position, time, pressure, rq);
+                true
+            },
+            Event::Device(DeviceEvent::Pen { status: PenStatus::Motion, position, time, pressure, .. }) => {
+                if matches!(self.interaction, Some((Source::Stylus, _))) {
+                    self.extend(position, time, pressure, rq);
+                }
+                true
+            },
+            Event::Device(DeviceEvent::Pen { status: PenStatus::Up, position, time, pressure, .. }) => {
+                if matches!(self.interaction, Some((Source::Stylus, _))) {
+                    self.finish(position, time, pressure, rq);
+                }
+                true
+            },
+            Event::Gesture(GestureEvent::Spread { center, factor, .. }) |
+            Event::Gesture(GestureEvent::Pinch { center, factor, .. }) if factor.is_finite() => {
+                self.zoom(center, factor, rq);
+                true
+            },
+            Event::Gesture(GestureEvent::MultiSwipe { dir, starts, ends }) => {
+                if self.scale > 1.0 {
+                    let delta = (Vec2::from(starts[0] - ends[0]) + Vec2::from(starts[1] - ends[1])) / 2.0;
+                    self.pan(delta, rq);
+                } else {
+                    match dir {
+                        Dir::West if self.current_page + 1 < self.notebook.pages.len() => {
+                            self.go_to_page(self.current_page + 1, rq);
+                        },
+                        Dir::East if self.current_page > 0 => {
+                            self.go_to_page(self.current_page - 1, rq);
+                        },
+                        _ => (),
+                    }
+                }
+                true
+            },
+            Event::Gesture(GestureEvent::MultiTap(..)) => {
+                self.history(false, rq);
                 true
             },
             Event::ToggleNear(ViewId::TitleMenu, rect) => {
                 self.toggle_title_menu(rect, None, rq, context);
                 true
             },
+            Event::Select(EntryId::SetSketchTool(tool)) => {
+                self.tool = tool;
+                if tool != Tool::Lasso && self.selection.take().is_some() {
+                    self.refresh(rq);
+                }
+                true
+            },
             Event::Select(EntryId::SetPenSize(size)) => {
                 self.pen.size = size;
                 true
@@ -260,13 +755,41 @@ impl View for Sketch {
                 self.pen.dynamic = !self.pen.dynamic;
                 true
             },
+            Event::Select(EntryId::SetTemplate(template)) => {
+                self.notebook.set_template(self.current_page, template);
+                self.refresh(rq);
+                true
+            },
+            Event::Select(EntryId::GoTo(index)) => {
+                self.go_to_page(index, rq);
+                true
+            },
+            Event::Select(EntryId::AddPage) => {
+                let index = self.notebook.insert_page(self.current_page + 1);
+                self.go_to_page(index, rq);
+                true
+            },
+            Event::Select(EntryId::RemovePage) => {
+                if let Some(index) = self.notebook.remove_page(self.current_page) {
+                    self.go_to_page(index, rq);
+                }
+                true
+            },
+            Event::Select(EntryId::Undo) => {
+                self.history(false, rq);
+                true
+            },
+            Event::Select(EntryId::Redo) => {
+                self.history(true, rq);
+                true
+            },
             Event::Select(EntryId::Load(ref name)) => {
                 if let Err(e) = self.load(name) {
-                    let msg = format!("Couldn't load sketch: {}).", e);
+                    let msg = format!("Can't load sketch: {:#}.", e);
                     let notif = Notification::new(msg, hub, rq, context);
                     self.children.push(Box::new(notif) as Box<dyn View>);
                 } else {
-                    rq.add(RenderData::new(self.id, self.rect, UpdateMode::Gui));
+                    self.go_to_page(0, rq);
                 }
                 true
             },
@@ -275,14 +798,19 @@ impl View for Sketch {
                 true
             },
             Event::Select(EntryId::New) => {
-                self.pixmap.clear(WHITE);
+                let template = self.notebook.pages[self.current_page].template;
+                self.notebook = Notebook::new(self.rect.width(), self.rect.height(), CURRENT_DEVICE.dpi, template);
                 self.filename = Local::now().format(FILENAME_PATTERN).to_string();
-                rq.add(RenderData::new(self.id, self.rect, UpdateMode::Gui));
+                self.recognitions.clear();
+                self.recognizer = None;
+                self.scale = 1.0;
+                self.offset = vec2!(0.0, 0.0);
+                self.go_to_page(0, rq);
                 true
             },
             Event::Select(EntryId::Save) => {
                 let mut msg = match self.save() {
-                    Err(e) => Some(format!("Can't save sketch: {}.", e)),
+                    Err(e) => Some(format!("Can't save sketch: {:#}.", e)),
                     Ok(..) => {
                         if context.settings.sketch.notify_success {
                             Some(format!("Saved {}.", self.filename))
@@ -297,6 +825,46 @@ impl View for Sketch {
                 }
                 true
             },
+            Event::Select(EntryId::ExportSketch(format)) => {
+                let msg = match self.export(format) {
+                    Err(e) => format!("Can't export sketch: {:#}.", e),
+                    Ok(name) => format!("Exported {}.", name),
+                };
+                let notif = Notification::new(msg, hub, rq, context);
+                self.children.push(Box::new(notif) as Box<dyn View>);
+                true
+            },
+            Event::Select(EntryId::ConvertSketchToText) => {
+                if let Err(e) = self.convert_to_text(hub, context) {
+                    self.recognitions.clear();
+                    self.recognizer = None;
+                    let msg = format!("Can't convert sketch to text: {:#}.", e);
+                    let notif = Notification::new(msg, hub, rq, context);
+                    self.children.push(Box::new(notif) as Box<dyn View>);
+                } else if self.recognitions.is_empty() {
+                    self.export_text(hub, rq, context);
+                }
+                true
+            },
+            Event::Recognition(id, ref candidates) if id == self.id => {
+                if let Some(index) = self.recognitions.pop_front() {
+                    if let Some(page) = self.notebook.pages.get_mut(index) {
+                        page.text = Some(candidates.first().cloned().unwrap_or_default());
+                    }
+                    if self.recognitions.is_empty() {
+                        self.export_text(hub, rq, context);
+                    }
+                }
+                true
+            },
+            Event::RecognizerExited(id) if id == self.id => {
+                self.recognizer = None;
+                self.recognitions.clear();
+                let msg = "Can't convert sketch to text: the recognizer exited.".to_string();
+                let notif = Notification::new(msg, hub, rq, context);
+                self.children.push(Box::new(notif) as Box<dyn View>);
+                true
+            },
             Event::Select(EntryId::Quit) => {
                 self.quit(context);
                 hub.send(Event::Back).ok();
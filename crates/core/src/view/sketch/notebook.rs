@@ -0,0 +1,714 @@
+use std::fs;
+use std::fmt;
+use std::path::Path;
+use std::fmt::Write;
+use serde::{Serialize, Deserialize};
+use anyhow::{Error, Context, format_err};
+use crate::geom::{Vec2, Boundary, nearest_segment_point, lerp};
+use crate::settings::Pen;
+use crate::color::{Color, GRAY11};
+use crate::unit::{mm_to_px, POINTS_PER_INCH};
+use crate::framebuffer::Pixmap;
+
+pub const NOTEBOOK_VERSION: u32 = 1;
+// The distance between the lines of the templates, in millimeters.
+const TEMPLATE_SPACING: f32 = 7.0;
+const TEMPLATE_COLOR: Color = GRAY11;
+const DOT_RADIUS: f32 = 1.5;
+// The font size of the text layer of the PDF exports, in points.
+const TEXT_SIZE: f32 = 12.0;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Template {
+    #[default]
+    Blank,
+    Lined,
+    Grid,
+    Dotted,
+}
+
+impl fmt::Display for Template {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Template::Blank => write!(f, "Blank"),
+            Template::Lined => write!(f, "Lined"),
+            Template::Grid => write!(f, "Grid"),
+            Template::Dotted => write!(f, "Dotted"),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum NotebookFormat {
+    Svg,
+    Pdf,
+}
+
+impl NotebookFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            NotebookFormat::Svg => "svg",
+            NotebookFormat::Pdf => "pdf",
+        }
+    }
+}
+
+impl fmt::Display for NotebookFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            NotebookFormat::Svg => write!(f, "SVG"),
+            NotebookFormat::Pdf => write!(f, "PDF"),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+pub struct StrokePoint {
+    pub x: f32,
+    pub y: f32,
+    pub pressure: f32,
+    pub time: f64,
+}
+
+impl StrokePoint {
+    pub fn position(&self) -> Vec2 {
+        vec2!(self.x, self.y)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Stroke {
+    pub pen: Pen,
+    pub points: Vec<StrokePoint>,
+}
+
+impl Stroke {
+    pub fn new(pen: Pen) -> Stroke {
+        Stroke { pen, points: Vec::new() }
+    }
+
+    pub fn base_radius(&self) -> f32 {
+        self.pen.size as f32 / 2.0
+    }
+
+    // The radius of the pen at the given point, before taking the pressure into account.
+    // Dynamic pens widen with the speed of the stroke.
+    pub fn next_radius(&self, index: usize, radius: f32) -> f32 {
+        if !self.pen.dynamic || index == 0 {
+            return radius;
+        }
+        let (prev, cur) = (&self.points[index - 1], &self.points[index]);
+        if cur.time <= prev.time {
+            return radius;
+        }
+        let pen = &self.pen;
+        let base_radius = self.base_radius();
+        let speed = (cur.position() - prev.position()).length() / (cur.time - prev.time) as f32;
+        base_radius * (1.0 + (pen.amplitude / base_radius) * speed.clamp(pen.min_speed, pen.max_speed) / (pen.max_speed - pen.min_speed))
+    }
+
+    pub fn radii(&self) -> Vec<f32> {
+        let mut radius = self.base_radius();
+        self.points.iter().enumerate().map(|(i, pt)| {
+            radius = self.next_radius(i, radius);
+            radius * (0.5 + pt.pressure)
+        }).collect()
+    }
+
+    pub fn boundary(&self) -> Option<Boundary> {
+        let radius = self.radii().into_iter().fold(0.0, f32::max);
+        let first = self.points.first()?.position();
+        let (min, max) = self.points.iter().fold((first, first), |(min, max), pt| {
+            (vec2!(min.x.min(pt.x), min.y.min(pt.y)),
+             vec2!(max.x.max(pt.x), max.y.max(pt.y)))
+        });
+        Some(Boundary::new(min - radius, max + radius))
+    }
+
+    // Whether the disk of the given center and radius touches the stroke.
+    pub fn hits(&self, center: Vec2, radius: f32) -> bool {
+        let radii = self.radii();
+        if let [pt] = self.points[..] {
+            return (pt.position() - center).length() <= radius + radii[0];
+        }
+        self.points.windows(2).zip(radii.windows(2)).any(|(pts, rds)| {
+            let (n, t) = nearest_segment_point(center, pts[0].position(), pts[1].position());
+            (n - center).length() <= radius + lerp(rds[0], rds[1], t)
+        })
+    }
+
+    pub fn is_enclosed_by(&self, polygon: &[Vec2]) -> bool {
+        !self.points.is_empty() && self.points.iter().all(|pt| polygon_contains(polygon, pt.position()))
+    }
+
+    pub fn translate(&mut self, delta: Vec2) {
+        for pt in &mut self.points {
+            pt.x += delta.x;
+            pt.y += delta.y;
+        }
+    }
+}
+
+// Even-odd rule.
+fn polygon_contains(polygon: &[Vec2], p: Vec2) -> bool {
+    let mut inside = false;
+    let mut j = polygon.len().wrapping_sub(1);
+    for (i, a) in polygon.iter().enumerate() {
+        let b = polygon[j];
+        if (a.y > p.y) != (b.y > p.y) && p.x < (b.x - a.x) * (p.y - a.y) / (b.y - a.y) + a.x {
+            inside = !inside;
+        }
+        j = i;
+    }
+    inside
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Page {
+    pub template: Template,
+    pub strokes: Vec<Stroke>,
+    // The recognized handwriting, discarded when the strokes change.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+    // The name of an image, in the directory of the notebook, drawn under the template.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub background: Option<String>,
+}
+
+// An edit of the notebook. Applying an action yields the action that reverts it.
+#[derive(Debug, Clone)]
+enum Action {
+    // The strokes are sorted by index.
+    InsertStrokes { page: usize, strokes: Vec<(usize, Stroke)> },
+    RemoveStrokes { page: usize, indices: Vec<usize> },
+    TranslateStrokes { page: usize, indices: Vec<usize>, delta: Vec2 },
+    SetTemplate { page: usize, template: Template },
+    InsertPage { index: usize, page: Page },
+    RemovePage { index: usize },
+}
+
+// The page coordinates are the screen coordinates at the time the notebook was created.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct Notebook {
+    pub version: u32,
+    pub width: u32,
+    pub height: u32,
+    pub dpi: u16,
+    pub pages: Vec<Page>,
+    #[serde(skip)]
+    undo: Vec<Action>,
+    #[serde(skip)]
+    redo: Vec<Action>,
+}
+
+impl Default for Notebook {
+    fn default() -> Self {
+        Notebook {
+            version: NOTEBOOK_VERSION,
+            width: 0,
+            height: 0,
+            dpi: 300,
+            pages: vec![Page::default()],
+            undo: Vec::new(),
+            redo: Vec::new(),
+        }
+    }
+}
+
+impl Notebook {
+    pub fn new(width: u32, height: u32, dpi: u16, template: Template) -> Notebook {
+        Notebook {
+            width,
+            height,
+            dpi,
+            pages: vec![Page { template, .. Default::default() }],
+            .. Default::default()
+        }
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Notebook, Error> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)
+                      .with_context(|| format!("can't read {}", path.display()))?;
+        let mut notebook: Notebook = serde_json::from_str(&text)
+                                               .with_context(|| format!("can't parse {}", path.display()))?;
+        if notebook.version > NOTEBOOK_VERSION {
+            return Err(format_err!("unsupported notebook version: {}", notebook.version));
+        }
+        if notebook.pages.is_empty() {
+            notebook.pages.push(Page::default());
+        }
+        Ok(notebook)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        let path = path.as_ref();
+        let text = serde_json::to_string(self)?;
+        fs::write(path, text).with_context(|| format!("can't write {}", path.display()))
+    }
+
+    // Returns the inverse action and the index of the affected page.
+    fn apply(&mut self, action: Action) -> (Action, usize) {
+        match action {
+            Action::InsertStrokes { page, strokes } => {
+                let indices = strokes.iter().map(|(i, _)| *i).collect();
+                for (i, stroke) in strokes {
+                    self.pages[page].strokes.insert(i, stroke);
+                }
+                self.pages[page].text = None;
+                (Action::RemoveStrokes { page, indices }, page)
+            },
+            Action::RemoveStrokes { page, indices } => {
+                let mut strokes = indices.iter().rev()
+                                         .map(|i| (*i, self.pages[page].strokes.remove(*i)))
+                                         .collect::<Vec<(usize, Stroke)>>();
+                strokes.reverse();
+                self.pages[page].text = None;
+                (Action::InsertStrokes { page, strokes }, page)
+            },
+            Action::TranslateStrokes { page, indices, delta } => {
+                for i in &indices {
+                    self.pages[page].strokes[*i].translate(delta);
+                }
+                (Action::TranslateStrokes { page, indices, delta: vec2!(-delta.x, -delta.y) }, page)
+            },
+            Action::SetTemplate { page, template } => {
+                let template = std::mem::replace(&mut self.pages[page].template, template);
+                (Action::SetTemplate { page, template }, page)
+            },
+            Action::InsertPage { index, page } => {
+                self.pages.insert(index, page);
+                (Action::RemovePage { index }, index)
+            },
+            Action::RemovePage { index } => {
+                let page = self.pages.remove(index);
+                (Action::InsertPage { index, page }, index.min(self.pages.len() - 1))
+            },
+        }
+    }
+
+    fn perform(&mut self, action: Action) -> usize {
+        let (inverse, page) = self.apply(action);
+        self.undo.push(inverse);
+        self.redo.clear();
+        page
+    }
+
+    pub fn undo(&mut self) -> Option<usize> {
+        let action = self.undo.pop()?;
+        let (inverse, page) = self.apply(action);
+        self.redo.push(inverse);
+        Some(page)
+    }
+
+    pub fn redo(&mut self) -> Option<usize> {
+        let action = self.redo.pop()?;
+        let (inverse, page) = self.apply(action);
+        self.undo.push(inverse);
+        Some(page)
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+
+    pub fn add_stroke(&mut self, page: usize, stroke: Stroke) {
+        let index = self.pages[page].strokes.len();
+        self.perform(Action::InsertStrokes { page, strokes: vec![(index, stroke)] });
+    }
+
+    // Removes the strokes touched by the eraser and returns the area they covered.
+    pub fn erase(&mut self, page: usize, center: Vec2, radius: f32) -> Option<Boundary> {
+        let strokes = &self.pages[page].strokes;
+        let indices = (0..strokes.len()).filter(|i| strokes[*i].hits(center, radius))
+                                        .collect::<Vec<usize>>();
+        let boundary = indices.iter().filter_map(|i| strokes[*i].boundary())
+                              .reduce(|a, b| Boundary::new(vec2!(a.min.x.min(b.min.x), a.min.y.min(b.min.y)),
+                                                           vec2!(a.max.x.max(b.max.x), a.max.y.max(b.max.y))))?;
+        self.perform(Action::RemoveStrokes { page, indices });
+        Some(boundary)
+    }
+
+    // The indices of the strokes inside the given lasso.
+    pub fn select(&self, page: usize, polygon: &[Vec2]) -> Vec<usize> {
+        let strokes = &self.pages[page].strokes;
+        (0..strokes.len()).filter(|i| strokes[*i].is_enclosed_by(polygon)).collect()
+    }
+
+    pub fn translate(&mut self, page: usize, indices: &[usize], delta: Vec2) {
+        if indices.is_empty() {
+            return;
+        }
+        self.perform(Action::TranslateStrokes { page, indices: indices.to_vec(), delta });
+    }
+
+    pub fn set_template(&mut self, page: usize, template: Template) {
+        if self.pages[page].template != template {
+            self.perform(Action::SetTemplate { page, template });
+        }
+    }
+
+    // The new page uses the template of the previous one.
+    pub fn insert_page(&mut self, index: usize) -> usize {
+        let template = self.pages.get(index.saturating_sub(1)).map(|p| p.template).unwrap_or_default();
+        self.perform(Action::InsertPage { index, page: Page { template, .. Default::default() } })
+    }
+
+    pub fn remove_page(&mut self, index: usize) -> Option<usize> {
+        if self.pages.len() < 2 {
+            return None;
+        }
+        Some(self.perform(Action::RemovePage { index }))
+    }
+
+    pub fn spacing(&self) -> f32 {
+        mm_to_px(TEMPLATE_SPACING, self.dpi)
+    }
+
+    pub fn template_lines(&self, index: usize) -> Vec<[Vec2; 2]> {
+        let (width, height) = (self.width as f32, self.height as f32);
+        let spacing = self.spacing();
+        let mut lines = Vec::new();
+        let template = self.pages[index].template;
+        if matches!(template, Template::Lined | Template::Grid) {
+            let mut y = spacing;
+            while y < height {
+                lines.push([vec2!(0.0, y), vec2!(width, y)]);
+                y += spacing;
+            }
+        }
+        if template == Template::Grid {
+            let mut x = spacing;
+            while x < width {
+                lines.push([vec2!(x, 0.0), vec2!(x, height)]);
+                x += spacing;
+            }
+        }
+        lines
+    }
+
+    pub fn template_dots(&self, index: usize) -> Vec<Vec2> {
+        let mut dots = Vec::new();
+        if self.pages[index].template != Template::Dotted {
+            return dots;
+        }
+        let spacing = self.spacing();
+        let mut y = spacing;
+        while y < self.height as f32 {
+            let mut x = spacing;
+            while x < self.width as f32 {
+                dots.push(vec2!(x, y));
+                x += spacing;
+            }
+            y += spacing;
+        }
+        dots
+    }
+
+    pub fn to_svg(&self, index: usize) -> String {
+        let (width, height) = (self.width, self.height);
+        let mut buf = format!("<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{0}\" height=\"{1}\" viewBox=\"0 0 {0} {1}\">\n", width, height);
+        buf.push_str(&format!("\t<rect width=\"{}\" height=\"{}\" fill=\"#FFFFFF\"/>\n", width, height));
+
+        if let Some(name) = self.pages[index].background.as_ref() {
+            buf.push_str(&format!("\t<image href=\"{}\" width=\"{}\" height=\"{}\"/>\n",
+                                  name.replace('&', "&amp;").replace('"', "&quot;"), width, height));
+        }
+
+        let lines = self.template_lines(index);
+        if !lines.is_empty() {
+            buf.push_str(&format!("\t<g stroke=\"{}\" stroke-width=\"1\">\n", svg_color(TEMPLATE_COLOR)));
+            for [a, b] in lines {
+                buf.push_str(&format!("\t\t<line x1=\"{:.1}\" y1=\"{:.1}\" x2=\"{:.1}\" y2=\"{:.1}\"/>\n", a.x, a.y, b.x, b.y));
+            }
+            buf.push_str("\t</g>\n");
+        }
+
+        let dots = self.template_dots(index);
+        if !dots.is_empty() {
+            buf.push_str(&format!("\t<g fill=\"{}\">\n", svg_color(TEMPLATE_COLOR)));
+            for p in dots {
+                buf.push_str(&format!("\t\t<circle cx=\"{:.1}\" cy=\"{:.1}\" r=\"{}\"/>\n", p.x, p.y, DOT_RADIUS));
+            }
+            buf.push_str("\t</g>\n");
+        }
+
+        for stroke in &self.pages[index].strokes {
+            let color = svg_color(stroke.pen.color);
+            let radii = stroke.radii();
+            buf.push_str(&format!("\t<g stroke=\"{0}\" fill=\"{0}\" stroke-linecap=\"round\">\n", color));
+            if let [pt] = stroke.points[..] {
+                buf.push_str(&format!("\t\t<circle cx=\"{:.1}\" cy=\"{:.1}\" r=\"{:.2}\" stroke=\"none\"/>\n", pt.x, pt.y, radii[0]));
+            }
+            // Each segment gets its own width.
+            for (pts, rds) in stroke.points.windows(2).zip(radii.windows(2)) {
+                buf.push_str(&format!("\t\t<line x1=\"{:.1}\" y1=\"{:.1}\" x2=\"{:.1}\" y2=\"{:.1}\" stroke-width=\"{:.2}\"/>\n",
+                                      pts[0].x, pts[0].y, pts[1].x, pts[1].y, rds[0] + rds[1]));
+            }
+            buf.push_str("\t</g>\n");
+        }
+
+        buf.push_str("</svg>\n");
+        buf
+    }
+
+    // The recognized text of the pages, separated by blank lines.
+    pub fn to_text(&self) -> String {
+        self.pages.iter()
+            .filter_map(|page| page.text.as_deref().filter(|text| !text.is_empty()))
+            .collect::<Vec<&str>>()
+            .join("\n\n")
+    }
+
+    // Each page becomes a page of the PDF, the strokes are kept as vector paths.
+    // The backgrounds are read from the given directory.
+    pub fn to_pdf(&self, dir: &Path) -> Vec<u8> {
+        let scale = POINTS_PER_INCH / self.dpi as f32;
+        let (width, height) = (self.width as f32 * scale, self.height as f32 * scale);
+        let pt = |p: Vec2| format!("{:.2} {:.2}", p.x * scale, height - p.y * scale);
+        let count = self.pages.len();
+        let mut objects = Vec::with_capacity(3 + 2 * count);
+        // The font of the text layer follows the pages, and the backgrounds follow the font.
+        let font_ref = format!("{} 0 R", 3 + 2 * count);
+        let backgrounds = self.pages.iter().map(|page| {
+            page.background.as_ref().and_then(|name| {
+                Pixmap::from_png(dir.join(name))
+                       .map_err(|e| eprintln!("Can't load background {}: {:#}.", name, e))
+                       .ok()
+            }).filter(|pixmap| matches!(pixmap.samples, 1 | 3))
+        }).collect::<Vec<Option<Pixmap>>>();
+        let mut next_image = 4 + 2 * count;
+
+        objects.push("<< /Type /Catalog /Pages 2 0 R >>".to_string());
+        let kids = (0..count).map(|i| format!("{} 0 R", 3 + 2 * i)).collect::<Vec<String>>().join(" ");
+        objects.push(format!("<< /Type /Pages /Kids [{}] /Count {} >>", kids, count));
+
+        for (index, background) in backgrounds.iter().enumerate() {
+            let mut content = "1 J 1 j\n".to_string();
+            let mut resources = Vec::new();
+
+            if background.is_some() {
+                content.push_str(&format!("q {:.2} 0 0 {:.2} 0 0 cm /Im1 Do Q\n", width, height));
+                resources.push(format!("/XObject << /Im1 {} 0 R >>", next_image));
+                next_image += 1;
+            }
+
+            let lines = self.template_lines(index);
+            if !lines.is_empty() {
+                content.push_str(&format!("{} {:.2} w\n", pdf_color(TEMPLATE_COLOR), scale));
+                for [a, b] in lines {
+                    content.push_str(&format!("{} m {} l S\n", pt(a), pt(b)));
+                }
+            }
+
+            let dots = self.template_dots(index);
+            if !dots.is_empty() {
+                // Zero length segments with round caps.
+                content.push_str(&format!("{} {:.2} w\n", pdf_color(TEMPLATE_COLOR), 2.0 * DOT_RADIUS * scale));
+                for p in dots {
+                    content.push_str(&format!("{0} m {0} l S\n", pt(p)));
+                }
+            }
+
+            for stroke in &self.pages[index].strokes {
+                content.push_str(&format!("{}\n", pdf_color(stroke.pen.color)));
+                let radii = stroke.radii();
+                if let [p] = stroke.points[..] {
+                    content.push_str(&format!("{:.2} w {1} m {1} l S\n", 2.0 * radii[0] * scale, pt(p.position())));
+                }
+                for (pts, rds) in stroke.points.windows(2).zip(radii.windows(2)) {
+                    content.push_str(&format!("{:.2} w {} m {} l S\n", (rds[0] + rds[1]) * scale,
+                                              pt(pts[0].position()), pt(pts[1].position())));
+                }
+            }
+
+            // The recognized text is invisible, it's there to be searched and selected.
+            if let Some(text) = self.pages[index].text.as_deref().filter(|text| !text.is_empty()) {
+                let origin = self.pages[index].strokes.iter().filter_map(|s| s.boundary())
+                                 .map(|b| b.min)
+                                 .reduce(|a, b| vec2!(a.x.min(b.x), a.y.min(b.y)))
+                                 .unwrap_or_else(|| vec2!(0.0, 0.0));
+                content.push_str(&format!("BT 3 Tr /F1 {:.2} Tf {:.2} TL {} Td\n",
+                                          TEXT_SIZE, 1.2 * TEXT_SIZE, pt(origin)));
+                for line in text.lines() {
+                    content.push_str(&format!("({}) '\n", pdf_string(line)));
+                }
+                content.push_str("ET\n");
+                resources.push(format!("/Font << /F1 {} >>", font_ref));
+            }
+
+            let resources = if resources.is_empty() {
+                String::new()
+            } else {
+                format!(" /Resources << {} >>", resources.join(" "))
+            };
+
+            objects.push(format!("<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {:.2} {:.2}]{} /Contents {} 0 R >>",
+                                 width, height, resources, 4 + 2 * index));
+            objects.push(format!("<< /Length {} >>\nstream\n{}endstream", content.len(), content));
+        }
+
+        objects.push("<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica /Encoding /WinAnsiEncoding >>".to_string());
+
+        // The samples are hex encoded: the file is built as a string.
+        for pixmap in backgrounds.iter().flatten() {
+            let mut data = String::with_capacity(2 * pixmap.data.len() + 1);
+            for byte in &pixmap.data {
+                write!(data, "{:02X}", byte).ok();
+            }
+            data.push('>');
+            let color_space = if pixmap.samples == 1 { "DeviceGray" } else { "DeviceRGB" };
+            objects.push(format!("<< /Type /XObject /Subtype /Image /Width {} /Height {} /ColorSpace /{} \
+                                  /BitsPerComponent 8 /Filter /ASCIIHexDecode /Length {} >>\nstream\n{}\nendstream",
+                                 pixmap.width, pixmap.height, color_space, data.len(), data));
+        }
+
+        let mut buf = "%PDF-1.4\n".to_string();
+        let mut offsets = Vec::with_capacity(objects.len());
+        for (i, object) in objects.iter().enumerate() {
+            offsets.push(buf.len());
+            buf.push_str(&format!("{} 0 obj\n{}\nendobj\n", i + 1, object));
+        }
+        let xref_offset = buf.len();
+        buf.push_str(&format!("xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1));
+        for offset in offsets {
+            buf.push_str(&format!("{:010} 00000 n \n", offset));
+        }
+        buf.push_str(&format!("trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{}\n%%EOF\n",
+                              objects.len() + 1, xref_offset));
+        buf.into_bytes()
+    }
+}
+
+fn svg_color(color: Color) -> String {
+    let [red, green, blue] = color.rgb();
+    format!("#{:02X}{:02X}{:02X}", red, green, blue)
+}
+
+// The characters outside of the Latin-1 range are replaced.
+fn pdf_string(text: &str) -> String {
+    let mut buf = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' | '(' | ')' => { buf.push('\\'); buf.push(c); },
+            ' '..='~' => buf.push(c),
+            '\u{A0}'..='\u{FF}' => buf.push_str(&format!("\\{:03o}", c as u32)),
+            _ => buf.push('?'),
+        }
+    }
+    buf
+}
+
+fn pdf_color(color: Color) -> String {
+    match color {
+        Color::Gray(level) => format!("{:.3} G", level as f32 / 255.0),
+        Color::Rgb(red, green, blue) => format!("{:.3} {:.3} {:.3} RG",
+                                                red as f32 / 255.0,
+                                                green as f32 / 255.0,
+                                                blue as f32 / 255.0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::framebuffer::Framebuffer;
+
+    fn stroke(points: &[(f32, f32)]) -> Stroke {
+        let pen = Pen { size: 4, dynamic: false, .. Default::default() };
+        Stroke {
+            pen,
+            points: points.iter().enumerate().map(|(i, &(x, y))| {
+                StrokePoint { x, y, pressure: 0.5, time: i as f64 }
+            }).collect(),
+        }
+    }
+
+    #[test]
+    fn test_undo_redo() {
+        let mut notebook = Notebook::new(600, 800, 300, Template::Lined);
+        notebook.add_stroke(0, stroke(&[(10.0, 10.0), (50.0, 10.0)]));
+        notebook.add_stroke(0, stroke(&[(10.0, 100.0), (50.0, 100.0)]));
+        assert!(notebook.erase(0, vec2!(30.0, 12.0), 2.0).is_some());
+        assert_eq!(notebook.pages[0].strokes.len(), 1);
+        assert!(notebook.erase(0, vec2!(300.0, 300.0), 2.0).is_none());
+        assert_eq!(notebook.undo(), Some(0));
+        assert_eq!(notebook.pages[0].strokes.len(), 2);
+        assert_eq!(notebook.pages[0].strokes[0].points[0].y, 10.0);
+        notebook.translate(0, &[1], vec2!(5.0, 5.0));
+        assert_eq!(notebook.pages[0].strokes[1].points[0].x, 15.0);
+        assert_eq!(notebook.undo(), Some(0));
+        assert_eq!(notebook.pages[0].strokes[1].points[0].x, 10.0);
+        assert_eq!(notebook.redo(), Some(0));
+        assert_eq!(notebook.pages[0].strokes[1].points[0].x, 15.0);
+        assert!(!notebook.can_redo());
+        assert_eq!(notebook.insert_page(1), 1);
+        assert_eq!(notebook.pages[1].template, Template::Lined);
+        assert_eq!(notebook.undo(), Some(0));
+        assert_eq!(notebook.pages.len(), 1);
+    }
+
+    #[test]
+    fn test_lasso() {
+        let mut notebook = Notebook::new(600, 800, 300, Template::Blank);
+        notebook.add_stroke(0, stroke(&[(10.0, 10.0), (50.0, 10.0)]));
+        notebook.add_stroke(0, stroke(&[(10.0, 100.0), (200.0, 100.0)]));
+        let lasso = [vec2!(0.0, 0.0), vec2!(100.0, 0.0), vec2!(100.0, 150.0), vec2!(0.0, 150.0)];
+        assert_eq!(notebook.select(0, &lasso), vec![0]);
+    }
+
+    #[test]
+    fn test_exports() {
+        let mut notebook = Notebook::new(600, 800, 300, Template::Grid);
+        notebook.add_stroke(0, stroke(&[(10.0, 10.0), (50.0, 10.0)]));
+        notebook.insert_page(1);
+        let svg = notebook.to_svg(0);
+        assert!(svg.contains("<line x1=\"10.0\" y1=\"10.0\" x2=\"50.0\" y2=\"10.0\" stroke-width=\"4.00\"/>"));
+        let pdf = String::from_utf8(notebook.to_pdf(Path::new(""))).unwrap();
+        assert!(pdf.starts_with("%PDF-1.4\n"));
+        assert!(pdf.contains("/Count 2"));
+        let xref_offset = pdf.find("xref\n").unwrap();
+        assert!(pdf.ends_with(&format!("startxref\n{}\n%%EOF\n", xref_offset)));
+    }
+
+    #[test]
+    fn test_text() {
+        let mut notebook = Notebook::new(600, 800, 300, Template::Blank);
+        notebook.add_stroke(0, stroke(&[(10.0, 10.0), (50.0, 10.0)]));
+        notebook.insert_page(1);
+        notebook.pages[0].text = Some("Déjà (vu)\nλ".to_string());
+        assert_eq!(notebook.to_text(), "Déjà (vu)\nλ");
+        let pdf = String::from_utf8(notebook.to_pdf(Path::new(""))).unwrap();
+        assert!(pdf.contains("(D\\351j\\340 \\(vu\\)) '\n(?) '\n"));
+        assert!(pdf.contains("/Resources << /Font << /F1 7 0 R >> >>"));
+        assert!(pdf.contains("7 0 obj\n<< /Type /Font"));
+        notebook.add_stroke(0, stroke(&[(10.0, 100.0)]));
+        assert!(notebook.pages[0].text.is_none());
+    }
+
+    #[test]
+    fn test_background() {
+        let dir = std::env::temp_dir().join(format!("plato-notebook-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let mut pixmap = Pixmap::new(2, 1, 1);
+        pixmap.data[0] = 0x3F;
+        pixmap.save(&dir.join("sketch.png").to_string_lossy()).unwrap();
+        let mut notebook = Notebook::new(2, 1, 300, Template::Blank);
+        notebook.pages[0].background = Some("sketch.png".to_string());
+        notebook.insert_page(1);
+        assert!(notebook.pages[1].background.is_none());
+        assert!(notebook.to_svg(0).contains("<image href=\"sketch.png\" width=\"2\" height=\"1\"/>"));
+        let pdf = String::from_utf8(notebook.to_pdf(&dir)).unwrap();
+        fs::remove_dir_all(&dir).ok();
+        assert!(pdf.contains("/Resources << /XObject << /Im1 8 0 R >> >>"));
+        assert!(pdf.contains("/Width 2 /Height 1 /ColorSpace /DeviceGray"));
+        assert!(pdf.contains("stream\n3FFF>\nendstream"));
+    }
+}
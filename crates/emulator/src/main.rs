@@ -17,6 +17,7 @@ use sdl2::rect::Rect as SdlRect;
 use plato_core::framebuffer::{Framebuffer, UpdateMode};
 use plato_core::input::{DeviceEvent, FingerStatus, ButtonCode, ButtonStatus};
 use plato_core::document::sys_info_as_html;
+use plato_core::statistics::statistics_as_html;
 use plato_core::view::{View, Event, ViewId, EntryId, AppCmd, EntryKind};
 use plato_core::view::{process_render_queue, wait_for_all, handle_event, RenderQueue, RenderData};
 use plato_core::view::home::Home;
@@ -533,6 +534,15 @@ fn main() -> Result<(), Error> {
                     history.push(view as Box<dyn View>);
                     view = next_view;
                 },
+                Event::Select(EntryId::ReadingStatistics) => {
+                    view.children_mut().retain(|child| !child.is::<Menu>());
+                    let html = statistics_as_html(&context.library.statistics());
+                    let r = Reader::from_html(context.fb.rect(), &html, None, &tx, &mut context);
+                    let mut next_view = Box::new(r) as Box<dyn View>;
+                    transfer_notifications(view.as_mut(), next_view.as_mut(), &mut rq, &mut context);
+                    history.push(view as Box<dyn View>);
+                    view = next_view;
+                },
                 Event::Select(EntryId::Rotate(n)) if n != context.display.rotation && view.might_rotate() => {
                     wait_for_all(&mut updating, &mut context);
                     if let Ok(dims) = context.fb.set_rotation(n) {
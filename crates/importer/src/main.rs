@@ -8,6 +8,8 @@ use plato_core::library::Library;
 use plato_core::settings::{LibraryMode, ImportSettings};
 use plato_core::metadata::{extract_metadata_from_document, extract_metadata_from_filename};
 use plato_core::metadata::{consolidate, rename_from_info};
+use plato_core::export::{self, ExportFormat};
+use plato_core::calibre::{CalibreDatabase, extract_metadata_from_calibre, DATABASE_FILENAME};
 
 fn main() -> Result<(), Error> {
     let args: Vec<String> = env::args().skip(1).collect();
@@ -19,17 +21,21 @@ fn main() -> Result<(), Error> {
     opts.optflag("C", "clean-up", "Remove reading states with unknown fingerprints.");
     opts.optflag("E", "extract-metadata-document", "Extract metadata from documents.");
     opts.optflag("F", "extract-metadata-filename", "Extract metadata from filenames.");
+    opts.optflag("c", "extract-metadata-calibre", "Extract metadata from Calibre's OPF files and database.");
     opts.optflag("S", "consolidate", "Autocorrect simple typographic mistakes.");
     opts.optflag("N", "rename-from-info", "Rename files based on their information.");
+    opts.optflag("b", "export-per-book", "Export the annotations of each book in a separate file.");
     opts.optopt("k", "allowed-kinds", "Comma separated list of allowed kinds.", "ALLOWED_KINDS");
     opts.optopt("e", "metadata-kinds", "Comma separated list of metadata kinds.", "METADATA_KINDS");
     opts.optopt("a", "added-after", "Only process entries added after the given date-time.", "ADDED_DATETIME");
     opts.optopt("m", "library-mode", "The library mode (`database` or `filesystem`).", "LIBRARY_MODE");
+    opts.optopt("x", "export-annotations", "Export the annotations (`markdown`, `json` or `csv`).", "FORMAT");
+    opts.optopt("o", "output-directory", "The directory where the annotations are exported.", "OUTPUT_DIR");
 
     let matches = opts.parse(&args).context("failed to parse the command line arguments")?;
 
     if matches.opt_present("h") {
-        println!("{}", opts.usage("Usage: plato-import -h|-I|-C|-EFSN [-k ALLOWED_KINDS] [-e METADATA_KINDS] [-a ADDED_DATETIME] [-m LIBRARY_MODE] LIBRARY_PATH"));
+        println!("{}", opts.usage("Usage: plato-import -h|-I|-C|-EFcSN|-x FORMAT [-b] [-o OUTPUT_DIR] [-k ALLOWED_KINDS] [-e METADATA_KINDS] [-a ADDED_DATETIME] [-m LIBRARY_MODE] LIBRARY_PATH"));
         return Ok(());
     }
 
@@ -66,6 +72,28 @@ fn main() -> Result<(), Error> {
 
     let mut library = Library::new(&library_path, mode)?;
 
+    if let Some(format) = matches.opt_str("x") {
+        let format = format.parse::<ExportFormat>()?;
+        let output_dir = matches.opt_str("o").map_or_else(|| library_path.join("Annotations"),
+                                                          |v| Path::new(&v).to_path_buf());
+        let infos = library.annotated().into_iter()
+                           .filter(|info| added_after.map_or(true, |added| info.added >= added))
+                           .collect::<Vec<_>>();
+        let books = export::collect(library_path, &infos);
+
+        if matches.opt_present("b") {
+            for book in &books {
+                let path = export::export_book(book, format, &output_dir)?;
+                println!("{}", path.display());
+            }
+        } else if !books.is_empty() {
+            let path = export::export_books(&books, format, &output_dir)?;
+            println!("{}", path.display());
+        }
+
+        return Ok(());
+    }
+
     if matches.opt_present("I") {
         library.import(&import_settings);
     } else if matches.opt_present("C") {
@@ -73,6 +101,14 @@ fn main() -> Result<(), Error> {
     } else {
         let opt_extract_metadata_document = matches.opt_present("E");
         let opt_extract_metadata_filename = matches.opt_present("F");
+        let calibre_db = if matches.opt_present("c") && library_path.join(DATABASE_FILENAME).exists() {
+            CalibreDatabase::open(library_path.join(DATABASE_FILENAME))
+                           .map_err(|e| eprintln!("Can't open Calibre database: {:#}.", e))
+                           .ok()
+        } else {
+            None
+        };
+        let opt_extract_metadata_calibre = matches.opt_present("c");
         let opt_consolidate = matches.opt_present("S");
         let opt_rename_from_info = matches.opt_present("N");
 
@@ -87,6 +123,10 @@ fn main() -> Result<(), Error> {
                     extract_metadata_from_filename(path, info);
                 }
 
+                if opt_extract_metadata_calibre {
+                    extract_metadata_from_calibre(path, calibre_db.as_ref(), info);
+                }
+
                 if opt_consolidate {
                     consolidate(path, info);
                 }
@@ -0,0 +1,407 @@
+use plato_core::anyhow::{Error, format_err};
+use plato_core::serde_json::{self, Value as JsonValue};
+use plato_core::helpers::decode_entities;
+use plato_core::document::html::xml::XmlParser;
+use plato_core::document::html::dom::NodeRef;
+
+pub const ACQUISITION_REL: &str = "http://opds-spec.org/acquisition";
+pub const IMAGE_REL: &str = "http://opds-spec.org/image";
+pub const THUMBNAIL_REL: &str = "http://opds-spec.org/image/thumbnail";
+pub const OPENSEARCH_TYPE: &str = "application/opensearchdescription+xml";
+
+#[derive(Debug, Clone, Default)]
+pub struct Link {
+    pub href: String,
+    pub rel: String,
+    pub kind: String,
+    pub title: String,
+    pub templated: bool,
+}
+
+impl Link {
+    pub fn is_acquisition(&self) -> bool {
+        self.rel.starts_with(ACQUISITION_REL)
+    }
+
+    pub fn is_feed(&self) -> bool {
+        self.kind.starts_with("application/atom+xml") ||
+        self.kind.starts_with("application/opds+json") ||
+        self.kind.starts_with("application/json")
+    }
+
+    pub fn is_cover(&self) -> bool {
+        self.rel == IMAGE_REL || self.rel == "cover"
+    }
+
+    pub fn is_thumbnail(&self) -> bool {
+        self.rel == THUMBNAIL_REL
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Publication {
+    pub id: String,
+    pub title: String,
+    pub authors: Vec<String>,
+    pub language: String,
+    pub publisher: String,
+    pub year: String,
+    pub identifier: String,
+    pub series: String,
+    pub number: String,
+    pub categories: Vec<String>,
+    pub links: Vec<Link>,
+}
+
+impl Publication {
+    // Returns the first acquisition link matching the given media types, in order of preference.
+    pub fn acquisition(&self, formats: &[String]) -> Option<&Link> {
+        formats.iter().find_map(|format| {
+            self.links.iter().find(|link| link.is_acquisition() &&
+                                          link.kind.split(';').next() == Some(format.as_str()))
+        })
+    }
+
+    pub fn cover(&self) -> Option<&Link> {
+        self.links.iter().find(|link| link.is_cover())
+            .or_else(|| self.links.iter().find(|link| link.is_thumbnail()))
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Feed {
+    pub title: String,
+    pub links: Vec<Link>,
+    pub navigation: Vec<Link>,
+    pub publications: Vec<Publication>,
+}
+
+impl Feed {
+    pub fn parse(text: &str) -> Result<Feed, Error> {
+        if text.trim_start().starts_with('{') {
+            parse_json(text)
+        } else {
+            parse_atom(text)
+        }
+    }
+
+    pub fn link(&self, rel: &str) -> Option<&Link> {
+        self.links.iter().find(|link| link.rel == rel)
+    }
+
+    pub fn next(&self) -> Option<&Link> {
+        self.link("next")
+    }
+
+    pub fn search(&self) -> Option<&Link> {
+        self.links.iter().find(|link| link.rel == "search" &&
+                                      (link.kind == OPENSEARCH_TYPE || link.is_feed() || link.templated))
+    }
+}
+
+fn text_content(node: NodeRef) -> String {
+    decode_entities(node.text().trim()).into_owned()
+}
+
+fn child_text(node: NodeRef, name: &str) -> Option<String> {
+    node.children()
+        .find(|child| child.tag_name() == Some(name))
+        .map(text_content)
+        .filter(|text| !text.is_empty())
+}
+
+fn atom_link(node: NodeRef) -> Option<Link> {
+    let href = node.attribute("href")?;
+    Some(Link {
+        href: decode_entities(href).into_owned(),
+        rel: node.attribute("rel").unwrap_or_default().to_string(),
+        kind: node.attribute("type").unwrap_or_default().to_string(),
+        title: node.attribute("title").map(|t| decode_entities(t).into_owned()).unwrap_or_default(),
+        templated: href.contains("{searchTerms}"),
+    })
+}
+
+fn atom_publication(entry: NodeRef) -> Publication {
+    let mut publication = Publication {
+        id: child_text(entry, "id").unwrap_or_default(),
+        title: child_text(entry, "title").unwrap_or_default(),
+        .. Default::default()
+    };
+
+    for child in entry.children() {
+        match child.tag_name() {
+            Some("author") => {
+                if let Some(name) = child_text(child, "name") {
+                    publication.authors.push(name);
+                }
+            },
+            Some("language") => publication.language = text_content(child),
+            Some("publisher") => publication.publisher = text_content(child),
+            Some("issued") | Some("published") if publication.year.is_empty() => {
+                publication.year = text_content(child).chars().take(4).collect();
+            },
+            Some("identifier") if publication.identifier.is_empty() => {
+                publication.identifier = text_content(child);
+            },
+            Some("category") => {
+                if let Some(label) = child.attribute("label").or_else(|| child.attribute("term")) {
+                    publication.categories.push(decode_entities(label).into_owned());
+                }
+            },
+            Some("meta") => {
+                let content = child.attribute("content").unwrap_or_default();
+                match child.attribute("name") {
+                    Some("calibre:series") => publication.series = decode_entities(content).into_owned(),
+                    Some("calibre:series_index") => publication.number = series_index(content),
+                    _ => (),
+                }
+            },
+            Some("link") => {
+                if let Some(link) = atom_link(child) {
+                    publication.links.push(link);
+                }
+            },
+            _ => (),
+        }
+    }
+
+    publication
+}
+
+fn parse_atom(text: &str) -> Result<Feed, Error> {
+    let xml = XmlParser::new(text).parse();
+    let root = xml.root().find("feed")
+                  .ok_or_else(|| format_err!("missing feed element"))?;
+    let mut feed = Feed {
+        title: child_text(root, "title").unwrap_or_default(),
+        .. Default::default()
+    };
+
+    for child in root.children() {
+        match child.tag_name() {
+            Some("link") => {
+                if let Some(link) = atom_link(child) {
+                    feed.links.push(link);
+                }
+            },
+            Some("entry") => {
+                let publication = atom_publication(child);
+                if publication.links.iter().any(Link::is_acquisition) {
+                    feed.publications.push(publication);
+                } else if let Some(link) = publication.links.iter().find(|link| link.is_feed()) {
+                    feed.navigation.push(Link {
+                        title: publication.title.clone(),
+                        .. link.clone()
+                    });
+                }
+            },
+            _ => (),
+        }
+    }
+
+    Ok(feed)
+}
+
+// Calibre writes series indices as floats.
+fn series_index(value: &str) -> String {
+    value.trim().trim_end_matches(".0").to_string()
+}
+
+fn json_string(value: &JsonValue) -> Option<String> {
+    match value {
+        JsonValue::String(s) => Some(s.clone()),
+        JsonValue::Number(n) => Some(series_index(&n.to_string())),
+        JsonValue::Object(map) => map.get("name").and_then(json_string),
+        JsonValue::Array(values) => values.first().and_then(json_string),
+        _ => None,
+    }
+}
+
+fn json_strings(value: Option<&JsonValue>) -> Vec<String> {
+    match value {
+        Some(JsonValue::Array(values)) => values.iter().filter_map(json_string).collect(),
+        Some(value) => json_string(value).into_iter().collect(),
+        None => Vec::new(),
+    }
+}
+
+fn json_links(value: Option<&JsonValue>) -> Vec<Link> {
+    value.and_then(JsonValue::as_array).map(|links| {
+        links.iter().filter_map(|link| {
+            let href = link.get("href").and_then(JsonValue::as_str)?;
+            let rel = match link.get("rel") {
+                Some(JsonValue::Array(rels)) => rels.first().and_then(JsonValue::as_str),
+                Some(rel) => rel.as_str(),
+                None => None,
+            };
+            Some(Link {
+                href: href.to_string(),
+                rel: rel.unwrap_or_default().to_string(),
+                kind: link.get("type").and_then(JsonValue::as_str).unwrap_or_default().to_string(),
+                title: link.get("title").and_then(JsonValue::as_str).unwrap_or_default().to_string(),
+                templated: link.get("templated").and_then(JsonValue::as_bool).unwrap_or(false),
+            })
+        }).collect()
+    }).unwrap_or_default()
+}
+
+fn json_publication(value: &JsonValue) -> Publication {
+    let metadata = value.get("metadata").cloned().unwrap_or(JsonValue::Null);
+    let mut publication = Publication {
+        id: metadata.get("identifier").and_then(json_string).unwrap_or_default(),
+        title: metadata.get("title").and_then(json_string).unwrap_or_default(),
+        authors: json_strings(metadata.get("author")),
+        language: metadata.get("language").and_then(json_string).unwrap_or_default(),
+        publisher: metadata.get("publisher").and_then(json_string).unwrap_or_default(),
+        year: metadata.get("published").and_then(json_string)
+                      .map(|date| date.chars().take(4).collect())
+                      .unwrap_or_default(),
+        categories: json_strings(metadata.get("subject")),
+        links: json_links(value.get("links")),
+        .. Default::default()
+    };
+
+    publication.identifier = publication.id.clone();
+
+    if let Some(series) = metadata.pointer("/belongsTo/series") {
+        let series = if let JsonValue::Array(values) = series {
+            values.first().cloned().unwrap_or(JsonValue::Null)
+        } else {
+            series.clone()
+        };
+        publication.series = json_string(&series).unwrap_or_default();
+        publication.number = series.get("position").and_then(json_string).unwrap_or_default();
+    }
+
+    for mut image in json_links(value.get("images")).into_iter().take(1) {
+        image.rel = IMAGE_REL.to_string();
+        publication.links.push(image);
+    }
+
+    publication
+}
+
+fn parse_json(text: &str) -> Result<Feed, Error> {
+    let value: JsonValue = serde_json::from_str(text)?;
+    let mut feed = Feed {
+        title: value.pointer("/metadata/title").and_then(json_string).unwrap_or_default(),
+        links: json_links(value.get("links")),
+        navigation: json_links(value.get("navigation")),
+        publications: value.get("publications").and_then(JsonValue::as_array)
+                           .map(|v| v.iter().map(json_publication).collect())
+                           .unwrap_or_default(),
+    };
+
+    if let Some(groups) = value.get("groups").and_then(JsonValue::as_array) {
+        for group in groups {
+            feed.navigation.extend(json_links(group.get("navigation")));
+            if let Some(publications) = group.get("publications").and_then(JsonValue::as_array) {
+                feed.publications.extend(publications.iter().map(json_publication));
+            }
+        }
+    }
+
+    Ok(feed)
+}
+
+// Extracts the Atom search template from an OpenSearch description document.
+pub fn opensearch_template(text: &str) -> Option<String> {
+    let xml = XmlParser::new(text).parse();
+    let root = xml.root();
+    let urls = root.descendants()
+                   .filter(|n| n.tag_name() == Some("Url"))
+                   .collect::<Vec<NodeRef>>();
+    urls.iter().find(|n| n.attribute("type").map_or(false, |t| t.starts_with("application/atom+xml")))
+        .or_else(|| urls.first())
+        .and_then(|n| n.attribute("template"))
+        .map(|t| decode_entities(t).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ATOM_FEED: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<feed xmlns="http://www.w3.org/2005/Atom" xmlns:dc="http://purl.org/dc/terms/">
+  <title>Books</title>
+  <link rel="next" type="application/atom+xml;profile=opds-catalog" href="/opds/books?page=2"/>
+  <link rel="search" type="application/opensearchdescription+xml" href="/opds/osd"/>
+  <entry>
+    <title>By Author</title>
+    <id>urn:authors</id>
+    <link rel="subsection" type="application/atom+xml;profile=opds-catalog;kind=navigation" href="/opds/authors"/>
+  </entry>
+  <entry>
+    <title>The Fellowship of the Ring</title>
+    <id>urn:uuid:1234</id>
+    <author><name>J. R. R. Tolkien</name></author>
+    <dc:language>en</dc:language>
+    <dc:issued>1954-07-29</dc:issued>
+    <dc:identifier>urn:isbn:9780261103573</dc:identifier>
+    <category term="Fantasy" label="Fantasy"/>
+    <meta name="calibre:series" content="The Lord of the Rings"/>
+    <meta name="calibre:series_index" content="1.0"/>
+    <link rel="http://opds-spec.org/image" type="image/jpeg" href="/cover/1"/>
+    <link rel="http://opds-spec.org/acquisition" type="application/epub+zip" href="/download/1/epub"/>
+  </entry>
+</feed>"#;
+
+    #[test]
+    fn test_atom_feed() {
+        let feed = Feed::parse(ATOM_FEED).unwrap();
+        assert_eq!(feed.title, "Books");
+        assert_eq!(feed.next().map(|l| l.href.as_str()), Some("/opds/books?page=2"));
+        assert_eq!(feed.search().map(|l| l.kind.as_str()), Some(OPENSEARCH_TYPE));
+        assert_eq!(feed.navigation.len(), 1);
+        assert_eq!(feed.navigation[0].title, "By Author");
+        assert_eq!(feed.publications.len(), 1);
+        let publication = &feed.publications[0];
+        assert_eq!(publication.authors, vec!["J. R. R. Tolkien".to_string()]);
+        assert_eq!(publication.year, "1954");
+        assert_eq!(publication.series, "The Lord of the Rings");
+        assert_eq!(publication.number, "1");
+        assert_eq!(publication.identifier, "urn:isbn:9780261103573");
+        let formats = vec!["application/pdf".to_string(), "application/epub+zip".to_string()];
+        assert_eq!(publication.acquisition(&formats).map(|l| l.href.as_str()), Some("/download/1/epub"));
+        assert_eq!(publication.cover().map(|l| l.href.as_str()), Some("/cover/1"));
+    }
+
+    #[test]
+    fn test_json_feed() {
+        let text = r#"{
+            "metadata": {"title": "Catalog"},
+            "links": [{"rel": "search", "href": "/search{?query}", "type": "application/opds+json", "templated": true}],
+            "navigation": [{"href": "/new", "title": "New", "type": "application/opds+json"}],
+            "publications": [{
+                "metadata": {
+                    "title": "Dune",
+                    "author": [{"name": "Frank Herbert"}],
+                    "identifier": "urn:isbn:9780441013593",
+                    "language": "en",
+                    "published": "1965",
+                    "belongsTo": {"series": {"name": "Dune", "position": 1}}
+                },
+                "links": [{"rel": "http://opds-spec.org/acquisition", "href": "/dune.epub", "type": "application/epub+zip"}],
+                "images": [{"href": "/dune.jpg", "type": "image/jpeg"}]
+            }]
+        }"#;
+        let feed = Feed::parse(text).unwrap();
+        assert_eq!(feed.title, "Catalog");
+        assert!(feed.search().map_or(false, |l| l.templated));
+        assert_eq!(feed.navigation.len(), 1);
+        let publication = &feed.publications[0];
+        assert_eq!(publication.authors, vec!["Frank Herbert".to_string()]);
+        assert_eq!(publication.series, "Dune");
+        assert_eq!(publication.number, "1");
+        assert_eq!(publication.cover().map(|l| l.href.as_str()), Some("/dune.jpg"));
+    }
+
+    #[test]
+    fn test_opensearch_template() {
+        let text = r#"<OpenSearchDescription xmlns="http://a9.com/-/spec/opensearch/1.1/">
+            <Url type="text/html" template="/search?q={searchTerms}"/>
+            <Url type="application/atom+xml" template="/opds/search?q={searchTerms}&amp;p={startPage?}"/>
+        </OpenSearchDescription>"#;
+        assert_eq!(opensearch_template(text).as_deref(), Some("/opds/search?q={searchTerms}&p={startPage?}"));
+    }
+}
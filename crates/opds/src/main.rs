@@ -0,0 +1,460 @@
+mod feed;
+
+use std::io;
+use std::env;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::collections::BTreeSet;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use plato_core::fxhash::FxHashSet;
+use plato_core::serde::{Serialize, Deserialize};
+use plato_core::serde_json::json;
+use plato_core::anyhow::{Error, Context, format_err};
+use plato_core::helpers::{load_toml, load_json, save_json, Fingerprint};
+use plato_core::metadata::{Info, FileInfo, file_name_from_info};
+use plato_core::library::{FAT32_EPOCH_FILENAME, THUMBNAIL_PREVIEWS_DIRNAME};
+use plato_core::document::open;
+use plato_core::framebuffer::Framebuffer;
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use reqwest::blocking::Client;
+use reqwest::Url;
+use crate::feed::{Feed, Publication, OPENSEARCH_TYPE, opensearch_template};
+
+const SETTINGS_PATH: &str = "Settings.toml";
+const SESSION_PATH: &str = ".session.json";
+// The size of the generated thumbnail previews, they're scaled when displayed.
+const COVER_WIDTH: f32 = 300.0;
+const COVER_HEIGHT: f32 = 400.0;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(crate = "plato_core::serde")]
+#[serde(default, rename_all = "kebab-case")]
+struct Settings {
+    catalogs: Vec<Catalog>,
+    // Media types of the acquisition links, in order of preference.
+    formats: Vec<String>,
+    max_depth: usize,
+    max_downloads: usize,
+    covers: bool,
+}
+
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[serde(crate = "plato_core::serde")]
+#[serde(default, rename_all = "kebab-case")]
+struct Catalog {
+    name: String,
+    url: String,
+    username: String,
+    password: String,
+    queries: Vec<String>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            catalogs: Vec::new(),
+            formats: vec!["application/epub+zip".to_string(),
+                          "application/pdf".to_string(),
+                          "application/x-cbz".to_string(),
+                          "image/vnd.djvu".to_string(),
+                          "application/x-fictionbook+xml".to_string()],
+            max_depth: 2,
+            max_downloads: 20,
+            covers: true,
+        }
+    }
+}
+
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[serde(crate = "plato_core::serde")]
+#[serde(default, rename_all = "camelCase")]
+struct Session {
+    downloaded: BTreeSet<String>,
+    downloads_count: usize,
+}
+
+fn kind_from_media_type(media_type: &str) -> Option<&'static str> {
+    match media_type.split(';').next().map(str::trim)? {
+        "application/epub+zip" => Some("epub"),
+        "application/pdf" => Some("pdf"),
+        "application/x-cbz" | "application/vnd.comicbook+zip" => Some("cbz"),
+        "image/vnd.djvu" | "image/x-djvu" => Some("djvu"),
+        "application/x-fictionbook+xml" | "application/fb2" => Some("fb2"),
+        "application/x-mobipocket-ebook" => Some("mobi"),
+        "application/vnd.ms-xpsdocument" | "application/oxps" => Some("xps"),
+        "text/plain" => Some("txt"),
+        "image/jpeg" => Some("jpg"),
+        "image/png" => Some("png"),
+        "image/gif" => Some("gif"),
+        _ => None,
+    }
+}
+
+// Expands the search terms of OpenSearch (`{searchTerms}`) and OPDS 2 (`{?query}`) templates.
+fn expand_template(template: &str, query: &str) -> String {
+    let terms = utf8_percent_encode(query, NON_ALPHANUMERIC).to_string();
+    let mut result = String::new();
+    let mut rest = template;
+
+    while let Some(start) = rest.find('{') {
+        let end = match rest[start..].find('}') {
+            Some(index) => start + index,
+            None => break,
+        };
+        result.push_str(&rest[..start]);
+        let name = &rest[start+1..end];
+        if name.trim_end_matches('?') == "searchTerms" {
+            result.push_str(&terms);
+        } else if name.starts_with('?') && name[1..].split(',').any(|v| v == "query") {
+            result.push_str("?query=");
+            result.push_str(&terms);
+        }
+        // Other parameters are optional and left empty.
+        rest = &rest[end+1..];
+    }
+
+    result.push_str(rest);
+    result
+}
+
+struct Fetcher<'a> {
+    client: Client,
+    settings: &'a Settings,
+    session: &'a mut Session,
+    library_path: &'a Path,
+    fat32_epoch: Option<std::time::SystemTime>,
+    visited: FxHashSet<Url>,
+    sigterm: Arc<AtomicBool>,
+    downloads_count: usize,
+}
+
+impl<'a> Fetcher<'a> {
+    fn fetch(&self, catalog: &Catalog, url: &Url) -> Result<Vec<u8>, Error> {
+        if url.scheme() == "file" {
+            let path = url.to_file_path()
+                          .map_err(|_| format_err!("invalid file URL: {}", url))?;
+            // Static catalogs can be stored as a directory with an index file.
+            let path = if path.is_dir() {
+                ["index.xml", "index.json"].iter().map(|name| path.join(name))
+                                           .find(|path| path.exists())
+                                           .ok_or_else(|| format_err!("missing index in {}", path.display()))?
+            } else {
+                path
+            };
+            return fs::read(&path).with_context(|| format!("can't read {}", path.display()));
+        }
+
+        let mut request = self.client.get(url.clone());
+
+        if !catalog.username.is_empty() {
+            request = request.basic_auth(&catalog.username, Some(&catalog.password));
+        }
+
+        let response = request.send()?.error_for_status()?;
+        Ok(response.bytes()?.to_vec())
+    }
+
+    fn is_done(&self) -> bool {
+        self.sigterm.load(Ordering::Relaxed) ||
+        (self.settings.max_downloads > 0 && self.downloads_count >= self.settings.max_downloads)
+    }
+
+    fn crawl(&mut self, catalog: &Catalog, save_path: &Path, url: Url, depth: usize) -> Result<(), Error> {
+        let mut next = Some(url);
+
+        while let Some(url) = next.take() {
+            if self.is_done() || !self.visited.insert(url.clone()) {
+                break;
+            }
+
+            let body = self.fetch(catalog, &url)?;
+            let feed = Feed::parse(&String::from_utf8_lossy(&body))
+                            .with_context(|| format!("can't parse feed {}", url))?;
+
+            for publication in &feed.publications {
+                if self.is_done() {
+                    return Ok(());
+                }
+                if let Err(e) = self.download(catalog, save_path, &url, publication) {
+                    eprintln!("Can't download {}: {:#}.", publication.title, e);
+                }
+            }
+
+            if depth < self.settings.max_depth {
+                for link in &feed.navigation {
+                    if let Ok(child) = url.join(&link.href) {
+                        if let Err(e) = self.crawl(catalog, save_path, child, depth + 1) {
+                            eprintln!("Can't browse {}: {:#}.", link.title, e);
+                        }
+                    }
+                }
+            }
+
+            next = feed.next().and_then(|link| url.join(&link.href).ok());
+        }
+
+        Ok(())
+    }
+
+    fn search(&mut self, catalog: &Catalog, save_path: &Path, root: &Url, query: &str) -> Result<(), Error> {
+        let body = self.fetch(catalog, root)?;
+        let feed = Feed::parse(&String::from_utf8_lossy(&body))
+                        .with_context(|| format!("can't parse feed {}", root))?;
+        let link = feed.search()
+                       .ok_or_else(|| format_err!("{} doesn't support searching", feed.title))?;
+
+        let template = if link.kind == OPENSEARCH_TYPE {
+            let url = root.join(&link.href)?;
+            let body = self.fetch(catalog, &url)?;
+            opensearch_template(&String::from_utf8_lossy(&body))
+                .ok_or_else(|| format_err!("missing search template in {}", url))?
+        } else {
+            link.href.clone()
+        };
+
+        let url = root.join(&expand_template(&template, query))?;
+        // Only follow the pagination of the results.
+        self.crawl(catalog, save_path, url, self.settings.max_depth)
+    }
+
+    fn download(&mut self, catalog: &Catalog, save_path: &Path, base: &Url, publication: &Publication) -> Result<(), Error> {
+        let link = publication.acquisition(&self.settings.formats)
+                              .ok_or_else(|| format_err!("no acquisition link matches the preferred formats"))?;
+        let url = base.join(&link.href)?;
+        let id = if publication.id.is_empty() { url.to_string() } else { publication.id.clone() };
+
+        if self.session.downloaded.contains(&id) {
+            return Ok(());
+        }
+
+        let kind = kind_from_media_type(&link.kind)
+                       .ok_or_else(|| format_err!("unknown media type: {}", link.kind))?;
+
+        let mut info = Info {
+            title: publication.title.clone(),
+            author: publication.authors.join(", "),
+            year: publication.year.clone(),
+            language: publication.language.clone(),
+            publisher: publication.publisher.clone(),
+            series: publication.series.clone(),
+            number: publication.number.clone(),
+            identifier: publication.identifier.clone(),
+            categories: publication.categories.iter().cloned().collect(),
+            file: FileInfo {
+                kind: kind.to_string(),
+                .. Default::default()
+            },
+            .. Default::default()
+        };
+
+        let mut file_name = file_name_from_info(&info);
+
+        if file_name.is_empty() {
+            file_name = url.path_segments()
+                           .and_then(|mut segments| segments.next_back())
+                           .filter(|name| !name.is_empty())
+                           .map(|name| format!("{}.{}", name.trim_end_matches(&format!(".{}", kind)), kind))
+                           .ok_or_else(|| format_err!("can't determine the file name"))?;
+        }
+
+        let doc_path = save_path.join(&file_name);
+
+        if doc_path.exists() {
+            self.session.downloaded.insert(id);
+            return Ok(());
+        }
+
+        let body = self.fetch(catalog, &url)?;
+        fs::write(&doc_path, &body)
+           .with_context(|| format!("can't write {}", doc_path.display()))?;
+
+        self.session.downloaded.insert(id);
+        self.session.downloads_count = self.session.downloads_count.wrapping_add(1);
+        self.downloads_count += 1;
+
+        if self.settings.covers {
+            if let Some(cover) = publication.cover() {
+                if let Err(e) = self.save_cover(catalog, base, cover, &doc_path) {
+                    eprintln!("Can't save the cover of {}: {:#}.", publication.title, e);
+                }
+            }
+        }
+
+        if let Ok(path) = doc_path.strip_prefix(self.library_path) {
+            info.file.path = path.to_path_buf();
+            info.file.size = body.len() as u64;
+
+            let event = json!({
+                "type": "addDocument",
+                "info": &info,
+            });
+            println!("{}", event);
+        }
+
+        Ok(())
+    }
+
+    // Renders the cover provided by the catalog as the document's thumbnail preview.
+    fn save_cover(&self, catalog: &Catalog, base: &Url, cover: &feed::Link, doc_path: &Path) -> Result<(), Error> {
+        let fat32_epoch = self.fat32_epoch
+                              .ok_or_else(|| format_err!("missing {}", FAT32_EPOCH_FILENAME))?;
+        let fp = doc_path.metadata()?.fingerprint(fat32_epoch)?;
+        let url = base.join(&cover.href)?;
+        let kind = kind_from_media_type(&cover.kind).unwrap_or("jpg");
+        let image_path = doc_path.with_extension(format!("cover.{}", kind));
+        let body = self.fetch(catalog, &url)?;
+        fs::write(&image_path, &body)?;
+
+        let pixmap = open(&image_path).and_then(|mut doc| {
+            doc.preview_pixmap(COVER_WIDTH, COVER_HEIGHT, 3)
+        });
+        fs::remove_file(&image_path).ok();
+        let pixmap = pixmap.ok_or_else(|| format_err!("can't render {}", url))?;
+
+        let thumbnail_previews_dir = self.library_path.join(THUMBNAIL_PREVIEWS_DIRNAME);
+        fs::create_dir_all(&thumbnail_previews_dir)?;
+        let thumb_path = thumbnail_previews_dir.join(format!("{}.png", fp));
+        pixmap.save(&thumb_path.to_string_lossy())
+    }
+}
+
+fn catalog_url(value: &str) -> Result<Url, Error> {
+    Url::parse(value).or_else(|_| {
+        // Accept paths to local static catalogs.
+        fs::canonicalize(value).ok()
+           .and_then(|path| Url::from_file_path(path).ok())
+           .ok_or_else(|| format_err!("invalid catalog URL: {}", value))
+    })
+}
+
+fn main() -> Result<(), Error> {
+    let mut args = env::args().skip(1);
+    let library_path = PathBuf::from(args.next()
+                                         .ok_or_else(|| format_err!("missing argument: library path"))?);
+    let save_path = PathBuf::from(args.next()
+                                      .ok_or_else(|| format_err!("missing argument: save path"))?);
+    let wifi = args.next()
+                   .ok_or_else(|| format_err!("missing argument: wifi status"))
+                   .and_then(|v| v.parse::<bool>().map_err(Into::into))?;
+    let online = args.next()
+                     .ok_or_else(|| format_err!("missing argument: online status"))
+                     .and_then(|v| v.parse::<bool>().map_err(Into::into))?;
+    let settings = load_toml::<Settings, _>(SETTINGS_PATH)
+                             .with_context(|| format!("can't load settings from {}", SETTINGS_PATH))?;
+    let mut session = load_json::<Session, _>(SESSION_PATH)
+                                .unwrap_or_default();
+
+    let needs_network = settings.catalogs.iter()
+                                .any(|catalog| !catalog.url.starts_with("file:") &&
+                                               !Path::new(&catalog.url).exists());
+
+    if !online && needs_network {
+        if !wifi {
+            let event = json!({
+                "type": "notify",
+                "message": "Establishing a network connection.",
+            });
+            println!("{}", event);
+            let event = json!({
+                "type": "setWifi",
+                "enable": true,
+            });
+            println!("{}", event);
+        } else {
+            let event = json!({
+                "type": "notify",
+                "message": "Waiting for the network to come up.",
+            });
+            println!("{}", event);
+        }
+        let mut line = String::new();
+        io::stdin().read_line(&mut line)?;
+    }
+
+    if !save_path.exists() {
+        fs::create_dir(&save_path)?;
+    }
+
+    let sigterm = Arc::new(AtomicBool::new(false));
+    signal_hook::flag::register(signal_hook::consts::SIGTERM, Arc::clone(&sigterm))?;
+
+    let fat32_epoch = File::open(library_path.join(FAT32_EPOCH_FILENAME))
+                           .and_then(|file| file.metadata())
+                           .and_then(|md| md.modified())
+                           .ok();
+
+    let mut fetcher = Fetcher {
+        client: Client::new(),
+        settings: &settings,
+        session: &mut session,
+        library_path: &library_path,
+        fat32_epoch,
+        visited: FxHashSet::default(),
+        sigterm,
+        downloads_count: 0,
+    };
+
+    for catalog in &settings.catalogs {
+        if fetcher.is_done() {
+            break;
+        }
+
+        let root = match catalog_url(&catalog.url) {
+            Ok(url) => url,
+            Err(e) => {
+                eprintln!("Can't open {}: {:#}.", catalog.name, e);
+                continue;
+            },
+        };
+
+        let catalog_path = if settings.catalogs.len() > 1 && !catalog.name.is_empty() {
+            save_path.join(catalog.name.replace('/', " "))
+        } else {
+            save_path.clone()
+        };
+
+        if !catalog_path.exists() {
+            fs::create_dir(&catalog_path)?;
+        }
+
+        let result = if catalog.queries.is_empty() {
+            fetcher.crawl(catalog, &catalog_path, root, 0)
+        } else {
+            catalog.queries.iter().try_for_each(|query| {
+                fetcher.search(catalog, &catalog_path, &root, query)
+            })
+        };
+
+        if let Err(e) = result {
+            let event = json!({
+                "type": "notify",
+                "message": format!("Can't fetch {}: {:#}.", catalog.name, e),
+            });
+            println!("{}", event);
+        }
+    }
+
+    let downloads_count = fetcher.downloads_count;
+    let message = if downloads_count > 0 {
+        format!("Downloaded {} book{}.", downloads_count, if downloads_count != 1 { "s" } else { "" })
+    } else {
+        "No new books.".to_string()
+    };
+    let event = json!({
+        "type": "notify",
+        "message": &message,
+    });
+    println!("{}", event);
+
+    if !wifi && needs_network {
+        let event = json!({
+            "type": "setWifi",
+            "enable": false,
+        });
+        println!("{}", event);
+    }
+
+    save_json(&session, SESSION_PATH).context("can't save session")?;
+    Ok(())
+}
@@ -21,6 +21,7 @@ use plato_core::view::sketch::Sketch;
 use plato_core::view::touch_events::TouchEvents;
 use plato_core::view::rotation_values::RotationValues;
 use plato_core::document::sys_info_as_html;
+use plato_core::statistics::statistics_as_html;
 use plato_core::input::{DeviceEvent, PowerSource, ButtonCode, ButtonStatus, VAL_RELEASE, VAL_PRESS, EVENT_BUTTONS, EVENT_TOUCH_SCREEN, EVENT_WACOM, InputFilterCommand};
 use plato_core::input::{raw_events, device_events, usb_events, display_rotate_event, button_scheme_event};
 use plato_core::gesture::{GestureEvent, gesture_events};
@@ -856,6 +857,20 @@ pub fn run() -> Result<(), Error> {
                 });
                 view = next_view;
             },
+            Event::Select(EntryId::ReadingStatistics) => {
+                view.children_mut().retain(|child| !child.is::<Menu>());
+                let html = statistics_as_html(&context.library.statistics());
+                let r = Reader::from_html(context.fb.rect(), &html, None, &tx, &mut context);
+                let mut next_view = Box::new(r) as Box<dyn View>;
+                transfer_notifications(view.as_mut(), next_view.as_mut(), &mut rq, &mut context);
+                history.push(HistoryItem {
+                    view,
+                    rotation: context.display.rotation,
+                    monochrome: context.fb.monochrome(),
+                    dithered: context.fb.dithered(),
+                });
+                view = next_view;
+            },
             Event::OpenHtml(ref html, ref link_uri) => {
                 view.children_mut().retain(|child| !child.is::<Menu>());
                 let r = Reader::from_html(context.fb.rect(), html, link_uri.as_deref(), &tx, &mut context);
@@ -0,0 +1,238 @@
+use std::env;
+use std::fs;
+use std::path::Path;
+use getopts::Options;
+use plato_core::anyhow::{Error, Context, format_err};
+use plato_core::document::{Document, Location, open};
+use plato_core::framebuffer::Framebuffer;
+use plato_core::metadata::TextAlign;
+use plato_core::settings::{DEFAULT_FONT_PATH, DEFAULT_FONT_FAMILY, DEFAULT_FONT_SIZE};
+use plato_core::settings::{DEFAULT_MARGIN_WIDTH, DEFAULT_LINE_HEIGHT, DEFAULT_TEXT_ALIGN};
+use plato_core::settings::{HYPHEN_PENALTY, STRETCH_TOLERANCE};
+
+const DEFAULT_WIDTH: u32 = 1404;
+const DEFAULT_HEIGHT: u32 = 1872;
+const DEFAULT_DPI: u16 = 226;
+
+fn parse_text_align(value: &str) -> Option<TextAlign> {
+    match value {
+        "justify" => Some(TextAlign::Justify),
+        "left" => Some(TextAlign::Left),
+        "right" => Some(TextAlign::Right),
+        "center" => Some(TextAlign::Center),
+        _ => None,
+    }
+}
+
+// Parses a comma separated list of one-based page numbers and ranges, e.g. `1,3,5-8,12-`.
+fn parse_pages(value: &str, pages_count: usize) -> Result<Vec<usize>, Error> {
+    let mut pages = Vec::new();
+
+    for part in value.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+        let (start, end) = if let Some(index) = part.find('-') {
+            let start = if index == 0 {
+                1
+            } else {
+                part[..index].parse::<usize>()
+                             .with_context(|| format!("invalid page number: {}", &part[..index]))?
+            };
+            let end = if index == part.len() - 1 {
+                pages_count
+            } else {
+                part[index+1..].parse::<usize>()
+                               .with_context(|| format!("invalid page number: {}", &part[index+1..]))?
+            };
+            (start, end)
+        } else {
+            let page = part.parse::<usize>()
+                           .with_context(|| format!("invalid page number: {}", part))?;
+            (page, page)
+        };
+
+        if start == 0 || start > end {
+            return Err(format_err!("invalid page range: {}", part));
+        }
+
+        if end > pages_count {
+            return Err(format_err!("page out of range: {} (the document has {} pages)", part, pages_count));
+        }
+
+        pages.extend((start..=end).map(|n| n - 1));
+    }
+
+    Ok(pages)
+}
+
+fn main() -> Result<(), Error> {
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    let mut opts = Options::new();
+
+    opts.optflag("h", "help", "Print this help message.");
+    opts.optflag("c", "color", "Render pages in color instead of grayscale.");
+    opts.optflag("i", "ignore-document-css", "Ignore the document's stylesheets.");
+    opts.optopt("o", "output-directory", "The directory where the pages are written.", "OUTPUT_DIR");
+    opts.optopt("g", "pages", "Comma separated list of pages or page ranges (e.g. `1,4-7`).", "PAGES");
+    opts.optopt("W", "width", "The width of the pages in pixels.", "WIDTH");
+    opts.optopt("H", "height", "The height of the pages in pixels.", "HEIGHT");
+    opts.optopt("d", "dpi", "The resolution of the virtual screen.", "DPI");
+    opts.optopt("s", "font-size", "The font size in points.", "FONT_SIZE");
+    opts.optopt("f", "font-family", "The font family name.", "FONT_FAMILY");
+    opts.optopt("p", "font-path", "The directory where the font families are searched.", "FONT_PATH");
+    opts.optopt("m", "margin-width", "The margin width in millimeters.", "MARGIN_WIDTH");
+    opts.optopt("l", "line-height", "The line height in ems.", "LINE_HEIGHT");
+    opts.optopt("t", "text-align", "The text alignment (`justify`, `left`, `right` or `center`).", "TEXT_ALIGN");
+    opts.optopt("y", "hyphen-penalty", "The penalty for lines ending with a hyphen.", "HYPHEN_PENALTY");
+    opts.optopt("r", "stretch-tolerance", "The stretching allowed for word spaces.", "STRETCH_TOLERANCE");
+
+    let matches = opts.parse(&args).context("failed to parse the command line arguments")?;
+
+    if matches.opt_present("h") {
+        println!("{}", opts.usage("Usage: plato-render -h|[-ci] [-o OUTPUT_DIR] [-g PAGES] [-W WIDTH] [-H HEIGHT] [-d DPI] [-s FONT_SIZE] [-f FONT_FAMILY] [-p FONT_PATH] [-m MARGIN_WIDTH] [-l LINE_HEIGHT] [-t TEXT_ALIGN] [-y HYPHEN_PENALTY] [-r STRETCH_TOLERANCE] DOCUMENT_PATH"));
+        return Ok(());
+    }
+
+    if matches.free.is_empty() {
+        return Err(format_err!("missing required argument: document path"));
+    }
+
+    let path = Path::new(&matches.free[0]);
+
+    let width = matches.opt_get_default("W", DEFAULT_WIDTH)
+                       .context("invalid width")?;
+    let height = matches.opt_get_default("H", DEFAULT_HEIGHT)
+                        .context("invalid height")?;
+    let dpi = matches.opt_get_default("d", DEFAULT_DPI)
+                     .context("invalid DPI")?;
+    let font_size = matches.opt_get_default("s", DEFAULT_FONT_SIZE)
+                           .context("invalid font size")?;
+    let margin_width = matches.opt_get_default("m", DEFAULT_MARGIN_WIDTH)
+                              .context("invalid margin width")?;
+    let line_height = matches.opt_get_default("l", DEFAULT_LINE_HEIGHT)
+                             .context("invalid line height")?;
+    let hyphen_penalty = matches.opt_get_default("y", HYPHEN_PENALTY)
+                                .context("invalid hyphen penalty")?;
+    let stretch_tolerance = matches.opt_get_default("r", STRETCH_TOLERANCE)
+                                   .context("invalid stretch tolerance")?;
+    let font_family = matches.opt_str("f")
+                             .unwrap_or_else(|| DEFAULT_FONT_FAMILY.to_string());
+    let font_path = matches.opt_str("p")
+                           .unwrap_or_else(|| DEFAULT_FONT_PATH.to_string());
+    let text_align = match matches.opt_str("t") {
+        Some(value) => parse_text_align(&value)
+                                       .ok_or_else(|| format_err!("invalid text alignment: {}", value))?,
+        None => DEFAULT_TEXT_ALIGN,
+    };
+    let samples = if matches.opt_present("c") { 3 } else { 1 };
+
+    let output_dir = Path::new(matches.opt_str("o").as_deref().unwrap_or("."))
+                         .to_path_buf();
+    fs::create_dir_all(&output_dir)
+       .with_context(|| format!("can't create output directory {}", output_dir.display()))?;
+
+    let mut doc = open(path).ok_or_else(|| format_err!("can't open {}", path.display()))?;
+
+    doc.layout(width, height, font_size, dpi);
+
+    if margin_width != DEFAULT_MARGIN_WIDTH {
+        doc.set_margin_width(margin_width);
+    }
+
+    if font_family != DEFAULT_FONT_FAMILY {
+        doc.set_font_family(&font_family, &font_path);
+    }
+
+    if (line_height - DEFAULT_LINE_HEIGHT).abs() > f32::EPSILON {
+        doc.set_line_height(line_height);
+    }
+
+    if text_align != DEFAULT_TEXT_ALIGN {
+        doc.set_text_align(text_align);
+    }
+
+    if hyphen_penalty != HYPHEN_PENALTY {
+        doc.set_hyphen_penalty(hyphen_penalty);
+    }
+
+    if (stretch_tolerance - STRETCH_TOLERANCE).abs() > f32::EPSILON {
+        doc.set_stretch_tolerance(stretch_tolerance);
+    }
+
+    if matches.opt_present("i") {
+        doc.set_ignore_document_css(true);
+    }
+
+    let pages = if doc.has_synthetic_page_numbers() {
+        page_offsets(doc.as_mut())
+    } else {
+        (0..doc.pages_count()).collect()
+    };
+
+    let selection = match matches.opt_str("g") {
+        Some(value) => parse_pages(&value, pages.len())?,
+        None => (0..pages.len()).collect(),
+    };
+
+    let stem = path.file_stem()
+                   .map(|s| s.to_string_lossy().into_owned())
+                   .unwrap_or_else(|| "page".to_string());
+
+    for index in selection {
+        let location = pages[index];
+        let scale = if doc.is_reflowable() {
+            1.0
+        } else {
+            doc.dims(location).map_or(1.0, |(w, h)| {
+                (width as f32 / w).min(height as f32 / h)
+            })
+        };
+        let (pixmap, _) = doc.pixmap(Location::Exact(location), scale, samples)
+                             .ok_or_else(|| format_err!("can't render page {}", index + 1))?;
+        let output_path = output_dir.join(format!("{}-{:04}.png", stem, index + 1));
+        pixmap.save(&output_path.to_string_lossy())?;
+        println!("{}", output_path.display());
+    }
+
+    Ok(())
+}
+
+// Documents with synthetic page numbers are addressed by byte offsets:
+// walk the pages to collect the offset of each one.
+fn page_offsets(doc: &mut dyn Document) -> Vec<usize> {
+    let mut offsets = Vec::new();
+    let mut location = doc.resolve_location(Location::Exact(0));
+
+    while let Some(offset) = location {
+        offsets.push(offset);
+        location = doc.resolve_location(Location::Next(offset));
+    }
+
+    offsets
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_pages;
+
+    #[test]
+    fn test_parse_pages() {
+        assert_eq!(parse_pages("1,3,5-7", 10).unwrap(), vec![0, 2, 4, 5, 6]);
+        assert_eq!(parse_pages(" 2 , ,4", 10).unwrap(), vec![1, 3]);
+        assert_eq!(parse_pages("8-", 10).unwrap(), vec![7, 8, 9]);
+        assert_eq!(parse_pages("-3", 10).unwrap(), vec![0, 1, 2]);
+        assert_eq!(parse_pages("-", 3).unwrap(), vec![0, 1, 2]);
+        assert_eq!(parse_pages("10", 10).unwrap(), vec![9]);
+    }
+
+    #[test]
+    fn test_parse_pages_errors() {
+        assert!(parse_pages("7-3", 10).is_err());
+        assert!(parse_pages("0", 10).is_err());
+        assert!(parse_pages("999", 10).is_err());
+        assert!(parse_pages("5-11", 10).is_err());
+        assert!(parse_pages("11-", 10).is_err());
+        assert!(parse_pages("a", 10).is_err());
+        assert!(parse_pages("1-b", 10).is_err());
+        assert!(parse_pages("1-2-3", 10).is_err());
+    }
+}
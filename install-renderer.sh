@@ -0,0 +1,3 @@
+#!/bin/sh
+
+./service.sh install_renderer "$@"
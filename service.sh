@@ -30,6 +30,9 @@ case "$CMD" in
 	install_importer)
 		cargo install --path crates/importer "$@"
 		;;
+	install_renderer)
+		cargo install --path crates/renderer "$@"
+		;;
 	*)
 		printf 'Unknown command: %s.\n' "$CMD" 1>&2
 		exit 1